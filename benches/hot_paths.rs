@@ -0,0 +1,200 @@
+//! Benchmarks of the decisions which run every tick, on the fake game objects of the tests.
+//!
+//! Run with `cargo bench --features fakes`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use screeps::{Part, StructureType};
+
+use screeps_starter_rust::{
+    creeps::{self, RoomOrders},
+    movement::costs,
+    spawning::{self, Role},
+    state::fake::{at, FakeCreep, FakeHostile, FakeRoom, FakeSite, FakeSource, FakeStructure},
+    structures::towers,
+};
+
+/// The tile the `n`th of something is put on, spread over the room away from the edges.
+fn tile(n: u32) -> (u32, u32) {
+    (2 + n * 7 % 46, 2 + n * 13 % 46)
+}
+
+/// A room of ours with `structures` structures of the usual kinds, half of the extensions
+/// waiting for energy, some worn down, and a storage with energy in it.
+fn room(structures: u32) -> FakeRoom {
+    let mut room = FakeRoom {
+        sources: vec![FakeSource::full(1, 10, 40), FakeSource::full(2, 40, 10)],
+        sites: (0..structures / 20)
+            .map(|n| {
+                let (x, y) = tile(n + 1000);
+                FakeSite {
+                    id: 100 + n,
+                    pos: at(x, y),
+                }
+            })
+            .collect(),
+        controller: Some(3),
+        energy_capacity: 5600,
+        ..FakeRoom::default()
+    };
+    let mut storage = FakeStructure::new(4, StructureType::Storage, 25, 25);
+    storage.energy = 50_000;
+    room = room.with(storage);
+
+    const KINDS: &[StructureType] = &[
+        StructureType::Road,
+        StructureType::Road,
+        StructureType::Extension,
+        StructureType::Rampart,
+        StructureType::Container,
+        StructureType::Wall,
+    ];
+    for n in 0..structures {
+        let (x, y) = tile(n);
+        let kind = KINDS[n as usize % KINDS.len()];
+        let mut structure = FakeStructure::new(10_000 + n, kind, x, y);
+        match kind {
+            StructureType::Extension if n % 2 == 0 => structure.energy_room = 50,
+            StructureType::Rampart => {
+                structure.hits = 5_000 + n * 1_000;
+                structure.hits_max = 300_000_000;
+            }
+            StructureType::Wall => structure.mine = false,
+            _ if n % 5 == 0 => structure.hits = 100,
+            _ => {}
+        }
+        room = room.with(structure);
+    }
+    room
+}
+
+/// `count` creeps spread over the room, every other one carrying energy.
+fn creeps(count: u32) -> Vec<FakeCreep> {
+    (0..count)
+        .map(|n| {
+            let (x, y) = tile(n + 500);
+            if n % 2 == 0 {
+                FakeCreep::empty(x, y)
+            } else {
+                FakeCreep::full(x, y)
+            }
+        })
+        .collect()
+}
+
+/// `count` hostiles near the middle of the room, every third one a healer.
+fn hostiles(count: u32) -> Vec<FakeHostile> {
+    (0..count)
+        .map(|n| {
+            let parts = if n % 3 == 0 {
+                vec![(Part::Heal, None); 10]
+            } else {
+                vec![(Part::Attack, None); 10]
+            };
+            FakeHostile {
+                pos: at(20 + n % 10, 20 + n / 10),
+                hits: 1_000 + n * 100,
+                parts,
+            }
+        })
+        .collect()
+}
+
+fn pick_targets(creeps: &[FakeCreep], room: &FakeRoom, orders: &RoomOrders) {
+    for creep in creeps {
+        black_box(creeps::pick_target(creep, room, orders));
+    }
+}
+
+fn target_selection(c: &mut Criterion) {
+    let orders = RoomOrders::default();
+    let mut group = c.benchmark_group("target_selection");
+    for &(creep_count, structure_count) in &[(5, 20), (20, 80), (50, 200), (100, 400)] {
+        let room = room(structure_count);
+        let creeps = creeps(creep_count);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}x{}", creep_count, structure_count)),
+            &(creeps, room),
+            |b, (creeps, room)| b.iter(|| pick_targets(creeps, room, &orders)),
+        );
+    }
+    group.finish();
+}
+
+fn body_tiling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("body_tiling");
+    for &size in &[1, 4, 16] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                for &role in Role::ALL {
+                    black_box(role.sized_body(black_box(size)));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn block_matrix(c: &mut Criterion) {
+    // walls along the edges and in clumps, with swamps between them
+    let terrain: Vec<u8> = (0..2500u32)
+        .map(|i| {
+            let (x, y) = (i % 50, i / 50);
+            if x == 0 || y == 0 || x == 49 || y == 49 || (x * 3 + y * 5) % 17 == 0 {
+                1
+            } else if (x + y) % 7 == 0 {
+                2
+            } else {
+                0
+            }
+        })
+        .collect();
+    let mut group = c.benchmark_group("block_matrix");
+    for &size in &[1, 2] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| costs::block_matrix_from(&terrain, None, size, (2, 10)))
+        });
+    }
+    group.finish();
+}
+
+fn tower_solver(c: &mut Criterion) {
+    let towers: Vec<(u32, u32)> = (0..6).map(|n| (22 + n, 28)).collect();
+    let mut group = c.benchmark_group("tower_solver");
+    for &count in &[1, 5, 20] {
+        let room = FakeRoom {
+            hostiles: hostiles(count),
+            ..FakeRoom::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(count), &room, |b, room| {
+            b.iter(|| towers::aim(room, &towers))
+        });
+    }
+    group.finish();
+}
+
+/// What a busy room of ours decides in a tick: all of its creeps picking targets, its towers
+/// aiming and its spawn sizing the next creep.
+fn busy_room(c: &mut Criterion) {
+    let mut room = room(200);
+    room.hostiles = hostiles(4);
+    let creeps = creeps(50);
+    let orders = RoomOrders::default();
+    let towers: Vec<(u32, u32)> = (0..6).map(|n| (22 + n, 28)).collect();
+    c.bench_function("room_50_creeps_200_structures", |b| {
+        b.iter(|| {
+            pick_targets(&creeps, &room, &orders);
+            black_box(towers::aim(&room, &towers));
+            black_box(spawning::affordable_size(Role::Worker, &room));
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    target_selection,
+    body_tiling,
+    block_matrix,
+    tower_solver,
+    busy_room
+);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+//! Embeds when the code was built, and a hash of it, into the bot.
+//!
+//! The script only reruns when a source file or the manifest changes, so the timestamp is that of
+//! the last build with changes rather than of every compile.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Hashes the contents of every file below `dir`, in a stable order.
+fn hash_dir(dir: &Path, hasher: &mut DefaultHasher) {
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            hash_dir(&path, hasher);
+        } else if let Ok(contents) = fs::read(&path) {
+            path.hash(hasher);
+            contents.hash(hasher);
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let mut hasher = DefaultHasher::new();
+    hash_dir(Path::new("src"), &mut hasher);
+    fs::read("Cargo.toml").unwrap_or_default().hash(&mut hasher);
+    println!("cargo:rustc-env=BUILD_HASH={:016x}", hasher.finish());
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+}
@@ -0,0 +1,283 @@
+//! Creep actions, and what came of them.
+//!
+//! [`perform`] carries out an [`Action`] and sorts its return code into an [`ActionOutcome`], so
+//! each caller only decides what an outcome means for it. A target out of range has the creep
+//! move closer right away, commuting for deliveries, and a creep working on something from a
+//! distance holds its ground so it isn't shoved out of range. Everything which didn't go through,
+//! except running out of what the action takes, is [`failures::report`]ed, and everything which
+//! did is counted by [`energy`] and [`lifetimes`].
+
+use screeps::{
+    prelude::*, ConstructionSite, Creep, ResourceType, ReturnCode, Source, Structure,
+    StructureController,
+};
+
+use crate::{energy, failures, lifetimes, movement};
+
+/// Something a creep does to a target.
+#[derive(Clone, Copy)]
+pub enum Action<'a> {
+    Harvest(&'a Source),
+    /// Put all of a resource into a structure.
+    Transfer(&'a Structure, ResourceType),
+    /// Take as much of a resource out of a container or storage as fits.
+    Withdraw(&'a Structure, ResourceType),
+    Build(&'a ConstructionSite),
+    Repair(&'a Structure),
+    Upgrade(&'a StructureController),
+    Dismantle(&'a Structure),
+    Attack(&'a Structure),
+}
+
+impl Action<'_> {
+    /// How close a creep has to be to the target.
+    pub fn range(self) -> u32 {
+        match self {
+            Action::Build(_) | Action::Repair(_) | Action::Upgrade(_) => 3,
+            _ => 1,
+        }
+    }
+
+    /// The name of the action, for failure reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Harvest(_) => "harvest",
+            Action::Transfer(..) => "transfer",
+            Action::Withdraw(..) => "withdraw",
+            Action::Build(_) => "build",
+            Action::Repair(_) => "repair",
+            Action::Upgrade(_) => "upgrade",
+            Action::Dismantle(_) => "dismantle",
+            Action::Attack(_) => "attack",
+        }
+    }
+
+    fn target_pos(self) -> screeps::Position {
+        match self {
+            Action::Harvest(source) => source.pos(),
+            Action::Transfer(structure, _)
+            | Action::Withdraw(structure, _)
+            | Action::Repair(structure)
+            | Action::Dismantle(structure)
+            | Action::Attack(structure) => structure.pos(),
+            Action::Build(site) => site.pos(),
+            Action::Upgrade(controller) => controller.pos(),
+        }
+    }
+
+    /// Whether the action goes on over many ticks, so the creep holds its ground while it works.
+    fn is_lasting(self) -> bool {
+        !matches!(self, Action::Transfer(..) | Action::Withdraw(..))
+    }
+
+    fn call(self, creep: &Creep) -> ReturnCode {
+        match self {
+            Action::Harvest(source) => creep.harvest(source),
+            Action::Transfer(structure, resource) => transfer(creep, structure, resource),
+            Action::Withdraw(structure, resource) => withdraw(creep, structure, resource),
+            Action::Build(site) => creep.build(site),
+            Action::Repair(structure) => creep.repair(structure),
+            Action::Upgrade(controller) => creep.upgrade_controller(controller),
+            Action::Dismantle(structure) => creep.dismantle(structure),
+            Action::Attack(structure) => match structure.as_attackable() {
+                Some(attackable) => creep.attack(attackable),
+                None => ReturnCode::InvalidTarget,
+            },
+        }
+    }
+}
+
+/// What came of an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionOutcome {
+    /// It went through.
+    Done,
+    /// The target is out of range, and the creep is on its way.
+    MoveCloser,
+    /// The target can't take the action, because it's gone, full or the wrong kind.
+    TargetInvalid,
+    /// The creep or the target ran out of what the action takes.
+    OutOfResources,
+    /// Anything else, including there being no way to the target.
+    Failed(ReturnCode),
+}
+
+impl ActionOutcome {
+    /// Whether the creep is getting on with it, working or walking there.
+    pub fn is_working(self) -> bool {
+        matches!(self, ActionOutcome::Done | ActionOutcome::MoveCloser)
+    }
+}
+
+/// Has a creep carry out an action, moving it closer if it's out of range.
+pub fn perform(creep: &Creep, action: Action) -> ActionOutcome {
+    let target = action.target_pos();
+    let range = action.range();
+    let outcome = outcome_of(action.call(creep), || match action {
+        Action::Transfer(..) => movement::commute_to(creep, &target, range),
+        _ => movement::move_creep_to(creep, &target, range),
+    });
+    if outcome == ActionOutcome::Done {
+        let energy = record_energy(creep, action);
+        lifetimes::record(&creep.name(), action, energy);
+    }
+    match outcome {
+        ActionOutcome::Done if action.is_lasting() => movement::hold(creep, &target, range),
+        ActionOutcome::TargetInvalid | ActionOutcome::Failed(_) => {
+            let code = match outcome {
+                ActionOutcome::Failed(code) => code,
+                _ => ReturnCode::InvalidTarget,
+            };
+            failures::report(&creep.name(), action.name(), code);
+        }
+        _ => {}
+    }
+    outcome
+}
+
+/// Sorts an action's return code into an outcome, the same way for every action. `move_closer`
+/// is only called for a target out of range, and returns whether the creep is on its way.
+fn outcome_of(code: ReturnCode, move_closer: impl FnOnce() -> bool) -> ActionOutcome {
+    match code {
+        ReturnCode::Ok => ActionOutcome::Done,
+        ReturnCode::NotInRange => {
+            if move_closer() {
+                ActionOutcome::MoveCloser
+            } else {
+                ActionOutcome::Failed(ReturnCode::NoPath)
+            }
+        }
+        ReturnCode::NotEnough => ActionOutcome::OutOfResources,
+        ReturnCode::InvalidTarget | ReturnCode::NotFound | ReturnCode::Full => {
+            ActionOutcome::TargetInvalid
+        }
+        r => ActionOutcome::Failed(r),
+    }
+}
+
+/// Like [`perform`], but failures which only a bug causes, like acting on something the creep
+/// doesn't own or with arguments the game rejects, are an error instead of an outcome.
+pub fn try_perform(creep: &Creep, action: Action) -> Result<ActionOutcome, String> {
+    match perform(creep, action) {
+        ActionOutcome::Failed(r @ ReturnCode::NotOwner)
+        | ActionOutcome::Failed(r @ ReturnCode::InvalidArgs) => {
+            Err(format!("couldn't {}: {:?}", action.name(), r))
+        }
+        outcome => Ok(outcome),
+    }
+}
+
+/// Counts the energy an action which went through moved, returning how much it was.
+fn record_energy(creep: &Creep, action: Action) -> u32 {
+    match action {
+        Action::Harvest(source) => energy::record_harvest(creep, source),
+        Action::Transfer(structure, ResourceType::Energy) => {
+            energy::record_delivery(creep, structure)
+        }
+        Action::Build(site) => energy::record_build(creep, site.pos().room_name()),
+        Action::Repair(structure) => energy::record_repair(creep, structure.pos().room_name()),
+        Action::Upgrade(controller) => energy::record_upgrade(creep, controller.pos().room_name()),
+        _ => 0,
+    }
+}
+
+fn transfer(creep: &Creep, structure: &Structure, resource: ResourceType) -> ReturnCode {
+    match structure {
+        Structure::Spawn(s) => creep.transfer_all(s, resource),
+        Structure::Extension(s) => creep.transfer_all(s, resource),
+        Structure::Tower(s) => creep.transfer_all(s, resource),
+        Structure::Storage(s) => creep.transfer_all(s, resource),
+        Structure::Container(s) => creep.transfer_all(s, resource),
+        Structure::Lab(s) => creep.transfer_all(s, resource),
+        Structure::Terminal(s) => creep.transfer_all(s, resource),
+        _ => ReturnCode::InvalidTarget,
+    }
+}
+
+fn withdraw(creep: &Creep, structure: &Structure, resource: ResourceType) -> ReturnCode {
+    match structure {
+        Structure::Container(s) => creep.withdraw_all(s, resource),
+        Structure::Link(s) => creep.withdraw_all(s, resource),
+        Structure::Storage(s) => creep.withdraw_all(s, resource),
+        Structure::Terminal(s) => creep.withdraw_all(s, resource),
+        _ => ReturnCode::InvalidTarget,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_CODES: [ReturnCode; 15] = [
+        ReturnCode::Ok,
+        ReturnCode::NotOwner,
+        ReturnCode::NoPath,
+        ReturnCode::NameExists,
+        ReturnCode::Busy,
+        ReturnCode::NotFound,
+        ReturnCode::NotEnough,
+        ReturnCode::InvalidTarget,
+        ReturnCode::Full,
+        ReturnCode::NotInRange,
+        ReturnCode::InvalidArgs,
+        ReturnCode::Tired,
+        ReturnCode::NoBodypart,
+        ReturnCode::RclNotEnough,
+        ReturnCode::GclNotEnough,
+    ];
+
+    // every action goes through the same mapping, which is the point of it, so checking every
+    // code covers every action
+    #[test]
+    fn every_code_maps_to_its_outcome() {
+        use ActionOutcome::*;
+        let table = [
+            (ReturnCode::Ok, Done),
+            (ReturnCode::NotOwner, Failed(ReturnCode::NotOwner)),
+            (ReturnCode::NoPath, Failed(ReturnCode::NoPath)),
+            (ReturnCode::NameExists, Failed(ReturnCode::NameExists)),
+            (ReturnCode::Busy, Failed(ReturnCode::Busy)),
+            (ReturnCode::NotFound, TargetInvalid),
+            (ReturnCode::NotEnough, OutOfResources),
+            (ReturnCode::InvalidTarget, TargetInvalid),
+            (ReturnCode::Full, TargetInvalid),
+            (ReturnCode::InvalidArgs, Failed(ReturnCode::InvalidArgs)),
+            (ReturnCode::Tired, Failed(ReturnCode::Tired)),
+            (ReturnCode::NoBodypart, Failed(ReturnCode::NoBodypart)),
+            (ReturnCode::RclNotEnough, Failed(ReturnCode::RclNotEnough)),
+            (ReturnCode::GclNotEnough, Failed(ReturnCode::GclNotEnough)),
+        ];
+        assert_eq!(table.len() + 1, ALL_CODES.len());
+        for &(code, outcome) in &table {
+            assert_eq!(outcome_of(code, || true), outcome, "{:?}", code);
+        }
+        assert_eq!(outcome_of(ReturnCode::NotInRange, || true), MoveCloser);
+        assert_eq!(
+            outcome_of(ReturnCode::NotInRange, || false),
+            Failed(ReturnCode::NoPath)
+        );
+    }
+
+    #[test]
+    fn only_targets_out_of_range_move() {
+        for &code in &ALL_CODES {
+            let mut moved = false;
+            outcome_of(code, || {
+                moved = true;
+                true
+            });
+            assert_eq!(moved, code == ReturnCode::NotInRange, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn only_working_outcomes_keep_the_creep_at_it() {
+        assert!(ActionOutcome::Done.is_working());
+        assert!(ActionOutcome::MoveCloser.is_working());
+        assert!(!ActionOutcome::TargetInvalid.is_working());
+        assert!(!ActionOutcome::OutOfResources.is_working());
+        for &code in &ALL_CODES {
+            assert!(!ActionOutcome::Failed(code).is_working());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The players we're allied with, listed by username in `Memory.config.allies`.
+//!
+//! Allies' creeps and structures are neutral: they aren't counted as hostiles by the room
+//! snapshots, which the towers, the threat assessment, the defenders and fleeing creeps all go
+//! by, nor by intel, so their rooms aren't avoided as hostile or attacked. Everything asks
+//! [`is_ally`] or filters through [`without_allies`], so this is the one place to change when the
+//! list comes from somewhere else.
+
+const ALLIES_PATH: &str = "config.allies";
+
+/// The usernames of our allies.
+pub fn allies() -> Vec<String> {
+    screeps::memory::root()
+        .path_arr(ALLIES_PATH)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Whether a player is one of our allies.
+pub fn is_ally(username: &str) -> bool {
+    is_listed(&allies(), username)
+}
+
+/// Drops what our allies own from `items`, with `owner` telling who owns each. Things nobody
+/// owns are kept.
+pub fn without_allies<T>(
+    items: impl IntoIterator<Item = T>,
+    owner: impl Fn(&T) -> Option<String>,
+) -> Vec<T> {
+    strip(items, &allies(), owner)
+}
+
+fn is_listed(allies: &[String], username: &str) -> bool {
+    allies.iter().any(|ally| ally == username)
+}
+
+fn strip<T>(
+    items: impl IntoIterator<Item = T>,
+    allies: &[String],
+    owner: impl Fn(&T) -> Option<String>,
+) -> Vec<T> {
+    items
+        .into_iter()
+        .filter(|item| !owner(item).map_or(false, |owner| is_listed(allies, &owner)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allies() -> Vec<String> {
+        vec!["Friend".to_string(), "Neighbour".to_string()]
+    }
+
+    #[test]
+    fn only_listed_players_are_allies() {
+        let allies = allies();
+        assert!(is_listed(&allies, "Friend"));
+        assert!(!is_listed(&allies, "friend"));
+        assert!(!is_listed(&allies, "Invader"));
+        assert!(!is_listed(&[], "Friend"));
+    }
+
+    #[test]
+    fn mixed_room_keeps_only_the_hostiles() {
+        // creeps as (owner, name) and structures as (owner, kind), as a room would show them
+        let creeps = vec![
+            ("Friend", "hauler"),
+            ("Raider", "attacker"),
+            ("Neighbour", "scout"),
+            ("Invader", "invader"),
+            ("Raider", "healer"),
+        ];
+        let hostile = strip(creeps, &allies(), |&(owner, _)| Some(owner.to_string()));
+        let names: Vec<&str> = hostile.iter().map(|&(_, name)| name).collect();
+        assert_eq!(names, vec!["attacker", "invader", "healer"]);
+
+        let structures = vec![
+            (Some("Friend"), "tower"),
+            (None, "wall"),
+            (Some("Raider"), "tower"),
+        ];
+        let hostile = strip(structures, &allies(), |&(owner, _)| owner.map(String::from));
+        assert_eq!(hostile, vec![(None, "wall"), (Some("Raider"), "tower")]);
+    }
+
+    #[test]
+    fn without_allies_listed_everything_is_kept() {
+        let creeps = vec!["Friend", "Raider"];
+        assert_eq!(strip(creeps.clone(), &[], |c| Some(c.to_string())), creeps);
+    }
+}
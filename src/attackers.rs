@@ -0,0 +1,353 @@
+//! Who keeps attacking us.
+//!
+//! Whenever another player's creeps are in a room of ours or a remote, [`threat`] has the
+//! sighting recorded here. Each attack is kept in `Memory.attackers.<username>` as the tick it
+//! started and was last seen, the room, the most creeps and parts seen at once, whether any were
+//! boosted, and the damage done to our creeps and structures. Damage comes from how many hits
+//! those lost since the tick before, shared between the players in the room by their armed
+//! parts, so healing and repairs done meanwhile hide some of it. A sighting more than
+//! [`ATTACK_GAP`] ticks after the last one in the room starts a new attack, and only the last
+//! [`MAX_ATTACKS`] attacks of each player are kept.
+//!
+//! `threat_report()` from the console sums up each player's attacks. A remote next to a player
+//! who attacked [`HARASSER_ATTACKS`] times within the last [`RECENT_TICKS`], whether it's next
+//! to one of their attacks or to a room they own or reserve, is [`is_harassed`], and its spawn
+//! requests go behind the other remotes'.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{find, prelude::*, Attackable, Part, RawObjectId, Room, RoomName};
+
+use crate::{context::TickContext, heap::CacheSize, intel, threat};
+
+const ATTACKERS_KEY: &str = "attackers";
+
+/// How long a room has to be clear of a player before their next sighting is a new attack.
+pub const ATTACK_GAP: u32 = 100;
+
+/// How many attacks are kept per player.
+pub const MAX_ATTACKS: usize = 20;
+
+/// How many players are kept, dropping whoever was seen longest ago.
+const MAX_PLAYERS: usize = 30;
+
+/// How far back attacks count as recent.
+pub const RECENT_TICKS: u32 = 20_000;
+
+/// How many recent attacks make a player a harasser.
+pub const HARASSER_ATTACKS: usize = 3;
+
+/// One attack by a player on one of our rooms.
+#[derive(Clone, Debug, Default)]
+struct Attack {
+    started: u32,
+    last_seen: u32,
+    room: String,
+    creeps: u32,
+    attack: u32,
+    ranged: u32,
+    heal: u32,
+    work: u32,
+    boosted: bool,
+    damage: u32,
+}
+
+impl Attack {
+    /// Stores an attack as `started,last_seen,room,creeps,attack,ranged,heal,work,boosted,damage`.
+    fn encode(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.started,
+            self.last_seen,
+            self.room,
+            self.creeps,
+            self.attack,
+            self.ranged,
+            self.heal,
+            self.work,
+            self.boosted as u8,
+            self.damage
+        )
+    }
+
+    fn decode(encoded: &str) -> Option<Attack> {
+        let mut fields = encoded.split(',');
+        Some(Attack {
+            started: fields.next()?.parse().ok()?,
+            last_seen: fields.next()?.parse().ok()?,
+            room: fields.next()?.to_string(),
+            creeps: fields.next()?.parse().ok()?,
+            attack: fields.next()?.parse().ok()?,
+            ranged: fields.next()?.parse().ok()?,
+            heal: fields.next()?.parse().ok()?,
+            work: fields.next()?.parse().ok()?,
+            boosted: fields.next()? == "1",
+            damage: fields.next()?.parse().ok()?,
+        })
+    }
+
+    /// What the creeps came with, as a short summary.
+    fn composition(&self) -> String {
+        format!(
+            "{} creeps with {} attack, {} ranged, {} heal and {} work parts{}",
+            self.creeps,
+            self.attack,
+            self.ranged,
+            self.heal,
+            self.work,
+            if self.boosted { ", boosted" } else { "" }
+        )
+    }
+
+    /// The parts which do damage, which the damage in a room is shared out by.
+    fn armed_parts(&self) -> u32 {
+        self.attack + self.ranged + self.work
+    }
+}
+
+/// The hits of each of our creeps and structures in a room.
+type Hits = HashMap<RawObjectId, u32>;
+
+thread_local! {
+    /// The hits in each room with attackers in it, and the tick they were taken.
+    static HITS: RefCell<HashMap<RoomName, (u32, Hits)>> = RefCell::new(HashMap::new());
+}
+
+fn load(username: &str) -> Vec<Attack> {
+    screeps::memory::root()
+        .dict(ATTACKERS_KEY)
+        .ok()
+        .flatten()
+        .and_then(|attackers| attackers.string(username).ok().flatten())
+        .map_or_else(Vec::new, |encoded| {
+            encoded.split(';').filter_map(Attack::decode).collect()
+        })
+}
+
+/// Every player who attacked us with their attacks, oldest first.
+fn all() -> Vec<(String, Vec<Attack>)> {
+    let attackers = match screeps::memory::root().dict(ATTACKERS_KEY) {
+        Ok(Some(attackers)) => attackers,
+        _ => return Vec::new(),
+    };
+    let mut usernames = attackers.keys();
+    usernames.sort();
+    usernames
+        .into_iter()
+        .map(|username| {
+            let attacks = load(&username);
+            (username, attacks)
+        })
+        .collect()
+}
+
+/// Records the players in a room we own or mine, along with the damage they did since the tick
+/// before.
+pub fn record(room: &Room, ctx: &TickContext) {
+    let time = ctx.time;
+    let room_name = room.name();
+    let mut sightings: HashMap<String, Attack> = HashMap::new();
+    for hostile in ctx.rooms.snapshot(room).hostiles() {
+        let owner = hostile.owner_name();
+        if threat::NPC_OWNERS.contains(&owner.as_str()) {
+            continue;
+        }
+        let sighting = sightings.entry(owner).or_default();
+        sighting.creeps += 1;
+        sighting.attack += hostile.get_active_bodyparts(Part::Attack);
+        sighting.ranged += hostile.get_active_bodyparts(Part::RangedAttack);
+        sighting.heal += hostile.get_active_bodyparts(Part::Heal);
+        sighting.work += hostile.get_active_bodyparts(Part::Work);
+        sighting.boosted |= hostile.body().iter().any(|part| part.boost.is_some());
+    }
+    if sightings.is_empty() {
+        HITS.with(|hits| hits.borrow_mut().remove(&room_name));
+        return;
+    }
+
+    let damage = HITS.with(|hits| {
+        let now = our_hits(room, ctx);
+        let last = hits.borrow_mut().insert(room_name, (time, now.clone()));
+        match last {
+            Some((taken, last)) if taken + 1 == time => now
+                .iter()
+                .filter_map(|(id, &hits)| Some(last.get(id)?.saturating_sub(hits)))
+                .sum(),
+            _ => 0,
+        }
+    });
+    let armed: u32 = sightings.values().map(Attack::armed_parts).sum();
+    for (username, mut sighting) in sightings {
+        if armed > 0 {
+            sighting.damage = (damage as u64 * sighting.armed_parts() as u64 / armed as u64) as u32;
+        }
+        add_sighting(&username, room_name, sighting, time);
+    }
+}
+
+/// The hits of our creeps and structures in a room.
+fn our_hits(room: &Room, ctx: &TickContext) -> Hits {
+    let mut hits: Hits = room
+        .find(find::MY_CREEPS)
+        .into_iter()
+        .map(|creep| (creep.id().into(), Attackable::hits(&creep)))
+        .collect();
+    for structure in ctx.rooms.snapshot(room).all_structures() {
+        let mine = structure.as_owned().map_or(false, |owned| owned.my());
+        if let Some(attackable) = structure.as_attackable().filter(|_| mine) {
+            hits.insert(structure.id().into(), attackable.hits());
+        }
+    }
+    hits
+}
+
+/// Adds a sighting to the player's last attack on the room, or starts a new one.
+fn add_sighting(username: &str, room_name: RoomName, sighting: Attack, time: u32) {
+    let mut attacks = load(username);
+    let room = room_name.to_string();
+    let ongoing = attacks
+        .iter_mut()
+        .rev()
+        .find(|attack| attack.room == room && attack.last_seen + ATTACK_GAP >= time);
+    match ongoing {
+        Some(attack) => {
+            attack.last_seen = time;
+            attack.creeps = attack.creeps.max(sighting.creeps);
+            attack.attack = attack.attack.max(sighting.attack);
+            attack.ranged = attack.ranged.max(sighting.ranged);
+            attack.heal = attack.heal.max(sighting.heal);
+            attack.work = attack.work.max(sighting.work);
+            attack.boosted |= sighting.boosted;
+            attack.damage += sighting.damage;
+        }
+        None => {
+            info!(
+                "{} is attacking room {} with {}, their attack number {} on us",
+                username,
+                room_name,
+                sighting.composition(),
+                attacks.len() + 1
+            );
+            attacks.push(Attack {
+                started: time,
+                last_seen: time,
+                room,
+                ..sighting
+            });
+            let excess = attacks.len().saturating_sub(MAX_ATTACKS);
+            attacks.drain(..excess);
+        }
+    }
+    save(username, &attacks);
+}
+
+fn save(username: &str, attacks: &[Attack]) {
+    let attackers = match screeps::memory::root().dict_or_create(ATTACKERS_KEY) {
+        Ok(attackers) => attackers,
+        Err(e) => {
+            warn!("couldn't store the attacks of {}: {}", username, e);
+            return;
+        }
+    };
+    let encoded: Vec<String> = attacks.iter().map(Attack::encode).collect();
+    attackers.set(username, encoded.join(";"));
+
+    let mut players = attackers.keys();
+    if players.len() > MAX_PLAYERS {
+        players.sort_by_key(|player| load(player).last().map_or(0, |attack| attack.last_seen));
+        for player in &players[..players.len() - MAX_PLAYERS] {
+            debug!("forgetting the attacks of {}", player);
+            attackers.del(player);
+        }
+    }
+}
+
+/// How many times a player attacked us within the last [`RECENT_TICKS`].
+fn recent_attacks(attacks: &[Attack], time: u32) -> usize {
+    attacks
+        .iter()
+        .filter(|attack| attack.last_seen + RECENT_TICKS >= time)
+        .count()
+}
+
+/// Whether a remote is next to a player who keeps attacking us: to one of their recent attacks,
+/// or to a room they own or reserve.
+pub fn is_harassed(remote: RoomName) -> bool {
+    let time = screeps::game::time();
+    let mut nearby: Vec<RoomName> = screeps::game::map::describe_exits(remote)
+        .values()
+        .copied()
+        .collect();
+    nearby.push(remote);
+    let claimed_by = |username: &str| {
+        nearby.iter().any(|&room_name| {
+            intel::get(room_name)
+                .and_then(|intel| intel.controller)
+                .map_or(false, |c| {
+                    c.owner.as_deref() == Some(username)
+                        || c.reserved_by.as_deref() == Some(username)
+                })
+        })
+    };
+    all().into_iter().any(|(username, attacks)| {
+        recent_attacks(&attacks, time) >= HARASSER_ATTACKS
+            && (claimed_by(&username)
+                || attacks.iter().any(|attack| {
+                    attack.last_seen + RECENT_TICKS >= time
+                        && nearby.iter().any(|room_name| *room_name == attack.room)
+                }))
+    })
+}
+
+/// A line per player who attacked us, most recent attacks first, with how much damage they did
+/// and what they were last seen with.
+pub fn report() -> Vec<String> {
+    let time = screeps::game::time();
+    let mut players = all();
+    players.retain(|(_, attacks)| !attacks.is_empty());
+    players.sort_by_key(|(_, attacks)| {
+        let last_seen = attacks.last().map_or(0, |attack| attack.last_seen);
+        (
+            std::cmp::Reverse(recent_attacks(attacks, time)),
+            std::cmp::Reverse(last_seen),
+        )
+    });
+    players
+        .into_iter()
+        .filter_map(|(username, attacks)| {
+            let last = attacks.last()?;
+            let damage: u32 = attacks.iter().map(|attack| attack.damage).sum();
+            let mut rooms: Vec<&str> = attacks.iter().map(|attack| attack.room.as_str()).collect();
+            rooms.sort_unstable();
+            rooms.dedup();
+            Some(format!(
+                "{}: {} attacks, {} recent, {} damage, in {}; last seen in {} at tick {} ({} ticks \
+                 ago) with {}",
+                username,
+                attacks.len(),
+                recent_attacks(&attacks, time),
+                damage,
+                rooms.join(", "),
+                last.room,
+                last.last_seen,
+                time.saturating_sub(last.last_seen),
+                last.composition()
+            ))
+        })
+        .collect()
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![HITS.with(|h| {
+        CacheSize::of_map("attackers.hits", &h.borrow(), |_, (_, hits)| {
+            hits.capacity() * (std::mem::size_of::<RawObjectId>() + 4)
+        })
+    })]
+}
+
+/// Forgets the hits taken, so the next tick of each attack counts no damage.
+pub fn purge() {
+    HITS.with(|h| std::mem::take(&mut *h.borrow_mut()));
+}
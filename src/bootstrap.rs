@@ -0,0 +1,170 @@
+//! Getting a freshly claimed room on its feet.
+//!
+//! A claimed room has no spawn of its own, so its parent, the closest room of ours with one,
+//! spawns [`PIONEERS`] pioneers for it. Pioneers travel to the new room and then work like the
+//! workers of any room, harvesting its sources to build the spawn from its plan, whose site is
+//! placed here if construction hasn't yet. They upgrade the controller first whenever it gets
+//! close to downgrading.
+//!
+//! Each room being bootstrapped is an [`operations`] operation with the parent as its home. Once
+//! the room's spawn stands the pioneers become its workers and it spawns its own from then on. If
+//! the claim is lost on the way, the pioneers go back to the parent and work for it instead.
+
+use log::*;
+use screeps::{
+    prelude::*, Creep, Position, ResourceType, ReturnCode, Room, RoomName, StructureType,
+};
+
+use crate::{
+    context::TickContext,
+    creeps::{self, CreepTarget},
+    emergency, movement,
+    operations::{self, Kind, Operation, Outcome, Wanted},
+    planner,
+    spawning::{self, Role},
+};
+
+/// Where the rooms being bootstrapped were kept before they were operations.
+const LEGACY_KEY: &str = "bootstrap";
+
+/// How many pioneers work on a new room at once.
+pub const PIONEERS: u32 = 4;
+
+/// The priority of pioneer spawn requests, above the remotes'.
+const PIONEER_PRIORITY: u8 = 160;
+
+/// Pioneers upgrade the controller before anything else below this many ticks to downgrade.
+const DOWNGRADE_GUARD: u32 = 3000;
+
+/// Starts bootstrapping a room we just claimed, from the closest room of ours with a spawn.
+pub fn start(room_name: RoomName) {
+    let parent = screeps::game::spawns::values()
+        .into_iter()
+        .map(|spawn| spawn.pos().room_name())
+        .filter(|&name| name != room_name && !emergency::is_abandoned(name))
+        .min_by_key(|&name| screeps::game::map::get_room_linear_distance(name, room_name, false));
+    match parent {
+        Some(parent) => operations::start(Kind::Bootstrap, room_name, parent),
+        None => warn!("no room of ours can bootstrap {}", room_name),
+    }
+}
+
+/// Turns the rooms kept in `Memory.bootstrap` into operations.
+pub fn migrate() {
+    let memory = screeps::memory::root();
+    let legacy = match memory.dict(LEGACY_KEY) {
+        Ok(Some(legacy)) => legacy,
+        _ => return,
+    };
+    for room in legacy.keys() {
+        let parent = legacy.string(&room).ok().flatten().unwrap_or_default();
+        if let (Ok(room), Ok(parent)) = (RoomName::new(&room), RoomName::new(&parent)) {
+            operations::start(Kind::Bootstrap, room, parent);
+        }
+    }
+    memory.del(LEGACY_KEY);
+}
+
+/// Checks on a room being bootstrapped, ending it if it's done or lost and otherwise keeping it
+/// supplied with pioneers.
+pub fn check_operation(operation: &mut Operation, ctx: &TickContext) -> Outcome {
+    let room_name = operation.room;
+    // our rooms are always visible, so one which isn't has been lost
+    let room = screeps::game::rooms::get(room_name)
+        .filter(|room| room.controller().map_or(false, |c| c.my()));
+    let room = match room {
+        Some(room) => room,
+        None => {
+            finish(operation, operation.home);
+            return Outcome::Failed(format!(
+                "lost the room, sending its pioneers back to {}",
+                operation.home
+            ));
+        }
+    };
+
+    let snapshot = ctx.rooms.snapshot(&room);
+    if snapshot
+        .my_structures(StructureType::Spawn)
+        .next()
+        .is_some()
+    {
+        finish(operation, room_name);
+        return Outcome::Done("the room has a spawn".to_string());
+    }
+    let has_site = snapshot
+        .construction_sites()
+        .iter()
+        .any(|site| site.structure_type() == StructureType::Spawn);
+    if !has_site {
+        place_spawn(&room);
+    }
+    operation.wanted = vec![Wanted {
+        role: Role::Pioneer,
+        count: PIONEERS,
+        size: 1,
+        priority: PIONEER_PRIORITY,
+    }];
+    Outcome::Continue
+}
+
+/// Places the site of the spawn the room's plan starts with.
+fn place_spawn(room: &Room) {
+    let plan = match planner::load(room.name()) {
+        Some(plan) => plan,
+        None => {
+            debug!(
+                "room {} isn't planned yet, waiting for its spawn",
+                room.name()
+            );
+            return;
+        }
+    };
+    let entry = plan
+        .entries_at(1)
+        .find(|entry| entry.structure == StructureType::Spawn);
+    if let Some(entry) = entry {
+        let pos = Position::new(entry.x as u32, entry.y as u32, room.name());
+        let r = room.create_construction_site(&pos, StructureType::Spawn);
+        if r != ReturnCode::Ok {
+            warn!("couldn't place the spawn of room {}: {:?}", room.name(), r);
+        }
+    }
+}
+
+/// Ends bootstrapping a room, turning its pioneers into workers of `home`.
+fn finish(operation: &Operation, home: RoomName) {
+    for creep in operation.live_creeps() {
+        spawning::make_worker(&creep, home);
+        if !creep.spawning() && creep.pos().room_name() != home {
+            creeps::set_target(&creep.name(), CreepTarget::Rebase(home));
+        }
+    }
+}
+
+/// Takes a pioneer to its room, returning whether that's all it does this tick. In the room it
+/// works like a worker, except for keeping the controller from downgrading.
+pub fn run_creep(creep: &Creep) -> bool {
+    let room_name = match spawning::work_room(creep) {
+        Some(room_name) => room_name,
+        None => return false,
+    };
+    if creep.pos().room_name() != room_name {
+        movement::move_to_room(creep, room_name);
+        return true;
+    }
+
+    let controller = match creep.room().and_then(|room| room.controller()) {
+        Some(controller) if controller.my() => controller,
+        _ => return false,
+    };
+    let has_energy = creep.store_used_capacity(Some(ResourceType::Energy)) > 0;
+    let upgrading = matches!(
+        creeps::current_target(&creep.name()),
+        Some(CreepTarget::Upgrade(_))
+    );
+    if has_energy && !upgrading && controller.ticks_to_downgrade() < DOWNGRADE_GUARD {
+        creeps::set_target(&creep.name(), CreepTarget::Upgrade(controller.id()));
+    }
+    false
+}
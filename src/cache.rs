@@ -0,0 +1,149 @@
+//! Per-tick snapshots of what's in a room.
+//!
+//! Looking things up in a room is one of the pricier calls, and every creep picking a target
+//! would otherwise repeat the same finds. A room's snapshot is built on first use in a tick and
+//! shared by everything else running in that room during the tick.
+//!
+//! Game objects are only valid for the tick they were fetched in, so snapshots are dropped as
+//! soon as the tick changes, and debug builds assert that an old one isn't read by mistake.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use screeps::{
+    find, prelude::*, ConstructionSite, Creep, ObjectId, Room, RoomName, Source, Structure,
+    StructureController, StructureType,
+};
+
+use crate::allies;
+
+pub struct RoomSnapshot {
+    time: u32,
+    structures: HashMap<StructureType, Vec<Structure>>,
+    sources: Vec<Source>,
+    construction_sites: Vec<ConstructionSite>,
+    hostiles: Vec<Creep>,
+    my_controller: Option<ObjectId<StructureController>>,
+    energy_capacity: u32,
+}
+
+thread_local! {
+    /// The snapshots taken this tick, along with the tick they're for.
+    static SNAPSHOTS: RefCell<(u32, HashMap<RoomName, Rc<RoomSnapshot>>)> =
+        RefCell::new((0, HashMap::new()));
+}
+
+/// A handle on the snapshots of one tick, which the loop hands out in its
+/// [`TickContext`](crate::context::TickContext).
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomCache {
+    time: u32,
+}
+
+impl RoomCache {
+    pub fn new(time: u32) -> RoomCache {
+        RoomCache { time }
+    }
+
+    /// The tick's snapshot of a room, taken now if nothing asked for it yet.
+    pub fn snapshot(&self, room: &Room) -> Rc<RoomSnapshot> {
+        SNAPSHOTS.with(|snapshots| {
+            let mut snapshots = snapshots.borrow_mut();
+            if snapshots.0 != self.time {
+                *snapshots = (self.time, HashMap::new());
+            }
+            snapshots
+                .1
+                .entry(room.name())
+                .or_insert_with(|| Rc::new(RoomSnapshot::take(room, self.time)))
+                .clone()
+        })
+    }
+}
+
+impl RoomSnapshot {
+    fn take(room: &Room, time: u32) -> RoomSnapshot {
+        let mut structures: HashMap<StructureType, Vec<Structure>> = HashMap::new();
+        for structure in room.find(find::STRUCTURES) {
+            structures
+                .entry(structure.structure_type())
+                .or_insert_with(Vec::new)
+                .push(structure);
+        }
+
+        RoomSnapshot {
+            time,
+            structures,
+            sources: room.find(find::SOURCES),
+            construction_sites: room.find(find::MY_CONSTRUCTION_SITES),
+            hostiles: allies::without_allies(room.find(find::HOSTILE_CREEPS), |c| {
+                Some(c.owner_name())
+            }),
+            my_controller: room.controller().filter(|c| c.my()).map(|c| c.id()),
+            energy_capacity: room.energy_capacity_available(),
+        }
+    }
+
+    fn check_fresh(&self) {
+        debug_assert_eq!(
+            self.time,
+            screeps::game::time(),
+            "room snapshot used after its tick"
+        );
+    }
+
+    /// The structures of one type, whoever owns them.
+    pub fn structures(&self, structure_type: StructureType) -> &[Structure] {
+        self.check_fresh();
+        self.structures
+            .get(&structure_type)
+            .map_or(&[], |structures| structures.as_slice())
+    }
+
+    /// Every structure in the room, whoever owns them.
+    pub fn all_structures(&self) -> impl Iterator<Item = &Structure> {
+        self.check_fresh();
+        self.structures.values().flatten()
+    }
+
+    /// The structures of one type which are ours.
+    pub fn my_structures(&self, structure_type: StructureType) -> impl Iterator<Item = &Structure> {
+        self.structures(structure_type)
+            .iter()
+            .filter(|s| s.as_owned().map_or(false, |o| o.my()))
+    }
+
+    /// Every source, including the ones which are regenerating.
+    pub fn sources(&self) -> &[Source] {
+        self.check_fresh();
+        &self.sources
+    }
+
+    /// The sources with energy left.
+    pub fn sources_active(&self) -> impl Iterator<Item = &Source> {
+        self.sources().iter().filter(|s| s.energy() > 0)
+    }
+
+    /// Our construction sites.
+    pub fn construction_sites(&self) -> &[ConstructionSite] {
+        self.check_fresh();
+        &self.construction_sites
+    }
+
+    /// Other players' creeps, except for our allies'.
+    pub fn hostiles(&self) -> &[Creep] {
+        self.check_fresh();
+        &self.hostiles
+    }
+
+    /// The room's controller, if it's ours.
+    pub fn my_controller(&self) -> Option<ObjectId<StructureController>> {
+        self.check_fresh();
+        self.my_controller
+    }
+
+    /// The most energy the room's spawns and extensions hold.
+    pub fn energy_capacity(&self) -> u32 {
+        self.check_fresh();
+        self.energy_capacity
+    }
+}
@@ -0,0 +1,268 @@
+//! Tearing down structures in rooms which aren't ours, like a neighbour's abandoned extensions or
+//! whatever is left standing around an invader core.
+//!
+//! A flag named `attack`, or starting with `attack:`, starts a cleanup [`operations`] operation in
+//! its room, served by the closest room of ours which can spawn a dismantler. It sends one
+//! dismantler, or two once the structures left have more than [`HITS_PER_DISMANTLER`] hits
+//! between them. Dismantlers go for towers, then spawns, then extensions, and first take down any
+//! wall or rampart on the way to the one they're after.
+//!
+//! Rooms owned by an ally are never attacked, and a room which goes into safe mode can't be, so
+//! the operation fails and says so. It's done once nothing is left or its flag has been removed,
+//! with any flags left removed too. Dismantlers without an operation go home to be recycled.
+
+use std::collections::HashMap;
+
+use log::*;
+use screeps::{
+    pathfinder::{self, LocalCostMatrix, MultiRoomCostResult, SearchOptions},
+    prelude::*,
+    Creep, Flag, RawObjectId, Room, RoomName, Structure, StructureType,
+};
+
+use crate::{
+    allies,
+    context::TickContext,
+    creeps::{self, CreepTarget},
+    emergency, events, intel, movement,
+    operations::{self, Kind, Operation, Outcome, Wanted},
+    remotes,
+    spawning::{self, Role},
+};
+
+const ATTACK_FLAG: &str = "attack";
+const ATTACK_FLAG_PREFIX: &str = "attack:";
+
+/// What's torn down, first to last.
+const PRIORITIES: &[StructureType] = &[
+    StructureType::Tower,
+    StructureType::Spawn,
+    StructureType::Extension,
+];
+
+/// How many hits of structures one dismantler takes on.
+pub const HITS_PER_DISMANTLER: u32 = 50_000;
+
+/// The most dismantlers sent to a room.
+const MAX_DISMANTLERS: u32 = 2;
+
+/// The priority of dismantler spawn requests, below the remotes', as nothing depends on them.
+const DISMANTLER_PRIORITY: u8 = 90;
+
+/// What a wall or rampart in the way costs to path through, so paths go around them if they can.
+const BARRIER_COST: u8 = 100;
+
+fn attack_flags(room_name: RoomName) -> Vec<Flag> {
+    screeps::game::flags::values()
+        .into_iter()
+        .filter(|flag| {
+            let name = flag.name();
+            flag.pos().room_name() == room_name
+                && (name == ATTACK_FLAG || name.starts_with(ATTACK_FLAG_PREFIX))
+        })
+        .collect()
+}
+
+fn remove_flags(room_name: RoomName) {
+    for flag in attack_flags(room_name) {
+        flag.remove();
+    }
+}
+
+/// The player owning a room, as far as we know.
+fn owner(room_name: RoomName) -> Option<String> {
+    match screeps::game::rooms::get(room_name) {
+        Some(room) => room.controller()?.owner_name(),
+        None => intel::get(room_name)?.controller?.owner,
+    }
+}
+
+/// Starts a cleanup operation for every room with an attack flag, unless it's an ally's.
+pub fn start_flagged() {
+    let mut rooms: Vec<RoomName> = screeps::game::flags::values()
+        .into_iter()
+        .filter(|flag| {
+            let name = flag.name();
+            name == ATTACK_FLAG || name.starts_with(ATTACK_FLAG_PREFIX)
+        })
+        .map(|flag| flag.pos().room_name())
+        .collect();
+    rooms.sort_unstable();
+    rooms.dedup();
+    for room_name in rooms {
+        if let Some(ally) = owner(room_name).filter(|owner| allies::is_ally(owner)) {
+            error!(
+                "not attacking room {}, it's owned by our ally {}",
+                room_name, ally
+            );
+            remove_flags(room_name);
+            continue;
+        }
+        match home_for(room_name) {
+            Some(home) => operations::start(Kind::Cleanup, room_name, home),
+            None => debug!("no room of ours can send dismantlers to {}", room_name),
+        }
+    }
+}
+
+/// The closest room of ours which can spawn a dismantler.
+fn home_for(room_name: RoomName) -> Option<RoomName> {
+    screeps::game::spawns::values()
+        .into_iter()
+        .filter_map(|spawn| spawn.room())
+        .filter(|room| {
+            room.name() != room_name
+                && !emergency::is_abandoned(room.name())
+                && room.energy_capacity_available() >= Role::Dismantler.cost()
+        })
+        .map(|room| room.name())
+        .min_by_key(|&name| screeps::game::map::get_room_linear_distance(name, room_name, false))
+}
+
+/// Whether a structure is one to tear down: one of the [`PRIORITIES`], owned by someone who
+/// isn't us or an ally.
+fn is_target(structure: &Structure) -> bool {
+    PRIORITIES.contains(&structure.structure_type())
+        && structure.as_owned().map_or(false, |owned| {
+            !owned.my() && !owned.owner_name().map_or(false, |n| allies::is_ally(&n))
+        })
+}
+
+/// Checks on a room being cleaned up, ending it when there's nothing left and failing it if the
+/// room can't be attacked.
+pub fn check_operation(operation: &mut Operation, ctx: &TickContext) -> Outcome {
+    let room_name = operation.room;
+    if attack_flags(room_name).is_empty() {
+        return Outcome::Done("its attack flag was removed".to_string());
+    }
+    if let Some(ally) = owner(room_name).filter(|owner| allies::is_ally(owner)) {
+        remove_flags(room_name);
+        return Outcome::Failed(format!("the room is owned by our ally {}", ally));
+    }
+
+    let role = Role::Dismantler;
+    let size = screeps::game::rooms::get(operation.home).map_or(1, |room| {
+        spawning::affordable_size(role, &*ctx.rooms.snapshot(&room))
+    });
+    let mut count = 1;
+    if let Some(room) = screeps::game::rooms::get(room_name) {
+        if let Some(ticks) = room.controller().and_then(|c| c.safe_mode()) {
+            remove_flags(room_name);
+            let message = format!(
+                "attack on room {} aborted, it's in safe mode for {} more ticks",
+                room_name, ticks
+            );
+            events::notify(&message);
+            return Outcome::Failed(message);
+        }
+        let hits: u32 = ctx
+            .rooms
+            .snapshot(&room)
+            .all_structures()
+            .filter(|s| is_target(s))
+            .filter_map(|s| s.as_attackable().map(|a| a.hits()))
+            .sum();
+        if hits == 0 {
+            remove_flags(room_name);
+            return Outcome::Done("nothing is left to tear down".to_string());
+        }
+        count = ((hits + HITS_PER_DISMANTLER - 1) / HITS_PER_DISMANTLER).min(MAX_DISMANTLERS);
+    }
+    operation.wanted = vec![Wanted {
+        role,
+        count,
+        size,
+        priority: DISMANTLER_PRIORITY,
+    }];
+    Outcome::Continue
+}
+
+/// Takes a dismantler to its room and points it at the next structure, returning whether that's
+/// all it does this tick. Once it has a target it works on it like any other creep.
+pub fn run_creep(creep: &Creep, ctx: &TickContext) -> bool {
+    let room_name = spawning::work_room(creep)
+        .filter(|&room_name| operations::get(Kind::Cleanup, room_name).is_some());
+    let room_name = match room_name {
+        Some(room_name) => room_name,
+        None => {
+            if let Some(home) = spawning::home_room(creep) {
+                remotes::recycle(creep, home);
+            }
+            return true;
+        }
+    };
+    if creep.pos().room_name() != room_name {
+        movement::move_to_room(creep, room_name);
+        return true;
+    }
+    if let Some(CreepTarget::AttackStructure(_)) = creeps::current_target(&creep.name()) {
+        return false;
+    }
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return true,
+    };
+    match next_target(creep, &room, ctx) {
+        Some(id) => {
+            creeps::set_target(&creep.name(), CreepTarget::AttackStructure(id));
+            false
+        }
+        None => true,
+    }
+}
+
+/// The structure a dismantler goes for next: the closest of the most important kind left, or
+/// the first wall or rampart on the way to it.
+fn next_target(creep: &Creep, room: &Room, ctx: &TickContext) -> Option<RawObjectId> {
+    let snapshot = ctx.rooms.snapshot(room);
+    let pos = creep.pos();
+    let target = snapshot
+        .all_structures()
+        .filter(|s| is_target(s))
+        .min_by_key(|s| {
+            let priority = PRIORITIES.iter().position(|&ty| ty == s.structure_type());
+            (priority, pos.get_range_to(*s))
+        })?;
+
+    let mut matrix = LocalCostMatrix::new();
+    let mut barriers: HashMap<(u32, u32), &Structure> = HashMap::new();
+    for structure in snapshot.all_structures() {
+        let (x, y) = (structure.pos().x(), structure.pos().y());
+        let cost = match structure {
+            Structure::Road(_) => 1,
+            Structure::Container(_) => continue,
+            Structure::Rampart(rampart) if rampart.my() => continue,
+            Structure::Wall(_) => BARRIER_COST,
+            Structure::Rampart(rampart)
+                if !rampart.owner_name().map_or(false, |n| allies::is_ally(&n)) =>
+            {
+                BARRIER_COST
+            }
+            _ => 255,
+        };
+        if cost == BARRIER_COST {
+            barriers.insert((x, y), structure);
+        }
+        matrix.set(x as u8, y as u8, cost);
+    }
+    let room_name = room.name();
+    let options = SearchOptions::new().room_callback(move |name| {
+        if name == room_name {
+            MultiRoomCostResult::CostMatrix(matrix.upload())
+        } else {
+            MultiRoomCostResult::Impassable
+        }
+    });
+    let path = pathfinder::search(&pos, &target.pos(), 1, options).load_local_path();
+    let barrier = path
+        .iter()
+        .find_map(|step| barriers.get(&(step.x(), step.y())));
+    let chosen = barrier.copied().unwrap_or(target);
+    debug!(
+        "{} is going for the {:?} at {}",
+        creep.name(),
+        chosen.structure_type(),
+        chosen.pos()
+    );
+    Some(chosen.id().into())
+}
@@ -0,0 +1,584 @@
+//! Functions callable from the game console.
+//!
+//! Each one returns a string describing what it did, which the console prints.
+
+use std::collections::BTreeMap;
+
+use screeps::{prelude::*, Position, RoomName};
+use stdweb::{js, unstable::TryInto};
+
+use crate::{
+    attackers, creeps, emergency, formation, heap, intel, journal, logging, operations, planner,
+    profiler, remotes, rng, scheduler,
+    spawning::{self, Role, SpawnRequest},
+    version, visuals,
+};
+
+// js! turns its snippets into functions taking each value passed in
+#[allow(clippy::too_many_arguments)]
+pub fn register() {
+    // js! takes at most 16 values
+    js! {
+        global.accept_plan = @{accept_plan};
+        global.reanchor_plan = @{reanchor_plan};
+        global.portal_jump = @{portal_jump};
+        global.purge_caches = @{purge_caches};
+        global.emergency_halt = @{emergency_halt};
+        global.emergency_resume = @{emergency_resume};
+        global.log_filters = @{log_filters};
+        global.dashboard = @{dashboard};
+        global.dump_state = @{dump_state};
+        global.trace_creep = @{trace_creep};
+        global.reseed = @{reseed};
+        global.clear_target = @{clear_target};
+        global.clear_all_targets = @{clear_all_targets};
+        global.request_spawn = @{request_spawn};
+        global.pause = @{pause};
+        global.pause_for = @{pause_for};
+    }
+
+    js! {
+        global.resume = @{resume};
+        global.version = @{version};
+        global.plan_room = @{plan_room};
+        global.activate_safe_mode = @{activate_safe_mode};
+        global.abandon_room = @{abandon_room};
+        global.set_visuals = @{set_visuals};
+        global.set_profiling = @{set_profiling};
+        global.set_journal = @{set_journal};
+        global.dump_journal = @{dump_journal};
+        global.set_debug_creep = @{set_debug_creep};
+        global.status = @{status};
+        global.list_operations = @{list_operations};
+        global.enable_remote = @{enable_remote};
+        global.threat_report = @{threat_report};
+        global.form_quad = @{form_quad};
+        global.move_quad = @{move_quad};
+    }
+
+    js! {
+        global.disband_quad = @{disband_quad};
+    }
+}
+
+fn accept_plan(room_name: String) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    match planner::accept(room_name) {
+        Ok(()) => format!("accepted the plan for room {}", room_name),
+        Err(e) => e,
+    }
+}
+
+fn plan_room(room_name: String, preview_only: bool) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let room = match screeps::game::rooms::get(room_name) {
+        Some(room) => room,
+        None => return format!("room {} isn't visible", room_name),
+    };
+    match planner::plan_room(&room, preview_only) {
+        Ok(()) if preview_only => format!(
+            "planning room {}, the plan is previewed for {} ticks until accept_plan(\"{}\")",
+            room_name,
+            planner::PREVIEW_TICKS,
+            room_name
+        ),
+        Ok(()) => format!("planning room {} again", room_name),
+        Err(e) => e,
+    }
+}
+
+fn reanchor_plan(room_name: String, x: u32, y: u32) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let room = match screeps::game::rooms::get(room_name) {
+        Some(room) => room,
+        None => return format!("room {} isn't visible", room_name),
+    };
+    if x > 49 || y > 49 {
+        return format!("{},{} is outside the room", x, y);
+    }
+    match planner::reanchor(&room, (x as u8, y as u8)) {
+        Ok(()) => format!(
+            "moved the bunker in room {} to {},{}, accept_plan(\"{}\") to build it",
+            room_name, x, y, room_name
+        ),
+        Err(e) => e,
+    }
+}
+
+fn portal_jump(creep_name: String, room_name: String) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let creep = match screeps::game::creeps::get(&creep_name) {
+        Some(creep) => creep,
+        None => return format!("there is no creep named {}", creep_name),
+    };
+    if creep.spawning() {
+        return format!("{} is still spawning", creep_name);
+    }
+    let portal = intel::portals(room_name)
+        .into_iter()
+        .min_by_key(|p| creep.pos().get_range_to(&p.pos));
+    match portal {
+        Some(portal) => {
+            creeps::set_target(&creep.name(), creeps::CreepTarget::Portal(portal.pos));
+            format!(
+                "sending {} through the portal at {} to {}",
+                creep_name, portal.pos, portal.destination
+            )
+        }
+        None => format!("no known portals in room {}", room_name),
+    }
+}
+
+fn purge_caches() -> String {
+    format!(
+        "purged all caches, freeing about {} kB",
+        heap::purge() / 1024
+    )
+}
+
+fn emergency_halt() -> String {
+    scheduler::set_halted(true);
+    "halted everything but towers, spawns and memory cleanup, emergency_resume() to undo"
+        .to_string()
+}
+
+fn emergency_resume() -> String {
+    scheduler::set_halted(false);
+    "resumed normal operation".to_string()
+}
+
+fn activate_safe_mode(room_name: String) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let room = match screeps::game::rooms::get(room_name) {
+        Some(room) => room,
+        None => return format!("room {} isn't visible", room_name),
+    };
+    match emergency::activate_safe_mode(&room, "from the console") {
+        Ok(()) => format!("activated safe mode in room {}", room_name),
+        Err(e) => e,
+    }
+}
+
+/// Only hands out a confirmation token without one, as unclaiming can't be undone.
+fn abandon_room(room_name: String, token: Option<String>) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let room = match screeps::game::rooms::get(room_name) {
+        Some(room) => room,
+        None => return format!("room {} isn't visible", room_name),
+    };
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return match emergency::abandon_token(&room) {
+                Ok(token) => format!(
+                    "this unclaims room {} for good, abandon_room(\"{}\", \"{}\") within {} \
+                     ticks to go ahead",
+                    room_name,
+                    room_name,
+                    token,
+                    emergency::CONFIRM_TICKS
+                ),
+                Err(e) => e,
+            }
+        }
+    };
+    match emergency::abandon(&room, &token) {
+        Ok(moved) => format!(
+            "abandoned room {}, sending {} creeps away",
+            room_name, moved
+        ),
+        Err(e) => e,
+    }
+}
+
+fn pause() -> String {
+    scheduler::pause(None);
+    "paused all intents, resume() to undo".to_string()
+}
+
+fn pause_for(ticks: u32) -> String {
+    let until = screeps::game::time() + ticks;
+    scheduler::pause(Some(until));
+    format!("paused all intents until tick {}", until)
+}
+
+fn resume() -> String {
+    scheduler::unpause();
+    "resumed".to_string()
+}
+
+fn log_filters() -> String {
+    logging::describe_filters()
+}
+
+fn dashboard(enabled: bool) -> String {
+    visuals::set_dashboard(enabled);
+    if enabled {
+        "drawing the dashboard in every owned room".to_string()
+    } else {
+        "stopped drawing the dashboard".to_string()
+    }
+}
+
+fn set_visuals(enabled: bool) -> String {
+    visuals::set_visuals(enabled);
+    format!("visuals: {}", visuals::describe())
+}
+
+fn set_profiling(enabled: bool) -> String {
+    profiler::set_enabled(enabled);
+    format!(
+        "profiling {}",
+        if profiler::is_enabled() { "on" } else { "off" }
+    )
+}
+
+fn set_journal(enabled: bool) -> String {
+    journal::set_enabled(enabled);
+    format!(
+        "decision journal {}",
+        if journal::is_enabled() { "on" } else { "off" }
+    )
+}
+
+/// Prints the latest `count` decisions in the journal, or all of them.
+fn dump_journal(count: Option<u32>) -> String {
+    let count = count.map_or(journal::MAX_ENTRIES, |count| count as usize);
+    let entries = journal::latest(count);
+    if entries.is_empty() {
+        return "the journal is empty".to_string();
+    }
+    let chunks = print_chunked(&entries);
+    format!("dumped {} decisions in {} chunks", entries.len(), chunks)
+}
+
+/// Logs a creep's debug lines for `ticks` ticks, or stops with 0.
+fn set_debug_creep(creep_name: String, ticks: u32) -> String {
+    if ticks == 0 {
+        logging::untrace_creep(&creep_name);
+    } else if screeps::game::creeps::get(&creep_name).is_none() {
+        return format!("there is no creep named {}", creep_name);
+    } else {
+        logging::trace_creep(&creep_name, ticks);
+    }
+    format!("creep debug lines: {}", logging::describe_sampling())
+}
+
+/// Prints every toggle in one go.
+fn status() -> String {
+    let state = if scheduler::is_paused() {
+        "paused"
+    } else if scheduler::is_halted() {
+        "halted"
+    } else {
+        "running"
+    };
+    let lines = vec![
+        format!("tick {}, {}", screeps::game::time(), version::describe()),
+        format!("state: {}", state),
+        format!("visuals: {}", visuals::describe()),
+        format!(
+            "profiling: {}",
+            if profiler::is_enabled() { "on" } else { "off" }
+        ),
+        format!(
+            "decision journal: {}",
+            if journal::is_enabled() { "on" } else { "off" }
+        ),
+        format!(
+            "creeps: {} alive, spawning whenever there's energy",
+            screeps::game::creeps::keys().len()
+        ),
+        format!("creep debug lines: {}", logging::describe_sampling()),
+        format!("log levels: {}", logging::describe_filters()),
+    ];
+    lines.join("\n")
+}
+
+/// Prints every operation with its phase, creeps and what it wants.
+fn list_operations() -> String {
+    let lines = operations::describe();
+    if lines.is_empty() {
+        return "no operations".to_string();
+    }
+    lines.join("\n")
+}
+
+/// Lets a remote which was wound down for not paying for itself be mined again.
+fn enable_remote(room_name: String) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    if remotes::enable(room_name) {
+        format!("remote {} will be mined again", room_name)
+    } else {
+        format!("remote {} wasn't wound down", room_name)
+    }
+}
+
+/// Prints every player who attacked us, with their attacks, damage and when they were last seen.
+fn threat_report() -> String {
+    let lines = attackers::report();
+    if lines.is_empty() {
+        return "nobody has attacked us".to_string();
+    }
+    lines.join("\n")
+}
+
+/// Registers four creeps as a quad led by the first, front left, front right, back left and
+/// back right.
+fn form_quad(
+    front_left: String,
+    front_right: String,
+    back_left: String,
+    back_right: String,
+) -> String {
+    match formation::form(&[front_left, front_right, back_left, back_right]) {
+        Ok(message) => message,
+        Err(e) => format!("couldn't form a quad: {}", e),
+    }
+}
+
+/// Sends the quad led by `leader` to a tile.
+fn move_quad(leader: String, room_name: String, x: u32, y: u32) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    if x > 49 || y > 49 {
+        return format!("{},{} isn't a tile", x, y);
+    }
+    let target = Position::new(x, y, room_name);
+    if formation::send(&leader, target) {
+        format!("sending quad {} to {}", leader, target)
+    } else {
+        format!("there is no quad led by {}", leader)
+    }
+}
+
+/// Lets the creeps of the quad led by `leader` go back to their roles.
+fn disband_quad(leader: String) -> String {
+    if formation::disband(&leader) {
+        format!("disbanded quad {}", leader)
+    } else {
+        format!("there is no quad led by {}", leader)
+    }
+}
+
+/// How long each printed chunk of a state dump gets, so the console doesn't cut it off.
+const DUMP_CHUNK_LENGTH: usize = 1000;
+
+/// Prints every creep's target, optionally only of creeps whose name contains `filter`, along
+/// with the creeps of each role per home room and how many more are queued, the spawns and their
+/// queue, `Memory.config` and the cache sizes. Only reads state.
+fn dump_state(filter: Option<String>) -> String {
+    let mut lines = vec!["creep targets:".to_string()];
+    // how many creeps of each role a room has, and how many more it has queued
+    let mut roles_by_room: BTreeMap<String, BTreeMap<&'static str, (u32, u32)>> = BTreeMap::new();
+    let mut creeps = screeps::game::creeps::values();
+    creeps.sort_by_key(|c| c.name());
+    for creep in &creeps {
+        let target = if creep.spawning() {
+            None
+        } else {
+            creeps::current_target(&creep.name())
+        };
+        let home = spawning::home_room(creep).unwrap_or_else(|| creep.pos().room_name());
+        roles_by_room
+            .entry(home.to_string())
+            .or_default()
+            .entry(spawning::role_of(creep).name())
+            .or_default()
+            .0 += 1;
+
+        let name = creep.name();
+        if filter.as_ref().map_or(true, |f| name.contains(f.as_str())) {
+            lines.push(format!("  {} in {}: {:?}", name, creep.pos(), target));
+        }
+    }
+
+    let queue = spawning::queue();
+    for request in &queue {
+        roles_by_room
+            .entry(request.room_name.to_string())
+            .or_default()
+            .entry(request.role.name())
+            .or_default()
+            .1 += 1;
+    }
+    lines.push("creeps per role and home room:".to_string());
+    for (room, roles) in &roles_by_room {
+        let roles: Vec<String> = roles
+            .iter()
+            .map(|(role, &(alive, queued))| match queued {
+                0 => format!("{} {}", role, alive),
+                _ => format!("{} {} (+{} queued)", role, alive, queued),
+            })
+            .collect();
+        lines.push(format!("  {}: {}", room, roles.join(", ")));
+    }
+
+    lines.push("spawns:".to_string());
+    for spawn in screeps::game::spawns::values() {
+        let spawning = spawn.spawning().map_or("idle".to_string(), |s| {
+            format!("{}, {} ticks left", s.name(), s.remaining_time())
+        });
+        lines.push(format!("  {}: {}", spawn.name(), spawning));
+    }
+
+    lines.push("spawn queue:".to_string());
+    for request in queue {
+        lines.push(format!(
+            "  {} in {}, priority {}",
+            request.role.name(),
+            request.room_name,
+            request.priority
+        ));
+    }
+
+    let config = js! { return JSON.stringify(Memory.config || {}) };
+    let config: String = config.try_into().unwrap_or_default();
+    lines.push(format!("config: {}", config));
+
+    lines.push("caches:".to_string());
+    for size in heap::all_caches() {
+        lines.push(format!(
+            "  {}: {} entries, {} kB",
+            size.name,
+            size.entries,
+            size.bytes / 1024
+        ));
+    }
+
+    let chunks = print_chunked(&lines);
+    format!("dumped the state in {} chunks", chunks)
+}
+
+/// Prints `lines` to the console in chunks of up to [`DUMP_CHUNK_LENGTH`] characters, returning
+/// how many chunks it took.
+fn print_chunked(lines: &[String]) -> usize {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in lines {
+        if !chunk.is_empty() && chunk.len() + line.len() >= DUMP_CHUNK_LENGTH {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    for chunk in &chunks {
+        js! {
+            console.log(@{chunk});
+        }
+    }
+    chunks.len()
+}
+
+fn trace_creep(creep_name: String, ticks: Option<u32>) -> String {
+    if screeps::game::creeps::get(&creep_name).is_none() {
+        return format!("there is no creep named {}", creep_name);
+    }
+    let ticks = ticks.unwrap_or(logging::TRACE_TICKS);
+    logging::trace_creep(&creep_name, ticks);
+    format!("logging debug lines of {} for {} ticks", creep_name, ticks)
+}
+
+/// Takes the seed as a string, as numbers from the console are doubles.
+fn reseed(seed: String) -> String {
+    match seed.parse() {
+        Ok(seed) => {
+            rng::reseed(seed);
+            format!("seeded the rng from {}", seed)
+        }
+        Err(e) => format!("invalid seed {:?}: {}", seed, e),
+    }
+}
+
+fn clear_target(creep_name: String) -> String {
+    let creep = match screeps::game::creeps::get(&creep_name) {
+        Some(creep) => creep,
+        None => return format!("there is no creep named {}", creep_name),
+    };
+    if creep.spawning() {
+        return format!("{} is still spawning", creep_name);
+    }
+    match creeps::clear_target(&creep.name()) {
+        Some(target) => format!("cleared {:?} from {}", target, creep_name),
+        None => format!("{} has no target", creep_name),
+    }
+}
+
+fn clear_all_targets() -> String {
+    format!(
+        "cleared the targets of {} creeps",
+        creeps::clear_all_targets()
+    )
+}
+
+fn request_spawn(room_name: String, role: String, priority: Option<u8>) -> String {
+    let room_name = match RoomName::new(&room_name) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid room name {:?}: {}", room_name, e),
+    };
+    let role = match Role::from_name(&role) {
+        Some(role) => role,
+        None => {
+            let roles: Vec<&str> = Role::ALL.iter().map(|r| r.name()).collect();
+            return format!("unknown role {:?}, roles are {}", role, roles.join(", "));
+        }
+    };
+    if role.is_remote() {
+        return format!(
+            "{} creeps are spawned for the remotes in Memory.config.remotes or for expanding",
+            role.name()
+        );
+    }
+    let has_spawn = screeps::game::spawns::values()
+        .iter()
+        .any(|s| s.pos().room_name() == room_name);
+    if !has_spawn {
+        return format!("room {} has none of our spawns", room_name);
+    }
+    let priority = priority.unwrap_or(spawning::DEFAULT_REQUEST_PRIORITY);
+    spawning::request(SpawnRequest {
+        room_name,
+        role,
+        priority,
+        work_room: None,
+        size: 1,
+    });
+    format!(
+        "queued a {} in room {} at priority {}",
+        role.name(),
+        room_name,
+        priority
+    )
+}
+
+fn version() -> String {
+    version::describe()
+}
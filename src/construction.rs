@@ -0,0 +1,394 @@
+//! Places construction sites for planned structures as the controller level allows.
+//!
+//! Sites are queued rather than placed all at once: each room keeps only a few active sites,
+//! picked from its plan in priority order, and the next one is placed once one of them is
+//! finished. This keeps builders focused and the account well under the global site cap.
+//!
+//! The plan is compared against what's standing on every run, so structures lost in an attack
+//! are rebuilt ahead of everything else as soon as the room is clear again.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{
+    find, prelude::*, ConstructionSite, ObjectId, Part, Position, ReturnCode, Room, RoomName,
+    StructureType, Terrain,
+};
+
+use crate::{
+    context::TickContext,
+    heap::CacheSize,
+    nukes,
+    planner::{self, PlanEntry, RoomPlan},
+    traffic,
+};
+
+/// How many sites may be active in one room at a time.
+const MAX_SITES_PER_ROOM: usize = 5;
+
+/// The game's cap on construction sites across all rooms.
+const MAX_SITES: usize = 100;
+
+/// How long a site may go without progress, while the room has creeps able to build it, before
+/// it's reported as stuck.
+const STUCK_TICKS: u32 = 1500;
+
+const LAST_RCL_KEY: &str = "rcl";
+
+/// Sites outside the plan with more progress than this are kept and left for manual review
+/// instead of being removed.
+const ORPHAN_PROGRESS_THRESHOLD: u32 = 1000;
+
+struct SiteProgress {
+    progress: u32,
+    since: u32,
+    reported: bool,
+}
+
+/// A structure on a tile, as `(x, y, type)`.
+type Tile = (u32, u32, StructureType);
+
+thread_local! {
+    static SITE_PROGRESS: RefCell<HashMap<ObjectId<ConstructionSite>, SiteProgress>> =
+        RefCell::new(HashMap::new());
+    /// Planned structures which have been seen standing, so ones which go missing are known to
+    /// have been destroyed rather than never built.
+    static BUILT: RefCell<HashMap<RoomName, HashSet<Tile>>> = RefCell::new(HashMap::new());
+    /// Rooms whose rebuild after an attack has already been reported.
+    static REBUILDING: RefCell<HashSet<RoomName>> = RefCell::new(HashSet::new());
+}
+
+pub fn run(ctx: &TickContext) {
+    let mut global_slots =
+        MAX_SITES.saturating_sub(screeps::game::construction_sites::keys().len());
+
+    for room in screeps::game::rooms::values() {
+        let rcl = match room.controller() {
+            Some(controller) if controller.my() => controller.level(),
+            _ => continue,
+        };
+        if planner::is_pending(room.name()) {
+            continue;
+        }
+        if let Some(plan) = planner::load(room.name()) {
+            global_slots -= place_sites(&room, &plan, rcl, rcl, global_slots, ctx);
+        }
+    }
+
+    check_stuck_sites();
+}
+
+/// Places sites right away in rooms whose controller just levelled up, starting with the
+/// structures the new level unlocked.
+///
+/// The last seen level is kept in `Memory.rooms.<name>.rcl`, so this is cheap enough to run
+/// every tick.
+pub fn check_level_ups(ctx: &TickContext) {
+    let mut global_slots = None;
+
+    for room in screeps::game::rooms::values() {
+        let rcl = match room.controller() {
+            Some(controller) if controller.my() => controller.level(),
+            _ => continue,
+        };
+        let memory = match planner::room_memory(room.name()) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        let last_rcl = memory.i32(LAST_RCL_KEY).ok().flatten().map(|l| l as u32);
+        if last_rcl == Some(rcl) {
+            continue;
+        }
+        memory.set(LAST_RCL_KEY, rcl as i32);
+        let last_rcl = match last_rcl {
+            Some(last_rcl) if last_rcl < rcl => last_rcl,
+            _ => continue,
+        };
+
+        info!("room {} reached level {}", room.name(), rcl);
+        if planner::is_pending(room.name()) {
+            continue;
+        }
+        if let Some(plan) = planner::load(room.name()) {
+            let slots = global_slots.get_or_insert_with(|| {
+                MAX_SITES.saturating_sub(screeps::game::construction_sites::keys().len())
+            });
+            *slots -= place_sites(&room, &plan, rcl, last_rcl, *slots, ctx);
+        }
+    }
+}
+
+/// Removes sites in planned rooms which aren't part of the plan, such as misplaced manual sites
+/// or ones left over from an older plan.
+pub fn remove_orphans() {
+    for room in screeps::game::rooms::values() {
+        match room.controller() {
+            Some(controller) if controller.my() => {}
+            _ => continue,
+        }
+        if planner::is_pending(room.name()) {
+            continue;
+        }
+        let plan = match planner::load(room.name()) {
+            Some(plan) => plan,
+            None => continue,
+        };
+
+        for site in room.find(find::MY_CONSTRUCTION_SITES) {
+            let pos = site.pos();
+            let planned = plan.entries.iter().any(|e| {
+                e.x as u32 == pos.x()
+                    && e.y as u32 == pos.y()
+                    && e.structure == site.structure_type()
+            });
+            if planned {
+                continue;
+            }
+
+            if site.progress() > ORPHAN_PROGRESS_THRESHOLD {
+                info!(
+                    "{:?} site at {} isn't planned but has {} progress, leaving it",
+                    site.structure_type(),
+                    pos,
+                    site.progress()
+                );
+                continue;
+            }
+            match site.remove() {
+                ReturnCode::Ok => info!(
+                    "removed unplanned {:?} site at {}",
+                    site.structure_type(),
+                    pos
+                ),
+                r => warn!(
+                    "couldn't remove {:?} site at {}: {:?}",
+                    site.structure_type(),
+                    pos,
+                    r
+                ),
+            }
+        }
+    }
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![
+        SITE_PROGRESS
+            .with(|p| CacheSize::of_map("construction.site_progress", &p.borrow(), |_, _| 0)),
+        BUILT.with(|b| {
+            CacheSize::of_map("construction.built", &b.borrow(), |_, tiles| {
+                tiles.capacity() * std::mem::size_of::<Tile>()
+            })
+        }),
+        REBUILDING.with(|r| CacheSize::of_set("construction.rebuilding", &r.borrow())),
+    ]
+}
+
+/// Clears this module's caches. Structures which were destroyed before the purge won't be
+/// prioritized when they're rebuilt.
+pub fn purge() {
+    SITE_PROGRESS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    BUILT.with(|b| std::mem::take(&mut *b.borrow_mut()));
+    REBUILDING.with(|r| std::mem::take(&mut *r.borrow_mut()));
+}
+
+/// Where a structure falls in the build order, lowest first.
+fn priority(structure: StructureType) -> u32 {
+    match structure {
+        StructureType::Spawn => 0,
+        StructureType::Extension => 1,
+        StructureType::Container => 2,
+        StructureType::Tower => 3,
+        StructureType::Road => 5,
+        StructureType::Wall | StructureType::Rampart => 6,
+        _ => 4,
+    }
+}
+
+/// Places the next sites of a room's queue, returning how many were placed.
+///
+/// Entries unlocked after `last_rcl` go first, ahead of the usual priorities.
+fn place_sites(
+    room: &Room,
+    plan: &RoomPlan,
+    rcl: u32,
+    last_rcl: u32,
+    global_slots: usize,
+    ctx: &TickContext,
+) -> usize {
+    let built: HashSet<Tile> = room
+        .find(find::STRUCTURES)
+        .into_iter()
+        .map(|s| (s.pos().x(), s.pos().y(), s.structure_type()))
+        .collect();
+    let mut present = built.clone();
+    let sites = room.find(find::MY_CONSTRUCTION_SITES);
+    for site in &sites {
+        let pos = site.pos();
+        present.insert((pos.x(), pos.y(), site.structure_type()));
+    }
+
+    // destroyed structures are rebuilt first, but only once the attackers are gone
+    let traffic = traffic::road_traffic(room.name());
+    let mut destroyed = track_destroyed(room.name(), plan, &built, &present);
+    destroyed.retain(|t| !is_unused_road(&traffic, t));
+    let under_attack = !ctx.rooms.snapshot(room).hostiles().is_empty();
+    if destroyed.is_empty() {
+        REBUILDING.with(|r| r.borrow_mut().remove(&room.name()));
+    } else if !under_attack {
+        let first_report = REBUILDING.with(|r| r.borrow_mut().insert(room.name()));
+        if first_report {
+            info!(
+                "rebuilding {} structures after attack in room {}",
+                destroyed.len(),
+                room.name()
+            );
+        }
+    }
+
+    let slots = MAX_SITES_PER_ROOM
+        .saturating_sub(sites.len())
+        .min(global_slots);
+    if slots == 0 {
+        return 0;
+    }
+
+    // roads on swamps save the most, so they go before the other roads
+    let terrain = room.get_terrain();
+    let tile = |e: &PlanEntry| (e.x as u32, e.y as u32, e.structure);
+    let mut queue: Vec<&PlanEntry> = plan
+        .entries_at(rcl)
+        .filter(|e| !present.contains(&tile(e)))
+        .filter(|e| !under_attack || !destroyed.contains(&tile(e)))
+        .filter(|e| !is_unused_road(&traffic, &tile(e)))
+        .filter(|e| !nukes::in_imminent_blast(Position::new(e.x as u32, e.y as u32, room.name())))
+        .collect();
+    queue.sort_by_key(|e| {
+        (
+            !destroyed.contains(&tile(e)),
+            e.min_rcl <= last_rcl,
+            priority(e.structure),
+            terrain.get(e.x as u32, e.y as u32) != Terrain::Swamp,
+        )
+    });
+
+    let mut placed = 0;
+    for entry in queue {
+        if placed == slots {
+            break;
+        }
+        if place(room, entry) {
+            placed += 1;
+        }
+    }
+    placed
+}
+
+/// Whether a tile is a road which was built before but is left to decay for lack of traffic.
+fn is_unused_road(traffic: &HashMap<(u8, u8), u32>, &(x, y, structure): &Tile) -> bool {
+    structure == StructureType::Road
+        && traffic
+            .get(&(x as u8, y as u8))
+            .map_or(false, |&count| count < traffic::MIN_REPAIR_TRAFFIC)
+}
+
+/// Finds the planned structures of a room which were standing at some point but are now gone
+/// without a site to replace them.
+fn track_destroyed(
+    room_name: RoomName,
+    plan: &RoomPlan,
+    built: &HashSet<Tile>,
+    present: &HashSet<Tile>,
+) -> HashSet<Tile> {
+    let planned: HashSet<Tile> = plan
+        .entries
+        .iter()
+        .map(|e| (e.x as u32, e.y as u32, e.structure))
+        .collect();
+
+    let destroyed: HashSet<Tile> = BUILT.with(|seen| {
+        let mut seen = seen.borrow_mut();
+        let seen = seen.entry(room_name).or_insert_with(HashSet::new);
+        seen.extend(built.intersection(&planned).copied());
+        seen.retain(|tile| planned.contains(tile));
+        seen.difference(present).copied().collect()
+    });
+    destroyed
+}
+
+fn place(room: &Room, entry: &PlanEntry) -> bool {
+    let pos = Position::new(entry.x as u32, entry.y as u32, room.name());
+    match room.create_construction_site(&pos, entry.structure) {
+        ReturnCode::Ok => {
+            debug!("placed {:?} site at {}", entry.structure, pos);
+            return true;
+        }
+        // the cap is already used up by structures outside of the plan
+        ReturnCode::RclNotEnough => {}
+        // something else was built on the tile, so find the tower a new home
+        ReturnCode::InvalidTarget if entry.structure == StructureType::Tower => {
+            match planner::relocate(room, entry) {
+                Some((x, y)) => info!("moved planned tower from {} to {},{}", pos, x, y),
+                None => warn!("planned tower at {} is blocked with nowhere to move", pos),
+            }
+        }
+        r => warn!(
+            "couldn't place {:?} site at {}: {:?}",
+            entry.structure, pos, r
+        ),
+    };
+    false
+}
+
+/// Reports sites which haven't made progress in a long time even though there are creeps around
+/// which could build them.
+fn check_stuck_sites() {
+    let time = screeps::game::time();
+    let sites = screeps::game::construction_sites::values();
+
+    SITE_PROGRESS.with(|progress| {
+        let mut progress = progress.borrow_mut();
+        let live: HashSet<ObjectId<ConstructionSite>> = sites.iter().map(|s| s.id()).collect();
+        progress.retain(|id, _| live.contains(id));
+
+        for site in &sites {
+            let entry = progress.entry(site.id()).or_insert(SiteProgress {
+                progress: site.progress(),
+                since: time,
+                reported: false,
+            });
+            if site.progress() != entry.progress {
+                *entry = SiteProgress {
+                    progress: site.progress(),
+                    since: time,
+                    reported: false,
+                };
+                continue;
+            }
+            if entry.reported || time - entry.since < STUCK_TICKS {
+                continue;
+            }
+
+            let builders_available = site.room().map_or(false, |room| {
+                room.find(find::MY_CREEPS)
+                    .iter()
+                    .any(|c| c.get_active_bodyparts(Part::Work) > 0)
+            });
+            if builders_available {
+                warn!(
+                    "{:?} site at {} has been stuck at {}/{} for {} ticks",
+                    site.structure_type(),
+                    site.pos(),
+                    site.progress(),
+                    site.progress_total(),
+                    time - entry.since
+                );
+                entry.reported = true;
+            }
+        }
+    });
+}
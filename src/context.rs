@@ -0,0 +1,98 @@
+//! What the main loop knows about the tick it runs.
+//!
+//! The tick's number, CPU budget and the settings read every tick are read from the game once,
+//! at the start of the loop, into a [`TickContext`] which the loop hands to the subsystems it
+//! runs, so they work from the same numbers instead of each asking the game again. The context
+//! also carries handles on the tick's room snapshots and on where its stats go.
+
+use crate::{
+    cache::RoomCache,
+    creep_costs, movement,
+    profiler::{self, Stats},
+    scheduler,
+};
+
+/// The settings in `Memory.config` which are read every tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    /// The share of the CPU limit the watchdog lets the loop use, if it's set.
+    pub watchdog_share: Option<f64>,
+    pub generate_pixels: bool,
+    /// Whether expensive creeps only run every other tick.
+    pub demote_expensive_creeps: bool,
+    /// Whether creeps search a new path for every step, for debugging movement.
+    pub no_path_reuse: bool,
+    pub profile: bool,
+}
+
+impl Config {
+    fn read() -> Config {
+        let memory = screeps::memory::root();
+        Config {
+            watchdog_share: memory.path_f64(scheduler::WATCHDOG_PATH).ok().flatten(),
+            generate_pixels: memory.path_bool(scheduler::GENERATE_PIXELS_PATH),
+            demote_expensive_creeps: memory.path_bool(creep_costs::DEMOTE_PATH),
+            no_path_reuse: memory.path_bool(movement::NO_REUSE_PATH),
+            profile: memory.path_bool(profiler::ENABLED_PATH),
+        }
+    }
+}
+
+/// The tick the loop is running.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickContext {
+    pub time: u32,
+    /// The CPU the tick may use.
+    pub cpu_limit: u32,
+    /// The CPU left in the bucket at the start of the tick.
+    pub bucket: u32,
+    pub config: Config,
+    /// The snapshots of the rooms taken this tick.
+    pub rooms: RoomCache,
+    pub stats: Stats,
+}
+
+impl TickContext {
+    /// Reads the tick from the game. Called once at the start of the loop.
+    pub fn read() -> TickContext {
+        let time = screeps::game::time();
+        TickContext {
+            time,
+            cpu_limit: screeps::game::cpu::limit(),
+            bucket: screeps::game::cpu::bucket(),
+            config: Config::read(),
+            rooms: RoomCache::new(time),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Whether work which runs every `interval` ticks, on the tick `offset` of them, is due.
+    pub fn is_due(&self, interval: u32, offset: u32) -> bool {
+        self.time % interval == offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: u32) -> TickContext {
+        TickContext {
+            time,
+            cpu_limit: 20,
+            bucket: 10_000,
+            config: Config::default(),
+            rooms: RoomCache::new(time),
+            stats: Stats::default(),
+        }
+    }
+
+    #[test]
+    fn work_is_due_on_its_tick_of_the_interval() {
+        assert!(at(0).is_due(10, 0));
+        assert!(at(31).is_due(10, 1));
+        assert!(!at(32).is_due(10, 1));
+        assert!(at(1_000_089).is_due(100, 89));
+        assert!(!at(89).is_due(100, 90));
+    }
+}
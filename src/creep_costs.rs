@@ -0,0 +1,198 @@
+//! CPU accounting per creep, to find the ones burning far more than the rest.
+//!
+//! The CPU each creep uses is added up per creep and per target kind, and every
+//! [`REPORT_INTERVAL`] ticks the most expensive creeps and the totals per kind are logged at debug
+//! level. A creep over `Memory.creep_cpu_warning` (1 CPU by default) for several ticks in a row
+//! is warned about right away, along with what it's working on.
+//!
+//! Creeps which averaged over the threshold in the last report count as expensive, and with
+//! `Memory.config.demote_expensive_creeps` set they only run every other tick.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{prelude::*, Creep};
+
+use crate::{creeps, heap::CacheSize};
+
+/// How many ticks of costs go into each report.
+pub const REPORT_INTERVAL: u32 = 50;
+
+/// How many creeps the report lists.
+const REPORT_TOP: usize = 10;
+
+const WARNING_KEY: &str = "creep_cpu_warning";
+const DEFAULT_WARNING_CPU: f64 = 1.0;
+
+pub const DEMOTE_PATH: &str = "config.demote_expensive_creeps";
+
+/// How many ticks in a row a creep has to be over the warning threshold to be reported.
+const WARNING_TICKS: u32 = 5;
+
+#[derive(Default)]
+struct CreepCost {
+    total: f64,
+    ticks: u32,
+    /// The last target kind the creep ran with.
+    kind: &'static str,
+    /// How many ticks in a row the creep has been over the warning threshold.
+    over: u32,
+}
+
+#[derive(Default)]
+struct KindCost {
+    total: f64,
+    runs: u32,
+}
+
+thread_local! {
+    /// Costs since the last report, by creep name.
+    static CREEPS: RefCell<HashMap<String, CreepCost>> = RefCell::new(HashMap::new());
+    /// Costs since the last report, by target kind.
+    static KINDS: RefCell<HashMap<&'static str, KindCost>> = RefCell::new(HashMap::new());
+    /// Creeps which averaged over the warning threshold in the last report.
+    static EXPENSIVE: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Records the CPU a creep used this tick.
+pub fn record(creep: &Creep, used: f64) {
+    let target = if creep.spawning() {
+        None
+    } else {
+        creeps::current_target(&creep.name())
+    };
+    let creep_name = creep.name();
+    let kind = target.map_or("idle", |t| t.kind());
+    let threshold = warning_threshold();
+
+    CREEPS.with(|costs| {
+        let mut costs = costs.borrow_mut();
+        let cost = costs.entry(creep_name.clone()).or_default();
+        cost.total += used;
+        cost.ticks += 1;
+        cost.kind = kind;
+
+        if used <= threshold {
+            cost.over = 0;
+            return;
+        }
+        cost.over += 1;
+        if cost.over == WARNING_TICKS {
+            warn!(
+                "creep {} used over {:.2} cpu for {} ticks, {:.2} this tick, target {:?}",
+                creep_name, threshold, WARNING_TICKS, used, target
+            );
+        }
+    });
+
+    KINDS.with(|kinds| {
+        let mut kinds = kinds.borrow_mut();
+        let cost = kinds.entry(kind).or_default();
+        cost.total += used;
+        cost.runs += 1;
+    });
+}
+
+fn warning_threshold() -> f64 {
+    screeps::memory::root()
+        .f64(WARNING_KEY)
+        .ok()
+        .flatten()
+        .unwrap_or(DEFAULT_WARNING_CPU)
+}
+
+/// Logs the most expensive creeps and the costs per target kind, and starts a new window.
+pub fn report() {
+    let mut creeps: Vec<(String, f64, &'static str)> = CREEPS.with(|costs| {
+        let mut costs = costs.borrow_mut();
+        let averages = costs
+            .iter()
+            .filter(|(_, cost)| cost.ticks > 0)
+            .map(|(name, cost)| (name.clone(), cost.total / cost.ticks as f64, cost.kind))
+            .collect();
+        // keep the streaks going across windows
+        for cost in costs.values_mut() {
+            cost.total = 0.0;
+            cost.ticks = 0;
+        }
+        costs.retain(|_, cost| cost.over > 0);
+        averages
+    });
+    let mut kinds: Vec<(&'static str, KindCost)> =
+        KINDS.with(|kinds| kinds.replace(HashMap::new()).into_iter().collect());
+    let threshold = warning_threshold();
+    EXPENSIVE.with(|expensive| {
+        *expensive.borrow_mut() = creeps
+            .iter()
+            .filter(|(_, average, _)| *average > threshold)
+            .map(|(name, _, _)| name.clone())
+            .collect();
+    });
+    if creeps.is_empty() || !log_enabled!(Level::Debug) {
+        return;
+    }
+
+    creeps.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    debug!(
+        "most expensive creeps over the last {} ticks:",
+        REPORT_INTERVAL
+    );
+    for (name, average, kind) in creeps.iter().take(REPORT_TOP) {
+        debug!("  {}: {:.2} per tick, {}", name, average, kind);
+    }
+
+    kinds.sort_by(|a, b| {
+        b.1.total
+            .partial_cmp(&a.1.total)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    debug!("creep cpu by target:");
+    for (kind, cost) in kinds {
+        debug!(
+            "  {}: {:.2} total, {:.2} per creep per tick",
+            kind,
+            cost.total,
+            cost.total / cost.runs as f64
+        );
+    }
+}
+
+/// Whether a creep averaged over the warning threshold in the last report.
+pub fn is_expensive(creep_name: &str) -> bool {
+    EXPENSIVE.with(|expensive| expensive.borrow().contains(creep_name))
+}
+
+/// Drops the costs of creeps which are no longer alive.
+pub fn forget_dead(alive_creeps: &HashSet<String>) {
+    CREEPS.with(|costs| {
+        costs
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+    EXPENSIVE.with(|expensive| {
+        expensive
+            .borrow_mut()
+            .retain(|name| alive_creeps.contains(name))
+    });
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![
+        CREEPS.with(|c| {
+            CacheSize::of_map("creep_costs.creeps", &c.borrow(), |name, _| name.capacity())
+        }),
+        KINDS.with(|k| CacheSize::of_map("creep_costs.kinds", &k.borrow(), |_, _| 0)),
+        EXPENSIVE.with(|e| CacheSize::of_set("creep_costs.expensive", &e.borrow())),
+    ]
+}
+
+/// Drops the costs recorded since the last report.
+pub fn purge() {
+    CREEPS.with(|c| std::mem::take(&mut *c.borrow_mut()));
+    KINDS.with(|k| std::mem::take(&mut *k.borrow_mut()));
+    EXPENSIVE.with(|e| std::mem::take(&mut *e.borrow_mut()));
+}
@@ -0,0 +1,343 @@
+//! The work each kind of creep target stands for.
+//!
+//! Creeps keep their target as a [`CreepTarget`], which only holds ids and is what the target
+//! map stores. When a creep runs, its target is turned into a [`CreepTask`], which is checked
+//! for still being worth working on and then worked on for the tick.
+//!
+//! These are unrelated to the long-running jobs of [`tasks`](crate::tasks).
+
+use screeps::{
+    prelude::*, ConstructionSite, Creep, ObjectId, Part, Position, RawObjectId, ResourceType,
+    ReturnCode, RoomName, Source, Structure, StructureController, StructureLab,
+};
+
+use crate::{
+    actions::{self, Action, ActionOutcome},
+    context::TickContext,
+    creeps::{self, AtSource},
+    failures, movement,
+};
+
+/// How close to the center of the room creeps moving there have to get.
+const REBASE_RANGE: u32 = 20;
+
+/// Whether a creep keeps its task after a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// There's more to do, on the next tick.
+    Working,
+    /// The task is finished or can't be finished, and the creep picks a new one.
+    Done,
+}
+
+impl TaskStatus {
+    fn keep_if(working: bool) -> TaskStatus {
+        if working {
+            TaskStatus::Working
+        } else {
+            TaskStatus::Done
+        }
+    }
+}
+
+/// Something a creep works on over one or more ticks.
+pub trait CreepTask {
+    /// Whether the task is still worth working on. Checked before every run, and a task which
+    /// isn't valid is dropped without running.
+    fn is_valid(&self, creep: &Creep, ctx: &TickContext) -> bool;
+
+    /// Works on the task for one tick. An error trips the creep.
+    fn run(&self, creep: &Creep, ctx: &TickContext) -> Result<TaskStatus, String>;
+}
+
+fn has_energy(creep: &Creep) -> bool {
+    creep.store_used_capacity(Some(ResourceType::Energy)) > 0
+}
+
+fn has_room(creep: &Creep) -> bool {
+    creep.store_free_capacity(Some(ResourceType::Energy)) > 0
+}
+
+/// Harvest energy from a source, waiting at it if it regenerates soon.
+pub struct Harvest {
+    pub source: ObjectId<Source>,
+}
+
+impl CreepTask for Harvest {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        // workers move on to another source rather than wait long for this one
+        has_room(creep)
+            && self.source.resolve().map_or(false, |source| {
+                creeps::at_source(&source, creep.pos().get_range_to(&source), false)
+                    != AtSource::Release
+            })
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let source = match self.source.resolve() {
+            Some(source) => source,
+            None => return Ok(TaskStatus::Done),
+        };
+        let working = match creeps::at_source(&source, creep.pos().get_range_to(&source), false) {
+            AtSource::Harvest => {
+                actions::try_perform(creep, Action::Harvest(&source))?.is_working()
+            }
+            AtSource::Wait => movement::move_creep_to(creep, &source, 1),
+            AtSource::Release => false,
+        };
+        Ok(TaskStatus::keep_if(working))
+    }
+}
+
+/// Bring energy to a spawn, extension, tower or storage.
+pub struct Fill {
+    pub structure: ObjectId<Structure>,
+}
+
+impl CreepTask for Fill {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        has_energy(creep)
+            && self
+                .structure
+                .resolve()
+                .map_or(false, |structure| creeps::accepts_energy(&structure))
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let structure = match self.structure.resolve() {
+            Some(structure) => structure,
+            None => return Ok(TaskStatus::Done),
+        };
+        let action = Action::Transfer(&structure, ResourceType::Energy);
+        let outcome = actions::try_perform(creep, action)?;
+        Ok(TaskStatus::keep_if(outcome == ActionOutcome::MoveCloser))
+    }
+}
+
+pub struct Build {
+    pub site: ObjectId<ConstructionSite>,
+}
+
+impl CreepTask for Build {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        has_energy(creep) && self.site.resolve().is_some()
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let site = match self.site.resolve() {
+            Some(site) => site,
+            None => return Ok(TaskStatus::Done),
+        };
+        let outcome = actions::try_perform(creep, Action::Build(&site))?;
+        Ok(TaskStatus::keep_if(outcome.is_working()))
+    }
+}
+
+/// Repair a structure up to its [`repair_goal`](creeps::repair_goal).
+pub struct Repair {
+    pub structure: ObjectId<Structure>,
+}
+
+impl CreepTask for Repair {
+    fn is_valid(&self, creep: &Creep, ctx: &TickContext) -> bool {
+        has_energy(creep)
+            && self.structure.resolve().map_or(false, |structure| {
+                structure
+                    .as_attackable()
+                    .map_or(false, |a| a.hits() < creeps::repair_goal(&structure, ctx))
+            })
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let structure = match self.structure.resolve() {
+            Some(structure) => structure,
+            None => return Ok(TaskStatus::Done),
+        };
+        let outcome = actions::try_perform(creep, Action::Repair(&structure))?;
+        Ok(TaskStatus::keep_if(outcome.is_working()))
+    }
+}
+
+pub struct Upgrade {
+    pub controller: ObjectId<StructureController>,
+}
+
+impl CreepTask for Upgrade {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        has_energy(creep) && self.controller.resolve().is_some()
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let controller = match self.controller.resolve() {
+            Some(controller) => controller,
+            None => return Ok(TaskStatus::Done),
+        };
+        let outcome = actions::try_perform(creep, Action::Upgrade(&controller))?;
+        Ok(TaskStatus::keep_if(outcome.is_working()))
+    }
+}
+
+/// Take energy out of a container or storage. Creeps only do this when told to with a flag.
+pub struct Withdraw {
+    pub structure: ObjectId<Structure>,
+}
+
+impl CreepTask for Withdraw {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        has_room(creep) && self.structure.resolve().is_some()
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let structure = match self.structure.resolve() {
+            Some(structure) => structure,
+            None => return Ok(TaskStatus::Done),
+        };
+        let action = Action::Withdraw(&structure, ResourceType::Energy);
+        let outcome = actions::try_perform(creep, action)?;
+        Ok(TaskStatus::keep_if(outcome == ActionOutcome::MoveCloser))
+    }
+}
+
+/// Step into the portal on a tile. Creeps only do this when told to from the console, and pick
+/// a new target wherever they come out.
+pub struct Portal {
+    pub pos: Position,
+}
+
+impl CreepTask for Portal {
+    fn is_valid(&self, _creep: &Creep, _ctx: &TickContext) -> bool {
+        true
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        Ok(TaskStatus::keep_if(movement::move_through_portal(
+            creep, self.pos,
+        )))
+    }
+}
+
+/// Move to another room, picking a new target once there. Creeps are given this when their room
+/// is abandoned.
+pub struct Rebase {
+    pub room_name: RoomName,
+}
+
+impl Rebase {
+    fn center(&self) -> Position {
+        Position::new(25, 25, self.room_name)
+    }
+}
+
+impl CreepTask for Rebase {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        // anywhere away from the exits will do
+        !creep.pos().in_range_to(&self.center(), REBASE_RANGE)
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        Ok(TaskStatus::keep_if(movement::move_creep_to(
+            creep,
+            &self.center(),
+            REBASE_RANGE,
+        )))
+    }
+}
+
+/// Dismantle a structure which isn't ours, or attack it if the creep has no work parts.
+pub struct AttackStructure {
+    pub structure: RawObjectId,
+}
+
+impl AttackStructure {
+    fn resolve(&self) -> Option<Structure> {
+        ObjectId::<Structure>::from(self.structure).resolve()
+    }
+}
+
+impl CreepTask for AttackStructure {
+    fn is_valid(&self, _creep: &Creep, _ctx: &TickContext) -> bool {
+        self.resolve().is_some()
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let structure = match self.resolve() {
+            Some(structure) => structure,
+            None => return Ok(TaskStatus::Done),
+        };
+        let action = if creep.get_active_bodyparts(Part::Work) > 0 {
+            Action::Dismantle(&structure)
+        } else {
+            Action::Attack(&structure)
+        };
+        let outcome = actions::try_perform(creep, action)?;
+        Ok(TaskStatus::keep_if(outcome.is_working()))
+    }
+}
+
+/// Get boosted with whatever is in a lab.
+pub struct Boost {
+    pub lab: ObjectId<StructureLab>,
+}
+
+impl CreepTask for Boost {
+    fn is_valid(&self, _creep: &Creep, _ctx: &TickContext) -> bool {
+        self.lab.resolve().is_some()
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        let lab = match self.lab.resolve() {
+            Some(lab) => lab,
+            None => return Ok(TaskStatus::Done),
+        };
+        if !creep.pos().in_range_to(&lab, 1) {
+            return Ok(TaskStatus::keep_if(movement::move_creep_to(creep, &lab, 1)));
+        }
+        let r = lab.boost_creep(creep, None);
+        if r != ReturnCode::Ok {
+            failures::report(&creep.name(), "boost", r);
+        }
+        Ok(TaskStatus::Done)
+    }
+}
+
+/// Take energy out of one structure and bring it to another, in one go.
+pub struct Haul {
+    pub from: ObjectId<Structure>,
+    pub to: ObjectId<Structure>,
+}
+
+impl CreepTask for Haul {
+    fn is_valid(&self, creep: &Creep, _ctx: &TickContext) -> bool {
+        let delivers = self
+            .to
+            .resolve()
+            .map_or(false, |to| creeps::accepts_energy(&to));
+        // a creep which already picked up only needs somewhere to deliver to
+        let picks_up = has_energy(creep)
+            || self
+                .from
+                .resolve()
+                .map_or(false, |from| creeps::holds_energy(&from));
+        delivers && picks_up
+    }
+
+    fn run(&self, creep: &Creep, _ctx: &TickContext) -> Result<TaskStatus, String> {
+        // the creep keeps the task after picking up, to deliver on the next tick
+        if !has_energy(creep) {
+            let from = match self.from.resolve() {
+                Some(from) => from,
+                None => return Ok(TaskStatus::Done),
+            };
+            let action = Action::Withdraw(&from, ResourceType::Energy);
+            let outcome = actions::try_perform(creep, action)?;
+            return Ok(TaskStatus::keep_if(outcome.is_working()));
+        }
+        let to = match self.to.resolve() {
+            Some(to) => to,
+            None => return Ok(TaskStatus::Done),
+        };
+        let action = Action::Transfer(&to, ResourceType::Energy);
+        let outcome = actions::try_perform(creep, action)?;
+        Ok(TaskStatus::keep_if(outcome == ActionOutcome::MoveCloser))
+    }
+}
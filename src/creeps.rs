@@ -0,0 +1,1062 @@
+//! Creep behaviour.
+//!
+//! Every creep works on a single [`CreepTarget`] held in heap memory. A creep without a target
+//! picks a new one, and a creep with a target keeps working on its [`CreepTask`] until the task
+//! is finished or no longer valid. Creeps in a room which isn't ours, like a highway, a source
+//! keeper room or another player's, don't look for work there but head back to their home room,
+//! unless it's the room they were sent to work in.
+//!
+//! Targets are copied into each creep's memory every [`SAVE_INTERVAL`] ticks, as a tag naming
+//! the kind of target followed by its ids, so a reset doesn't make every creep pick anew. A saved
+//! target which doesn't decode, like one of a kind that was since renamed or removed, is dropped
+//! and the creep picks a new one, so changing the format needs no migration.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::Entry, HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{
+    prelude::*, Attackable, ConstructionSite, Creep, ObjectId, Part, Position, RawObjectId,
+    ResourceType, Room, RoomName, Source, Structure, StructureController, StructureLab,
+    StructureType,
+};
+
+use crate::{
+    bootstrap, cleanup,
+    context::TickContext,
+    creep_costs, creep_debug,
+    creep_tasks::{self, CreepTask, TaskStatus},
+    defense, expansion, formation,
+    heap::CacheSize,
+    journal, movement, nukes, panics, power, remotes, rng,
+    scheduler::{self, Tier},
+    spawning::{self, Role},
+    state::{CreepState, RoomState, SiteState, SourceState, StructureState},
+    structures::links,
+    traffic::{self, TileCounts},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CreepTarget {
+    Harvest(ObjectId<Source>),
+    Fill(ObjectId<Structure>),
+    Build(ObjectId<ConstructionSite>),
+    Repair(ObjectId<Structure>),
+    Upgrade(ObjectId<StructureController>),
+    /// Take energy out of a container or storage. Creeps only do this when told to with a flag.
+    Withdraw(ObjectId<Structure>),
+    /// Step into the portal on a tile. Creeps only do this when told to from the console, and
+    /// pick a new target wherever they come out.
+    Portal(Position),
+    /// Move to another room, picking a new target once there. Creeps are given this when their
+    /// room is abandoned.
+    Rebase(RoomName),
+    /// Dismantle a structure which isn't ours, or attack it if the creep has no work parts.
+    AttackStructure(RawObjectId),
+    /// Get boosted with whatever is in a lab.
+    Boost(ObjectId<StructureLab>),
+    /// Take energy out of the first structure and bring it to the second, in one go.
+    Haul(ObjectId<Structure>, ObjectId<Structure>),
+}
+
+impl CreepTarget {
+    /// The work the target stands for.
+    pub fn task(self) -> Box<dyn CreepTask> {
+        match self {
+            CreepTarget::Harvest(source) => Box::new(creep_tasks::Harvest { source }),
+            CreepTarget::Fill(structure) => Box::new(creep_tasks::Fill { structure }),
+            CreepTarget::Build(site) => Box::new(creep_tasks::Build { site }),
+            CreepTarget::Repair(structure) => Box::new(creep_tasks::Repair { structure }),
+            CreepTarget::Upgrade(controller) => Box::new(creep_tasks::Upgrade { controller }),
+            CreepTarget::Withdraw(structure) => Box::new(creep_tasks::Withdraw { structure }),
+            CreepTarget::Portal(pos) => Box::new(creep_tasks::Portal { pos }),
+            CreepTarget::Rebase(room_name) => Box::new(creep_tasks::Rebase { room_name }),
+            CreepTarget::AttackStructure(structure) => {
+                Box::new(creep_tasks::AttackStructure { structure })
+            }
+            CreepTarget::Boost(lab) => Box::new(creep_tasks::Boost { lab }),
+            CreepTarget::Haul(from, to) => Box::new(creep_tasks::Haul { from, to }),
+        }
+    }
+
+    /// The name of the target's variant, for grouping stats.
+    pub fn kind(self) -> &'static str {
+        match self {
+            CreepTarget::Harvest(_) => "harvest",
+            CreepTarget::Fill(_) => "fill",
+            CreepTarget::Build(_) => "build",
+            CreepTarget::Repair(_) => "repair",
+            CreepTarget::Upgrade(_) => "upgrade",
+            CreepTarget::Withdraw(_) => "withdraw",
+            CreepTarget::Portal(_) => "portal",
+            CreepTarget::Rebase(_) => "rebase",
+            CreepTarget::AttackStructure(_) => "attack_structure",
+            CreepTarget::Boost(_) => "boost",
+            CreepTarget::Haul(..) => "haul",
+        }
+    }
+
+    /// Stores a target as its [`kind`](CreepTarget::kind) and its fields, like `fill:<id>` or
+    /// `haul:<from>,<to>`.
+    pub fn encode(self) -> String {
+        let fields = match self {
+            CreepTarget::Harvest(source) => source.to_string(),
+            CreepTarget::Fill(structure)
+            | CreepTarget::Repair(structure)
+            | CreepTarget::Withdraw(structure) => structure.to_string(),
+            CreepTarget::Build(site) => site.to_string(),
+            CreepTarget::Upgrade(controller) => controller.to_string(),
+            CreepTarget::Portal(pos) => format!("{},{},{}", pos.room_name(), pos.x(), pos.y()),
+            CreepTarget::Rebase(room_name) => room_name.to_string(),
+            CreepTarget::AttackStructure(structure) => structure.to_string(),
+            CreepTarget::Boost(lab) => lab.to_string(),
+            CreepTarget::Haul(from, to) => format!("{},{}", from, to),
+        };
+        format!("{}:{}", self.kind(), fields)
+    }
+
+    pub fn decode(encoded: &str) -> Option<CreepTarget> {
+        let mut parts = encoded.splitn(2, ':');
+        let kind = parts.next()?;
+        let mut fields = parts.next()?.split(',');
+        let target = match kind {
+            "harvest" => CreepTarget::Harvest(fields.next()?.parse().ok()?),
+            "fill" => CreepTarget::Fill(fields.next()?.parse().ok()?),
+            "build" => CreepTarget::Build(fields.next()?.parse().ok()?),
+            "repair" => CreepTarget::Repair(fields.next()?.parse().ok()?),
+            "upgrade" => CreepTarget::Upgrade(fields.next()?.parse().ok()?),
+            "withdraw" => CreepTarget::Withdraw(fields.next()?.parse().ok()?),
+            "portal" => {
+                let room_name = RoomName::new(fields.next()?).ok()?;
+                let x = fields.next()?.parse().ok()?;
+                let y = fields.next()?.parse().ok()?;
+                CreepTarget::Portal(Position::new(x, y, room_name))
+            }
+            "rebase" => CreepTarget::Rebase(RoomName::new(fields.next()?).ok()?),
+            "attack_structure" => CreepTarget::AttackStructure(fields.next()?.parse().ok()?),
+            "boost" => CreepTarget::Boost(fields.next()?.parse().ok()?),
+            "haul" => CreepTarget::Haul(fields.next()?.parse().ok()?, fields.next()?.parse().ok()?),
+            _ => return None,
+        };
+        Some(target)
+    }
+}
+
+const TARGET_KEY: &str = "target";
+
+/// How often creeps' targets are copied into their memory.
+pub const SAVE_INTERVAL: u32 = 50;
+
+/// Ramparts below this are repaired before anything else is built, so fresh ones don't decay away.
+const RAMPART_CRITICAL_HITS: u32 = 10_000;
+
+/// How far ramparts are repaired when there's nothing else to do.
+const RAMPART_TARGET_HITS: u32 = 100_000;
+
+/// How much energy is put into storage before creeps move on to building and upgrading.
+const STORAGE_RESERVE: u32 = 10_000;
+
+thread_local! {
+    /// Every creep's target, by the creep's name, which unlike its id every creep has.
+    static CREEP_TARGETS: RefCell<HashMap<String, CreepTarget>> = RefCell::new(HashMap::new());
+    /// Where in the list of creeps the creep loop starts.
+    static CREEP_OFFSET: Cell<usize> = Cell::new(0);
+    /// Whether the targets saved in creep memory were taken back since the reset.
+    static TARGETS_RESTORED: Cell<bool> = Cell::new(false);
+}
+
+/// Runs every creep, starting where the last tick's loop stopped, so the creeps the watchdog
+/// left over go first instead of being skipped every tick.
+///
+/// Moves are only resolved once all creeps ran, so the order doesn't change where they go.
+pub fn run_all(ctx: &TickContext) {
+    let mut creeps = screeps::game::creeps::values();
+    if !TARGETS_RESTORED.with(|restored| restored.replace(true)) {
+        restore_targets(&creeps);
+    }
+    let offset = CREEP_OFFSET.with(Cell::get) % creeps.len().max(1);
+    creeps.rotate_left(offset);
+    let demote = ctx.time % 2 == 1 && ctx.config.demote_expensive_creeps;
+
+    let mut ran = creeps.len();
+    for (index, creep) in creeps.iter().enumerate() {
+        if scheduler::near_limit(Tier::Critical) {
+            scheduler::skip(format!("{} creeps", creeps.len() - index));
+            ran = index;
+            break;
+        }
+        if demote && creep_costs::is_expensive(&creep.name()) {
+            continue;
+        }
+        let start = screeps::game::cpu::get_used();
+        // a creep which panicked or failed is skipped for a while, so it can't stop all the others
+        panics::guard(&format!("creep:{}", creep.name()), || run_creep(creep, ctx));
+        creep_costs::record(creep, screeps::game::cpu::get_used() - start);
+        ctx.stats.time_section("traffic", || traffic::record(creep));
+    }
+    ctx.stats
+        .time_section("intents", movement::intents::resolve);
+
+    CREEP_OFFSET.with(|o| o.set((offset + ran) % creeps.len().max(1)));
+}
+
+/// Runs a creep for one tick, returning an error if it's in a state it can't be run in, which
+/// trips it for a few ticks.
+pub fn run_creep(creep: &Creep, ctx: &TickContext) -> Result<(), String> {
+    // spawning creeps can't do anything yet
+    if creep.spawning() {
+        return Ok(());
+    }
+    creep_debug!(creep.name(), "running creep {}", creep.name());
+
+    // quads move their creeps together
+    if formation::is_member(&creep.name()) {
+        return Ok(());
+    }
+
+    if nukes::evacuate(creep) {
+        return Ok(());
+    }
+
+    // creeps which can't fight get out of the way until the towers have dealt with attackers
+    let fighter = creep.get_active_bodyparts(Part::Attack) > 0
+        || creep.get_active_bodyparts(Part::RangedAttack) > 0;
+    if !fighter && movement::flee(creep, ctx) {
+        return Ok(());
+    }
+
+    match spawning::checked_role_of(creep)? {
+        Role::Worker => {}
+        Role::Pioneer => {
+            if bootstrap::run_creep(creep) {
+                movement::step_off_exit(creep);
+                return Ok(());
+            }
+        }
+        role @ Role::PowerAttacker | role @ Role::PowerHealer | role @ Role::PowerHauler => {
+            power::run_creep(creep, role);
+            movement::step_off_exit(creep);
+            return Ok(());
+        }
+        Role::Claimer => {
+            expansion::run_claimer(creep);
+            movement::step_off_exit(creep);
+            return Ok(());
+        }
+        Role::RampartDefender | Role::BoostedDefender => {
+            if defense::run_rampart_defender(creep, ctx) {
+                return Ok(());
+            }
+        }
+        Role::Dismantler => {
+            if cleanup::run_creep(creep, ctx) {
+                movement::step_off_exit(creep);
+                return Ok(());
+            }
+        }
+        role => {
+            remotes::run_creep(creep, role, ctx);
+            movement::step_off_exit(creep);
+            return Ok(());
+        }
+    }
+
+    CREEP_TARGETS.with(|targets| {
+        let mut targets = targets
+            .try_borrow_mut()
+            .map_err(|_| "creep targets already in use".to_string())?;
+        match targets.entry(creep.name()) {
+            Entry::Occupied(entry) => {
+                // a target the creep failed at is dropped too, so it starts over once untripped
+                let status = run_task(&*entry.get().task(), creep, ctx);
+                if status != Ok(TaskStatus::Working) {
+                    entry.remove();
+                }
+                status?;
+            }
+            Entry::Vacant(entry) => match find_target(creep, ctx) {
+                Some(target) => {
+                    let pos = creep.pos();
+                    let energy = creep.store_used_capacity(Some(ResourceType::Energy));
+                    let inputs = (pos.room_name().to_string(), pos.x(), pos.y(), energy);
+                    journal::record("creeps", inputs, || {
+                        format!("{} picked {:?}", creep.name(), target)
+                    });
+                    if run_task(&*target.task(), creep, ctx)? == TaskStatus::Working {
+                        entry.insert(target);
+                    }
+                }
+                // nothing to do, which is best done away from the spawns
+                None => movement::leave_core(creep),
+            },
+        }
+        Ok::<_, String>(())
+    })?;
+
+    movement::step_off_exit(creep);
+    Ok(())
+}
+
+/// Works on a task for one tick if it's still valid, returning whether the creep should keep it.
+fn run_task(task: &dyn CreepTask, creep: &Creep, ctx: &TickContext) -> Result<TaskStatus, String> {
+    if !task.is_valid(creep, ctx) {
+        return Ok(TaskStatus::Done);
+    }
+    task.run(creep, ctx)
+}
+
+/// What a creep is working on, if anything.
+pub fn current_target(creep: &str) -> Option<CreepTarget> {
+    CREEP_TARGETS.with(|targets| targets.borrow().get(creep).copied())
+}
+
+/// Drops a creep's target, so it picks a new one on its next run.
+pub fn clear_target(creep: &str) -> Option<CreepTarget> {
+    CREEP_TARGETS.with(|targets| targets.borrow_mut().remove(creep))
+}
+
+/// Drops every creep's target, returning how many there were.
+pub fn clear_all_targets() -> usize {
+    CREEP_TARGETS.with(|targets| std::mem::take(&mut *targets.borrow_mut()).len())
+}
+
+/// Gives a creep a target, dropping whatever else it was doing.
+pub fn set_target(creep: &str, target: CreepTarget) {
+    journal::record("creeps", creep, || {
+        format!("{} was given {:?}", creep, target)
+    });
+    CREEP_TARGETS.with(|targets| targets.borrow_mut().insert(creep.to_string(), target));
+}
+
+/// Copies every creep's target into its memory, so they're kept over a reset.
+pub fn save_targets() {
+    CREEP_TARGETS.with(|targets| {
+        let targets = targets.borrow();
+        for creep in screeps::game::creeps::values() {
+            match targets.get(&creep.name()) {
+                Some(target) => creep.memory().set(TARGET_KEY, target.encode()),
+                None => creep.memory().del(TARGET_KEY),
+            }
+        }
+    });
+}
+
+/// Takes back the targets saved in creep memory, dropping the ones which don't decode.
+fn restore_targets(creeps: &[Creep]) {
+    let mut restored = 0;
+    CREEP_TARGETS.with(|targets| {
+        let mut targets = targets.borrow_mut();
+        for creep in creeps {
+            let encoded = match creep.memory().string(TARGET_KEY) {
+                Ok(Some(encoded)) => encoded,
+                _ => continue,
+            };
+            match CreepTarget::decode(&encoded) {
+                Some(target) => {
+                    targets.insert(creep.name(), target);
+                    restored += 1;
+                }
+                None => {
+                    warn!("dropping saved target {} of {}", encoded, creep.name());
+                    creep.memory().del(TARGET_KEY);
+                }
+            }
+        }
+    });
+    info!("restored {} creep targets", restored);
+}
+
+/// Drops the targets of creeps which are no longer alive.
+pub fn forget_dead() {
+    let alive_creeps: HashSet<String> = screeps::game::creeps::keys().into_iter().collect();
+    CREEP_TARGETS.with(|targets| {
+        targets
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![CREEP_TARGETS
+        .with(|t| CacheSize::of_map("creeps.targets", &t.borrow(), |name, _| name.capacity()))]
+}
+
+/// Drops every creep's target, so they all pick a new one.
+pub fn purge() {
+    CREEP_TARGETS.with(|t| std::mem::take(&mut *t.borrow_mut()));
+}
+
+fn find_target(creep: &Creep, ctx: &TickContext) -> Option<CreepTarget> {
+    let room = creep.room()?;
+    let owned = room.controller().map_or(false, |c| c.my());
+    if !owned && spawning::work_room(creep) != Some(room.name()) {
+        return head_home(creep, &room);
+    }
+    let snapshot = ctx.rooms.snapshot(&room);
+    let orders = RoomOrders {
+        hold: defense::rampart_to_hold(room.name(), &snapshot).map(|s| s.id()),
+        reinforce: nukes::rampart_to_reinforce(room.name(), &snapshot).map(|s| s.id()),
+        traffic: traffic::road_traffic(room.name()),
+    };
+    pick_target(creep, &*snapshot, &orders)
+}
+
+/// What picking a target in a room goes by besides what's in the room.
+#[derive(Debug, Default)]
+pub struct RoomOrders {
+    /// The rampart to hold against the attackers, if the room is under a critical attack.
+    pub hold: Option<ObjectId<Structure>>,
+    /// The rampart with the furthest to go of those which are to survive a nuke.
+    pub reinforce: Option<ObjectId<Structure>>,
+    /// How busy each road tile of the room is.
+    pub traffic: TileCounts,
+}
+
+/// Picks a target for a creep out of what's in its room, if one of its kind is worth working on.
+type TargetFactory<C, R> = fn(&C, &R, &RoomOrders) -> Option<CreepTarget>;
+
+/// The target most worth working on for a creep in a room of ours, or the room it was sent to.
+///
+/// Empty creeps go get energy, and the others take it where it's needed most, so each of them
+/// tries its list of factories in order and goes with the first one which finds something.
+pub fn pick_target<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    let factories: Vec<TargetFactory<C, R>> = if creep.store_used() == 0 {
+        vec![haul_for_spawns, harvest]
+    } else {
+        vec![
+            fill_spawns,
+            hold_rampart,
+            fill_storage,
+            save_rampart,
+            reinforce_rampart,
+            build,
+            repair_ours,
+            repair_road,
+            raise_rampart,
+            upgrade,
+        ]
+    };
+    factories
+        .iter()
+        .find_map(|factory| factory(creep, room, orders))
+}
+
+/// The spawns, extensions and towers of ours waiting for energy.
+fn fillable<R: RoomState>(room: &R) -> Vec<&R::Structure> {
+    [
+        StructureType::Spawn,
+        StructureType::Extension,
+        StructureType::Tower,
+    ]
+    .iter()
+    .flat_map(|&ty| room.my_structures(ty))
+    .filter(|s| energy_free_capacity(*s) > 0)
+    .collect()
+}
+
+/// Spawns and extensions waiting for energy are filled from the hub link or the storage first,
+/// which is quicker than harvesting for them. The hub link goes first, so the energy the other
+/// links send it doesn't sit there.
+fn haul_for_spawns<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    let from = links::hub(room)
+        .into_iter()
+        .chain(room.my_structures(StructureType::Storage))
+        .find(|s| holds_energy(*s))?;
+    let structure = closest(creep, fillable(room))?;
+    Some(CreepTarget::Haul(
+        from.structure_id(),
+        structure.structure_id(),
+    ))
+}
+
+/// Sources are picked at random, favouring close ones with energy left, so harvesters spread out
+/// over them.
+fn harvest<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    let sources: Vec<(&R::Source, f64)> = room
+        .sources()
+        .iter()
+        .filter(|s| at_source(*s, creep.pos_range_to(*s), false) != AtSource::Release)
+        .map(|s| {
+            // a regenerating source is full by the time the creep gets there
+            let energy = if s.energy_left() > 0 {
+                s.energy_left()
+            } else {
+                s.energy_max()
+            };
+            (s, energy as f64 / (creep.pos_range_to(s) + 1) as f64)
+        })
+        .collect();
+    rng::choose_weighted(&sources).map(|source| CreepTarget::Harvest(source.source_id()))
+}
+
+fn fill_spawns<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    closest(creep, fillable(room)).map(|structure| CreepTarget::Fill(structure.structure_id()))
+}
+
+fn hold_rampart<C: CreepState, R: RoomState>(
+    _creep: &C,
+    _room: &R,
+    orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    orders.hold.map(CreepTarget::Repair)
+}
+
+fn fill_storage<C: CreepState, R: RoomState>(
+    _creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    room.my_structures(StructureType::Storage)
+        .into_iter()
+        .find(|s| energy_free_capacity(*s) > 0)
+        .map(|storage| CreepTarget::Fill(storage.structure_id()))
+}
+
+/// Fresh ramparts are kept from decaying away before anything else is built.
+fn save_rampart<C: CreepState, R: RoomState>(
+    _creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    weakest_rampart(room, RAMPART_CRITICAL_HITS)
+        .map(|rampart| CreepTarget::Repair(rampart.structure_id()))
+}
+
+fn reinforce_rampart<C: CreepState, R: RoomState>(
+    _creep: &C,
+    _room: &R,
+    orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    orders.reinforce.map(CreepTarget::Repair)
+}
+
+fn build<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    closest(creep, room.construction_sites()).map(|site| CreepTarget::Build(site.site_id()))
+}
+
+/// Our structures and containers, leaving roads, walls and ramparts to their own factories.
+fn repair_ours<C: CreepState, R: RoomState>(
+    creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    let repairable = room.every_structure().filter(|s| {
+        let ours = match s.structure_kind() {
+            StructureType::Container => true,
+            StructureType::Road | StructureType::Wall | StructureType::Rampart => false,
+            _ => s.is_mine(),
+        };
+        ours && needs_repair(*s)
+    });
+    closest(creep, repairable).map(|structure| CreepTarget::Repair(structure.structure_id()))
+}
+
+/// Roads only get repaired if they're used enough, busiest first.
+fn repair_road<C: CreepState, R: RoomState>(
+    _creep: &C,
+    room: &R,
+    orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    room.structures(StructureType::Road)
+        .iter()
+        .filter_map(|s| {
+            let pos = s.pos();
+            let count = *orders.traffic.get(&(pos.x() as u8, pos.y() as u8))?;
+            if count >= traffic::MIN_REPAIR_TRAFFIC && needs_repair(s) {
+                Some((count, s))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(count, _)| *count)
+        .map(|(_, road)| CreepTarget::Repair(road.structure_id()))
+}
+
+fn raise_rampart<C: CreepState, R: RoomState>(
+    _creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    weakest_rampart(room, RAMPART_TARGET_HITS)
+        .map(|rampart| CreepTarget::Repair(rampart.structure_id()))
+}
+
+fn upgrade<C: CreepState, R: RoomState>(
+    _creep: &C,
+    room: &R,
+    _orders: &RoomOrders,
+) -> Option<CreepTarget> {
+    room.my_controller().map(CreepTarget::Upgrade)
+}
+
+/// Sends a creep in a room which isn't ours back to its home room, or leaves it idle if it has
+/// none or the home room is no longer ours.
+fn head_home(creep: &Creep, room: &Room) -> Option<CreepTarget> {
+    let home = spawning::home_room(creep).filter(|&home| home != room.name());
+    let ours = home
+        .and_then(screeps::game::rooms::get)
+        .and_then(|home| home.controller())
+        .map_or(false, |c| c.my());
+    match home {
+        Some(home) if ours => {
+            creep_debug!(
+                creep.name(),
+                "{} is in room {} which isn't ours, heading home to {}",
+                creep.name(),
+                room.name(),
+                home
+            );
+            Some(CreepTarget::Rebase(home))
+        }
+        _ => {
+            creep_debug!(
+                creep.name(),
+                "{} is in room {} which isn't ours, with no home to go to",
+                creep.name(),
+                room.name()
+            );
+            None
+        }
+    }
+}
+
+/// What a creep at or on its way to a source does about it this tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtSource {
+    /// There's energy to harvest.
+    Harvest,
+    /// It's empty, but worth staying at until it regenerates.
+    Wait,
+    /// It's empty for longer than it's worth waiting, so the creep looks elsewhere.
+    Release,
+}
+
+/// What a creep `ticks` away from a source does about it. Static miners have nowhere better to
+/// be and wait out the regeneration, while other creeps only keep a source that regenerates by
+/// the time they get there. Both picking a source and keeping at one go by this, so a creep isn't
+/// sent to a source it'd drop right away, and nobody harvests an empty one.
+pub fn at_source(source: &impl SourceState, ticks: u32, static_miner: bool) -> AtSource {
+    source_plan(
+        source.energy_left(),
+        source.regenerates_in(),
+        ticks,
+        static_miner,
+    )
+}
+
+fn source_plan(
+    energy: u32,
+    ticks_to_regeneration: u32,
+    ticks: u32,
+    static_miner: bool,
+) -> AtSource {
+    if energy > 0 {
+        AtSource::Harvest
+    } else if static_miner || ticks_to_regeneration <= ticks {
+        AtSource::Wait
+    } else {
+        AtSource::Release
+    }
+}
+
+fn closest<'a, T: HasPosition>(
+    creep: &impl HasPosition,
+    candidates: impl IntoIterator<Item = &'a T>,
+) -> Option<&'a T> {
+    let pos = creep.pos();
+    candidates
+        .into_iter()
+        .min_by_key(|candidate| pos.get_range_to(*candidate))
+}
+
+/// Whether a structure has lost enough hits to be worth sending a creep to.
+fn needs_repair(structure: &impl StructureState) -> bool {
+    let (hits, hits_max) = structure.health();
+    hits < hits_max / 2
+}
+
+/// How many hits a structure is repaired up to. Ramparts would soak up all energy if they were
+/// repaired to full, unless they're to survive a nuke or are under attack.
+pub fn repair_goal(structure: &Structure, ctx: &TickContext) -> u32 {
+    match structure {
+        Structure::Rampart(rampart) => rampart.hits_max().min(
+            RAMPART_TARGET_HITS
+                .max(nukes::rampart_goal(rampart.pos()))
+                .max(defense::rampart_goal(rampart.pos(), ctx)),
+        ),
+        _ => structure.as_attackable().map(|a| a.hits_max()).unwrap_or(0),
+    }
+}
+
+/// The rampart of ours with the fewest hits of those below `below`.
+fn weakest_rampart<R: RoomState>(room: &R, below: u32) -> Option<&R::Structure> {
+    room.my_structures(StructureType::Rampart)
+        .into_iter()
+        .filter(|s| s.health().0 < below)
+        .min_by_key(|s| s.health().0)
+}
+
+fn energy_free_capacity(structure: &impl StructureState) -> i32 {
+    match structure.structure_kind() {
+        StructureType::Spawn | StructureType::Extension | StructureType::Tower => {
+            structure.energy_room() as i32
+        }
+        StructureType::Storage => STORAGE_RESERVE as i32 - structure.stored_energy() as i32,
+        _ => 0,
+    }
+}
+
+/// Whether a structure has room for energy creeps would fill it with.
+pub fn accepts_energy(structure: &impl StructureState) -> bool {
+    energy_free_capacity(structure) > 0
+}
+
+/// Whether energy can be withdrawn from a structure at all.
+pub fn holds_energy(structure: &impl StructureState) -> bool {
+    match structure.structure_kind() {
+        StructureType::Container | StructureType::Storage | StructureType::Link => {
+            structure.stored_energy() > 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fake::{at, id, FakeCreep, FakeRoom, FakeSite, FakeSource, FakeStructure};
+
+    fn no_orders() -> RoomOrders {
+        RoomOrders {
+            hold: None,
+            reinforce: None,
+            traffic: TileCounts::new(),
+        }
+    }
+
+    fn structure(n: u32, kind: StructureType, x: u32, y: u32) -> FakeStructure {
+        FakeStructure::new(n, kind, x, y)
+    }
+
+    fn wanting_energy(n: u32, kind: StructureType, x: u32, y: u32) -> FakeStructure {
+        FakeStructure {
+            energy_room: 50,
+            ..structure(n, kind, x, y)
+        }
+    }
+
+    fn storage(n: u32, energy: u32) -> FakeStructure {
+        FakeStructure {
+            energy,
+            energy_room: 1_000_000 - energy,
+            ..structure(n, StructureType::Storage, 25, 25)
+        }
+    }
+
+    fn damaged(mut structure: FakeStructure, hits: u32) -> FakeStructure {
+        structure.hits = hits;
+        structure
+    }
+
+    /// A room with a controller of ours, and nothing else.
+    fn room() -> FakeRoom {
+        FakeRoom {
+            controller: Some(1),
+            ..FakeRoom::default()
+        }
+    }
+
+    #[test]
+    fn empty_creeps_haul_from_storage_to_the_closest_spawn_or_extension() {
+        let room = room()
+            .with(storage(2, 5000))
+            .with(wanting_energy(3, StructureType::Spawn, 20, 20))
+            .with(wanting_energy(4, StructureType::Extension, 11, 10));
+        let target = pick_target(&FakeCreep::empty(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Haul(id(2), id(4))));
+    }
+
+    #[test]
+    fn empty_creeps_empty_the_hub_link_before_the_storage() {
+        let mut hub = structure(6, StructureType::Link, 26, 26);
+        hub.energy = 400;
+        let room = room().with(storage(2, 5000)).with(hub).with(wanting_energy(
+            4,
+            StructureType::Extension,
+            11,
+            10,
+        ));
+        let target = pick_target(&FakeCreep::empty(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Haul(id(6), id(4))));
+    }
+
+    #[test]
+    fn empty_creeps_harvest_without_energy_in_storage() {
+        let mut room =
+            room()
+                .with(storage(2, 0))
+                .with(wanting_energy(3, StructureType::Spawn, 20, 20));
+        room.sources = vec![FakeSource::full(5, 40, 40)];
+        let target = pick_target(&FakeCreep::empty(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Harvest(id(5))));
+    }
+
+    #[test]
+    fn empty_creeps_skip_sources_which_are_empty_until_they_get_there() {
+        let mut room = room();
+        let mut regenerating = FakeSource::full(5, 12, 10);
+        regenerating.energy = 0;
+        regenerating.regenerates_in = 50;
+        room.sources = vec![regenerating.clone(), FakeSource::full(6, 40, 40)];
+        let target = pick_target(&FakeCreep::empty(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Harvest(id(6))));
+
+        // one which is full again by then is fine
+        regenerating.regenerates_in = 2;
+        room.sources = vec![regenerating];
+        let target = pick_target(&FakeCreep::empty(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Harvest(id(5))));
+
+        room.sources[0].regenerates_in = 3;
+        assert_eq!(
+            pick_target(&FakeCreep::empty(10, 10), &room, &no_orders()),
+            None
+        );
+    }
+
+    #[test]
+    fn full_creeps_fill_the_closest_structure_wanting_energy() {
+        let room = room()
+            .with(wanting_energy(3, StructureType::Spawn, 30, 30))
+            .with(wanting_energy(4, StructureType::Tower, 12, 12))
+            .with(structure(5, StructureType::Extension, 10, 11));
+        let target = pick_target(&FakeCreep::full(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Fill(id(4))));
+    }
+
+    #[test]
+    fn other_players_structures_are_not_filled() {
+        let mut theirs = wanting_energy(3, StructureType::Spawn, 11, 11);
+        theirs.mine = false;
+        let room = room().with(theirs);
+        let target = pick_target(&FakeCreep::full(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Upgrade(id(1))));
+    }
+
+    #[test]
+    fn held_ramparts_go_before_storage() {
+        let room = room()
+            .with(storage(2, 0))
+            .with(damaged(structure(3, StructureType::Rampart, 5, 5), 500));
+        let orders = RoomOrders {
+            hold: Some(id(3)),
+            ..no_orders()
+        };
+        let creep = FakeCreep::full(10, 10);
+        assert_eq!(
+            pick_target(&creep, &room, &orders),
+            Some(CreepTarget::Repair(id(3)))
+        );
+        assert_eq!(
+            pick_target(&creep, &room, &no_orders()),
+            Some(CreepTarget::Fill(id(2)))
+        );
+    }
+
+    #[test]
+    fn storage_is_only_filled_up_to_its_reserve() {
+        let room = room().with(storage(2, STORAGE_RESERVE));
+        let target = pick_target(&FakeCreep::full(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Upgrade(id(1))));
+    }
+
+    #[test]
+    fn weak_ramparts_and_nuked_ones_go_before_building() {
+        let mut room = room()
+            .with(damaged(structure(3, StructureType::Rampart, 5, 5), 9000))
+            .with(damaged(structure(4, StructureType::Rampart, 6, 5), 3000))
+            .with(damaged(structure(5, StructureType::Rampart, 7, 5), 50_000));
+        room.sites = vec![FakeSite {
+            id: 6,
+            pos: at(11, 11),
+        }];
+        let creep = FakeCreep::full(10, 10);
+        let orders = RoomOrders {
+            reinforce: Some(id(5)),
+            ..no_orders()
+        };
+        assert_eq!(
+            pick_target(&creep, &room, &orders),
+            Some(CreepTarget::Repair(id(4)))
+        );
+
+        let room = FakeRoom {
+            structures: room
+                .structures
+                .into_iter()
+                .map(|(ty, ramparts)| {
+                    let healthy = ramparts
+                        .into_iter()
+                        .map(|r| damaged(r, RAMPART_CRITICAL_HITS * 5))
+                        .collect();
+                    (ty, healthy)
+                })
+                .collect(),
+            ..room
+        };
+        assert_eq!(
+            pick_target(&creep, &room, &orders),
+            Some(CreepTarget::Repair(id(5)))
+        );
+        assert_eq!(
+            pick_target(&creep, &room, &no_orders()),
+            Some(CreepTarget::Build(id(6)))
+        );
+    }
+
+    #[test]
+    fn only_our_structures_and_containers_are_repaired() {
+        let mut wall = damaged(structure(3, StructureType::Wall, 11, 11), 10);
+        wall.mine = false;
+        let mut theirs = damaged(structure(4, StructureType::Tower, 11, 10), 10);
+        theirs.mine = false;
+        let mut container = damaged(structure(5, StructureType::Container, 30, 30), 400);
+        container.mine = false;
+        let room = room().with(wall).with(theirs).with(container);
+        let target = pick_target(&FakeCreep::full(10, 10), &room, &no_orders());
+        assert_eq!(target, Some(CreepTarget::Repair(id(5))));
+    }
+
+    #[test]
+    fn only_busy_roads_are_repaired_busiest_first() {
+        let room = room()
+            .with(damaged(structure(3, StructureType::Road, 11, 11), 100))
+            .with(damaged(structure(4, StructureType::Road, 12, 12), 100))
+            .with(damaged(structure(5, StructureType::Road, 13, 13), 100))
+            .with(structure(6, StructureType::Road, 14, 14));
+        let mut orders = no_orders();
+        orders
+            .traffic
+            .insert((11, 11), traffic::MIN_REPAIR_TRAFFIC - 1);
+        orders.traffic.insert((12, 12), traffic::MIN_REPAIR_TRAFFIC);
+        orders
+            .traffic
+            .insert((13, 13), traffic::MIN_REPAIR_TRAFFIC + 5);
+        orders
+            .traffic
+            .insert((14, 14), traffic::MIN_REPAIR_TRAFFIC + 10);
+        let creep = FakeCreep::full(10, 10);
+        assert_eq!(
+            pick_target(&creep, &room, &orders),
+            Some(CreepTarget::Repair(id(5)))
+        );
+        assert_eq!(
+            pick_target(&creep, &room, &no_orders()),
+            Some(CreepTarget::Upgrade(id(1)))
+        );
+    }
+
+    #[test]
+    fn creeps_upgrade_with_nothing_else_to_do_where_the_controller_is_ours() {
+        let creep = FakeCreep::full(10, 10);
+        assert_eq!(
+            pick_target(&creep, &room(), &no_orders()),
+            Some(CreepTarget::Upgrade(id(1)))
+        );
+        assert_eq!(
+            pick_target(&creep, &FakeRoom::default(), &no_orders()),
+            None
+        );
+        // ramparts are topped up before that
+        let room = room().with(damaged(structure(3, StructureType::Rampart, 5, 5), 20_000));
+        assert_eq!(
+            pick_target(&creep, &room, &no_orders()),
+            Some(CreepTarget::Repair(id(3)))
+        );
+    }
+
+    #[test]
+    fn a_source_with_energy_is_harvested() {
+        for &static_miner in &[false, true] {
+            assert_eq!(source_plan(1, 300, 0, static_miner), AtSource::Harvest);
+            assert_eq!(source_plan(3000, 0, 20, static_miner), AtSource::Harvest);
+        }
+    }
+
+    #[test]
+    fn workers_keep_a_source_regenerating_before_they_get_there() {
+        assert_eq!(source_plan(0, 10, 12, false), AtSource::Wait);
+        assert_eq!(source_plan(0, 12, 12, false), AtSource::Wait);
+        assert_eq!(source_plan(0, 0, 0, false), AtSource::Wait);
+    }
+
+    #[test]
+    fn workers_release_a_source_regenerating_after_they_get_there() {
+        assert_eq!(source_plan(0, 13, 12, false), AtSource::Release);
+        // already next to it, and it doesn't regenerate this tick
+        assert_eq!(source_plan(0, 1, 0, false), AtSource::Release);
+        assert_eq!(source_plan(0, 300, 1, false), AtSource::Release);
+    }
+
+    #[test]
+    fn static_miners_wait_out_the_regeneration() {
+        assert_eq!(source_plan(0, 1, 0, true), AtSource::Wait);
+        assert_eq!(source_plan(0, 300, 0, true), AtSource::Wait);
+    }
+
+    #[test]
+    fn targets_are_saved_with_their_kind() {
+        let room_name = RoomName::new("W2N3").unwrap();
+        let targets = [
+            CreepTarget::Harvest(id(1)),
+            CreepTarget::Fill(id(2)),
+            CreepTarget::Build(id(3)),
+            CreepTarget::Repair(id(4)),
+            CreepTarget::Upgrade(id(5)),
+            CreepTarget::Withdraw(id(6)),
+            CreepTarget::Portal(at(12, 34)),
+            CreepTarget::Rebase(room_name),
+            CreepTarget::AttackStructure(id::<Structure>(7).into()),
+            CreepTarget::Boost(id(8)),
+            CreepTarget::Haul(id(9), id(10)),
+        ];
+        for &target in &targets {
+            let encoded = target.encode();
+            assert!(encoded.starts_with(&format!("{}:", target.kind())));
+            assert_eq!(CreepTarget::decode(&encoded), Some(target), "{}", encoded);
+        }
+        assert_eq!(CreepTarget::Rebase(room_name).encode(), "rebase:W2N3");
+    }
+
+    #[test]
+    fn saved_targets_which_dont_decode_are_dropped() {
+        assert_eq!(CreepTarget::decode("harvest"), None);
+        assert_eq!(CreepTarget::decode("mine:1"), None);
+        assert_eq!(CreepTarget::decode("fill:not an id"), None);
+        assert_eq!(CreepTarget::decode("haul:1"), None);
+        assert_eq!(CreepTarget::decode("portal:W1N1,12"), None);
+    }
+}
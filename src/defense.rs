@@ -0,0 +1,569 @@
+//! Defending our own rooms with creeps, alongside the towers.
+//!
+//! Against melee attackers the best spot is a rampart they can't step onto, so rooms facing a
+//! medium threat or worse with attack parts in it get rampart defenders, one per
+//! [`PARTS_PER_RAMPART_DEFENDER`] hostile attack parts and never more than there are ramparts to
+//! hold. Only perimeter ramparts count, those next to a tile hostiles can walk to from an exit,
+//! which is found with a flood fill kept for [`PERIMETER_TTL`] ticks.
+//!
+//! Every tick the perimeter ramparts closest to the hostiles are manned, one defender each. A
+//! defender already on one of those stays put, and the others take the closest free one, so the
+//! defenders shift along the wall as the hostiles do. While the threat is medium or worse a
+//! defender on a rampart only ever steps onto a neighbouring rampart, so it can't be caught in
+//! the open, even if that means it can't reach its station. Defenders hit whatever hostile is
+//! next to them.
+//!
+//! Against a critical threat which is boosted itself, or has at least [`BOOST_MIN_PARTS`] fighting
+//! parts, the room spawns boosted defenders instead, as long as its labs hold enough XUH2O, XLHO2
+//! or XGHO2 to boost a whole defender's parts. Boosts are expensive, so the decision and what it
+//! was based on are logged whenever it changes. Each boosted defender visits the labs on its way
+//! to its rampart. While the threat is critical the ramparts next to the attackers are repaired
+//! before anything but filling, and all the way up.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+};
+
+use log::*;
+use screeps::{
+    prelude::*, Attackable, Creep, Part, Position, ResourceType, Room, RoomName, Structure,
+    StructureLab, StructureType, Terrain,
+};
+
+use crate::{
+    cache::RoomSnapshot,
+    context::TickContext,
+    creeps::{self, CreepTarget},
+    failures,
+    heap::CacheSize,
+    movement,
+    spawning::{self, Role, SpawnRequest},
+    threat::{self, Assessment, Level},
+};
+
+/// How many of the hostiles' attack parts one rampart defender takes on.
+pub const PARTS_PER_RAMPART_DEFENDER: u32 = 10;
+
+/// The most rampart defenders a room has at once.
+const MAX_RAMPART_DEFENDERS: u32 = 4;
+
+/// The priority of rampart defender spawn requests, above everything but the console.
+const RAMPART_DEFENDER_PRIORITY: u8 = 190;
+
+/// How long a room's perimeter is kept before it's flood filled again.
+pub const PERIMETER_TTL: u32 = 100;
+
+/// How often defenders are requested.
+const REQUEST_INTERVAL: u32 = 10;
+
+/// The compounds defenders are boosted with, in the order they're applied, with the part each
+/// one boosts.
+const BOOSTS: &[(ResourceType, Part)] = &[
+    (ResourceType::CatalyzedUtriumAcid, Part::Attack),
+    (ResourceType::CatalyzedLemergiumAlkalide, Part::Heal),
+    (ResourceType::CatalyzedGhodiumAlkalide, Part::Tough),
+];
+
+/// What a lab spends boosting one part.
+const BOOST_COMPOUND_PER_PART: u32 = 30;
+const BOOST_ENERGY_PER_PART: u32 = 20;
+
+/// Unboosted attackers need this many fighting parts to be worth spending boosts on.
+pub const BOOST_MIN_PARTS: u32 = 20;
+
+/// Ramparts this close to a hostile are repaired to full while the threat is critical.
+const PRESSED_RANGE: u32 = 3;
+
+type Tile = (u8, u8);
+
+struct Perimeter {
+    time: u32,
+    ramparts: Vec<Tile>,
+}
+
+thread_local! {
+    static PERIMETERS: RefCell<HashMap<RoomName, Perimeter>> = RefCell::new(HashMap::new());
+    /// The rampart each defender is to hold this tick, by creep name.
+    static STATIONS: RefCell<HashMap<String, Tile>> = RefCell::new(HashMap::new());
+    /// The compounds defenders are boosted with in each room under critical threat.
+    static BOOSTING: RefCell<HashMap<RoomName, Vec<ResourceType>>> =
+        RefCell::new(HashMap::new());
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+fn neighbours((x, y): Tile) -> impl Iterator<Item = Tile> {
+    NEIGHBOURS.iter().filter_map(move |&(dx, dy)| {
+        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+        if (0..50).contains(&nx) && (0..50).contains(&ny) {
+            Some((nx as u8, ny as u8))
+        } else {
+            None
+        }
+    })
+}
+
+/// The ramparts of ours next to a tile hostiles can walk to from an exit.
+fn find_perimeter(room: &Room, ctx: &TickContext) -> Vec<Tile> {
+    let terrain = room.get_terrain();
+    let snapshot = ctx.rooms.snapshot(room);
+    let ramparts: HashSet<Tile> = snapshot
+        .my_structures(StructureType::Rampart)
+        .map(|s| (s.pos().x() as u8, s.pos().y() as u8))
+        .collect();
+    let blocked: HashSet<Tile> = snapshot
+        .all_structures()
+        .filter(|s| {
+            !matches!(
+                s.structure_type(),
+                StructureType::Road | StructureType::Container
+            )
+        })
+        .map(|s| (s.pos().x() as u8, s.pos().y() as u8))
+        .collect();
+    let walkable = |(x, y): Tile| {
+        terrain.get(x as u32, y as u32) != Terrain::Wall && !blocked.contains(&(x, y))
+    };
+
+    let mut reached: HashSet<Tile> = HashSet::new();
+    let mut queue: VecDeque<Tile> = VecDeque::new();
+    for i in 0..50u8 {
+        for &tile in &[(i, 0), (i, 49), (0, i), (49, i)] {
+            if walkable(tile) && reached.insert(tile) {
+                queue.push_back(tile);
+            }
+        }
+    }
+    while let Some(tile) = queue.pop_front() {
+        for next in neighbours(tile) {
+            if walkable(next) && reached.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    let mut perimeter: Vec<Tile> = ramparts
+        .into_iter()
+        .filter(|&rampart| neighbours(rampart).any(|n| reached.contains(&n)))
+        .collect();
+    perimeter.sort_unstable();
+    perimeter
+}
+
+fn perimeter(room: &Room, ctx: &TickContext) -> Vec<Tile> {
+    let time = ctx.time;
+    PERIMETERS.with(|p| {
+        let mut p = p.borrow_mut();
+        let fresh = p.get(&room.name()).map_or(false, |cached| {
+            time.saturating_sub(cached.time) < PERIMETER_TTL
+        });
+        if !fresh {
+            let ramparts = find_perimeter(room, ctx);
+            debug!(
+                "room {} has {} perimeter ramparts",
+                room.name(),
+                ramparts.len()
+            );
+            p.insert(room.name(), Perimeter { time, ramparts });
+        }
+        p[&room.name()].ramparts.clone()
+    })
+}
+
+/// Requests rampart defenders for the rooms which need them, and hands out the ramparts to hold.
+pub fn run(ctx: &TickContext) {
+    let time = ctx.time;
+    let mut defenders: HashMap<RoomName, Vec<Creep>> = HashMap::new();
+    for creep in screeps::game::creeps::values() {
+        let role = spawning::role_of(&creep);
+        if role == Role::RampartDefender || role == Role::BoostedDefender {
+            if let Some(home) = spawning::home_room(&creep) {
+                defenders.entry(home).or_default().push(creep);
+            }
+        }
+    }
+
+    let mut stations = HashMap::new();
+    let mut boosting = HashMap::new();
+    for room in screeps::game::rooms::values() {
+        if !room.controller().map_or(false, |c| c.my()) {
+            continue;
+        }
+        let assessment = match threat::get(room.name()) {
+            Some(assessment) if assessment.level >= Level::Medium && assessment.attack > 0 => {
+                assessment
+            }
+            _ => continue,
+        };
+        let perimeter = perimeter(&room, ctx);
+        if perimeter.is_empty() {
+            continue;
+        }
+        let room_defenders = defenders.remove(&room.name()).unwrap_or_default();
+
+        let boosts = if assessment.level == Level::Critical {
+            let boosts = decide_boosts(&room, &assessment, ctx);
+            boosting.insert(room.name(), boosts.clone());
+            boosts
+        } else {
+            Vec::new()
+        };
+        if time % REQUEST_INTERVAL == 0 {
+            let wanted = ((assessment.attack + PARTS_PER_RAMPART_DEFENDER - 1)
+                / PARTS_PER_RAMPART_DEFENDER)
+                .min(MAX_RAMPART_DEFENDERS)
+                .min(perimeter.len() as u32);
+            let role = if boosts.is_empty() {
+                Role::RampartDefender
+            } else {
+                Role::BoostedDefender
+            };
+            request_defenders(&room, role, wanted, room_defenders.len() as u32, ctx);
+        }
+        assign(&room, &perimeter, &room_defenders, &mut stations, ctx);
+    }
+    STATIONS.with(|s| *s.borrow_mut() = stations);
+    BOOSTING.with(|b| *b.borrow_mut() = boosting);
+}
+
+fn compound_name(compound: ResourceType) -> &'static str {
+    match compound {
+        ResourceType::CatalyzedUtriumAcid => "XUH2O",
+        ResourceType::CatalyzedLemergiumAlkalide => "XLHO2",
+        ResourceType::CatalyzedGhodiumAlkalide => "XGHO2",
+        _ => "?",
+    }
+}
+
+/// The lab holding the most of a compound, if it holds enough of it and of energy to boost
+/// `parts` parts.
+fn lab_with(
+    room: &Room,
+    compound: ResourceType,
+    parts: u32,
+    ctx: &TickContext,
+) -> Option<StructureLab> {
+    ctx.rooms
+        .snapshot(room)
+        .my_structures(StructureType::Lab)
+        .filter_map(|s| match s {
+            Structure::Lab(lab) if lab.mineral_type() == Some(compound) => Some(lab),
+            _ => None,
+        })
+        .filter(|lab| {
+            lab.store_used_capacity(Some(compound)) >= parts * BOOST_COMPOUND_PER_PART
+                && lab.store_used_capacity(Some(ResourceType::Energy))
+                    >= parts * BOOST_ENERGY_PER_PART
+        })
+        .max_by_key(|lab| lab.store_used_capacity(Some(compound)))
+        .cloned()
+}
+
+/// The compounds the room's defenders are to be boosted with against a critical threat, logging
+/// the decision and why whenever it changes.
+fn decide_boosts(room: &Room, assessment: &Assessment, ctx: &TickContext) -> Vec<ResourceType> {
+    let role = Role::BoostedDefender;
+    let size = spawning::affordable_size(role, &*ctx.rooms.snapshot(room));
+    let parts = assessment.fighting_parts();
+    let worth_it = assessment.boosted || parts >= BOOST_MIN_PARTS;
+    let mut boosts = Vec::new();
+    let mut stock = Vec::new();
+    for &(compound, part) in BOOSTS {
+        let needed = role.body().iter().filter(|&&p| p == part).count() as u32 * size;
+        let held = lab_with(room, compound, 0, ctx)
+            .map_or(0, |lab| lab.store_used_capacity(Some(compound)));
+        stock.push(format!(
+            "{} {}/{}",
+            compound_name(compound),
+            held,
+            needed * BOOST_COMPOUND_PER_PART
+        ));
+        if worth_it && lab_with(room, compound, needed, ctx).is_some() {
+            boosts.push(compound);
+        }
+    }
+
+    let previous = BOOSTING.with(|b| b.borrow().get(&room.name()).cloned());
+    if previous.as_ref() != Some(&boosts) {
+        let reason = format!(
+            "{} {}attackers with {} fighting parts, {} needed unboosted, labs hold {}",
+            assessment.level.name(),
+            if assessment.boosted { "boosted " } else { "" },
+            parts,
+            BOOST_MIN_PARTS,
+            stock.join(", ")
+        );
+        if boosts.is_empty() {
+            info!("not boosting defenders in room {}: {}", room.name(), reason);
+        } else {
+            let names: Vec<&str> = boosts.iter().map(|&c| compound_name(c)).collect();
+            warn!(
+                "boosting defenders in room {} with {}: {}",
+                room.name(),
+                names.join(", "),
+                reason
+            );
+        }
+    }
+    boosts
+}
+
+fn request_defenders(room: &Room, role: Role, wanted: u32, alive: u32, ctx: &TickContext) {
+    let queued = spawning::queue()
+        .iter()
+        .filter(|r| {
+            (r.role == Role::RampartDefender || r.role == Role::BoostedDefender)
+                && r.room_name == room.name()
+        })
+        .count() as u32;
+    let size = spawning::affordable_size(role, &*ctx.rooms.snapshot(room));
+    for _ in (alive + queued)..wanted {
+        info!("requesting a {} for room {}", role.name(), room.name());
+        spawning::request(SpawnRequest {
+            room_name: room.name(),
+            role,
+            priority: RAMPART_DEFENDER_PRIORITY,
+            work_room: None,
+            size,
+        });
+    }
+}
+
+/// Hands out the perimeter ramparts closest to the hostiles, keeping defenders which already
+/// stand on one of them where they are.
+fn assign(
+    room: &Room,
+    perimeter: &[Tile],
+    defenders: &[Creep],
+    stations: &mut HashMap<String, Tile>,
+    ctx: &TickContext,
+) {
+    let hostiles: Vec<Position> = ctx
+        .rooms
+        .snapshot(room)
+        .hostiles()
+        .iter()
+        .map(|h| h.pos())
+        .collect();
+    let distance = |&(x, y): &Tile| {
+        let pos = Position::new(x as u32, y as u32, room.name());
+        hostiles
+            .iter()
+            .map(|h| pos.get_range_to(h))
+            .min()
+            .unwrap_or(u32::MAX)
+    };
+    let mut wanted: Vec<Tile> = perimeter.to_vec();
+    wanted.sort_by_key(|tile| (distance(tile), *tile));
+    wanted.truncate(defenders.len());
+
+    let mut free: Vec<&Creep> = Vec::new();
+    for creep in defenders.iter().filter(|c| !c.spawning()) {
+        let tile = (creep.pos().x() as u8, creep.pos().y() as u8);
+        match wanted.iter().position(|&w| w == tile) {
+            Some(index) if creep.pos().room_name() == room.name() => {
+                stations.insert(creep.name(), wanted.remove(index));
+            }
+            _ => free.push(creep),
+        }
+    }
+    for tile in wanted {
+        let pos = Position::new(tile.0 as u32, tile.1 as u32, room.name());
+        let closest = free
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| c.pos().get_range_to(&pos))
+            .map(|(i, _)| i);
+        if let Some(i) = closest {
+            stations.insert(free.remove(i).name(), tile);
+        }
+    }
+}
+
+/// Takes a rampart defender to the rampart it's to hold and hits whatever comes next to it,
+/// returning whether that's all it does this tick. Boosted defenders are pointed at the labs
+/// first, and get there like any other creep.
+pub fn run_rampart_defender(creep: &Creep, ctx: &TickContext) -> bool {
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return true,
+    };
+    let pos = creep.pos();
+    let snapshot = ctx.rooms.snapshot(&room);
+    let target = snapshot
+        .hostiles()
+        .iter()
+        .filter(|h| pos.is_near_to(*h))
+        .min_by_key(|h| Attackable::hits(*h));
+    if let Some(target) = target {
+        let r = creep.attack(target);
+        if r != screeps::ReturnCode::Ok {
+            failures::report(&creep.name(), "attack", r);
+        }
+    }
+
+    if let Some(CreepTarget::Boost(_)) = creeps::current_target(&creep.name()) {
+        return false;
+    }
+    if let Some(lab) = next_boost(creep, &room, ctx) {
+        creeps::set_target(&creep.name(), CreepTarget::Boost(lab.id()));
+        return false;
+    }
+
+    let station = match STATIONS.with(|s| s.borrow().get(&creep.name()).copied()) {
+        Some(station) => station,
+        None => return true,
+    };
+    let station_pos = Position::new(station.0 as u32, station.1 as u32, room.name());
+    if pos == station_pos {
+        // stations can be in the core, which idle creeps get pushed out of
+        movement::hold(creep, &station_pos, 0);
+        return true;
+    }
+    let ramparts: HashSet<Tile> = snapshot
+        .my_structures(StructureType::Rampart)
+        .map(|s| (s.pos().x() as u8, s.pos().y() as u8))
+        .collect();
+    let here = (pos.x() as u8, pos.y() as u8);
+    let holding = pos.room_name() == room.name()
+        && ramparts.contains(&here)
+        && threat::level(room.name()) >= Level::Medium;
+    if !holding {
+        movement::move_creep_to(creep, &station_pos, 0);
+        return true;
+    }
+    match step_along(here, station, &ramparts) {
+        Some(next) => {
+            let next = Position::new(next.0 as u32, next.1 as u32, room.name());
+            if let Some(direction) = pos.get_direction_to(&next) {
+                if creep.move_direction(direction) == screeps::ReturnCode::Ok {
+                    movement::intents::register(creep.name(), pos, next);
+                }
+            }
+        }
+        None => debug!(
+            "{} can't reach {:?} along the ramparts, holding",
+            creep.name(),
+            station
+        ),
+    }
+    true
+}
+
+/// The lab a boosted defender is to get its next boost from, if its room is boosting and it
+/// still has parts the boost is for.
+fn next_boost(creep: &Creep, room: &Room, ctx: &TickContext) -> Option<StructureLab> {
+    if spawning::role_of(creep) != Role::BoostedDefender {
+        return None;
+    }
+    let boosts = BOOSTING.with(|b| b.borrow().get(&room.name()).cloned())?;
+    let body = creep.body();
+    boosts.into_iter().find_map(|compound| {
+        let part = BOOSTS.iter().find(|(c, _)| *c == compound)?.1;
+        let parts = body
+            .iter()
+            .filter(|p| p.part == part && p.boost.is_none())
+            .count() as u32;
+        if parts == 0 {
+            return None;
+        }
+        lab_with(room, compound, parts, ctx)
+    })
+}
+
+fn is_pressed(pos: Position, snapshot: &RoomSnapshot) -> bool {
+    snapshot
+        .hostiles()
+        .iter()
+        .any(|h| h.pos().in_range_to(&pos, PRESSED_RANGE))
+}
+
+/// The weakest rampart next to the attackers which isn't at full hits, while a room's threat is
+/// critical.
+pub fn rampart_to_hold(room_name: RoomName, snapshot: &RoomSnapshot) -> Option<&Structure> {
+    if threat::level(room_name) < Level::Critical {
+        return None;
+    }
+    snapshot
+        .my_structures(StructureType::Rampart)
+        .filter_map(|s| match s {
+            Structure::Rampart(rampart)
+                if rampart.hits() < rampart.hits_max() && is_pressed(s.pos(), snapshot) =>
+            {
+                Some((rampart.hits(), s))
+            }
+            _ => None,
+        })
+        .min_by_key(|(hits, _)| *hits)
+        .map(|(_, s)| s)
+}
+
+/// How many hits the rampart on a tile is to have to hold off the attackers: all of them if
+/// they're next to it while the threat is critical, and none otherwise.
+pub fn rampart_goal(pos: Position, ctx: &TickContext) -> u32 {
+    if threat::level(pos.room_name()) < Level::Critical {
+        return 0;
+    }
+    match screeps::game::rooms::get(pos.room_name()) {
+        Some(room) if is_pressed(pos, &ctx.rooms.snapshot(&room)) => u32::MAX,
+        _ => 0,
+    }
+}
+
+/// The first step from one rampart to another without leaving the ramparts.
+fn step_along(from: Tile, to: Tile, ramparts: &HashSet<Tile>) -> Option<Tile> {
+    let mut came_from: HashMap<Tile, Tile> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+    came_from.insert(from, from);
+    while let Some(tile) = queue.pop_front() {
+        if tile == to {
+            let mut step = tile;
+            while came_from[&step] != from {
+                step = came_from[&step];
+            }
+            return Some(step);
+        }
+        for next in neighbours(tile) {
+            if ramparts.contains(&next) && !came_from.contains_key(&next) {
+                came_from.insert(next, tile);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![
+        PERIMETERS.with(|p| {
+            CacheSize::of_map("defense.perimeters", &p.borrow(), |_, perimeter| {
+                perimeter.ramparts.capacity() * std::mem::size_of::<Tile>()
+            })
+        }),
+        STATIONS.with(|s| {
+            CacheSize::of_map("defense.stations", &s.borrow(), |name, _| name.capacity())
+        }),
+        BOOSTING.with(|b| {
+            CacheSize::of_map("defense.boosting", &b.borrow(), |_, boosts| {
+                boosts.capacity() * std::mem::size_of::<ResourceType>()
+            })
+        }),
+    ]
+}
+
+/// Forgets the perimeters, which are flood filled again when they're needed.
+pub fn purge() {
+    PERIMETERS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+}
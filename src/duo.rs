@@ -0,0 +1,206 @@
+//! Attackers paired with healers, for hostiles a lone defender can't out-heal.
+//!
+//! A duo attacker and a duo healer working in the same room pair up as soon as both are alive,
+//! each keeping the other's name in its memory as `partner`, so pairs survive a reset. The
+//! attacker leads and waits for its healer whenever it falls behind, and the healer follows it,
+//! healing it, or itself when it's the one hurt, every tick. Once the attacker drops below
+//! [`RETREAT_SHARE`] of its hits the pair falls back to a rampart of ours in the room, or towards
+//! home where there's none, until it's healed up to [`RESUME_SHARE`].
+//!
+//! A creep whose partner died pairs up again with whoever's free, and waits at home while its
+//! new partner is still queued. An attacker with nobody left fights on like a plain defender,
+//! and a healer with nobody left goes home.
+
+use log::*;
+use screeps::{find, prelude::*, Attackable, Creep, ReturnCode, RoomName, StructureType};
+
+use crate::{
+    context::TickContext,
+    failures, movement, remotes,
+    spawning::{self, Role},
+};
+
+const PARTNER_KEY: &str = "partner";
+/// Set in an attacker's memory while the pair is falling back.
+const RETREATING_KEY: &str = "retreating";
+
+/// The share of its hits an attacker falls back at.
+pub const RETREAT_SHARE: f64 = 0.5;
+
+/// The share of its hits an attacker goes back in at.
+pub const RESUME_SHARE: f64 = 0.9;
+
+/// How far healers heal from, with ranged heals.
+const RANGED_HEAL_RANGE: u32 = 3;
+
+fn hits_share(creep: &Creep) -> f64 {
+    Attackable::hits(creep) as f64 / Attackable::hits_max(creep).max(1) as f64
+}
+
+fn partner_role(role: Role) -> Role {
+    if role == Role::DuoAttacker {
+        Role::DuoHealer
+    } else {
+        Role::DuoAttacker
+    }
+}
+
+/// A creep's partner, pairing it up with a free creep of the other role if it has none.
+fn partner(creep: &Creep, role: Role, work_room: RoomName) -> Option<Creep> {
+    let memory = creep.memory();
+    if let Some(name) = memory.string(PARTNER_KEY).ok().flatten() {
+        match screeps::game::creeps::get(&name) {
+            Some(partner) => return Some(partner),
+            None => {
+                info!("{} lost its partner {}", creep.name(), name);
+                memory.del(PARTNER_KEY);
+            }
+        }
+    }
+
+    let wanted = partner_role(role);
+    let free = screeps::game::creeps::values().into_iter().find(|other| {
+        spawning::role_of(other) == wanted
+            && spawning::work_room(other) == Some(work_room)
+            && other.memory().string(PARTNER_KEY).ok().flatten().is_none()
+    })?;
+    memory.set(PARTNER_KEY, free.name());
+    free.memory().set(PARTNER_KEY, creep.name());
+    info!("paired {} with {}", creep.name(), free.name());
+    Some(free)
+}
+
+/// Whether a partner for the creep is still to be spawned.
+fn partner_queued(role: Role, work_room: RoomName) -> bool {
+    let wanted = partner_role(role);
+    spawning::queue()
+        .iter()
+        .any(|r| r.role == wanted && r.work_room == Some(work_room))
+}
+
+/// Runs a duo attacker or healer working in `work_room`.
+pub fn run_creep(
+    creep: &Creep,
+    role: Role,
+    home: RoomName,
+    work_room: RoomName,
+    ctx: &TickContext,
+) {
+    let partner = partner(creep, role, work_room).filter(|p| !p.spawning());
+    match (role, partner) {
+        (Role::DuoAttacker, Some(healer)) => run_attacker(creep, &healer, home, work_room, ctx),
+        (Role::DuoHealer, Some(attacker)) => run_healer(creep, &attacker),
+        _ if partner_queued(role, work_room) => {
+            movement::move_to_room(creep, home);
+        }
+        (Role::DuoAttacker, None) => remotes::run_defender(creep, work_room, ctx),
+        _ => {
+            heal_self(creep);
+            movement::move_to_room(creep, home);
+        }
+    }
+}
+
+fn run_attacker(
+    creep: &Creep,
+    healer: &Creep,
+    home: RoomName,
+    work_room: RoomName,
+    ctx: &TickContext,
+) {
+    let memory = creep.memory();
+    let share = hits_share(creep);
+    let retreating = memory.bool(RETREATING_KEY);
+    if !retreating && share < RETREAT_SHARE {
+        debug!("{} is falling back to be healed", creep.name());
+        memory.set(RETREATING_KEY, true);
+    } else if retreating && share >= RESUME_SHARE {
+        memory.del(RETREATING_KEY);
+    }
+
+    let pos = creep.pos();
+    let room = creep.room();
+    let hostile = room.as_ref().and_then(|room| {
+        ctx.rooms
+            .snapshot(room)
+            .hostiles()
+            .iter()
+            .min_by_key(|hostile| pos.get_range_to(*hostile))
+            .cloned()
+    });
+    if let Some(hostile) = hostile.as_ref().filter(|h| pos.is_near_to(*h)) {
+        let r = creep.attack(hostile);
+        if r != ReturnCode::Ok {
+            failures::report(&creep.name(), "attack", r);
+        }
+    }
+
+    if memory.bool(RETREATING_KEY) {
+        let rampart = room.as_ref().and_then(|room| {
+            room.find(find::MY_STRUCTURES)
+                .into_iter()
+                .filter(|s| s.structure_type() == StructureType::Rampart)
+                .min_by_key(|s| pos.get_range_to(s))
+        });
+        match rampart {
+            Some(rampart) => {
+                movement::move_creep_to(creep, &rampart, 0);
+            }
+            None => {
+                movement::move_to_room(creep, home);
+            }
+        }
+        return;
+    }
+
+    // the healer can't keep up across room edges, so the attacker waits for it
+    if pos.get_range_to(healer) > 1 && pos.room_name() == healer.pos().room_name() {
+        return;
+    }
+    if pos.room_name() != work_room {
+        movement::move_to_room(creep, work_room);
+        return;
+    }
+    match hostile {
+        Some(hostile) => {
+            movement::move_creep_to(creep, &hostile, 1);
+        }
+        None => {
+            movement::move_to_room(creep, work_room);
+        }
+    }
+}
+
+fn run_healer(creep: &Creep, attacker: &Creep) {
+    let pos = creep.pos();
+    let range = if pos.room_name() == attacker.pos().room_name() {
+        pos.get_range_to(attacker)
+    } else {
+        u32::MAX
+    };
+    let attacker_hurt = Attackable::hits(attacker) < Attackable::hits_max(attacker);
+    let self_hurt = hits_share(creep) < hits_share(attacker);
+    // heals go on the attacker even at full hits, so they land on the tick it's hit
+    let r = if self_hurt || range > RANGED_HEAL_RANGE {
+        creep.heal(creep)
+    } else if range <= 1 {
+        creep.heal(attacker)
+    } else if attacker_hurt {
+        creep.ranged_heal(attacker)
+    } else {
+        ReturnCode::Ok
+    };
+    if r != ReturnCode::Ok {
+        failures::report(&creep.name(), "heal", r);
+    }
+
+    if range > 1 {
+        movement::move_creep_to(creep, attacker, 1);
+    }
+}
+
+fn heal_self(creep: &Creep) {
+    if Attackable::hits(creep) < Attackable::hits_max(creep) {
+        creep.heal(creep);
+    }
+}
@@ -0,0 +1,195 @@
+//! The big red buttons: safe mode and giving up a room, from the console.
+//!
+//! `activate_safe_mode(room)` checks the controller can go into safe mode before it does. The
+//! threat assessment does the same when one of our rooms turns critical.
+//!
+//! `abandon_room(room)` on its own only prints a confirmation token, valid for
+//! [`CONFIRM_TICKS`] ticks. `abandon_room(room, token)` then unclaims the controller, marks the
+//! room abandoned in `Memory.rooms.<name>.abandoned` so it isn't planned or spawned in any more,
+//! and sends the creeps in it to the nearest room we keep. Doing it takes two calls on purpose,
+//! so a typo can't unclaim the main room.
+//!
+//! Both log an error and send a notification, so there's a record of who pressed what.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{prelude::*, ReturnCode, Room, RoomName};
+
+use crate::{creeps, events, id, planner};
+
+/// How long a confirmation token for abandoning a room stays valid.
+pub const CONFIRM_TICKS: u32 = 100;
+
+const ABANDONED_KEY: &str = "abandoned";
+
+thread_local! {
+    /// Confirmation tokens handed out for abandoning a room, with the tick they expire at.
+    static TOKENS: RefCell<HashMap<RoomName, (String, u32)>> = RefCell::new(HashMap::new());
+}
+
+/// Puts an owned room into safe mode, if it has one available and isn't cooling down. `reason`
+/// says who asked, like "from the console".
+pub fn activate_safe_mode(room: &Room, reason: &str) -> Result<(), String> {
+    let controller = match room.controller() {
+        Some(controller) if controller.my() => controller,
+        _ => return Err(format!("room {} isn't ours", room.name())),
+    };
+    if let Some(ticks) = controller.safe_mode() {
+        return Err(format!(
+            "room {} is already in safe mode for {} ticks",
+            room.name(),
+            ticks
+        ));
+    }
+    if controller.safe_mode_available() == 0 {
+        return Err(format!("room {} has no safe modes available", room.name()));
+    }
+    if let Some(ticks) = controller.safe_mode_cooldown() {
+        return Err(format!(
+            "safe mode in room {} is cooling down for {} ticks",
+            room.name(),
+            ticks
+        ));
+    }
+    if let Some(ticks) = controller.upgrade_blocked() {
+        return Err(format!(
+            "the controller in room {} is blocked for {} ticks",
+            room.name(),
+            ticks
+        ));
+    }
+
+    let r = controller.activate_safe_mode();
+    if r != ReturnCode::Ok {
+        return Err(format!(
+            "couldn't activate safe mode in room {}: {:?}",
+            room.name(),
+            r
+        ));
+    }
+    let message = format!("activated safe mode in room {} {}", room.name(), reason);
+    error!("{}", message);
+    events::notify(&message);
+    Ok(())
+}
+
+/// Hands out the token [`abandon`] needs to abandon an owned room.
+pub fn abandon_token(room: &Room) -> Result<String, String> {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return Err(format!("room {} isn't ours", room.name())),
+    }
+    let token = id::short_id();
+    let until = screeps::game::time() + CONFIRM_TICKS;
+    TOKENS.with(|tokens| {
+        tokens
+            .borrow_mut()
+            .insert(room.name(), (token.clone(), until))
+    });
+    Ok(token)
+}
+
+/// Unclaims an owned room, returning how many creeps were sent away from it.
+pub fn abandon(room: &Room, token: &str) -> Result<usize, String> {
+    let time = screeps::game::time();
+    let expected = TOKENS.with(|tokens| tokens.borrow_mut().remove(&room.name()));
+    match expected {
+        Some((expected, until)) if expected == token && time < until => {}
+        Some((_, until)) if time >= until => {
+            return Err(format!(
+                "the token for room {} expired, call abandon_room(\"{}\") for a new one",
+                room.name(),
+                room.name()
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "wrong token for room {}, call abandon_room(\"{}\") for a new one",
+                room.name(),
+                room.name()
+            ))
+        }
+    }
+    let controller = match room.controller() {
+        Some(controller) if controller.my() => controller,
+        _ => return Err(format!("room {} isn't ours", room.name())),
+    };
+
+    let r = controller.unclaim();
+    if r != ReturnCode::Ok {
+        return Err(format!("couldn't unclaim room {}: {:?}", room.name(), r));
+    }
+    if let Some(memory) = planner::room_memory(room.name()) {
+        memory.set(ABANDONED_KEY, time);
+    }
+
+    let home = nearest_owned_room(room.name());
+    let mut moved = 0;
+    for creep in screeps::game::creeps::values() {
+        if creep.spawning() || creep.pos().room_name() != room.name() {
+            continue;
+        }
+        if let Some(home) = home {
+            creeps::set_target(&creep.name(), creeps::CreepTarget::Rebase(home));
+            moved += 1;
+        }
+    }
+
+    let message = match home {
+        Some(home) => format!(
+            "abandoned room {} from the console, sending {} creeps to {}",
+            room.name(),
+            moved,
+            home
+        ),
+        None => format!(
+            "abandoned room {} from the console, there's no other room for its creeps",
+            room.name()
+        ),
+    };
+    error!("{}", message);
+    events::notify(&message);
+    Ok(moved)
+}
+
+/// Whether a room was abandoned from the console.
+pub fn is_abandoned(room_name: RoomName) -> bool {
+    planner::room_memory(room_name)
+        .and_then(|memory| memory.i32(ABANDONED_KEY).ok().flatten())
+        .is_some()
+}
+
+/// Drops the mark of abandoned rooms which have been claimed again since.
+pub fn forget_reclaimed() {
+    let time = screeps::game::time();
+    for room in screeps::game::rooms::values() {
+        if !room.controller().map_or(false, |c| c.my()) {
+            continue;
+        }
+        let memory = match planner::room_memory(room.name()) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        match memory.i32(ABANDONED_KEY).ok().flatten() {
+            Some(abandoned) if (abandoned as u32) < time => {
+                info!(
+                    "room {} was claimed again after being abandoned",
+                    room.name()
+                );
+                memory.del(ABANDONED_KEY);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The closest other room we own, by linear distance.
+fn nearest_owned_room(from: RoomName) -> Option<RoomName> {
+    screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.name() != from && !is_abandoned(room.name()))
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .map(|room| room.name())
+        .min_by_key(|&name| screeps::game::map::get_room_linear_distance(from, name, false))
+}
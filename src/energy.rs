@@ -0,0 +1,237 @@
+//! Where each room's energy comes from and goes.
+//!
+//! Creep actions which went through are counted here as they happen: energy harvested from each
+//! source, delivered into spawns, extensions and storage, and spent building, repairing and
+//! upgrading, along with what spawning creeps cost. Amounts are worked out from the creep's work
+//! parts and what it carries rather than read back, which is cheap but leaves out boosts.
+//!
+//! Every [`REPORT_INTERVAL`] ticks each room's income and spending per tick are logged and
+//! written to `Memory.stats.energy`, along with how much of what each source regenerates was
+//! harvested, which is only logged at debug level. The counts start over with every report, and
+//! after a reset.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{prelude::*, Creep, ObjectId, Part, ResourceType, RoomName, Source, Structure};
+
+use crate::heap::CacheSize;
+
+/// How many ticks of counts go into each report.
+pub const REPORT_INTERVAL: u32 = 500;
+
+const STATS_PATH: &str = "stats.energy";
+
+/// How long a source takes to regenerate.
+const REGEN_TICKS: u32 = 300;
+
+/// Energy harvested per work part and tick.
+const HARVEST_POWER: u32 = 2;
+
+/// Energy built per work part and tick.
+const BUILD_POWER: u32 = 5;
+
+/// What went through a room since the last report.
+#[derive(Default)]
+struct Flow {
+    harvested: u32,
+    delivered: u32,
+    spawning: u32,
+    building: u32,
+    repairing: u32,
+    upgrading: u32,
+}
+
+impl Flow {
+    fn spent(&self) -> u32 {
+        self.spawning + self.building + self.repairing + self.upgrading
+    }
+}
+
+/// What was harvested from a source since the last report.
+struct Harvest {
+    room_name: RoomName,
+    x: u32,
+    y: u32,
+    /// How much the source regenerates to.
+    capacity: u32,
+    harvested: u32,
+}
+
+struct Window {
+    start: u32,
+    rooms: HashMap<RoomName, Flow>,
+    sources: HashMap<ObjectId<Source>, Harvest>,
+}
+
+thread_local! {
+    static WINDOW: RefCell<Option<Window>> = RefCell::new(None);
+}
+
+fn with_window(f: impl FnOnce(&mut Window)) {
+    WINDOW.with(|window| {
+        let mut window = window.borrow_mut();
+        let window = window.get_or_insert_with(|| Window {
+            start: screeps::game::time(),
+            rooms: HashMap::new(),
+            sources: HashMap::new(),
+        });
+        f(window)
+    });
+}
+
+fn add(room_name: RoomName, f: impl FnOnce(&mut Flow)) {
+    with_window(|window| f(window.rooms.entry(room_name).or_default()));
+}
+
+/// Room for energy in a spawn, extension or storage, the structures delivered energy counts for.
+pub fn free_capacity(structure: &Structure) -> u32 {
+    let free = match structure {
+        Structure::Storage(s) => s.store_free_capacity(Some(ResourceType::Energy)),
+        Structure::Spawn(s) => s.store_free_capacity(Some(ResourceType::Energy)),
+        Structure::Extension(s) => s.store_free_capacity(Some(ResourceType::Energy)),
+        _ => 0,
+    };
+    free.max(0) as u32
+}
+
+fn work_parts(creep: &Creep) -> u32 {
+    creep.get_active_bodyparts(Part::Work)
+}
+
+fn carried(creep: &Creep) -> u32 {
+    creep.store_used_capacity(Some(ResourceType::Energy))
+}
+
+/// Counts a harvest which went through, returning how much it was.
+pub fn record_harvest(creep: &Creep, source: &Source) -> u32 {
+    let amount = (work_parts(creep) * HARVEST_POWER).min(source.energy());
+    let pos = source.pos();
+    add(pos.room_name(), |flow| flow.harvested += amount);
+    with_window(|window| {
+        window
+            .sources
+            .entry(source.id())
+            .or_insert_with(|| Harvest {
+                room_name: pos.room_name(),
+                x: pos.x(),
+                y: pos.y(),
+                capacity: source.energy_capacity(),
+                harvested: 0,
+            })
+            .harvested += amount;
+    });
+    amount
+}
+
+/// Counts energy put into a spawn, extension or storage, returning how much it was.
+pub fn record_delivery(creep: &Creep, structure: &Structure) -> u32 {
+    let amount = carried(creep).min(free_capacity(structure));
+    if amount > 0 {
+        add(structure.pos().room_name(), |flow| flow.delivered += amount);
+    }
+    amount
+}
+
+pub fn record_build(creep: &Creep, room_name: RoomName) -> u32 {
+    let amount = (work_parts(creep) * BUILD_POWER).min(carried(creep));
+    add(room_name, |flow| flow.building += amount);
+    amount
+}
+
+pub fn record_repair(creep: &Creep, room_name: RoomName) -> u32 {
+    let amount = work_parts(creep).min(carried(creep));
+    add(room_name, |flow| flow.repairing += amount);
+    amount
+}
+
+pub fn record_upgrade(creep: &Creep, room_name: RoomName) -> u32 {
+    let amount = work_parts(creep).min(carried(creep));
+    add(room_name, |flow| flow.upgrading += amount);
+    amount
+}
+
+/// Counts the energy spent spawning a creep in a room.
+pub fn record_spawn(room_name: RoomName, cost: u32) {
+    add(room_name, |flow| flow.spawning += cost);
+}
+
+/// Logs each room's income and spending since the last report, stores them in `Memory.stats` and
+/// starts a new window.
+pub fn report() {
+    let window = match WINDOW.with(|w| w.borrow_mut().take()) {
+        Some(window) => window,
+        None => return,
+    };
+    let ticks = screeps::game::time().saturating_sub(window.start).max(1);
+    let per_tick = |amount: u32| amount as f64 / ticks as f64;
+
+    let memory = screeps::memory::root();
+    memory.path_del(STATS_PATH);
+    let mut rooms: Vec<(&RoomName, &Flow)> = window.rooms.iter().collect();
+    rooms.sort_by_key(|(room_name, _)| room_name.to_string());
+    info!("energy over the last {} ticks:", ticks);
+    for (room_name, flow) in rooms {
+        info!(
+            "  {}: {:.1}/tick harvested, {:.1} delivered, {:.1} spent ({:.1} spawning, {:.1} \
+             building, {:.1} repairing, {:.1} upgrading), {:+.1} net",
+            room_name,
+            per_tick(flow.harvested),
+            per_tick(flow.delivered),
+            per_tick(flow.spent()),
+            per_tick(flow.spawning),
+            per_tick(flow.building),
+            per_tick(flow.repairing),
+            per_tick(flow.upgrading),
+            per_tick(flow.harvested) - per_tick(flow.spent())
+        );
+        let path = format!("{}.{}", STATS_PATH, room_name);
+        for (key, amount) in &[
+            ("harvested", flow.harvested),
+            ("delivered", flow.delivered),
+            ("spawning", flow.spawning),
+            ("building", flow.building),
+            ("repairing", flow.repairing),
+            ("upgrading", flow.upgrading),
+        ] {
+            memory.path_set(&format!("{}.{}", path, key), per_tick(*amount));
+        }
+    }
+
+    let mut sources: Vec<&Harvest> = window.sources.values().collect();
+    sources.sort_by_key(|harvest| (harvest.room_name.to_string(), harvest.x, harvest.y));
+    for harvest in sources {
+        let rate = per_tick(harvest.harvested);
+        let regenerated = harvest.capacity as f64 / REGEN_TICKS as f64;
+        let share = if regenerated > 0.0 {
+            rate / regenerated
+        } else {
+            0.0
+        };
+        debug!(
+            "  source {},{} in {}: {:.1} of {:.1}/tick harvested, {:.0}%",
+            harvest.x,
+            harvest.y,
+            harvest.room_name,
+            rate,
+            regenerated,
+            share * 100.0
+        );
+        let path = format!(
+            "{}.{}.sources.{}_{}",
+            STATS_PATH, harvest.room_name, harvest.x, harvest.y
+        );
+        memory.path_set(&path, share);
+    }
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    WINDOW.with(|w| match &*w.borrow() {
+        Some(window) => vec![
+            CacheSize::of_map("energy.rooms", &window.rooms, |_, _| 0),
+            CacheSize::of_map("energy.sources", &window.sources, |_, _| 0),
+        ],
+        None => Vec::new(),
+    })
+}
@@ -0,0 +1,213 @@
+//! Notifications about events which matter whatever the log level.
+//!
+//! Every tick the rooms and spawns we own and the GCL level are compared with the last tick's,
+//! kept in `Memory.events`, and losing a room or a spawn, respawning and GCL level-ups are sent
+//! as game notifications right away. So is a player's creep showing up in one of our rooms, if
+//! none was seen there for [`ATTACK_QUIET_TICKS`] ticks.
+//!
+//! Each kind of event has its own cooldown, so a siege which keeps destroying spawns doesn't
+//! send one email per spawn.
+
+use std::collections::BTreeSet;
+
+use log::*;
+use screeps::{prelude::*, RoomName};
+use stdweb::js;
+
+use crate::{
+    context::TickContext,
+    emergency,
+    features::{self, Feature},
+    threat,
+};
+
+const EVENTS_KEY: &str = "events";
+
+/// How long a room has to be free of player creeps for the next one to be notified about.
+pub const ATTACK_QUIET_TICKS: u32 = 1500;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Event {
+    RoomLost,
+    SpawnLost,
+    Respawn,
+    Attack,
+    GclLevel,
+}
+
+impl Event {
+    fn key(self) -> &'static str {
+        match self {
+            Event::RoomLost => "room_lost",
+            Event::SpawnLost => "spawn_lost",
+            Event::Respawn => "respawn",
+            Event::Attack => "attack",
+            Event::GclLevel => "gcl_level",
+        }
+    }
+
+    /// How many ticks after a notification the next one of the same kind is held back.
+    fn cooldown(self) -> u32 {
+        match self {
+            Event::RoomLost | Event::SpawnLost => 100,
+            Event::Respawn | Event::GclLevel => 0,
+            Event::Attack => 300,
+        }
+    }
+}
+
+/// Compares with the last tick's state and notifies about what changed.
+pub fn run(ctx: &TickContext) {
+    let memory = match screeps::memory::root().dict_or_create(EVENTS_KEY) {
+        Ok(memory) => memory,
+        Err(e) => {
+            warn!("couldn't read the event state: {}", e);
+            return;
+        }
+    };
+    let time = ctx.time;
+    // nothing to compare with on the first tick
+    let known = memory.string("rooms").ok().flatten().is_some();
+
+    let rooms: BTreeSet<String> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .map(|room| room.name().to_string())
+        .collect();
+    let spawns: BTreeSet<String> = screeps::game::spawns::keys().into_iter().collect();
+    let gcl = screeps::game::gcl::level();
+
+    let mut events: Vec<(Event, String)> = Vec::new();
+    if known {
+        let last_rooms = read_set(&memory, "rooms");
+        let last_spawns = read_set(&memory, "spawns");
+        // rooms abandoned from the console have already been notified about
+        let lost = last_rooms.difference(&rooms).filter(|room| {
+            RoomName::new(room.as_str()).map_or(true, |name| !emergency::is_abandoned(name))
+        });
+        for room in lost {
+            events.push((Event::RoomLost, format!("lost room {}", room)));
+        }
+        for spawn in last_spawns.difference(&spawns) {
+            events.push((Event::SpawnLost, format!("spawn {} was destroyed", spawn)));
+        }
+        if last_rooms.is_empty() && !rooms.is_empty() {
+            let names: Vec<&str> = rooms.iter().map(String::as_str).collect();
+            events.push((Event::Respawn, format!("respawned in {}", names.join(", "))));
+        }
+        let last_gcl = memory.i32("gcl").ok().flatten().unwrap_or(0) as u32;
+        if last_gcl > 0 && gcl > last_gcl {
+            events.push((Event::GclLevel, format!("reached gcl {}", gcl)));
+        }
+    }
+    for room in &rooms {
+        if let Some(event) = check_attack(&memory, room, time, ctx) {
+            events.push((Event::Attack, event));
+        }
+    }
+
+    memory.set("rooms", rooms.iter().cloned().collect::<Vec<_>>().join(","));
+    memory.set(
+        "spawns",
+        spawns.iter().cloned().collect::<Vec<_>>().join(","),
+    );
+    memory.set("gcl", gcl);
+
+    for kind in &[
+        Event::RoomLost,
+        Event::SpawnLost,
+        Event::Respawn,
+        Event::Attack,
+        Event::GclLevel,
+    ] {
+        let messages: Vec<&str> = events
+            .iter()
+            .filter(|(event, _)| event == kind)
+            .map(|(_, message)| message.as_str())
+            .collect();
+        if !messages.is_empty() {
+            send(&memory, *kind, &messages.join("; "), time);
+        }
+    }
+}
+
+fn read_set(memory: &screeps::memory::MemoryReference, key: &str) -> BTreeSet<String> {
+    memory
+        .string(key)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Describes the player creeps in one of our rooms, if they're the first seen there in a while.
+fn check_attack(
+    memory: &screeps::memory::MemoryReference,
+    room_name: &str,
+    time: u32,
+    ctx: &TickContext,
+) -> Option<String> {
+    let room = screeps::game::rooms::get(RoomName::new(room_name).ok()?)?;
+    let snapshot = ctx.rooms.snapshot(&room);
+    let owners: BTreeSet<String> = snapshot
+        .hostiles()
+        .iter()
+        .map(|c| c.owner_name())
+        .filter(|owner| !threat::NPC_OWNERS.contains(&owner.as_str()))
+        .collect();
+    if owners.is_empty() {
+        return None;
+    }
+
+    let seen = memory.dict_or_create("attacked").ok()?;
+    let last_seen = seen.i32(room_name).ok().flatten().map(|t| t as u32);
+    seen.set(room_name, time);
+    match last_seen {
+        Some(last) if time.saturating_sub(last) < ATTACK_QUIET_TICKS => None,
+        _ => {
+            let owners: Vec<&str> = owners.iter().map(String::as_str).collect();
+            Some(format!(
+                "{} creeps entered room {}",
+                owners.join(", "),
+                room_name
+            ))
+        }
+    }
+}
+
+/// Sends a notification unless one of the same kind was sent within its cooldown.
+fn send(memory: &screeps::memory::MemoryReference, event: Event, message: &str, time: u32) {
+    let sent = match memory.dict_or_create("sent") {
+        Ok(sent) => sent,
+        Err(_) => return,
+    };
+    let last = sent.i32(event.key()).ok().flatten().map(|t| t as u32);
+    info!("{}", message);
+    if let Some(last) = last {
+        if time.saturating_sub(last) < event.cooldown() {
+            return;
+        }
+    }
+    sent.set(event.key(), time);
+    notify(message);
+}
+
+/// Sends a notification right away, prefixed with the shard and tick like the logged ones.
+pub fn notify(message: &str) {
+    let text = format!(
+        "[{} {}] {}",
+        screeps::game::shards::name(),
+        screeps::game::time(),
+        message
+    );
+    if !features::has(Feature::Notify) {
+        info!("notification: {}", text);
+        return;
+    }
+    js! {
+        Game.notify(@{text});
+    }
+}
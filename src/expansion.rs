@@ -0,0 +1,600 @@
+//! Picking rooms to expand into, and claiming them.
+//!
+//! Every [`SCORE_INTERVAL`] ticks each room intel knows of with a free controller is scored on
+//! what it would be like to live in: two sources, minerals we don't have yet in it or next to it,
+//! being [`MIN_DISTANCE`] to [`MAX_DISTANCE`] rooms from our closest one, little swamp around the
+//! sources and no strong neighbours, with hostile and source keeper neighbours counting against
+//! it. The best few are logged, kept in `Memory.expansion.candidates` and shaded on the map.
+//!
+//! While the GCL allows another room, the best candidate becomes `Memory.expansion.target` and a
+//! claimer is spawned for it from the closest room which can afford one. A flag named `claim`, or
+//! starting with `claim:`, always takes precedence over the scores; it's removed once the room
+//! under it is ours.
+
+use std::collections::HashSet;
+
+use log::*;
+use screeps::{prelude::*, Creep, ReturnCode, RoomName, Terrain};
+
+use crate::{
+    bootstrap, emergency, failures, intel, movement,
+    spawning::{self, Role, SpawnRequest},
+};
+
+/// How often the candidates are scored.
+pub const SCORE_INTERVAL: u32 = 500;
+
+const TARGET_PATH: &str = "expansion.target";
+const CANDIDATES_PATH: &str = "expansion.candidates";
+
+const CLAIM_FLAG: &str = "claim";
+const CLAIM_FLAG_PREFIX: &str = "claim:";
+
+/// How many of the best candidates are logged and kept.
+const KEPT_CANDIDATES: usize = 3;
+
+/// The closest and furthest a new room should be from our closest one.
+pub const MIN_DISTANCE: u32 = 2;
+pub const MAX_DISTANCE: u32 = 4;
+
+/// Neighbours owned by someone else at this level or above are strong.
+const STRONG_LEVEL: u32 = 6;
+
+/// How far around each source the terrain counts.
+const SOURCE_TERRAIN_RANGE: u32 = 2;
+
+const TWO_SOURCES_POINTS: f64 = 40.0;
+const MINERAL_POINTS: f64 = 10.0;
+const DISTANCE_POINTS: f64 = 20.0;
+/// Points for sources without any swamp around them, less for the share that is.
+const TERRAIN_POINTS: f64 = 15.0;
+const NO_STRONG_NEIGHBOUR_POINTS: f64 = 15.0;
+const HOSTILE_NEIGHBOUR_PENALTY: f64 = 25.0;
+const SOURCE_KEEPER_PENALTY: f64 = 15.0;
+
+/// The priority of the claimer's spawn request, above the remotes'.
+const CLAIM_PRIORITY: u8 = 150;
+
+/// What a candidate is scored on.
+#[derive(Clone, Debug, Default)]
+pub struct Facts {
+    pub sources: u32,
+    /// Minerals in the room or its neighbours which none of our rooms have.
+    pub new_minerals: u32,
+    /// Rooms to our closest one, in a straight line.
+    pub distance: u32,
+    /// How much of the walkable terrain around the sources is swamp, from 0 to 1.
+    pub swamp_share: f64,
+    /// Neighbours owned by others at [`STRONG_LEVEL`] or above.
+    pub strong_neighbours: u32,
+    /// Neighbours intel marked hostile.
+    pub hostile_neighbours: u32,
+    pub source_keeper_neighbours: u32,
+}
+
+/// How good a room to expand into the facts make a candidate, higher is better.
+pub fn score(facts: &Facts) -> f64 {
+    let mut score = 0.0;
+    if facts.sources >= 2 {
+        score += TWO_SOURCES_POINTS;
+    }
+    score += facts.new_minerals as f64 * MINERAL_POINTS;
+    if (MIN_DISTANCE..=MAX_DISTANCE).contains(&facts.distance) {
+        score += DISTANCE_POINTS;
+    }
+    score += (1.0 - facts.swamp_share) * TERRAIN_POINTS;
+    if facts.strong_neighbours == 0 {
+        score += NO_STRONG_NEIGHBOUR_POINTS;
+    }
+    score -= facts.hostile_neighbours as f64 * HOSTILE_NEIGHBOUR_PENALTY;
+    score -= facts.source_keeper_neighbours as f64 * SOURCE_KEEPER_PENALTY;
+    score
+}
+
+/// Scores every known room we could claim, logging and keeping the best.
+pub fn score_candidates() {
+    let owned = owned_rooms();
+    if owned.is_empty() {
+        return;
+    }
+    let our_minerals: HashSet<String> = owned
+        .iter()
+        .filter_map(|&name| intel::get(name)?.mineral)
+        .collect();
+    let us = our_name();
+
+    let scored = rank(
+        intel::known_rooms()
+            .into_iter()
+            .filter(|(name, room)| is_claimable(*name, room, us.as_deref()))
+            .map(|(name, room)| {
+                let facts = gather_facts(name, &room, &owned, &our_minerals, us.as_deref());
+                (name, score(&facts))
+            })
+            .collect(),
+    );
+
+    for (rank, (name, score)) in scored.iter().enumerate() {
+        info!(
+            "expansion candidate {}: {} scoring {:.1}",
+            rank + 1,
+            name,
+            score
+        );
+    }
+    let encoded: Vec<String> = scored
+        .iter()
+        .map(|(name, score)| format!("{}:{:.1}", name, score))
+        .collect();
+    screeps::memory::root().path_set(CANDIDATES_PATH, encoded.join(";"));
+}
+
+/// The best [`KEPT_CANDIDATES`] of scored rooms, best first. Ties go by name, so the same rooms
+/// always come out the same way.
+fn rank(mut scored: Vec<(RoomName, f64)>) -> Vec<(RoomName, f64)> {
+    scored.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+    });
+    scored.truncate(KEPT_CANDIDATES);
+    scored
+}
+
+/// The best candidates from the last scoring, best first.
+pub fn candidates() -> Vec<(RoomName, f64)> {
+    let encoded = screeps::memory::root()
+        .path_string(CANDIDATES_PATH)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    encoded
+        .split(';')
+        .filter_map(|entry| {
+            let mut fields = entry.split(':');
+            let name = RoomName::new(fields.next()?).ok()?;
+            Some((name, fields.next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Whether a room's controller is free for us to claim, as far as intel knows.
+fn is_claimable(room_name: RoomName, room: &intel::RoomIntel, us: Option<&str>) -> bool {
+    let controller = match &room.controller {
+        Some(controller) => controller,
+        None => return false,
+    };
+    let reserved_by_others = match (&controller.reserved_by, us) {
+        (Some(by), Some(us)) => by != us,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    controller.owner.is_none()
+        && !reserved_by_others
+        && !room.hostile
+        && !intel::is_source_keeper(room_name)
+}
+
+fn gather_facts(
+    room_name: RoomName,
+    room: &intel::RoomIntel,
+    owned: &[RoomName],
+    our_minerals: &HashSet<String>,
+    us: Option<&str>,
+) -> Facts {
+    let neighbours: Vec<RoomName> = screeps::game::map::describe_exits(room_name)
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect();
+    let mut facts = Facts {
+        sources: room.sources.len() as u32,
+        distance: owned
+            .iter()
+            .map(|&name| screeps::game::map::get_room_linear_distance(room_name, name, false))
+            .min()
+            .unwrap_or(0),
+        swamp_share: swamp_share(room_name, &room.sources),
+        ..Facts::default()
+    };
+
+    let mut minerals: HashSet<String> = room.mineral.iter().cloned().collect();
+    for &neighbour in &neighbours {
+        if intel::is_source_keeper(neighbour) {
+            facts.source_keeper_neighbours += 1;
+        }
+        let intel = match intel::get(neighbour) {
+            Some(intel) => intel,
+            None => continue,
+        };
+        if intel.hostile {
+            facts.hostile_neighbours += 1;
+        }
+        let strong = intel.controller.as_ref().map_or(false, |c| {
+            c.owner.is_some() && c.owner.as_deref() != us && c.level >= STRONG_LEVEL
+        });
+        if strong {
+            facts.strong_neighbours += 1;
+        }
+        minerals.extend(intel.mineral);
+    }
+    facts.new_minerals = minerals.difference(our_minerals).count() as u32;
+    facts
+}
+
+/// The share of swamp in the walkable tiles around the sources.
+fn swamp_share(room_name: RoomName, sources: &[(u8, u8)]) -> f64 {
+    let terrain = screeps::game::map::get_room_terrain(room_name);
+    let (mut walkable, mut swamp) = (0, 0);
+    for &(x, y) in sources {
+        let (x, y) = (x as u32, y as u32);
+        for tx in x.saturating_sub(SOURCE_TERRAIN_RANGE)..=(x + SOURCE_TERRAIN_RANGE).min(49) {
+            for ty in y.saturating_sub(SOURCE_TERRAIN_RANGE)..=(y + SOURCE_TERRAIN_RANGE).min(49) {
+                match terrain.get(tx, ty) {
+                    Terrain::Wall => {}
+                    Terrain::Swamp => {
+                        walkable += 1;
+                        swamp += 1;
+                    }
+                    Terrain::Plain => walkable += 1,
+                }
+            }
+        }
+    }
+    if walkable == 0 {
+        return 0.0;
+    }
+    swamp as f64 / walkable as f64
+}
+
+/// Follows up on the expansion target, picking one and spawning its claimer.
+pub fn run() {
+    let memory = screeps::memory::root();
+    let flag = claim_flag();
+    let target = flag.as_ref().map(|(_, room)| *room).or_else(|| {
+        let name = memory.path_string(TARGET_PATH).ok().flatten()?;
+        RoomName::new(&name).ok()
+    });
+
+    let owned = owned_rooms();
+    if let Some(target) = target {
+        if owned.contains(&target) {
+            info!("claimed room {}", target);
+            memory.path_del(TARGET_PATH);
+            bootstrap::start(target);
+            if let Some((flag, _)) = flag {
+                if let Some(flag) = screeps::game::flags::get(&flag) {
+                    flag.remove();
+                }
+            }
+            return;
+        }
+        let lost = is_lost(
+            target,
+            flag.is_some(),
+            intel::get(target).as_ref(),
+            our_name().as_deref(),
+        );
+        if lost {
+            warn!("expansion target {} can't be claimed any more", target);
+            memory.path_del(TARGET_PATH);
+            return;
+        }
+    }
+
+    if owned.len() as u32 >= screeps::game::gcl::level() {
+        return;
+    }
+    let target = match target {
+        Some(target) => target,
+        None => match candidates().first() {
+            Some(&(best, score)) => {
+                info!("expanding into {}, which scored {:.1}", best, score);
+                memory.path_set(TARGET_PATH, best.to_string());
+                best
+            }
+            None => return,
+        },
+    };
+    request_claimer(target, &owned);
+}
+
+/// Whether the expansion target can't be claimed any more, as far as intel knows. A flag is
+/// followed regardless of what intel thinks of the room.
+fn is_lost(
+    target: RoomName,
+    flagged: bool,
+    room: Option<&intel::RoomIntel>,
+    us: Option<&str>,
+) -> bool {
+    !flagged && room.map_or(false, |room| !is_claimable(target, room, us))
+}
+
+/// The room under the first claim flag, along with the flag's name.
+fn claim_flag() -> Option<(String, RoomName)> {
+    first_claim_flag(
+        screeps::game::flags::values()
+            .into_iter()
+            .map(|flag| (flag.name(), flag.pos().room_name()))
+            .collect(),
+    )
+}
+
+/// The first of `flags` by name which is a claim flag.
+fn first_claim_flag(mut flags: Vec<(String, RoomName)>) -> Option<(String, RoomName)> {
+    flags.retain(|(name, _)| name == CLAIM_FLAG || name.starts_with(CLAIM_FLAG_PREFIX));
+    flags.sort();
+    flags.into_iter().next()
+}
+
+/// Queues a claimer in the closest room which can afford one, unless there already is one.
+fn request_claimer(target: RoomName, owned: &[RoomName]) {
+    let has_claimer = screeps::game::creeps::values()
+        .iter()
+        .any(|c| spawning::role_of(c) == Role::Claimer && spawning::work_room(c) == Some(target));
+    let queued = spawning::queue()
+        .iter()
+        .any(|r| r.role == Role::Claimer && r.work_room == Some(target));
+    if has_claimer || queued {
+        return;
+    }
+
+    let home = owned
+        .iter()
+        .copied()
+        .filter(|&name| {
+            screeps::game::rooms::get(name).map_or(false, |room| {
+                room.energy_capacity_available() >= Role::Claimer.cost()
+            })
+        })
+        .min_by_key(|&name| screeps::game::map::get_room_linear_distance(name, target, false));
+    match home {
+        Some(home) => {
+            info!("requesting a claimer for {} from {}", target, home);
+            spawning::request(SpawnRequest {
+                room_name: home,
+                role: Role::Claimer,
+                priority: CLAIM_PRIORITY,
+                work_room: Some(target),
+                size: 1,
+            });
+        }
+        None => debug!("none of our rooms can afford a claimer for {}", target),
+    }
+}
+
+/// Takes a claimer to its room and claims the controller.
+pub fn run_claimer(creep: &Creep) {
+    let target = match spawning::work_room(creep) {
+        Some(target) => target,
+        None => return,
+    };
+    if creep.pos().room_name() != target {
+        movement::move_to_room(creep, target);
+        return;
+    }
+    let controller = match creep.room().and_then(|room| room.controller()) {
+        Some(controller) => controller,
+        None => return,
+    };
+    if controller.my() {
+        // nothing left to do for a creep that can only claim
+        creep.suicide();
+        return;
+    }
+    if !creep.pos().is_near_to(&controller) {
+        movement::move_creep_to(creep, &controller, 1);
+        return;
+    }
+    let r = creep.claim_controller(&controller);
+    match r {
+        ReturnCode::Ok => info!("{} claimed room {}", creep.name(), target),
+        // someone else's reservation has to be worn down first
+        ReturnCode::InvalidTarget if controller.reservation().is_some() => {
+            let r = creep.attack_controller(&controller);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "attack_controller", r);
+            }
+        }
+        _ => failures::report(&creep.name(), "claim", r),
+    }
+    movement::hold(creep, &controller, 1);
+}
+
+/// The rooms we own, leaving out abandoned ones.
+fn owned_rooms() -> Vec<RoomName> {
+    screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .map(|room| room.name())
+        .filter(|&name| !emergency::is_abandoned(name))
+        .collect()
+}
+
+/// Our username, read off one of our controllers.
+fn our_name() -> Option<String> {
+    screeps::game::rooms::values()
+        .into_iter()
+        .filter_map(|room| room.controller())
+        .find(|c| c.my())
+        .and_then(|c| c.owner_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(name: &str) -> RoomName {
+        RoomName::new(name).unwrap()
+    }
+
+    /// A room two rooms out with everything going for it.
+    fn ideal() -> Facts {
+        Facts {
+            sources: 2,
+            new_minerals: 1,
+            distance: 2,
+            ..Facts::default()
+        }
+    }
+
+    fn free_room() -> intel::RoomIntel {
+        let mut room = intel::RoomIntel::default();
+        room.sources = vec![(10, 10), (40, 40)];
+        room.controller = Some(intel::ControllerIntel::default());
+        room
+    }
+
+    #[test]
+    fn ideal_room_gets_every_bonus() {
+        let expected = TWO_SOURCES_POINTS
+            + MINERAL_POINTS
+            + DISTANCE_POINTS
+            + TERRAIN_POINTS
+            + NO_STRONG_NEIGHBOUR_POINTS;
+        assert!((score(&ideal()) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn each_drawback_costs_points() {
+        let best = score(&ideal());
+        let worse = [
+            Facts {
+                sources: 1,
+                ..ideal()
+            },
+            Facts {
+                new_minerals: 0,
+                ..ideal()
+            },
+            Facts {
+                distance: 1,
+                ..ideal()
+            },
+            Facts {
+                distance: MAX_DISTANCE + 1,
+                ..ideal()
+            },
+            Facts {
+                swamp_share: 0.5,
+                ..ideal()
+            },
+            Facts {
+                strong_neighbours: 1,
+                ..ideal()
+            },
+            Facts {
+                hostile_neighbours: 1,
+                ..ideal()
+            },
+            Facts {
+                source_keeper_neighbours: 1,
+                ..ideal()
+            },
+        ];
+        for facts in &worse {
+            assert!(
+                score(facts) < best,
+                "{:?} scored as well as the ideal room",
+                facts
+            );
+        }
+        // the edges of the distance window still count
+        let far = Facts {
+            distance: MAX_DISTANCE,
+            ..ideal()
+        };
+        assert!((score(&far) - best).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hostile_neighbours_outweigh_minerals() {
+        let contested = Facts {
+            new_minerals: 2,
+            hostile_neighbours: 1,
+            ..ideal()
+        };
+        assert!(score(&contested) < score(&ideal()));
+    }
+
+    #[test]
+    fn ranking_is_deterministic() {
+        let scored = vec![
+            (room("W3N3"), 50.0),
+            (room("W1N1"), 80.0),
+            (room("W2N2"), 50.0),
+            (room("W4N4"), 10.0),
+            (room("W5N5"), f64::NAN),
+        ];
+        let mut reversed = scored.clone();
+        reversed.reverse();
+        let ranked = rank(scored);
+        let names: Vec<String> = ranked.iter().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(names, vec!["W1N1", "W2N2", "W3N3"]);
+        let again: Vec<String> = rank(reversed)
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        assert_eq!(again, names);
+    }
+
+    #[test]
+    fn only_free_rooms_can_be_claimed() {
+        let name = room("W2N1");
+        assert!(is_claimable(name, &free_room(), Some("me")));
+
+        let mut owned = free_room();
+        owned.controller.as_mut().unwrap().owner = Some("them".to_string());
+        assert!(!is_claimable(name, &owned, Some("me")));
+
+        let mut reserved = free_room();
+        reserved.controller.as_mut().unwrap().reserved_by = Some("them".to_string());
+        assert!(!is_claimable(name, &reserved, Some("me")));
+        assert!(is_claimable(name, &reserved, Some("them")));
+
+        let mut no_controller = free_room();
+        no_controller.controller = None;
+        assert!(!is_claimable(name, &no_controller, Some("me")));
+
+        let mut hostile = free_room();
+        hostile.hostile = true;
+        assert!(!is_claimable(name, &hostile, Some("me")));
+
+        // source keeper rooms have no controller to claim anyway, but are never picked
+        assert!(!is_claimable(room("W5N4"), &free_room(), Some("me")));
+    }
+
+    #[test]
+    fn claim_flag_wins_over_the_scores() {
+        let name = room("W2N1");
+        let mut owned = free_room();
+        owned.controller.as_mut().unwrap().owner = Some("them".to_string());
+        assert!(is_lost(name, false, Some(&owned), Some("me")));
+        assert!(!is_lost(name, true, Some(&owned), Some("me")));
+        // rooms intel hasn't seen aren't given up on
+        assert!(!is_lost(name, false, None, Some("me")));
+        assert!(!is_lost(name, false, Some(&free_room()), Some("me")));
+    }
+
+    #[test]
+    fn first_claim_flag_by_name_is_followed() {
+        let flags = vec![
+            ("rally".to_string(), room("W1N1")),
+            ("claim:b".to_string(), room("W3N3")),
+            ("claim:a".to_string(), room("W2N2")),
+            ("claimed".to_string(), room("W4N4")),
+        ];
+        assert_eq!(
+            first_claim_flag(flags),
+            Some(("claim:a".to_string(), room("W2N2")))
+        );
+        let flags = vec![
+            ("claim:a".to_string(), room("W2N2")),
+            ("claim".to_string(), room("W5N5")),
+        ];
+        assert_eq!(first_claim_flag(flags).map(|(_, r)| r), Some(room("W5N5")));
+        assert_eq!(
+            first_claim_flag(vec![("rally".to_string(), room("W1N1"))]),
+            None
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Failed actions, collected over the tick.
+//!
+//! Creeps and structures [`report`] actions which didn't go through instead of warning about
+//! each one. At the end of the tick a single warning counts them by action and return code, like
+//! `transfer Full ×4, harvest NotInRange ×2`, and each failure is listed at debug level. Errors
+//! are still logged right away where they happen.
+//!
+//! The counts are also added up over windows of [`WINDOW_TICKS`] ticks, to see trends like
+//! `NotInRange` growing after a pathing change. The current and the last window are kept in
+//! `Memory.stats.failures`, so they survive resets, and a failure which became at least twice as
+//! common as in the window before is warned about when a window ends.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use log::*;
+use screeps::ReturnCode;
+
+use crate::log_kv;
+
+struct Failure {
+    /// The name of the creep or structure which failed.
+    actor: String,
+    action: &'static str,
+    code: ReturnCode,
+}
+
+/// How many ticks each window of counts covers.
+pub const WINDOW_TICKS: u32 = 1000;
+
+/// How often a failure has to happen in a window to be warned about when it wasn't seen in the
+/// window before.
+const MIN_NEW_COUNT: u32 = 10;
+
+/// Counts by action and code, like `transfer Full`.
+type Counts = BTreeMap<String, u32>;
+
+struct Window {
+    start: u32,
+    counts: Counts,
+    previous: Counts,
+}
+
+thread_local! {
+    /// Failures this tick.
+    static FAILURES: RefCell<Vec<Failure>> = RefCell::new(Vec::new());
+    /// The counts of the current and the last window, read from memory after a reset.
+    static WINDOW: RefCell<Option<Window>> = RefCell::new(None);
+}
+
+/// Records that `actor` couldn't do `action`.
+pub fn report(actor: &str, action: &'static str, code: ReturnCode) {
+    FAILURES.with(|failures| {
+        failures.borrow_mut().push(Failure {
+            actor: actor.to_string(),
+            action,
+            code,
+        })
+    });
+}
+
+/// Sums up this tick's failures and starts collecting the next tick's. Called once at the end of
+/// the loop.
+pub fn end_tick() {
+    let failures = FAILURES.with(|f| std::mem::take(&mut *f.borrow_mut()));
+    update_window(&failures);
+    if failures.is_empty() {
+        return;
+    }
+
+    let mut counts: BTreeMap<(&'static str, String), u32> = BTreeMap::new();
+    for failure in &failures {
+        *counts
+            .entry((failure.action, format!("{:?}", failure.code)))
+            .or_default() += 1;
+    }
+    let mut counts: Vec<((&'static str, String), u32)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    let summary: Vec<String> = counts
+        .iter()
+        .map(|((action, code), count)| format!("{} {} ×{}", action, code, count))
+        .collect();
+    warn!("failed actions: {}", summary.join(", "));
+
+    if log_enabled!(Level::Debug) {
+        for failure in &failures {
+            log_kv!(
+                debug,
+                "action failed",
+                actor = failure.actor,
+                action = failure.action,
+                code = ?failure.code
+            );
+        }
+    }
+}
+
+/// Adds this tick's failures to the window, starting a new one when it's over, and stores it.
+fn update_window(failures: &[Failure]) {
+    let time = screeps::game::time();
+    WINDOW.with(|window| {
+        let mut window = window.borrow_mut();
+        let window = window.get_or_insert_with(|| load_window(time));
+        let mut changed = !failures.is_empty();
+
+        if time.saturating_sub(window.start) >= WINDOW_TICKS {
+            compare_windows(&window.counts, &window.previous);
+            window.previous = std::mem::take(&mut window.counts);
+            window.start = time;
+            changed = true;
+        }
+        for failure in failures {
+            *window
+                .counts
+                .entry(format!("{} {:?}", failure.action, failure.code))
+                .or_default() += 1;
+        }
+        if changed {
+            save_window(window);
+        }
+    });
+}
+
+/// Warns about failures which became at least twice as common as in the window before.
+fn compare_windows(counts: &Counts, previous: &Counts) {
+    let grown: Vec<String> = counts
+        .iter()
+        .filter_map(|(key, &count)| {
+            let before = previous.get(key).copied().unwrap_or(0);
+            let doubled = if before == 0 {
+                count >= MIN_NEW_COUNT
+            } else {
+                count >= before * 2
+            };
+            if doubled {
+                Some(format!("{} {} → {}", key, before, count))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if !grown.is_empty() {
+        warn!(
+            "failures grown over the last {} ticks: {}",
+            WINDOW_TICKS,
+            grown.join(", ")
+        );
+    }
+}
+
+fn load_window(time: u32) -> Window {
+    let stats = screeps::memory::root()
+        .path_dict("stats.failures")
+        .ok()
+        .flatten();
+    let read_counts = |key: &str| -> Counts {
+        let dict = match stats.as_ref().and_then(|s| s.dict(key).ok().flatten()) {
+            Some(dict) => dict,
+            None => return Counts::new(),
+        };
+        dict.keys()
+            .into_iter()
+            .filter_map(|name| {
+                let count = dict.i32(&name).ok().flatten()?;
+                Some((name, count.max(0) as u32))
+            })
+            .collect()
+    };
+    Window {
+        start: stats
+            .as_ref()
+            .and_then(|s| s.i32("window_start").ok().flatten())
+            .map_or(time, |start| start as u32),
+        counts: read_counts("current"),
+        previous: read_counts("previous"),
+    }
+}
+
+fn save_window(window: &Window) {
+    let stats = match screeps::memory::root()
+        .dict_or_create("stats")
+        .and_then(|stats| {
+            stats.del("failures");
+            stats.dict_or_create("failures")
+        }) {
+        Ok(stats) => stats,
+        Err(e) => {
+            warn!("couldn't store failure stats: {}", e);
+            return;
+        }
+    };
+    stats.set("window_start", window.start);
+    for (key, counts) in &[("current", &window.counts), ("previous", &window.previous)] {
+        if let Ok(dict) = stats.dict_or_create(key) {
+            for (name, &count) in counts.iter() {
+                dict.set(name, count);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! What the server we run on has.
+//!
+//! Private servers and the simulator leave out parts of the official server's API, and calling
+//! into what isn't there throws. Each [`Feature`] is probed the first time it's asked about after
+//! a reset, and the answer kept until the next one, so a missing API costs one check per reset
+//! rather than one per tick. A missing feature is logged once, and `Memory.stats.features` lists
+//! what was found.
+//!
+//! Pixel generation in [`scheduler::generate_pixel`] needs [`Feature::Pixels`], and notifications
+//! from [`events`] and [`logging`] need [`Feature::Notify`], without which they're only logged.
+//! Nothing uses the market or inter-shard memory yet, but they're probed along with the rest so
+//! the stats show what a server has.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use stdweb::{js, unstable::TryInto};
+
+const STATS_PATH: &str = "stats.features";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Market,
+    Pixels,
+    InterShardMemory,
+    Notify,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 4] = [
+        Feature::Market,
+        Feature::Pixels,
+        Feature::InterShardMemory,
+        Feature::Notify,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Market => "market",
+            Feature::Pixels => "pixels",
+            Feature::InterShardMemory => "inter_shard_memory",
+            Feature::Notify => "notify",
+        }
+    }
+
+    /// Whether the server has the feature.
+    fn probe(self) -> bool {
+        // typeof is safe on anything, even globals which don't exist
+        let found = match self {
+            Feature::Market => js! {
+                return typeof Game.market === "object" && Game.market !== null
+                    && typeof Game.market.createOrder === "function"
+            },
+            Feature::Pixels => js! {
+                return typeof Game.cpu.generatePixel === "function"
+            },
+            Feature::InterShardMemory => js! {
+                return typeof InterShardMemory === "object"
+                    && typeof InterShardMemory.getLocal === "function"
+            },
+            Feature::Notify => js! {
+                return typeof Game.notify === "function"
+            },
+        };
+        found.try_into().unwrap_or(false)
+    }
+}
+
+thread_local! {
+    /// What's been probed since the reset.
+    static FOUND: RefCell<HashMap<Feature, bool>> = RefCell::new(HashMap::new());
+}
+
+/// Whether the server has a feature, probing it if it hasn't been since the reset.
+pub fn has(feature: Feature) -> bool {
+    if let Some(found) = FOUND.with(|f| f.borrow().get(&feature).copied()) {
+        return found;
+    }
+    let found = feature.probe();
+    // kept before logging, as the logger asks about notifications
+    FOUND.with(|f| f.borrow_mut().insert(feature, found));
+    if !found {
+        warn!(
+            "this server doesn't have {}, leaving it out until the next reset",
+            feature.name()
+        );
+    }
+    screeps::memory::root().path_set(&format!("{}.{}", STATS_PATH, feature.name()), found);
+    found
+}
+
+/// Probes every feature, so the stats list all of them. Called once after a reset.
+pub fn probe_all() {
+    let found: Vec<&str> = Feature::ALL
+        .iter()
+        .filter(|&&feature| has(feature))
+        .map(|feature| feature.name())
+        .collect();
+    info!("server features: {}", found.join(", "));
+}
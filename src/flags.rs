@@ -0,0 +1,126 @@
+//! Commands given with flags.
+//!
+//! A flag named `cmd:<creep>:<verb>`, like `cmd:BraveOtter42:withdraw`, points the creep at the
+//! object under the flag, or the one nearest to it in the flag's room. The verbs are `harvest`,
+//! `fill`, `withdraw`, `build`, `repair`, `upgrade`, `portal` and `attack`. The flag is removed
+//! once the creep has taken the command on, and commands which can't be carried out are logged as
+//! errors and removed as well.
+
+use log::*;
+use screeps::{prelude::*, Flag, Position, Room};
+
+use crate::{
+    context::TickContext,
+    creeps::{self, CreepTarget},
+};
+
+const COMMAND_PREFIX: &str = "cmd:";
+
+/// Carries out every command flag.
+pub fn run(ctx: &TickContext) {
+    for flag in screeps::game::flags::values() {
+        let name = flag.name();
+        let command = match name.strip_prefix(COMMAND_PREFIX) {
+            Some(command) => command,
+            None => continue,
+        };
+        match apply(&flag, command, ctx) {
+            Ok(message) => info!("flag {}: {}", name, message),
+            Err(e) => error!("flag {}: {}", name, e),
+        }
+        flag.remove();
+    }
+}
+
+fn apply(flag: &Flag, command: &str, ctx: &TickContext) -> Result<String, String> {
+    let (creep_name, verb) = match command.rfind(':') {
+        Some(split) => (&command[..split], &command[split + 1..]),
+        None => return Err("expected a name like cmd:<creep>:<verb>".to_string()),
+    };
+    let creep = screeps::game::creeps::get(creep_name)
+        .ok_or_else(|| format!("there is no creep named {}", creep_name))?;
+    if creep.spawning() {
+        return Err(format!("{} is still spawning", creep_name));
+    }
+
+    let pos = flag.pos();
+    let room = screeps::game::rooms::get(pos.room_name())
+        .ok_or_else(|| format!("room {} isn't visible", pos.room_name()))?;
+    let target = target_for(verb, &room, pos, ctx)?;
+    creeps::set_target(&creep.name(), target);
+    Ok(format!("{} is set to {:?}", creep_name, target))
+}
+
+/// The target for a verb, on the object nearest to `pos`.
+fn target_for(
+    verb: &str,
+    room: &Room,
+    pos: Position,
+    ctx: &TickContext,
+) -> Result<CreepTarget, String> {
+    let snapshot = ctx.rooms.snapshot(room);
+    let missing = |what: &str| format!("no {} near {} to {}", what, pos, verb);
+    let target = match verb {
+        "harvest" => nearest(pos, snapshot.sources_active())
+            .map(|s| CreepTarget::Harvest(s.id()))
+            .ok_or_else(|| missing("source with energy"))?,
+        "fill" => nearest(
+            pos,
+            snapshot
+                .all_structures()
+                .filter(|s| creeps::accepts_energy(*s)),
+        )
+        .map(|s| CreepTarget::Fill(s.id()))
+        .ok_or_else(|| missing("structure with room for energy"))?,
+        "withdraw" => nearest(
+            pos,
+            snapshot
+                .all_structures()
+                .filter(|s| creeps::holds_energy(*s)),
+        )
+        .map(|s| CreepTarget::Withdraw(s.id()))
+        .ok_or_else(|| missing("container or storage with energy"))?,
+        "build" => nearest(pos, snapshot.construction_sites())
+            .map(|s| CreepTarget::Build(s.id()))
+            .ok_or_else(|| missing("construction site"))?,
+        "repair" => nearest(
+            pos,
+            snapshot
+                .all_structures()
+                .filter(|s| s.as_attackable().map_or(false, |a| a.hits() < a.hits_max())),
+        )
+        .map(|s| CreepTarget::Repair(s.id()))
+        .ok_or_else(|| missing("damaged structure"))?,
+        "upgrade" => room
+            .controller()
+            .filter(|c| c.my())
+            .map(|c| CreepTarget::Upgrade(c.id()))
+            .ok_or_else(|| missing("controller of ours"))?,
+        "portal" => CreepTarget::Portal(pos),
+        "attack" => nearest(
+            pos,
+            snapshot
+                .all_structures()
+                .filter(|s| s.as_attackable().is_some() && !s.as_owned().map_or(false, |o| o.my())),
+        )
+        .map(|s| CreepTarget::AttackStructure(s.id().into()))
+        .ok_or_else(|| missing("structure which isn't ours"))?,
+        _ => {
+            return Err(format!(
+                "unknown verb {:?}, verbs are harvest, fill, withdraw, build, repair, upgrade, \
+                 portal and attack",
+                verb
+            ))
+        }
+    };
+    Ok(target)
+}
+
+fn nearest<'a, T: HasPosition + 'a>(
+    pos: Position,
+    candidates: impl IntoIterator<Item = &'a T>,
+) -> Option<&'a T> {
+    candidates
+        .into_iter()
+        .min_by_key(|candidate| pos.get_range_to(*candidate))
+}
@@ -0,0 +1,593 @@
+//! Quads: four creeps moving as one 2x2 square.
+//!
+//! A quad is registered from the console with `form_quad`, its first creep being the leader it's
+//! known by, and sent somewhere with `move_quad`. Each quad is kept in `Memory.quads.<leader>`,
+//! with its members in slot order (front left, front right, back left, back right), where it's
+//! headed, which way it faces and the tile of its top left corner, its anchor.
+//!
+//! A formed quad paths for its anchor with [`costs::block_matrix`], so every tile the square
+//! covers has to be passable, and all four members then take the same step, waiting for whoever
+//! is tired. When the path ahead turns, the members first swap around into their slots for the
+//! new heading, so the front row stays in front. Outside its target's room the quad heads for an
+//! exit on the way there, lined up against it. Creeps which cross an exit land somewhere along
+//! the other side and can't stay in formation, so once any member is in a room closer to the
+//! target the quad comes apart: every member walks to its slot around a fresh anchor there, and
+//! the quad only moves on once it's formed up again.
+//!
+//! Every tick each healer heals whichever member is missing the most hits still, counting the
+//! heals already given out, so damage is spread over the quad's healers. Dead members leave
+//! their slot empty, and a quad is dropped once all of them are dead. Quad members are run here
+//! and nowhere else, so there's no attacking yet.
+
+use std::{cell::RefCell, collections::HashSet};
+
+use log::*;
+use screeps::{
+    memory::MemoryReference,
+    pathfinder::{self, LocalCostMatrix, MultiRoomCostResult, SearchOptions},
+    prelude::*,
+    Attackable, Creep, ExitDirection, Part, Position, ReturnCode, RoomName, Terrain,
+};
+
+use crate::{
+    context::TickContext,
+    failures,
+    heap::CacheSize,
+    movement::{self, costs, intents},
+};
+
+const QUADS_KEY: &str = "quads";
+const MEMBERS_KEY: &str = "members";
+const TARGET_KEY: &str = "target";
+const HEADING_KEY: &str = "heading";
+const ANCHOR_KEY: &str = "anchor";
+
+/// How many creeps make up a quad, and how many tiles across it is.
+pub const QUAD_SIZE: usize = 4;
+const SIDE: u32 = 2;
+
+/// The highest anchor coordinate which keeps the whole square off the room's exits.
+const MAX_ANCHOR: u32 = 49 - SIDE;
+
+/// What the square pays for plains and swamps, as a half-speed creep would.
+const PLAIN_COST: u8 = 2;
+const SWAMP_COST: u8 = 10;
+
+/// How many steps ahead the quad looks to decide which way to face.
+const LOOKAHEAD: usize = 3;
+
+/// How far from a member a quad looks for room to form up.
+const FORM_RANGE: u32 = 10;
+
+/// How many hits a heal part heals next to its target, and from further away.
+const HEAL_POWER: u32 = 12;
+const RANGED_HEAL_POWER: u32 = 4;
+const RANGED_HEAL_RANGE: u32 = 3;
+
+thread_local! {
+    /// The names of every creep in a quad, as of the last [`run`].
+    static MEMBERS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Which way a quad faces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Heading {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+impl Heading {
+    fn name(self) -> &'static str {
+        match self {
+            Heading::Top => "top",
+            Heading::Right => "right",
+            Heading::Bottom => "bottom",
+            Heading::Left => "left",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Heading> {
+        [Heading::Top, Heading::Right, Heading::Bottom, Heading::Left]
+            .iter()
+            .copied()
+            .find(|heading| heading.name() == name)
+    }
+
+    /// Where each slot is from the anchor, front left first.
+    fn offsets(self) -> [(u32, u32); QUAD_SIZE] {
+        match self {
+            Heading::Top => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            Heading::Right => [(1, 0), (1, 1), (0, 0), (0, 1)],
+            Heading::Bottom => [(1, 1), (0, 1), (1, 0), (0, 0)],
+            Heading::Left => [(0, 1), (0, 0), (1, 1), (1, 0)],
+        }
+    }
+
+    /// The heading closest to going `dx`, `dy`, or `None` for no move at all.
+    fn toward(dx: i32, dy: i32) -> Option<Heading> {
+        if dx == 0 && dy == 0 {
+            None
+        } else if dx.abs() >= dy.abs() {
+            Some(if dx > 0 {
+                Heading::Right
+            } else {
+                Heading::Left
+            })
+        } else {
+            Some(if dy > 0 {
+                Heading::Bottom
+            } else {
+                Heading::Top
+            })
+        }
+    }
+}
+
+struct Quad {
+    leader: String,
+    members: Vec<String>,
+    target: Option<Position>,
+    heading: Heading,
+    anchor: Option<Position>,
+}
+
+fn encode(pos: Position) -> String {
+    format!("{},{},{}", pos.room_name(), pos.x(), pos.y())
+}
+
+fn decode(encoded: &str) -> Option<Position> {
+    let mut fields = encoded.split(',');
+    let room_name = RoomName::new(fields.next()?).ok()?;
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    Some(Position::new(x, y, room_name))
+}
+
+fn load(leader: String, memory: &MemoryReference) -> Quad {
+    let string = |key| memory.string(key).ok().flatten().unwrap_or_default();
+    Quad {
+        leader,
+        members: string(MEMBERS_KEY).split(',').map(str::to_string).collect(),
+        target: decode(&string(TARGET_KEY)),
+        heading: Heading::from_name(&string(HEADING_KEY)).unwrap_or(Heading::Top),
+        anchor: decode(&string(ANCHOR_KEY)),
+    }
+}
+
+fn save(quad: &Quad) {
+    let memory = screeps::memory::root()
+        .dict_or_create(QUADS_KEY)
+        .and_then(|quads| quads.dict_or_create(&quad.leader));
+    let memory = match memory {
+        Ok(memory) => memory,
+        Err(e) => {
+            warn!("couldn't store quad {}: {}", quad.leader, e);
+            return;
+        }
+    };
+    memory.set(MEMBERS_KEY, quad.members.join(","));
+    memory.set(HEADING_KEY, quad.heading.name());
+    match quad.target {
+        Some(target) => memory.set(TARGET_KEY, encode(target)),
+        None => memory.del(TARGET_KEY),
+    }
+    match quad.anchor {
+        Some(anchor) => memory.set(ANCHOR_KEY, encode(anchor)),
+        None => memory.del(ANCHOR_KEY),
+    }
+}
+
+fn all() -> Vec<Quad> {
+    let quads = match screeps::memory::root().dict(QUADS_KEY) {
+        Ok(Some(quads)) => quads,
+        _ => return Vec::new(),
+    };
+    let mut keys = quads.keys();
+    keys.sort();
+    keys.into_iter()
+        .filter_map(|key| {
+            let memory = quads.dict(&key).ok()??;
+            Some(load(key, &memory))
+        })
+        .collect()
+}
+
+fn get(leader: &str) -> Option<Quad> {
+    let memory = screeps::memory::root()
+        .dict(QUADS_KEY)
+        .ok()??
+        .dict(leader)
+        .ok()??;
+    Some(load(leader.to_string(), &memory))
+}
+
+/// Whether a creep is part of a quad, and so only moved by it.
+pub fn is_member(name: &str) -> bool {
+    MEMBERS.with(|members| members.borrow().contains(name))
+}
+
+/// Registers four creeps as a quad, the first leading it, returning what was done.
+pub fn form(names: &[String]) -> Result<String, String> {
+    if names.len() != QUAD_SIZE {
+        return Err(format!("a quad takes {} creeps", QUAD_SIZE));
+    }
+    let taken: HashSet<String> = all().into_iter().flat_map(|quad| quad.members).collect();
+    for (index, name) in names.iter().enumerate() {
+        if names[..index].contains(name) {
+            return Err(format!("{} is listed twice", name));
+        }
+        if screeps::game::creeps::get(name).is_none() {
+            return Err(format!("there is no creep named {}", name));
+        }
+        if taken.contains(name) {
+            return Err(format!("{} is already in a quad", name));
+        }
+    }
+    let quad = Quad {
+        leader: names[0].clone(),
+        members: names.to_vec(),
+        target: None,
+        heading: Heading::Top,
+        anchor: None,
+    };
+    save(&quad);
+    MEMBERS.with(|members| members.borrow_mut().extend(names.iter().cloned()));
+    Ok(format!(
+        "formed quad {} of {}",
+        quad.leader,
+        quad.members.join(", ")
+    ))
+}
+
+/// Sends a quad to `target`, returning whether there's a quad led by `leader`.
+pub fn send(leader: &str, target: Position) -> bool {
+    match get(leader) {
+        Some(mut quad) => {
+            quad.target = Some(target);
+            save(&quad);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Lets the creeps of a quad go back to their roles, returning whether there was one.
+pub fn disband(leader: &str) -> bool {
+    let quads = match screeps::memory::root().dict(QUADS_KEY) {
+        Ok(Some(quads)) => quads,
+        _ => return false,
+    };
+    if quads.dict(leader).ok().flatten().is_none() {
+        return false;
+    }
+    quads.del(leader);
+    true
+}
+
+/// Heals and moves every quad.
+pub fn run(ctx: &TickContext) {
+    let quads = all();
+    ctx.stats.count("quads", quads.len() as f64);
+    MEMBERS.with(|members| {
+        let mut members = members.borrow_mut();
+        members.clear();
+        members.extend(quads.iter().flat_map(|quad| quad.members.iter().cloned()));
+    });
+    for quad in quads {
+        run_quad(quad);
+    }
+}
+
+fn run_quad(mut quad: Quad) {
+    let creeps: Vec<Option<Creep>> = quad
+        .members
+        .iter()
+        .map(|name| screeps::game::creeps::get(name))
+        .collect();
+    if creeps.iter().all(Option::is_none) {
+        info!("quad {} is gone, all its creeps died", quad.leader);
+        disband(&quad.leader);
+        return;
+    }
+    // nobody leaves without the creeps still spawning
+    if creeps.iter().flatten().any(|creep| creep.spawning()) {
+        return;
+    }
+    let members: Vec<(usize, Creep)> = creeps
+        .into_iter()
+        .enumerate()
+        .filter_map(|(slot, creep)| Some((slot, creep?)))
+        .collect();
+    heal(&members);
+
+    let room_name = quad_room(&members, quad.target);
+    let matrix = footprint_matrix(room_name);
+    let anchor = quad
+        .anchor
+        .filter(|anchor| anchor.room_name() == room_name && is_open(&matrix, *anchor))
+        .or_else(|| {
+            let (_, first) = members
+                .iter()
+                .find(|(_, creep)| creep.pos().room_name() == room_name)?;
+            find_anchor(&matrix, first.pos())
+        });
+    let anchor = match anchor {
+        Some(anchor) => anchor,
+        None => {
+            warn!(
+                "quad {} has no room to form up in {}",
+                quad.leader, room_name
+            );
+            return;
+        }
+    };
+    quad.anchor = Some(anchor);
+
+    let formed = members
+        .iter()
+        .all(|(slot, creep)| creep.pos() == slot_position(anchor, quad.heading, *slot));
+    if !formed {
+        move_to_slots(&members, anchor, quad.heading);
+        save(&quad);
+        return;
+    }
+    if members.iter().any(|(_, creep)| creep.fatigue() > 0) {
+        move_to_slots(&members, anchor, quad.heading);
+        return;
+    }
+
+    match next_move(anchor, &matrix, quad.target) {
+        Move::Stay => move_to_slots(&members, anchor, quad.heading),
+        Move::Cross(next_room) => {
+            debug!("quad {} is crossing into {}", quad.leader, next_room);
+            for (_, creep) in &members {
+                movement::move_to_room(creep, next_room);
+            }
+        }
+        Move::Path(path) => {
+            let ahead = path[path.len().min(LOOKAHEAD) - 1];
+            let heading = Heading::toward(
+                ahead.x() as i32 - anchor.x() as i32,
+                ahead.y() as i32 - anchor.y() as i32,
+            );
+            match heading {
+                Some(heading) if heading != quad.heading && path.len() >= LOOKAHEAD => {
+                    debug!("quad {} turns {}", quad.leader, heading.name());
+                    quad.heading = heading;
+                    move_to_slots(&members, anchor, heading);
+                }
+                _ => {
+                    step(&members, anchor, path[0]);
+                    quad.anchor = Some(path[0]);
+                }
+            }
+            save(&quad);
+        }
+    }
+}
+
+/// The room a quad forms up in: the one of its members closest to the target, or the room of
+/// its first member still alive without a target.
+fn quad_room(members: &[(usize, Creep)], target: Option<Position>) -> RoomName {
+    let first = members[0].1.pos().room_name();
+    let target = match target {
+        Some(target) => target.room_name(),
+        None => return first,
+    };
+    let mut rooms: Vec<RoomName> = members
+        .iter()
+        .map(|(_, creep)| creep.pos().room_name())
+        .collect();
+    rooms.dedup();
+    if rooms.iter().all(|&room| room == first) {
+        return first;
+    }
+    rooms
+        .into_iter()
+        .min_by_key(|&room| {
+            if room == target {
+                0
+            } else {
+                screeps::game::map::find_route(&room.to_string(), &target.to_string())
+                    .map_or(usize::MAX, |r| r.len())
+            }
+        })
+        .unwrap_or(first)
+}
+
+/// The block matrix of a room, with every anchor which would put the square on an exit closed.
+fn footprint_matrix(room_name: RoomName) -> LocalCostMatrix {
+    let mut matrix = costs::block_matrix(room_name, SIDE, (PLAIN_COST, SWAMP_COST));
+    for y in 0..50u8 {
+        for x in 0..50u8 {
+            if x == 0 || y == 0 || u32::from(x) > MAX_ANCHOR || u32::from(y) > MAX_ANCHOR {
+                matrix.set(x, y, 255);
+            }
+        }
+    }
+    matrix
+}
+
+fn is_open(matrix: &LocalCostMatrix, anchor: Position) -> bool {
+    matrix.get(anchor.x() as u8, anchor.y() as u8) < 255
+}
+
+/// The open anchor closest to `pos`.
+fn find_anchor(matrix: &LocalCostMatrix, pos: Position) -> Option<Position> {
+    let range = |v: u32| v.saturating_sub(FORM_RANGE)..=(v + FORM_RANGE).min(MAX_ANCHOR);
+    range(pos.y())
+        .flat_map(|y| range(pos.x()).map(move |x| Position::new(x, y, pos.room_name())))
+        .filter(|anchor| is_open(matrix, *anchor))
+        .min_by_key(|anchor| (pos.get_range_to(anchor), anchor.x(), anchor.y()))
+}
+
+fn slot_position(anchor: Position, heading: Heading, slot: usize) -> Position {
+    let (dx, dy) = heading.offsets()[slot];
+    Position::new(anchor.x() + dx, anchor.y() + dy, anchor.room_name())
+}
+
+/// Walks every member to its slot, or keeps it there if it's in it already.
+fn move_to_slots(members: &[(usize, Creep)], anchor: Position, heading: Heading) {
+    for (slot, creep) in members {
+        let tile = slot_position(anchor, heading, *slot);
+        if creep.pos() == tile {
+            movement::hold(creep, &tile, 0);
+        } else {
+            movement::move_creep_to(creep, &tile, 0);
+        }
+    }
+}
+
+/// Moves every member the way the anchor goes to `next`.
+fn step(members: &[(usize, Creep)], anchor: Position, next: Position) {
+    let direction = match anchor.get_direction_to(&next) {
+        Some(direction) => direction,
+        None => return,
+    };
+    let dx = next.x() as i32 - anchor.x() as i32;
+    let dy = next.y() as i32 - anchor.y() as i32;
+    for (_, creep) in members {
+        let pos = creep.pos();
+        match creep.move_direction(direction) {
+            ReturnCode::Ok => {
+                let x = (pos.x() as i32 + dx) as u32;
+                let y = (pos.y() as i32 + dy) as u32;
+                let to = Position::new(x, y, pos.room_name());
+                intents::register(creep.name(), pos, to);
+            }
+            r => failures::report(&creep.name(), "move", r),
+        }
+    }
+}
+
+enum Move {
+    /// Where the quad is going, or no path there.
+    Stay,
+    /// Lined up against the exit to the next room on the way.
+    Cross(RoomName),
+    /// The anchor's path, starting with its next tile.
+    Path(Vec<Position>),
+}
+
+/// Where a formed quad goes next: towards its target in the same room, or otherwise to an exit
+/// on the way to it.
+fn next_move(anchor: Position, matrix: &LocalCostMatrix, target: Option<Position>) -> Move {
+    let target = match target {
+        Some(target) => target,
+        None => return Move::Stay,
+    };
+    let room_name = anchor.room_name();
+    let goals: Vec<(Position, u32)> = if target.room_name() == room_name {
+        if anchor.in_range_to(&target, 1) {
+            return Move::Stay;
+        }
+        vec![(target, 1)]
+    } else {
+        let route =
+            screeps::game::map::find_route(&room_name.to_string(), &target.room_name().to_string());
+        let next = match route.ok().and_then(|route| route.into_iter().next()) {
+            Some(next) => next,
+            None => return Move::Stay,
+        };
+        let goals = exit_anchors(room_name, next.exit, matrix);
+        if goals.contains(&anchor) {
+            return Move::Cross(next.room);
+        }
+        goals.into_iter().map(|goal| (goal, 0)).collect()
+    };
+    if goals.is_empty() {
+        return Move::Stay;
+    }
+
+    let matrix = matrix.clone();
+    let options = SearchOptions::new().room_callback(move |name| {
+        if name == room_name {
+            MultiRoomCostResult::CostMatrix(matrix.upload())
+        } else {
+            MultiRoomCostResult::Impassable
+        }
+    });
+    let path = pathfinder::search_many(&anchor, goals, options).load_local_path();
+    if path.is_empty() {
+        Move::Stay
+    } else {
+        Move::Path(path)
+    }
+}
+
+/// The open anchors up against an exit of a room, with at least one of the tiles in front of the
+/// square across it.
+fn exit_anchors(
+    room_name: RoomName,
+    exit: ExitDirection,
+    matrix: &LocalCostMatrix,
+) -> Vec<Position> {
+    let terrain = screeps::game::map::get_room_terrain(room_name);
+    let mut anchors = Vec::new();
+    for i in 1..=MAX_ANCHOR {
+        // the anchor, and the two exit tiles in front of the square
+        let (anchor, exits) = match exit {
+            ExitDirection::Top => ((i, 1), [(i, 0), (i + 1, 0)]),
+            ExitDirection::Right => ((MAX_ANCHOR, i), [(49, i), (49, i + 1)]),
+            ExitDirection::Bottom => ((i, MAX_ANCHOR), [(i, 49), (i + 1, 49)]),
+            ExitDirection::Left => ((1, i), [(0, i), (0, i + 1)]),
+        };
+        let anchor = Position::new(anchor.0, anchor.1, room_name);
+        if is_open(matrix, anchor)
+            && exits
+                .iter()
+                .any(|&(x, y)| terrain.get(x, y) != Terrain::Wall)
+        {
+            anchors.push(anchor);
+        }
+    }
+    anchors
+}
+
+/// Has each healer heal the member missing the most hits after the heals already given out.
+fn heal(members: &[(usize, Creep)]) {
+    let mut missing: Vec<u32> = members
+        .iter()
+        .map(|(_, creep)| Attackable::hits_max(creep) - Attackable::hits(creep))
+        .collect();
+    for (_, healer) in members {
+        let parts = healer.get_active_bodyparts(Part::Heal);
+        if parts == 0 {
+            continue;
+        }
+        let pos = healer.pos();
+        let patient = members
+            .iter()
+            .enumerate()
+            .filter(|&(index, (_, creep))| {
+                missing[index] > 0
+                    && creep.pos().room_name() == pos.room_name()
+                    && pos.in_range_to(creep, RANGED_HEAL_RANGE)
+            })
+            .max_by_key(|&(index, (_, creep))| (missing[index], pos.is_near_to(creep)));
+        let (index, patient) = match patient {
+            Some((index, (_, creep))) => (index, creep),
+            None => continue,
+        };
+        let (r, healed) = if pos.is_near_to(patient) {
+            (healer.heal(patient), HEAL_POWER * parts)
+        } else {
+            (healer.ranged_heal(patient), RANGED_HEAL_POWER * parts)
+        };
+        if r == ReturnCode::Ok {
+            missing[index] = missing[index].saturating_sub(healed);
+        } else {
+            failures::report(&healer.name(), "heal", r);
+        }
+    }
+}
+
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![MEMBERS.with(|m| CacheSize::of_set("formation.members", &m.borrow()))]
+}
+
+pub fn purge() {
+    MEMBERS.with(|m| std::mem::take(&mut *m.borrow_mut()));
+}
@@ -0,0 +1,159 @@
+//! Heap usage of the long-lived caches.
+//!
+//! Every module keeping state on the heap reports the size of its caches, which are logged at
+//! debug level and written to `Memory.stats.heap` every [`REPORT_INTERVAL`] ticks. A cache which
+//! kept growing for [`GROWTH_REPORTS`] reports in a row is warned about, since that's usually
+//! entries which are never dropped.
+//!
+//! [`purge`] clears everything which is rebuilt on its own, as a last resort for a heap which is
+//! about to run out. It's called with `purge_caches()` from the console.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    mem::size_of,
+};
+
+use log::*;
+
+use crate::{
+    attackers, construction, creep_costs, creeps, defense, energy, formation, intel, journal,
+    lifetimes, movement, nukes, planner, tasks, threat, traffic,
+};
+
+/// How often the sizes are reported.
+pub const REPORT_INTERVAL: u32 = 100;
+
+/// How many reports in a row a cache may grow before it's warned about.
+const GROWTH_REPORTS: u32 = 20;
+
+/// The size of one cache.
+pub struct CacheSize {
+    pub name: &'static str,
+    pub entries: usize,
+    /// Roughly how much heap the cache takes up.
+    pub bytes: usize,
+}
+
+impl CacheSize {
+    /// Sizes a map by its capacity, plus whatever `extra` says each entry keeps on the heap.
+    pub fn of_map<K, V>(
+        name: &'static str,
+        map: &HashMap<K, V>,
+        extra: impl Fn(&K, &V) -> usize,
+    ) -> Self {
+        CacheSize {
+            name,
+            entries: map.len(),
+            bytes: map.capacity() * (size_of::<K>() + size_of::<V>())
+                + map.iter().map(|(k, v)| extra(k, v)).sum::<usize>(),
+        }
+    }
+
+    pub fn of_set<T>(name: &'static str, set: &HashSet<T>) -> Self {
+        CacheSize {
+            name,
+            entries: set.len(),
+            bytes: set.capacity() * size_of::<T>(),
+        }
+    }
+}
+
+thread_local! {
+    /// The entry count of each cache at the last report, and how many reports in a row it grew.
+    static GROWTH: RefCell<HashMap<&'static str, (usize, u32)>> = RefCell::new(HashMap::new());
+}
+
+pub fn all_caches() -> Vec<CacheSize> {
+    let mut sizes = Vec::new();
+    sizes.extend(attackers::cache_sizes());
+    sizes.extend(construction::cache_sizes());
+    sizes.extend(creeps::cache_sizes());
+    sizes.extend(creep_costs::cache_sizes());
+    sizes.extend(defense::cache_sizes());
+    sizes.extend(energy::cache_sizes());
+    sizes.extend(formation::cache_sizes());
+    sizes.extend(intel::cache_sizes());
+    sizes.extend(journal::cache_sizes());
+    sizes.extend(lifetimes::cache_sizes());
+    sizes.extend(movement::cache_sizes());
+    sizes.extend(nukes::cache_sizes());
+    sizes.extend(planner::cache_sizes());
+    sizes.extend(tasks::cache_sizes());
+    sizes.extend(threat::cache_sizes());
+    sizes.extend(traffic::cache_sizes());
+    sizes
+}
+
+/// Logs the size of every cache, stores them in `Memory.stats.heap` and warns about caches which
+/// keep growing.
+pub fn report() {
+    let sizes = all_caches();
+
+    let stats = screeps::memory::root()
+        .dict_or_create("stats")
+        .and_then(|stats| stats.dict_or_create("heap"));
+    if let Err(e) = &stats {
+        warn!("couldn't store heap stats: {}", e);
+    }
+
+    let total: usize = sizes.iter().map(|s| s.bytes).sum();
+    debug!("caches take up about {} kB:", total / 1024);
+    for size in &sizes {
+        debug!(
+            "  {}: {} entries, {} kB",
+            size.name,
+            size.entries,
+            size.bytes / 1024
+        );
+        if let Ok(stats) = &stats {
+            if let Ok(entry) = stats.dict_or_create(size.name) {
+                entry.set("entries", size.entries as u32);
+                entry.set("bytes", size.bytes as u32);
+            }
+        }
+    }
+
+    GROWTH.with(|growth| {
+        let mut growth = growth.borrow_mut();
+        for size in &sizes {
+            let (last, streak) = growth.entry(size.name).or_insert((size.entries, 0));
+            *streak = if size.entries > *last { *streak + 1 } else { 0 };
+            *last = size.entries;
+            if *streak == GROWTH_REPORTS {
+                warn!(
+                    "cache {} has grown for {} ticks straight, to {} entries",
+                    size.name,
+                    GROWTH_REPORTS * REPORT_INTERVAL,
+                    size.entries
+                );
+            }
+        }
+    });
+}
+
+/// Clears every cache which is rebuilt on its own, returning roughly how much was freed.
+pub fn purge() -> usize {
+    let before: usize = all_caches().iter().map(|s| s.bytes).sum();
+    attackers::purge();
+    construction::purge();
+    creeps::purge();
+    creep_costs::purge();
+    defense::purge();
+    formation::purge();
+    intel::purge();
+    movement::purge();
+    nukes::purge();
+    planner::purge();
+    tasks::purge();
+    threat::purge();
+    traffic::purge();
+    GROWTH.with(|growth| growth.borrow_mut().clear());
+    let after: usize = all_caches().iter().map(|s| s.bytes).sum();
+
+    warn!(
+        "purged all caches, freeing about {} kB",
+        before.saturating_sub(after) / 1024
+    );
+    before.saturating_sub(after)
+}
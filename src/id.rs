@@ -0,0 +1,297 @@
+//! Ids for naming creeps.
+//!
+//! Creeps get names like `H-BraveOtter42`, from the short code of their role, an adjective, an
+//! animal and a two digit number, which are easier to tell apart in logs than ids. The role can be
+//! told from the name alone, for creeps whose memory lost it. When no free name turns up in a few
+//! tries, they're named by their serial instead, like `H-c-1Z`.
+//!
+//! The serial is a number counting up over the whole game, in base62, so names stay short and
+//! show which creep came first. The next serial is kept in `Memory.next_creep_serial`.
+//! Should it go missing or get mangled, counting picks up after the highest serial among the live
+//! creeps.
+//!
+//! Short random ids of [`SHORT_ID_LENGTH`] base62 characters are there for names which don't need
+//! an order. At that length two may well be the same over a long game, so random creep names are
+//! checked against the live creeps and drawn again if taken.
+//!
+//! Names come from the seeded [`rng`](crate::rng), so names drawn after two resets can repeat,
+//! which the check against live creeps deals with like any other taken name.
+
+use std::cell::Cell;
+
+use log::*;
+
+use crate::rng;
+
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+pub const SHORT_ID_LENGTH: usize = 6;
+
+const SERIAL_KEY: &str = "next_creep_serial";
+
+const ADJECTIVES: &[&str] = &[
+    "Brave", "Calm", "Clever", "Eager", "Fierce", "Gentle", "Happy", "Jolly", "Keen", "Lucky",
+    "Nimble", "Proud", "Quick", "Sly", "Steady", "Wild",
+];
+
+const ANIMALS: &[&str] = &[
+    "Badger", "Beaver", "Falcon", "Ferret", "Heron", "Lynx", "Marten", "Mole", "Otter", "Owl",
+    "Panda", "Raven", "Stoat", "Tapir", "Viper", "Wombat",
+];
+
+/// What serial creep names start with, after the role's code.
+pub const SERIAL_PREFIX: &str = "c-";
+
+/// What separates the role's code from the rest of a creep name.
+const ROLE_SEPARATOR: char = '-';
+
+/// How long the role codes names start with can be, keeping names under the game's 20
+/// characters.
+pub const MAX_ROLE_CODE_LENGTH: usize = 2;
+
+thread_local! {
+    /// The next serial, once it's been read from memory.
+    static NEXT_SERIAL: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// How many ids are drawn for a creep name before giving up on finding a free one.
+const MAX_NAME_ATTEMPTS: u32 = 10;
+
+/// Encodes `n` in base62, padded with zeros to at least `length` characters.
+pub fn encode_base62(mut n: u64, length: usize) -> String {
+    let mut digits = Vec::with_capacity(length);
+    while n > 0 || digits.len() < length {
+        digits.push(ALPHABET[(n % 62) as usize]);
+        n /= 62;
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("expected base62 digits to be ascii")
+}
+
+/// Decodes a base62 number, or `None` if it has other characters or doesn't fit.
+pub fn decode_base62(s: &str) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    s.bytes().try_fold(0u64, |n, digit| {
+        let value = ALPHABET.iter().position(|&d| d == digit)? as u64;
+        n.checked_mul(62)?.checked_add(value)
+    })
+}
+
+/// Takes the next serial and saves the one after it.
+pub fn next_serial() -> u64 {
+    let serial = NEXT_SERIAL.with(Cell::get).unwrap_or_else(load_serial);
+    NEXT_SERIAL.with(|next| next.set(Some(serial + 1)));
+    // memory only holds numbers as doubles
+    screeps::memory::root().set(SERIAL_KEY, (serial + 1) as f64);
+    serial
+}
+
+/// Reads the next serial after a reset, recovering it from the live creeps' names if memory
+/// doesn't have a usable one.
+fn load_serial() -> u64 {
+    let stored = screeps::memory::root().f64(SERIAL_KEY).ok().flatten();
+    match stored_serial(stored) {
+        Some(serial) => serial,
+        None => {
+            let recovered = serial_after(screeps::game::creeps::keys().iter().map(String::as_str));
+            warn!(
+                "no usable Memory.{}, counting creeps from {}",
+                SERIAL_KEY, recovered
+            );
+            recovered
+        }
+    }
+}
+
+/// The serial stored in memory, if it's one.
+fn stored_serial(stored: Option<f64>) -> Option<u64> {
+    stored
+        .filter(|serial| serial.is_finite() && *serial >= 0.0)
+        .map(|serial| serial as u64)
+}
+
+/// The role code a creep name starts with, if it has one. Names from before roles were put in
+/// them don't.
+pub fn role_code(name: &str) -> Option<&str> {
+    if name.starts_with(SERIAL_PREFIX) {
+        return None;
+    }
+    let end = name.find(ROLE_SEPARATOR)?;
+    Some(&name[..end])
+}
+
+/// A creep name without its role code.
+fn without_role_code(name: &str) -> &str {
+    match role_code(name) {
+        Some(code) => &name[code.len() + 1..],
+        None => name,
+    }
+}
+
+/// The serial after the highest one among `names`, or 0 if none of them is a serial name.
+fn serial_after<'a>(names: impl IntoIterator<Item = &'a str>) -> u64 {
+    names
+        .into_iter()
+        .filter_map(|name| decode_base62(without_role_code(name).strip_prefix(SERIAL_PREFIX)?))
+        .max()
+        .map_or(0, |serial| serial.saturating_add(1))
+}
+
+/// A name for the next creep of the role with `code`, from its serial.
+pub fn serial_creep_name(code: &str) -> String {
+    with_role_code(
+        code,
+        &format!("{}{}", SERIAL_PREFIX, encode_base62(next_serial(), 1)),
+    )
+}
+
+fn with_role_code(code: &str, name: &str) -> String {
+    debug_assert!(code.len() <= MAX_ROLE_CODE_LENGTH && !code.contains(ROLE_SEPARATOR));
+    format!("{}{}{}", code, ROLE_SEPARATOR, name)
+}
+
+/// A random id of [`SHORT_ID_LENGTH`] characters.
+pub fn short_id() -> String {
+    encode_base62(
+        rng::below(62u64.pow(SHORT_ID_LENGTH as u32)),
+        SHORT_ID_LENGTH,
+    )
+}
+
+/// A random short id after the role's `code` which no live creep is named, or `None` if none was
+/// found in a few tries.
+pub fn random_creep_name(code: &str) -> Option<String> {
+    (0..MAX_NAME_ATTEMPTS)
+        .map(|_| with_role_code(code, &short_id()))
+        .find(|name| screeps::game::creeps::get(name).is_none())
+}
+
+/// A random name like `BraveOtter42`, which is at most 14 characters long.
+fn friendly_name() -> String {
+    format!(
+        "{}{}{:02}",
+        ADJECTIVES[rng::below(ADJECTIVES.len() as u64) as usize],
+        ANIMALS[rng::below(ANIMALS.len() as u64) as usize],
+        rng::below(100)
+    )
+}
+
+/// A name for a new creep of the role with `code` which no live creep has, falling back to the
+/// next serial. It's at most 17 characters long.
+pub fn creep_name(code: &str) -> String {
+    (0..MAX_NAME_ATTEMPTS)
+        .map(|_| with_role_code(code, &friendly_name()))
+        .find(|name| screeps::game::creeps::get(name).is_none())
+        .unwrap_or_else(|| serial_creep_name(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base62_digits_follow_the_alphabet() {
+        assert_eq!(encode_base62(9, 1), "9");
+        assert_eq!(encode_base62(10, 1), "A");
+        assert_eq!(encode_base62(36, 1), "a");
+        assert_eq!(encode_base62(61, 1), "z");
+        assert_eq!(encode_base62(62, 1), "10");
+        assert_eq!(encode_base62(3843, 1), "zz");
+        assert_eq!(encode_base62(u64::MAX, 1), "LygHa16AHYF");
+    }
+
+    #[test]
+    fn base62_is_padded_to_the_length() {
+        assert_eq!(encode_base62(0, 0), "");
+        assert_eq!(encode_base62(0, 1), "0");
+        assert_eq!(encode_base62(5, 4), "0005");
+        assert_eq!(encode_base62(62 * 62 * 62, 2), "1000");
+        assert_eq!(decode_base62("0005"), Some(5));
+    }
+
+    #[test]
+    fn base62_round_trips() {
+        for &n in &[0, 1, 61, 62, 3843, 3844, 1_000_000_007, u64::MAX] {
+            assert_eq!(decode_base62(&encode_base62(n, 1)), Some(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn base62_rejects_what_isnt_a_number() {
+        assert_eq!(decode_base62(""), None);
+        assert_eq!(decode_base62("1-2"), None);
+        assert_eq!(decode_base62("ä"), None);
+    }
+
+    #[test]
+    fn base62_rejects_numbers_which_dont_fit() {
+        let max = encode_base62(u64::MAX, 1);
+        // one more than the largest number, and the same with another digit
+        assert_eq!(decode_base62("LygHa16AHYG"), None);
+        assert_eq!(decode_base62(&format!("{}0", max)), None);
+        assert_eq!(decode_base62(&"z".repeat(12)), None);
+    }
+
+    #[test]
+    fn short_ids_fit_the_length() {
+        let max = 62u64.pow(SHORT_ID_LENGTH as u32) - 1;
+        assert_eq!(encode_base62(max, SHORT_ID_LENGTH).len(), SHORT_ID_LENGTH);
+    }
+
+    #[test]
+    fn stored_serial_has_to_be_a_count() {
+        assert_eq!(stored_serial(Some(42.0)), Some(42));
+        assert_eq!(stored_serial(Some(0.0)), Some(0));
+        assert_eq!(stored_serial(None), None);
+        assert_eq!(stored_serial(Some(-1.0)), None);
+        assert_eq!(stored_serial(Some(f64::NAN)), None);
+        assert_eq!(stored_serial(Some(f64::INFINITY)), None);
+    }
+
+    #[test]
+    fn serial_picks_up_after_the_highest_serial_name() {
+        assert_eq!(serial_after(vec!["c-0", "c-Z", "c-9"]), 36);
+        assert_eq!(serial_after(vec!["c-10"]), 63);
+        assert_eq!(serial_after(Vec::new()), 0);
+    }
+
+    #[test]
+    fn serial_ignores_names_which_arent_serials() {
+        assert_eq!(
+            serial_after(vec!["BraveOtter42", "aB12cd", "c-", "c-1-2"]),
+            0
+        );
+        assert_eq!(serial_after(vec!["BraveOtter42", "c-5", "x-zz"]), 6);
+        // past what fits isn't a serial either
+        assert_eq!(serial_after(vec!["c-zzzzzzzzzzzz", "c-3"]), 4);
+        assert_eq!(serial_after(vec!["c-LygHa16AHYF"]), u64::MAX);
+    }
+
+    #[test]
+    fn serials_are_read_past_the_role_code() {
+        assert_eq!(serial_after(vec!["H-c-Z", "W-BraveOtter42"]), 36);
+        // names from before role codes count too
+        assert_eq!(serial_after(vec!["H-c-5", "c-9"]), 10);
+    }
+
+    #[test]
+    fn role_codes_are_read_from_the_start_of_names() {
+        assert_eq!(role_code("H-BraveOtter42"), Some("H"));
+        assert_eq!(role_code("RD-c-1Z"), Some("RD"));
+        assert_eq!(role_code("W-aB12cd"), Some("W"));
+        assert_eq!(role_code("BraveOtter42"), None);
+        assert_eq!(role_code("c-1Z"), None);
+    }
+
+    #[test]
+    fn names_with_a_role_code_fit_the_game_limit() {
+        let code = "X".repeat(MAX_ROLE_CODE_LENGTH);
+        let longest_friendly = format!("{}{}{:02}", "Nimble", "Wombat", 99);
+        assert_eq!(with_role_code(&code, &longest_friendly).len(), 17);
+        let serial = format!("{}{}", SERIAL_PREFIX, encode_base62(u64::MAX, 1));
+        assert!(with_role_code(&code, &serial).len() < 20);
+        assert!(with_role_code(&code, &"0".repeat(SHORT_ID_LENGTH)).len() < 20);
+    }
+}
@@ -0,0 +1,565 @@
+//! What we know about other rooms.
+//!
+//! Every visible room is scanned regularly into a [`RoomIntel`] record, so rooms we can't see
+//! right now can still be judged by what they looked like last time. The records are kept in
+//! the [`segments::INTEL`] memory segment, as there are far too many for `Memory`, and loaded
+//! into the heap after a reset, until which every room looks like it was never seen. A room
+//! [`get`] returns nothing for has never been seen, while an empty room we saw has a record with
+//! nothing in it; [`RoomIntel::age`] tells how old the record is.
+//!
+//! Only [`MAX_ROOMS`] rooms are kept, and past that the ones which have gone longest without
+//! being seen or looked up are dropped.
+//!
+//! Portals are kept in `Memory.rooms.<name>`, along with where they lead and, for the ones which
+//! decay, the tick they disappear at. So is the tick until which a room isn't to be used as a
+//! remote, for remotes which were wound down for not paying for themselves.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use log::*;
+use screeps::{
+    find, objects::PortalDestination, prelude::*, Attackable, Part, Position, Room, RoomName,
+    Structure,
+};
+use stdweb::{js, unstable::TryInto};
+
+use crate::{allies, context::TickContext, heap::CacheSize, planner, segments};
+
+const PORTALS_KEY: &str = "portals";
+const DO_NOT_REMOTE_KEY: &str = "do_not_remote";
+
+/// What intel used to keep in `Memory.rooms.<name>` before it had a segment.
+const LEGACY_KEYS: &[&str] = &["hostile", "threat", "sources"];
+
+/// Who owns the keepers guarding the sources of source keeper rooms.
+pub const SOURCE_KEEPER_OWNER: &str = "Source Keeper";
+
+/// How many rooms are kept.
+pub const MAX_ROOMS: usize = 1000;
+
+/// What a room looked like when we last saw it.
+#[derive(Clone, Debug, Default)]
+pub struct RoomIntel {
+    pub sources: Vec<(u8, u8)>,
+    /// The mineral's resource type, like `"H"`.
+    pub mineral: Option<String>,
+    pub controller: Option<ControllerIntel>,
+    /// How many structures other players have in the room.
+    pub hostile_structures: u32,
+    /// Owned by someone else or defended by their towers.
+    pub hostile: bool,
+    /// Armed hostile creeps, like invaders, were in the room. Source keepers, which are always
+    /// there, don't count.
+    pub threat: bool,
+    /// An invader core was in the room, which sends out raids.
+    pub invader_core: bool,
+    pub power_bank: Option<PowerBankIntel>,
+    pub last_seen: u32,
+    /// When the record was last looked up, so rooms nobody asks about are dropped first.
+    last_used: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ControllerIntel {
+    pub owner: Option<String>,
+    pub reserved_by: Option<String>,
+    pub level: u32,
+}
+
+/// A power bank on a highway.
+#[derive(Clone, Debug, Default)]
+pub struct PowerBankIntel {
+    pub pos: (u8, u8),
+    pub hits: u32,
+    pub power: u32,
+    /// The tick the bank disappears at.
+    pub decays_at: u32,
+}
+
+impl RoomIntel {
+    /// How many ticks ago the room was seen.
+    pub fn age(&self) -> u32 {
+        screeps::game::time().saturating_sub(self.last_seen)
+    }
+
+    fn survey(room: &Room, ctx: &TickContext) -> Self {
+        let time = ctx.time;
+        let controller = room.controller().map(|c| ControllerIntel {
+            owner: c.owner_name(),
+            reserved_by: c.reservation().map(|r| r.username),
+            level: c.level(),
+        });
+        let hostile_structures: Vec<Structure> =
+            allies::without_allies(room.find(find::HOSTILE_STRUCTURES), |s| s.owner_name())
+                .into_iter()
+                .map(|s| s.as_structure())
+                .collect();
+        let owned_by_others = room.controller().map_or(false, |c| {
+            !c.my() && c.level() > 0 && !c.owner_name().map_or(false, |o| allies::is_ally(&o))
+        });
+        let hostile = owned_by_others
+            || hostile_structures
+                .iter()
+                .any(|s| matches!(s, Structure::Tower(_)));
+        let threat = ctx.rooms.snapshot(room).hostiles().iter().any(|c| {
+            c.owner_name() != SOURCE_KEEPER_OWNER
+                && (c.get_active_bodyparts(Part::Attack) > 0
+                    || c.get_active_bodyparts(Part::RangedAttack) > 0)
+        });
+        let invader_core = hostile_structures
+            .iter()
+            .any(|s| matches!(s, Structure::InvaderCore(_)));
+        let power_bank = room
+            .find(find::STRUCTURES)
+            .into_iter()
+            .find_map(|s| match s {
+                Structure::PowerBank(bank) => Some(PowerBankIntel {
+                    pos: (bank.pos().x() as u8, bank.pos().y() as u8),
+                    hits: bank.hits(),
+                    power: bank.power(),
+                    decays_at: time + bank.ticks_to_decay(),
+                }),
+                _ => None,
+            });
+        RoomIntel {
+            sources: room
+                .find(find::SOURCES)
+                .iter()
+                .map(|s| (s.pos().x() as u8, s.pos().y() as u8))
+                .collect(),
+            mineral: room.find(find::MINERALS).first().and_then(|mineral| {
+                let id = mineral.id().to_string();
+                let kind = js! { return Game.getObjectById(@{id}).mineralType };
+                kind.try_into().ok()
+            }),
+            controller,
+            hostile_structures: hostile_structures.len() as u32,
+            hostile,
+            threat,
+            invader_core,
+            power_bank,
+            last_seen: time,
+            last_used: time,
+        }
+    }
+
+    /// Serializes the record as `name|last_seen|last_used|hostile|threat|hostile_structures|
+    /// controller|mineral|sources|invader_core|power_bank`, the controller as
+    /// `owner,reserved_by,level`, the sources as `x,y;...` and the power bank as
+    /// `x,y,hits,power,decays_at`. Missing values are left empty.
+    fn encode(&self, room_name: RoomName) -> String {
+        let controller = self.controller.as_ref().map_or(String::new(), |c| {
+            format!(
+                "{},{},{}",
+                c.owner.as_deref().unwrap_or(""),
+                c.reserved_by.as_deref().unwrap_or(""),
+                c.level
+            )
+        });
+        let power_bank = self.power_bank.as_ref().map_or(String::new(), |b| {
+            format!(
+                "{},{},{},{},{}",
+                b.pos.0, b.pos.1, b.hits, b.power, b.decays_at
+            )
+        });
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect();
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+            room_name,
+            self.last_seen,
+            self.last_used,
+            self.hostile as u8,
+            self.threat as u8,
+            self.hostile_structures,
+            controller,
+            self.mineral.as_deref().unwrap_or(""),
+            sources.join(";"),
+            self.invader_core as u8,
+            power_bank
+        )
+    }
+
+    fn decode(s: &str) -> Option<(RoomName, Self)> {
+        let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        let mut fields = s.split('|');
+        let room_name = RoomName::new(fields.next()?).ok()?;
+        let last_seen = fields.next()?.parse().ok()?;
+        let last_used = fields.next()?.parse().ok()?;
+        let hostile = fields.next()? == "1";
+        let threat = fields.next()? == "1";
+        let hostile_structures = fields.next()?.parse().ok()?;
+        let controller = match fields.next()? {
+            "" => None,
+            encoded => {
+                let mut parts = encoded.split(',');
+                Some(ControllerIntel {
+                    owner: non_empty(parts.next()?),
+                    reserved_by: non_empty(parts.next()?),
+                    level: parts.next()?.parse().ok()?,
+                })
+            }
+        };
+        let mineral = non_empty(fields.next()?);
+        let sources = fields
+            .next()?
+            .split(';')
+            .filter_map(|entry| {
+                let mut parts = entry.split(',');
+                Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+            })
+            .collect();
+        // records from before invader cores and power banks were kept don't have the fields
+        let invader_core = fields.next() == Some("1");
+        let power_bank = fields.next().and_then(|encoded| {
+            let mut parts = encoded.split(',');
+            Some(PowerBankIntel {
+                pos: (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?),
+                hits: parts.next()?.parse().ok()?,
+                power: parts.next()?.parse().ok()?,
+                decays_at: parts.next()?.parse().ok()?,
+            })
+        });
+        let intel = RoomIntel {
+            sources,
+            mineral,
+            controller,
+            hostile_structures,
+            hostile,
+            threat,
+            invader_core,
+            power_bank,
+            last_seen,
+            last_used,
+        };
+        Some((room_name, intel))
+    }
+}
+
+/// A portal seen in a room.
+#[derive(Clone, Debug)]
+pub struct Portal {
+    pub pos: Position,
+    /// Where the portal leads, either a position like `W10N10:25:25` or a room on another shard
+    /// like `shard1/W10N10`.
+    pub destination: String,
+    pub decays_at: Option<u32>,
+}
+
+thread_local! {
+    /// Every known room, or `None` while the segment hasn't been loaded.
+    static ROOMS: RefCell<Option<HashMap<RoomName, RoomIntel>>> = RefCell::new(None);
+    /// Whether the records changed since they were last saved.
+    static DIRTY: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` on the known rooms, loading them first if needed. Returns `None` while the segment
+/// can't be read yet.
+fn with_rooms<R>(f: impl FnOnce(&mut HashMap<RoomName, RoomIntel>) -> R) -> Option<R> {
+    ROOMS.with(|rooms| {
+        let mut rooms = rooms.borrow_mut();
+        if rooms.is_none() {
+            let encoded = segments::load(segments::INTEL)?;
+            let loaded: HashMap<RoomName, RoomIntel> =
+                encoded.lines().filter_map(RoomIntel::decode).collect();
+            debug!("loaded intel on {} rooms", loaded.len());
+            *rooms = Some(loaded);
+        }
+        rooms.as_mut().map(f)
+    })
+}
+
+/// Updates the record of every visible room and saves them.
+pub fn scan(ctx: &TickContext) {
+    for room in screeps::game::rooms::values() {
+        if let Some(memory) = planner::room_memory(room.name()) {
+            for key in LEGACY_KEYS {
+                memory.del(key);
+            }
+            let portals = find_portals(&room);
+            if portals.is_empty() {
+                memory.del(PORTALS_KEY);
+            } else {
+                memory.set(PORTALS_KEY, encode_portals(&portals));
+            }
+        }
+    }
+
+    let scanned = with_rooms(|rooms| {
+        for room in screeps::game::rooms::values() {
+            let mut intel = RoomIntel::survey(&room, ctx);
+            if let Some(old) = rooms.get(&room.name()) {
+                intel.last_used = intel.last_used.max(old.last_used);
+            }
+            rooms.insert(room.name(), intel);
+        }
+        evict(rooms);
+    });
+    if scanned.is_none() {
+        debug!("intel isn't loaded yet, not scanning");
+        return;
+    }
+    DIRTY.with(|d| d.set(true));
+    save();
+}
+
+/// Drops the rooms which have gone longest without being seen or looked up, past
+/// [`MAX_ROOMS`].
+fn evict(rooms: &mut HashMap<RoomName, RoomIntel>) {
+    if rooms.len() <= MAX_ROOMS {
+        return;
+    }
+    let mut by_relevance: Vec<(u32, RoomName)> = rooms
+        .iter()
+        .map(|(name, intel)| (intel.last_seen.max(intel.last_used), *name))
+        .collect();
+    by_relevance.sort();
+    let excess = rooms.len() - MAX_ROOMS;
+    for (_, name) in by_relevance.into_iter().take(excess) {
+        rooms.remove(&name);
+    }
+    debug!("dropped intel on {} rooms", excess);
+}
+
+/// Writes the records to their segment, if they changed.
+fn save() {
+    if !DIRTY.with(|d| d.replace(false)) {
+        return;
+    }
+    let encoded = with_rooms(|rooms| {
+        rooms
+            .iter()
+            .map(|(name, intel)| intel.encode(*name))
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    if let Some(encoded) = encoded {
+        if let Err(e) = segments::save(segments::INTEL, &encoded) {
+            error!("couldn't save intel: {}", e);
+        }
+    }
+}
+
+/// What a room looked like when we last saw it, or `None` if we never have.
+pub fn get(room_name: RoomName) -> Option<RoomIntel> {
+    with_rooms(|rooms| {
+        let intel = rooms.get_mut(&room_name)?;
+        intel.last_used = screeps::game::time();
+        Some(intel.clone())
+    })
+    .flatten()
+}
+
+/// Every room we have a record of, without counting as looking them up.
+pub fn known_rooms() -> Vec<(RoomName, RoomIntel)> {
+    with_rooms(|rooms| {
+        rooms
+            .iter()
+            .map(|(name, intel)| (*name, intel.clone()))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Known rooms which haven't been seen for more than `max_age` ticks, longest unseen first.
+pub fn rooms_needing_refresh(max_age: u32) -> Vec<RoomName> {
+    let mut stale: Vec<(u32, RoomName)> = with_rooms(|rooms| {
+        rooms
+            .iter()
+            .filter(|(_, intel)| intel.age() > max_age)
+            .map(|(name, intel)| (intel.last_seen, *name))
+            .collect()
+    })
+    .unwrap_or_default();
+    stale.sort();
+    stale.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Whether a room was owned by someone else or defended by their towers when we last saw it.
+pub fn is_hostile(room_name: RoomName) -> bool {
+    get(room_name).map_or(false, |intel| intel.hostile)
+}
+
+/// Whether armed hostile creeps were in a room when we last saw it.
+pub fn is_threatened(room_name: RoomName) -> bool {
+    get(room_name).map_or(false, |intel| intel.threat)
+}
+
+/// Where a room's sources are, or `None` if we haven't seen the room yet.
+pub fn sources(room_name: RoomName) -> Option<Vec<Position>> {
+    let intel = get(room_name)?;
+    let sources = intel
+        .sources
+        .iter()
+        .map(|&(x, y)| Position::new(x as u32, y as u32, room_name))
+        .collect();
+    Some(sources)
+}
+
+/// Every room marked hostile when we last saw it.
+pub fn hostile_rooms() -> Vec<RoomName> {
+    with_rooms(|rooms| {
+        rooms
+            .iter()
+            .filter(|(_, intel)| intel.hostile)
+            .map(|(name, _)| *name)
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The portals of a room when we last saw it, leaving out ones which have decayed since.
+pub fn portals(room_name: RoomName) -> Vec<Portal> {
+    let time = screeps::game::time();
+    planner::room_memory(room_name)
+        .and_then(|memory| memory.string(PORTALS_KEY).ok()?)
+        .map(|encoded| decode_portals(room_name, &encoded))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|p| p.decays_at.map_or(true, |at| at > time))
+        .collect()
+}
+
+/// Keeps a room from being used as a remote until the given tick.
+pub fn mark_do_not_remote(room_name: RoomName, until: u32) {
+    if let Some(memory) = planner::room_memory(room_name) {
+        memory.set(DO_NOT_REMOTE_KEY, until);
+    }
+}
+
+/// The tick until which a room isn't to be used as a remote, if that's still ahead.
+pub fn do_not_remote_until(room_name: RoomName) -> Option<u32> {
+    let until = planner::room_memory(room_name)?
+        .i32(DO_NOT_REMOTE_KEY)
+        .ok()??;
+    Some(until as u32).filter(|&until| until > screeps::game::time())
+}
+
+/// Lets a room be used as a remote again, returning whether it was marked.
+pub fn clear_do_not_remote(room_name: RoomName) -> bool {
+    let marked = do_not_remote_until(room_name).is_some();
+    if let Some(memory) = planner::room_memory(room_name) {
+        memory.del(DO_NOT_REMOTE_KEY);
+    }
+    marked
+}
+
+/// Whether a room is one of the source keeper rooms around a sector's center.
+pub fn is_source_keeper(room_name: RoomName) -> bool {
+    match sector_coords(room_name) {
+        Some((x, y)) => (4..=6).contains(&x) && (4..=6).contains(&y) && (x, y) != (5, 5),
+        None => false,
+    }
+}
+
+/// Whether a room is part of the highways between sectors.
+pub fn is_highway(room_name: RoomName) -> bool {
+    match sector_coords(room_name) {
+        Some((x, y)) => x == 0 || y == 0,
+        None => false,
+    }
+}
+
+/// Sizes of the loaded records, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    let size = ROOMS.with(|rooms| {
+        let rooms = rooms.borrow();
+        let empty = HashMap::new();
+        CacheSize::of_map(
+            "intel.rooms",
+            rooms.as_ref().unwrap_or(&empty),
+            |_, intel| intel.sources.capacity() * 2,
+        )
+    });
+    vec![size]
+}
+
+/// Saves the records and drops them from the heap, to be loaded from the segment again.
+pub fn purge() {
+    save();
+    ROOMS.with(|rooms| rooms.borrow_mut().take());
+}
+
+fn find_portals(room: &Room) -> Vec<Portal> {
+    let time = screeps::game::time();
+    room.find(find::STRUCTURES)
+        .into_iter()
+        .filter_map(|s| match s {
+            Structure::Portal(portal) => {
+                let destination = match portal.destination() {
+                    PortalDestination::InterRoom(pos) => {
+                        format!("{}:{}:{}", pos.room_name(), pos.x(), pos.y())
+                    }
+                    // the fields of inter-shard destinations aren't public
+                    PortalDestination::InterShard(_) => {
+                        let id = portal.id().to_string();
+                        let dest = js! {
+                            var dest = Game.getObjectById(@{id}).destination;
+                            return dest.shard + "/" + dest.room;
+                        };
+                        dest.try_into().unwrap_or_default()
+                    }
+                };
+                // portals which don't decay have no ticksToDecay at all
+                let id = portal.id().to_string();
+                let ticks = js! { return Game.getObjectById(@{id}).ticksToDecay };
+                let ticks: Option<u32> = ticks.try_into().ok();
+                Some(Portal {
+                    pos: portal.pos(),
+                    destination,
+                    decays_at: ticks.map(|t| time + t),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Serializes portals as `x,y,destination,decays_at;...`, with an empty `decays_at` for portals
+/// which don't decay.
+fn encode_portals(portals: &[Portal]) -> String {
+    let entries: Vec<String> = portals
+        .iter()
+        .map(|p| {
+            let decays_at = p.decays_at.map(|t| t.to_string()).unwrap_or_default();
+            format!(
+                "{},{},{},{}",
+                p.pos.x(),
+                p.pos.y(),
+                p.destination,
+                decays_at
+            )
+        })
+        .collect();
+    entries.join(";")
+}
+
+fn decode_portals(room_name: RoomName, s: &str) -> Vec<Portal> {
+    s.split(';')
+        .filter_map(|entry| {
+            let mut fields = entry.split(',');
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            Some(Portal {
+                pos: Position::new(x, y, room_name),
+                destination: fields.next()?.to_owned(),
+                decays_at: fields.next()?.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// A room's position within its 10x10 sector, parsed from names like `W12N5`.
+fn sector_coords(room_name: RoomName) -> Option<(u32, u32)> {
+    let name = room_name.to_string();
+    let split = name[1..].find(|c| c == 'N' || c == 'S')? + 1;
+    let x: u32 = name[1..split].parse().ok()?;
+    let y: u32 = name[split + 1..].parse().ok()?;
+    Some((x % 10, y % 10))
+}
@@ -0,0 +1,142 @@
+//! A journal of the decisions the bot makes, to work out afterwards why it did something.
+//!
+//! Target assignments, spawn requests, plans being saved or accepted and threat levels changing
+//! are [`record`]ed as `tick|subsystem|inputs|decision`, where `inputs` is a hash of what the
+//! decision was made from. Replaying a run from the same rng seed and intel should come to the
+//! same hashes, so the first entry which doesn't is where the replay went its own way.
+//!
+//! The journal is off unless `Memory.debug_journal` is set, which `set_journal(on)` does from the
+//! console. The flag is read once per tick, so recording costs a single check while it's off,
+//! and the decision is only formatted while it's on. The last [`MAX_ENTRIES`] entries are kept on
+//! the heap and written to the [`segments::JOURNAL`] segment at the end of every tick which added
+//! one, so they survive resets, and `dump_journal(count)` prints the latest ones.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use log::*;
+
+use crate::{heap::CacheSize, segments};
+
+const ENABLED_KEY: &str = "debug_journal";
+
+/// How many entries are kept.
+pub const MAX_ENTRIES: usize = 1000;
+
+thread_local! {
+    static ENABLED: Cell<bool> = Cell::new(false);
+    /// The encoded entries, oldest first.
+    static ENTRIES: RefCell<VecDeque<String>> = RefCell::new(VecDeque::new());
+    /// Whether the entries from before the reset were read from the segment.
+    static LOADED: Cell<bool> = Cell::new(false);
+    /// Whether entries were added since the segment was last written.
+    static DIRTY: Cell<bool> = Cell::new(false);
+}
+
+/// Reads whether the journal is enabled for this tick. Called once at the start of the loop.
+pub fn begin_tick() {
+    let enabled = screeps::memory::root().bool(ENABLED_KEY);
+    ENABLED.with(|e| e.set(enabled));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Turns the journal on or off, starting with the rest of this tick.
+pub fn set_enabled(enabled: bool) {
+    screeps::memory::root().set(ENABLED_KEY, enabled);
+    ENABLED.with(|e| e.set(enabled));
+}
+
+/// The hash of a decision's inputs. The hasher's keys are fixed, so it's the same in every run.
+fn hash_of(inputs: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    inputs.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Records a decision made by `subsystem` from `inputs`, if the journal is enabled.
+pub fn record(subsystem: &'static str, inputs: impl Hash, decision: impl FnOnce() -> String) {
+    if !ENABLED.with(Cell::get) {
+        return;
+    }
+    // entries are split on lines when they're loaded
+    let decision = decision().replace('\n', " ");
+    let entry = format!(
+        "{}|{}|{:016x}|{}",
+        screeps::game::time(),
+        subsystem,
+        hash_of(inputs),
+        decision
+    );
+    ENTRIES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        entries.push_back(entry);
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+    });
+    DIRTY.with(|d| d.set(true));
+}
+
+/// The latest `count` entries, oldest first.
+pub fn latest(count: usize) -> Vec<String> {
+    ENTRIES.with(|entries| {
+        let entries = entries.borrow();
+        let skip = entries.len().saturating_sub(count);
+        entries.iter().skip(skip).cloned().collect()
+    })
+}
+
+/// Writes the entries to the segment if any were added, once the ones from before the reset are
+/// loaded. Called once at the end of the loop.
+pub fn end_tick() {
+    if !DIRTY.with(Cell::get) {
+        return;
+    }
+    if !LOADED.with(Cell::get) {
+        let stored = match segments::load(segments::JOURNAL) {
+            Some(stored) => stored,
+            // not readable yet, the entries wait until it is
+            None => return,
+        };
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            for line in stored.lines().rev().filter(|line| !line.is_empty()) {
+                entries.push_front(line.to_string());
+            }
+            let excess = entries.len().saturating_sub(MAX_ENTRIES);
+            entries.drain(..excess);
+        });
+        LOADED.with(|l| l.set(true));
+    }
+
+    let encoded = ENTRIES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        let mut length: usize = entries.iter().map(|entry| entry.len() + 1).sum();
+        while length > segments::MAX_LENGTH {
+            length -= entries.pop_front().map_or(length, |entry| entry.len() + 1);
+        }
+        entries.iter().cloned().collect::<Vec<_>>().join("\n")
+    });
+    if let Err(e) = segments::save(segments::JOURNAL, &encoded) {
+        warn!("couldn't write the journal: {}", e);
+    }
+    DIRTY.with(|d| d.set(false));
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![ENTRIES.with(|e| {
+        let entries = e.borrow();
+        CacheSize {
+            name: "journal.entries",
+            entries: entries.len(),
+            bytes: entries.capacity() * std::mem::size_of::<String>()
+                + entries.iter().map(String::capacity).sum::<usize>(),
+        }
+    })]
+}
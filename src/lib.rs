@@ -0,0 +1,273 @@
+//! The bot. It's a library so that the benchmarks can reach its parts, and `main.rs` hands
+//! [`game_loop`] to the game.
+
+#![recursion_limit = "256"]
+
+use log::*;
+
+use context::TickContext;
+use scheduler::Tier;
+use structures::{links, towers};
+
+mod actions;
+mod allies;
+mod attackers;
+mod bootstrap;
+mod cache;
+mod cleanup;
+pub mod console;
+mod construction;
+mod context;
+mod creep_costs;
+mod creep_tasks;
+pub mod creeps;
+mod defense;
+mod duo;
+mod emergency;
+mod energy;
+mod events;
+mod expansion;
+mod failures;
+pub mod features;
+mod flags;
+mod formation;
+mod heap;
+mod id;
+mod intel;
+mod journal;
+mod lifetimes;
+pub mod logging;
+mod memory;
+pub mod movement;
+mod nukes;
+mod operations;
+mod operators;
+pub mod panics;
+mod planner;
+mod power;
+mod profiler;
+mod progress;
+mod remotes;
+mod retreat;
+mod rng;
+mod scheduler;
+mod segments;
+pub mod spawning;
+pub mod state;
+pub mod structures;
+mod tasks;
+mod terminals;
+mod threat;
+mod traffic;
+mod version;
+mod visuals;
+
+/// Runs one tick, called by the game's `loop`.
+pub fn game_loop() {
+    debug!("loop starting! CPU: {}", screeps::game::cpu::get_used());
+
+    let ctx = TickContext::read();
+    begin_tick(&ctx);
+
+    if scheduler::is_paused() {
+        return run_paused(&ctx);
+    }
+    if scheduler::is_halted() {
+        return run_halted(&ctx);
+    }
+
+    run_spawns(&ctx);
+    run_defense(&ctx);
+    run_creeps(&ctx);
+    run_rooms(&ctx);
+    run_background(&ctx);
+    run_periodic(&ctx);
+
+    finalize(&ctx);
+}
+
+/// Sets up what every loop needs during the tick, paused and halted ones included.
+fn begin_tick(ctx: &TickContext) {
+    version::begin_tick();
+    profiler::begin_tick(ctx);
+    journal::begin_tick();
+    movement::begin_tick(ctx);
+    scheduler::begin_tick(ctx);
+}
+
+fn run_spawns(ctx: &TickContext) {
+    scheduler::run(ctx, Tier::Critical, "spawns", || spawning::run(ctx));
+}
+
+/// Sizes up the threats to our rooms and has the towers deal with them.
+fn run_defense(ctx: &TickContext) {
+    scheduler::run(ctx, Tier::Critical, "threat", || threat::run(ctx));
+    scheduler::run(ctx, Tier::Critical, "defense", || defense::run(ctx));
+    scheduler::run(ctx, Tier::Critical, "towers", || towers::run(ctx));
+    scheduler::run(ctx, Tier::Normal, "links", || links::run(ctx));
+}
+
+/// Hands out the creeps' orders, then runs them all.
+fn run_creeps(ctx: &TickContext) {
+    scheduler::run(ctx, Tier::Normal, "flags", || flags::run(ctx));
+    scheduler::run(ctx, Tier::Critical, "nukes", || nukes::run(ctx));
+    scheduler::run(ctx, Tier::Critical, "quads", || formation::run(ctx));
+    scheduler::run(ctx, Tier::Critical, "creeps", || creeps::run_all(ctx));
+    scheduler::run(ctx, Tier::Normal, "operators", || operators::run(ctx));
+}
+
+/// Keeps up with what happened in our rooms, and draws them.
+fn run_rooms(ctx: &TickContext) {
+    scheduler::run(ctx, Tier::Critical, "level_ups", || {
+        construction::check_level_ups(ctx)
+    });
+    scheduler::run(ctx, Tier::Critical, "events", || events::run(ctx));
+    scheduler::run(ctx, Tier::Normal, "previews", planner::draw_previews);
+    scheduler::run(ctx, Tier::Normal, "dashboard", || {
+        visuals::draw_dashboards(ctx)
+    });
+    scheduler::run(ctx, Tier::Normal, "map", visuals::draw_map);
+    scheduler::run(ctx, Tier::Normal, "core_zones", movement::exclusion::draw);
+}
+
+/// Steps the long-running tasks and operations.
+fn run_background(ctx: &TickContext) {
+    scheduler::run(ctx, Tier::Expensive, "tasks", || tasks::run(ctx));
+    scheduler::run(ctx, Tier::Normal, "operations", || operations::run(ctx));
+}
+
+/// Wraps up a tick which ran normally.
+fn finalize(ctx: &TickContext) {
+    scheduler::generate_pixel(ctx);
+    end_tick(ctx);
+
+    info!("done! cpu: {}", screeps::game::cpu::get_used())
+}
+
+/// Runs whatever is due this tick of what only runs every so many ticks.
+fn run_periodic(ctx: &TickContext) {
+    if ctx.is_due(logging::LEVEL_CHECK_INTERVAL, 0) {
+        logging::update_levels();
+    }
+
+    if ctx.is_due(10, 1) {
+        scheduler::run(ctx, Tier::Normal, "intel", || intel::scan(ctx));
+    }
+
+    if ctx.is_due(10, 9) {
+        scheduler::run(ctx, Tier::Normal, "expansion", expansion::run);
+    }
+
+    if ctx.is_due(10, 7) {
+        scheduler::run(ctx, Tier::Normal, "power", power::run);
+    }
+
+    if ctx.is_due(expansion::SCORE_INTERVAL, 251) {
+        scheduler::run(
+            ctx,
+            Tier::Expensive,
+            "expansion_scores",
+            expansion::score_candidates,
+        );
+    }
+
+    if ctx.is_due(remotes::REPORT_INTERVAL, 17) {
+        scheduler::run(ctx, Tier::Normal, "remote_report", remotes::report);
+    }
+
+    if ctx.is_due(32, 3) {
+        info!("running memory cleanup");
+        scheduler::run(ctx, Tier::Critical, "cleanup", memory::cleanup);
+    }
+
+    if ctx.is_due(100, 7) {
+        scheduler::run(ctx, Tier::Normal, "planner", planner::run);
+    }
+
+    if ctx.is_due(20, 11) {
+        debug!("placing construction sites");
+        scheduler::run(ctx, Tier::Normal, "construction", || construction::run(ctx));
+    }
+
+    if ctx.is_due(creeps::SAVE_INTERVAL, 23) {
+        scheduler::run(ctx, Tier::Normal, "targets", creeps::save_targets);
+    }
+
+    if ctx.is_due(100, 37) {
+        debug!("flushing road traffic");
+        scheduler::run(ctx, Tier::Normal, "traffic", traffic::flush);
+    }
+
+    if ctx.is_due(100, 97) {
+        scheduler::run(ctx, Tier::Normal, "movement_report", movement::report);
+    }
+
+    if ctx.is_due(100, 53) {
+        debug!("removing unplanned construction sites");
+        scheduler::run(ctx, Tier::Normal, "orphans", construction::remove_orphans);
+    }
+
+    if ctx.is_due(heap::REPORT_INTERVAL, 61) {
+        scheduler::run(ctx, Tier::Normal, "heap", heap::report);
+    }
+
+    if ctx.is_due(progress::SAMPLE_INTERVAL, 89) {
+        scheduler::run(ctx, Tier::Normal, "progress", progress::run);
+    }
+
+    if ctx.is_due(energy::REPORT_INTERVAL, 71) {
+        scheduler::run(ctx, Tier::Normal, "energy_report", energy::report);
+    }
+
+    if ctx.is_due(terminals::CONSOLIDATE_INTERVAL, 43) {
+        scheduler::run(ctx, Tier::Normal, "consolidation", terminals::consolidate);
+    }
+
+    if ctx.is_due(creep_costs::REPORT_INTERVAL, 29) {
+        scheduler::run(ctx, Tier::Normal, "creep_costs", creep_costs::report);
+    }
+
+    if ctx.is_due(profiler::REPORT_INTERVAL, 0) {
+        profiler::report();
+    }
+}
+
+/// Flushes what every loop collects over the tick, paused and halted ones included.
+fn end_tick(ctx: &TickContext) {
+    failures::end_tick();
+    journal::end_tick();
+    lifetimes::end_tick();
+    scheduler::end_tick(ctx);
+    segments::end_tick();
+    logging::end_tick();
+}
+
+/// The loop while paused from the console: no intents at all, only memory cleanup and stats.
+fn run_paused(ctx: &TickContext) {
+    if ctx.is_due(50, 0) {
+        info!("paused, not issuing any intents until resume()");
+    }
+    if ctx.is_due(32, 3) {
+        if let Err(e) = memory::cleanup() {
+            error!("couldn't clean up memory: {}", e);
+        }
+    }
+    end_tick(ctx);
+}
+
+/// The safe mode loop while `Memory.emergency_halt` is set: only the towers, spawning, event
+/// notifications and memory cleanup run.
+fn run_halted(ctx: &TickContext) {
+    if ctx.is_due(10, 0) {
+        info!("emergency halt, running only towers and spawns until emergency_resume()");
+    }
+    spawning::run(ctx);
+    towers::run(ctx);
+    events::run(ctx);
+    if ctx.is_due(32, 3) {
+        if let Err(e) = memory::cleanup() {
+            error!("couldn't clean up memory: {}", e);
+        }
+    }
+    end_tick(ctx);
+}
@@ -0,0 +1,275 @@
+//! What each creep got done in its life.
+//!
+//! Every creep has a record of the energy it harvested, delivered and built, and of how many
+//! ticks it spent working, moving and idle. A tick it had an action go through counts as working,
+//! one it changed tiles in without that as moving, and any other as idle, so waiting out fatigue
+//! is idle too. When a creep is gone from `Game.creeps` its epitaph is logged, and its numbers
+//! are folded into rolling averages per role in `Memory.stats.lifetimes`.
+//!
+//! The records live on the heap, and are copied into each creep's memory every
+//! [`FLUSH_INTERVAL`] ticks so a reset picks them up again. A creep which died while nothing was
+//! watching still gets its epitaph from its last copy, marked as partial, when its memory is
+//! cleaned up.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{memory::MemoryReference, prelude::*, Creep, Part, Position, ResourceType};
+
+use crate::{
+    actions::Action,
+    heap::CacheSize,
+    spawning::{self, Role},
+};
+
+const LIFETIME_KEY: &str = "lifetime";
+
+const STATS_PATH: &str = "stats.lifetimes";
+
+/// How often the records are copied into creep memory.
+pub const FLUSH_INTERVAL: u32 = 50;
+
+/// How much each death moves the averages of its role.
+const AVERAGE_WEIGHT: f64 = 0.1;
+
+/// How long creeps live, and creeps with claim parts.
+const CREEP_LIFE_TIME: u32 = 1500;
+const CREEP_CLAIM_LIFE_TIME: u32 = 600;
+
+#[derive(Clone, Debug)]
+struct Record {
+    role: Role,
+    /// The tick the creep was spawned.
+    born: u32,
+    ticks: u32,
+    working: u32,
+    moving: u32,
+    harvested: u32,
+    delivered: u32,
+    built: u32,
+    /// Where the creep was on the last tick, which only the heap keeps.
+    last_pos: Option<Position>,
+    /// Whether an action went through this tick.
+    worked: bool,
+}
+
+impl Record {
+    fn new(creep: &Creep, time: u32) -> Record {
+        let claim = creep.body().iter().any(|part| part.part == Part::Claim);
+        let life_time = if claim {
+            CREEP_CLAIM_LIFE_TIME
+        } else {
+            CREEP_LIFE_TIME
+        };
+        Record {
+            role: spawning::role_of(creep),
+            born: time.saturating_sub(
+                life_time.saturating_sub(creep.ticks_to_live().unwrap_or(life_time)),
+            ),
+            ticks: 0,
+            working: 0,
+            moving: 0,
+            harvested: 0,
+            delivered: 0,
+            built: 0,
+            last_pos: None,
+            worked: false,
+        }
+    }
+
+    /// Stores a record as `role,born,ticks,working,moving,harvested,delivered,built`.
+    fn encode(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.role.name(),
+            self.born,
+            self.ticks,
+            self.working,
+            self.moving,
+            self.harvested,
+            self.delivered,
+            self.built
+        )
+    }
+
+    fn decode(encoded: &str) -> Option<Record> {
+        let mut fields = encoded.split(',');
+        Some(Record {
+            role: Role::from_name(fields.next()?)?,
+            born: fields.next()?.parse().ok()?,
+            ticks: fields.next()?.parse().ok()?,
+            working: fields.next()?.parse().ok()?,
+            moving: fields.next()?.parse().ok()?,
+            harvested: fields.next()?.parse().ok()?,
+            delivered: fields.next()?.parse().ok()?,
+            built: fields.next()?.parse().ok()?,
+            last_pos: None,
+            worked: false,
+        })
+    }
+
+    fn idle(&self) -> u32 {
+        self.ticks.saturating_sub(self.working + self.moving)
+    }
+
+    /// What share of its ticks the creep spent on something.
+    fn share(&self, ticks: u32) -> f64 {
+        if self.ticks == 0 {
+            0.0
+        } else {
+            ticks as f64 / self.ticks as f64
+        }
+    }
+}
+
+thread_local! {
+    /// The record of every creep seen since the reset, by name.
+    static RECORDS: RefCell<HashMap<String, Record>> = RefCell::new(HashMap::new());
+}
+
+fn load(creep: &Creep) -> Option<Record> {
+    let encoded = creep.memory().string(LIFETIME_KEY).ok()??;
+    Record::decode(&encoded)
+}
+
+/// Counts an action of a creep which went through, along with the energy it moved.
+pub fn record(creep_name: &str, action: Action, energy: u32) {
+    RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let record = match records.get_mut(creep_name) {
+            Some(record) => record,
+            // spawned this tick, and counted from the next one
+            None => return,
+        };
+        record.worked = true;
+        match action {
+            Action::Harvest(_) => record.harvested += energy,
+            Action::Transfer(_, ResourceType::Energy) => record.delivered += energy,
+            Action::Build(_) => record.built += energy,
+            _ => {}
+        }
+    });
+}
+
+/// Counts this tick for every creep, and writes the epitaphs of the ones which died. Called once
+/// at the end of the loop.
+pub fn end_tick() {
+    let time = screeps::game::time();
+    let flush = time % FLUSH_INTERVAL == 0;
+    let mut alive = HashSet::new();
+    RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        for creep in screeps::game::creeps::values() {
+            if creep.spawning() {
+                continue;
+            }
+            let name = creep.name();
+            let record = records
+                .entry(name.clone())
+                .or_insert_with(|| load(&creep).unwrap_or_else(|| Record::new(&creep, time)));
+            let pos = creep.pos();
+            record.ticks += 1;
+            if record.worked {
+                record.working += 1;
+            } else if record.last_pos.map_or(false, |last| last != pos) {
+                record.moving += 1;
+            }
+            record.worked = false;
+            record.last_pos = Some(pos);
+            if flush {
+                creep.memory().set(LIFETIME_KEY, record.encode());
+            }
+            alive.insert(name);
+        }
+
+        let dead: Vec<String> = records
+            .keys()
+            .filter(|name| !alive.contains(*name))
+            .cloned()
+            .collect();
+        for name in dead {
+            if let Some(record) = records.remove(&name) {
+                bury(&name, &record, time, false);
+                // so the memory cleanup doesn't bury it again
+                screeps::memory::root().path_del(&format!("creeps.{}.{}", name, LIFETIME_KEY));
+            }
+        }
+    });
+}
+
+/// Writes the epitaph of a creep whose memory is being cleaned up, if it still has a record which
+/// nothing on the heap knew about.
+pub fn bury_forgotten(creep_name: &str, memory: &MemoryReference) {
+    if RECORDS.with(|records| records.borrow().contains_key(creep_name)) {
+        return;
+    }
+    let record = memory
+        .string(LIFETIME_KEY)
+        .ok()
+        .flatten()
+        .and_then(|encoded| Record::decode(&encoded));
+    if let Some(record) = record {
+        bury(creep_name, &record, screeps::game::time(), true);
+    }
+}
+
+/// Logs a creep's epitaph and folds its numbers into the averages of its role.
+fn bury(creep_name: &str, record: &Record, time: u32, partial: bool) {
+    let lifespan = if partial {
+        record.ticks
+    } else {
+        time.saturating_sub(record.born)
+    };
+    info!(
+        "{} {} lived {} ticks{}: {} energy harvested, {} delivered and {} built, {:.0}% \
+         working, {:.0}% moving and {:.0}% idle",
+        record.role.name(),
+        creep_name,
+        lifespan,
+        if partial {
+            " that we know of, from before a reset"
+        } else {
+            ""
+        },
+        record.harvested,
+        record.delivered,
+        record.built,
+        record.share(record.working) * 100.0,
+        record.share(record.moving) * 100.0,
+        record.share(record.idle()) * 100.0
+    );
+
+    let memory = screeps::memory::root();
+    let path = format!("{}.{}", STATS_PATH, record.role.name());
+    let deaths = memory
+        .path_i32(&format!("{}.deaths", path))
+        .ok()
+        .flatten()
+        .unwrap_or(0);
+    memory.path_set(&format!("{}.deaths", path), deaths + 1);
+    for (key, value) in &[
+        ("lifespan", lifespan as f64),
+        ("harvested", record.harvested as f64),
+        ("delivered", record.delivered as f64),
+        ("built", record.built as f64),
+        ("working", record.share(record.working)),
+        ("moving", record.share(record.moving)),
+        ("idle", record.share(record.idle())),
+    ] {
+        let key = format!("{}.{}", path, key);
+        let average = match memory.path_f64(&key).ok().flatten() {
+            Some(average) if deaths > 0 => average + (value - average) * AVERAGE_WEIGHT,
+            _ => *value,
+        };
+        memory.path_set(&key, average);
+    }
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![RECORDS
+        .with(|r| CacheSize::of_map("lifetimes.records", &r.borrow(), |name, _| name.capacity()))]
+}
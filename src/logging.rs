@@ -1,10 +1,210 @@
+//! Logging to the game console.
+//!
+//! Everything is logged to the console, each line starting with the tick and the module it came
+//! from and colored by level. Private servers whose console shows HTML as text can turn the colors
+//! off with `Memory.config.log_plain`. Lines longer than `Memory.config.log_max_length`
+//! characters ([`MAX_LINE_LENGTH`] by default) are cut short.
+//!
+//! Messages logged with [`log_kv!`] carry fields like the creep's name, which are appended as
+//! `key=value`. Setting `Memory.config.log_format` to `"json"` logs one JSON object per line
+//! instead, with the tick, level, module, message and fields, for collecting the console output
+//! elsewhere.
+//!
+//! Errors are also sent as game notifications. Set
+//! `Memory.config.notify_level` to `"warn"` to get warnings as well, or to `"off"` to silence
+//! notifications, e.g. on private servers. Each message text is sent at most once every
+//! [`NOTIFY_REPEAT_TICKS`] ticks, and only a few go out per tick with the rest held back for the
+//! next ones.
+//!
+//! The level can be changed without a redeploy by setting `Memory.config.log_level`
+//! to a level like `"debug"`, which is picked up within [`LEVEL_CHECK_INTERVAL`] ticks. Filtered
+//! out messages cost next to nothing, as the `log` macros skip formatting them.
+//!
+//! A message logged over and over from the same place, like a stuck creep failing the same
+//! action every tick, is let through [`REPEAT_LIMIT`] times per [`REPEAT_WINDOW`] ticks, and once
+//! the window is over a single line says how often it repeated. Both can be changed in
+//! `Memory.config.log_repeat_limit` and `Memory.config.log_repeat_window`. Errors are never held
+//! back.
+//!
+//! Per-creep debug lines, logged with [`creep_debug!`], can be cut down to a few creeps by
+//! setting `Memory.config.debug_sample` to the share of creeps to log, like `0.1`, or listing
+//! creep names in `Memory.config.debug_creeps`. `trace_creep(name)` from the console adds a
+//! creep for [`TRACE_TICKS`] ticks, and `set_debug_creep(name, ticks)` for any number of them,
+//! with 0 ticks taking it off again. Which creeps are sampled is based on a hash of their name, so
+//! the same creeps stay sampled.
+//!
+//! Single modules can be given their own level in `Memory.config.log_filters`, keyed by module
+//! path within the crate, like `{"movement": "warn", "movement::flee": "debug"}`. The longest
+//! matching path wins, and modules without one use the global level.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use log::*;
 use stdweb::js;
 
+use crate::features::{self, Feature};
+
 pub use log::LevelFilter::*;
 
+/// How often `Memory.config.log_level` is checked for changes.
+pub const LEVEL_CHECK_INTERVAL: u32 = 20;
+
+const LEVEL_PATH: &str = "config.log_level";
+const FILTERS_PATH: &str = "config.log_filters";
+const NOTIFY_LEVEL_PATH: &str = "config.notify_level";
+
+const PLAIN_PATH: &str = "config.log_plain";
+const FORMAT_PATH: &str = "config.log_format";
+const MAX_LENGTH_PATH: &str = "config.log_max_length";
+
+/// How many characters of a message are logged by default.
+pub const MAX_LINE_LENGTH: usize = 1000;
+
+const SAMPLE_PATH: &str = "config.debug_sample";
+const DEBUG_CREEPS_PATH: &str = "config.debug_creeps";
+const TRACED_CREEPS_PATH: &str = "config.traced_creeps";
+
+/// How long `trace_creep()` logs a creep's debug lines by default.
+pub const TRACE_TICKS: u32 = 100;
+
+const REPEAT_LIMIT_PATH: &str = "config.log_repeat_limit";
+const REPEAT_WINDOW_PATH: &str = "config.log_repeat_window";
+
+/// How many messages from one place are logged per window by default.
+pub const REPEAT_LIMIT: u32 = 5;
+
+/// How many ticks a window of repeated messages lasts by default.
+pub const REPEAT_WINDOW: u32 = 100;
+
+/// How long the same message isn't sent again.
+pub const NOTIFY_REPEAT_TICKS: u32 = 1000;
+
+/// The most notifications sent in one tick.
+const MAX_NOTIFY_PER_TICK: u32 = 3;
+
+/// How many notifications may wait for a later tick before the oldest are dropped.
+const MAX_NOTIFY_QUEUE: usize = 20;
+
+/// Passed to `Game.notify`, which groups notifications sent within this many minutes into one
+/// email.
+const NOTIFY_GROUP_MINUTES: u32 = 30;
+
+thread_local! {
+    /// The level used while `Memory.config.log_level` isn't set.
+    static DEFAULT_LEVEL: Cell<LevelFilter> = Cell::new(LevelFilter::Info);
+    /// The last unreadable level setting, so it's only warned about once.
+    static INVALID_LEVEL: RefCell<Option<String>> = RefCell::new(None);
+    /// The level applied to modules without a filter.
+    static GLOBAL_LEVEL: Cell<LevelFilter> = Cell::new(LevelFilter::Info);
+    /// Per-module levels, longest module path first.
+    static FILTERS: RefCell<Vec<(String, LevelFilter)>> = RefCell::new(Vec::new());
+    /// Every module which logged something, listed by `log_filters()`.
+    static TARGETS: RefCell<BTreeSet<String>> = RefCell::new(BTreeSet::new());
+    /// The most verbose level which is sent as notifications.
+    static NOTIFY_LEVEL: Cell<LevelFilter> = Cell::new(LevelFilter::Error);
+    /// Notifications waiting for a tick with room for them.
+    static NOTIFY_QUEUE: RefCell<VecDeque<String>> = RefCell::new(VecDeque::new());
+    /// When each message was last sent or queued.
+    static NOTIFY_SENT: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    /// The tick notifications were last sent in, and how many.
+    static NOTIFY_COUNT: Cell<(u32, u32)> = Cell::new((0, 0));
+    /// Whether console lines are logged without HTML.
+    static PLAIN: Cell<bool> = Cell::new(false);
+    static MAX_LENGTH: Cell<usize> = Cell::new(MAX_LINE_LENGTH);
+    /// Whether console lines are logged as JSON.
+    static JSON: Cell<bool> = Cell::new(false);
+    /// The fields of the message being logged with `log_kv!`.
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new());
+    /// The share of creeps whose debug lines are logged, if they're sampled.
+    static SAMPLE: Cell<Option<f64>> = Cell::new(None);
+    /// Creeps whose debug lines are logged whether they're sampled or not, if there's a list.
+    static DEBUG_CREEPS: RefCell<Option<HashSet<String>>> = RefCell::new(None);
+    static REPEAT_LIMIT_SETTING: Cell<u32> = Cell::new(REPEAT_LIMIT);
+    static REPEAT_WINDOW_SETTING: Cell<u32> = Cell::new(REPEAT_WINDOW);
+    /// The current window of each place which logged, by target and line.
+    static REPEATS: RefCell<HashMap<(String, u32), Repeats>> = RefCell::new(HashMap::new());
+}
+
+/// Messages logged from one place in the current window.
+struct Repeats {
+    start: u32,
+    count: u32,
+    level: Level,
+}
+
+/// Holds back messages repeated too often before passing the rest on.
+struct Deduplicate {
+    inner: Box<dyn log::Log>,
+}
+
 struct JsLog;
 struct JsNotify;
 
+impl log::Log for Deduplicate {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+    fn log(&self, record: &log::Record<'_>) {
+        // messages filtered out anyway don't count as repeats
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        // the summaries come from here, and shouldn't be counted themselves
+        if record.level() == Level::Error || record.module_path() == Some(module_path!()) {
+            self.inner.log(record);
+            return;
+        }
+
+        let time = screeps::game::time();
+        let limit = REPEAT_LIMIT_SETTING.with(Cell::get);
+        let window = REPEAT_WINDOW_SETTING.with(Cell::get);
+        let key = (
+            record.target().to_string(),
+            record.line().unwrap_or_default(),
+        );
+        let (allowed, repeated) = REPEATS.with(|repeats| {
+            let mut repeats = repeats.borrow_mut();
+            let entry = repeats.entry(key).or_insert(Repeats {
+                start: time,
+                count: 0,
+                level: record.level(),
+            });
+            let mut repeated = 0;
+            if time.saturating_sub(entry.start) >= window {
+                repeated = entry.count.saturating_sub(limit);
+                entry.start = time;
+                entry.count = 0;
+            }
+            entry.count += 1;
+            (entry.count <= limit, repeated)
+        });
+
+        if repeated > 0 {
+            log_repeated(record.target(), record.level(), repeated);
+        }
+        if allowed {
+            self.inner.log(record);
+        }
+    }
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn log_repeated(target: &str, level: Level, repeated: u32) {
+    log!(
+        target: target,
+        level,
+        "last message repeated {} more times",
+        repeated
+    );
+}
+
 impl log::Log for JsLog {
     fn enabled(&self, _: &log::Metadata<'_>) -> bool {
         true
@@ -18,39 +218,537 @@ impl log::Log for JsLog {
     fn flush(&self) {}
 }
 impl log::Log for JsNotify {
-    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= NOTIFY_LEVEL.with(Cell::get)
     }
     fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
         let message = format!("{}", record.args());
-        js! {
-            Game.notify(@{message});
+        let time = screeps::game::time();
+
+        let repeated = NOTIFY_SENT.with(|sent| {
+            let mut sent = sent.borrow_mut();
+            match sent.get(&message) {
+                Some(&at) if time.saturating_sub(at) < NOTIFY_REPEAT_TICKS => true,
+                _ => {
+                    sent.insert(message.clone(), time);
+                    false
+                }
+            }
+        });
+        if repeated {
+            return;
+        }
+
+        let text = format!("[{} {}] {}", screeps::game::shards::name(), time, message);
+        // sent right away if there's room, as a panic ends the tick before the queue is flushed
+        if !try_notify(&text) {
+            NOTIFY_QUEUE.with(|queue| {
+                let mut queue = queue.borrow_mut();
+                if queue.len() == MAX_NOTIFY_QUEUE {
+                    queue.pop_front();
+                }
+                queue.push_back(text);
+            });
         }
     }
     fn flush(&self) {}
 }
 
-pub fn setup_logging(verbosity: log::LevelFilter) {
-    fern::Dispatch::new()
-        .level(verbosity)
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "({}) {}: {}",
-                record.level(),
-                record.target(),
-                message
-            ))
+/// Sends a notification unless this tick's are used up, returning whether it was sent.
+fn try_notify(text: &str) -> bool {
+    let time = screeps::game::time();
+    let (tick, count) = NOTIFY_COUNT.with(Cell::get);
+    let count = if tick == time { count } else { 0 };
+    if count >= MAX_NOTIFY_PER_TICK {
+        return false;
+    }
+    NOTIFY_COUNT.with(|c| c.set((time, count + 1)));
+    // nothing to send it with, and it has been logged already
+    if !features::has(Feature::Notify) {
+        return true;
+    }
+    js! {
+        Game.notify(@{text}, @{NOTIFY_GROUP_MINUTES});
+    }
+    true
+}
+
+/// Logs how often messages repeated in windows which are over, and sends notifications held back
+/// from earlier ticks. Called once at the end of the loop.
+pub fn end_tick() {
+    let time = screeps::game::time();
+    let window = REPEAT_WINDOW_SETTING.with(Cell::get);
+    let limit = REPEAT_LIMIT_SETTING.with(Cell::get);
+    let mut finished = Vec::new();
+    REPEATS.with(|repeats| {
+        repeats.borrow_mut().retain(|(target, _), entry| {
+            if time.saturating_sub(entry.start) < window {
+                return true;
+            }
+            if entry.count > limit {
+                finished.push((target.clone(), entry.level, entry.count - limit));
+            }
+            false
         })
-        .chain(Box::new(JsLog) as Box<dyn log::Log>)
+    });
+    for (target, level, repeated) in finished {
+        log_repeated(&target, level, repeated);
+    }
+
+    flush_notifications();
+}
+
+/// Sends notifications held back from earlier ticks, as far as this tick has room, and forgets
+/// messages which may be sent again.
+fn flush_notifications() {
+    NOTIFY_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        while let Some(text) = queue.front() {
+            if !try_notify(text) {
+                break;
+            }
+            queue.pop_front();
+        }
+    });
+
+    let time = screeps::game::time();
+    NOTIFY_SENT.with(|sent| {
+        sent.borrow_mut()
+            .retain(|_, &mut at| time.saturating_sub(at) < NOTIFY_REPEAT_TICKS)
+    });
+}
+
+/// Sets up logging, at `verbosity` unless `Memory.config.log_level` says otherwise.
+pub fn setup_logging(verbosity: log::LevelFilter) {
+    // the dispatch passes everything and the level is applied as `log`'s max level instead, so
+    // it can change later
+    let (_, dispatch) = fern::Dispatch::new()
+        .level(log::LevelFilter::Trace)
+        .filter(allows)
+        .chain(
+            fern::Dispatch::new()
+                .format(format_console)
+                .chain(Box::new(JsLog) as Box<dyn log::Log>),
+        )
         .chain(
             fern::Dispatch::new()
                 .level(log::LevelFilter::Warn)
-                .format(|out, message, _record| {
-                    let time = screeps::game::time();
-                    out.finish(format_args!("[{}] {}", time, message))
+                .format(|out, message, record| {
+                    out.finish(format_args!(
+                        "({}) {}: {}{}",
+                        record.level(),
+                        module_of(record.target()),
+                        message,
+                        fields_suffix()
+                    ))
                 })
                 .chain(Box::new(JsNotify) as Box<dyn log::Log>),
         )
-        .apply()
+        .into_log();
+    log::set_boxed_logger(Box::new(Deduplicate { inner: dispatch }))
         .expect("expected setup_logging to only ever be called once per instance");
+
+    DEFAULT_LEVEL.with(|d| d.set(verbosity));
+    log::set_max_level(verbosity);
+    update_levels();
+}
+
+/// Logs a message along with fields, which are strings or, prefixed with `?`, debug formatted.
+///
+/// ```ignore
+/// log_kv!(warn, "transfer failed", creep = name, code = ?r);
+/// ```
+#[macro_export]
+macro_rules! log_kv {
+    (@fields $fields:ident;) => {};
+    (@fields $fields:ident; $key:ident = ?$value:expr $(, $($rest:tt)*)?) => {
+        $fields.push((stringify!($key), format!("{:?}", $value)));
+        $crate::log_kv!(@fields $fields; $($($rest)*)?);
+    };
+    (@fields $fields:ident; $key:ident = $value:expr $(, $($rest:tt)*)?) => {
+        $fields.push((stringify!($key), format!("{}", $value)));
+        $crate::log_kv!(@fields $fields; $($($rest)*)?);
+    };
+    ($level:ident, $message:expr $(, $($fields:tt)*)?) => {{
+        let mut fields: Vec<(&'static str, String)> = Vec::new();
+        $crate::log_kv!(@fields fields; $($($fields)*)?);
+        $crate::logging::with_fields(fields, || ::log::$level!("{}", $message));
+    }};
+}
+
+/// Logs whatever `f` logs with `fields`. Used by [`log_kv!`].
+pub fn with_fields(fields: Vec<(&'static str, String)>, f: impl FnOnce()) {
+    FIELDS.with(|current| *current.borrow_mut() = fields);
+    f();
+    FIELDS.with(|current| current.borrow_mut().clear());
+}
+
+/// The fields of the message being logged as ` key=value key=value`.
+fn fields_suffix() -> String {
+    FIELDS.with(|fields| {
+        fields
+            .borrow()
+            .iter()
+            .map(|(key, value)| format!(" {}={}", key, value))
+            .collect()
+    })
+}
+
+/// Quotes a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Logs a debug line about a creep, if the creep's debug lines are logged at all.
+///
+/// ```ignore
+/// creep_debug!(creep.name(), "running creep {}", creep.name());
+/// ```
+#[macro_export]
+macro_rules! creep_debug {
+    ($creep_name:expr, $($arg:tt)+) => {
+        if ::log::log_enabled!(::log::Level::Debug) && $crate::logging::traces(&$creep_name) {
+            ::log::debug!($($arg)+);
+        }
+    };
+}
+
+/// Whether a creep's debug lines are logged: every creep's unless they're sampled or listed.
+pub fn traces(creep_name: &str) -> bool {
+    let sample = SAMPLE.with(Cell::get);
+    let listed = DEBUG_CREEPS.with(|creeps| {
+        creeps
+            .borrow()
+            .as_ref()
+            .map(|creeps| creeps.contains(creep_name))
+    });
+    if sample.is_none() && listed.is_none() {
+        return true;
+    }
+    if listed == Some(true) {
+        return true;
+    }
+    sample.map_or(false, |share| {
+        let mut hasher = DefaultHasher::new();
+        creep_name.hash(&mut hasher);
+        share * 10_000.0 > (hasher.finish() % 10_000) as f64
+    })
+}
+
+/// Logs a creep's debug lines for the next `ticks` ticks, whether it's sampled or not.
+pub fn trace_creep(creep_name: &str, ticks: u32) {
+    if let Ok(Some(traced)) = screeps::memory::root()
+        .dict_or_create("config")
+        .map(|config| config.dict_or_create("traced_creeps").ok())
+    {
+        traced.set(creep_name, screeps::game::time() + ticks);
+    }
+    DEBUG_CREEPS.with(|creeps| {
+        creeps
+            .borrow_mut()
+            .get_or_insert_with(HashSet::new)
+            .insert(creep_name.to_string());
+    });
+}
+
+/// Stops logging the debug lines of a creep added with [`trace_creep`]. Creeps listed in
+/// `Memory.config.debug_creeps` stay listed.
+pub fn untrace_creep(creep_name: &str) {
+    if let Ok(Some(traced)) = screeps::memory::root().path_dict(TRACED_CREEPS_PATH) {
+        traced.del(creep_name);
+    }
+    update_sampling();
+}
+
+/// Describes which creeps' debug lines are logged.
+pub fn describe_sampling() -> String {
+    let root = screeps::memory::root();
+    let time = screeps::game::time();
+    let mut parts = Vec::new();
+    if let Some(share) = SAMPLE.with(Cell::get) {
+        parts.push(format!("sampling {:.0}% of creeps", share * 100.0));
+    }
+    if let Ok(Some(listed)) = root.path_arr::<String>(DEBUG_CREEPS_PATH) {
+        parts.push(format!("listed {}", listed.join(", ")));
+    }
+    if let Ok(Some(traced)) = root.path_dict(TRACED_CREEPS_PATH) {
+        let traced: Vec<String> = traced
+            .keys()
+            .into_iter()
+            .filter_map(|name| {
+                let until = traced.i32(&name).ok().flatten()? as u32;
+                Some(format!("{} for {} ticks", name, until.saturating_sub(time)))
+            })
+            .collect();
+        if !traced.is_empty() {
+            parts.push(format!("tracing {}", traced.join(", ")));
+        }
+    }
+    if parts.is_empty() {
+        "every creep".to_string()
+    } else {
+        parts.join("; ")
+    }
+}
+
+/// Reads the sampled share and the listed creeps, dropping traced creeps whose time is up.
+fn update_sampling() {
+    let root = screeps::memory::root();
+    let sample = root.path_f64(SAMPLE_PATH).ok().flatten();
+    SAMPLE.with(|s| s.set(sample.map(|share| share.max(0.0).min(1.0))));
+
+    let listed: Option<Vec<String>> = root.path_arr(DEBUG_CREEPS_PATH).ok().flatten();
+    let mut creeps: Option<HashSet<String>> = listed.map(|names| names.into_iter().collect());
+    if let Ok(Some(traced)) = root.path_dict(TRACED_CREEPS_PATH) {
+        let time = screeps::game::time();
+        for name in traced.keys() {
+            match traced.i32(&name).ok().flatten() {
+                Some(until) if until as u32 >= time => {
+                    creeps.get_or_insert_with(HashSet::new).insert(name);
+                }
+                _ => traced.del(&name),
+            }
+        }
+    }
+    DEBUG_CREEPS.with(|c| *c.borrow_mut() = creeps);
+}
+
+/// Formats a console line as a JSON object.
+fn format_json(text: &str, record: &Record<'_>) -> String {
+    let fields: Vec<String> = FIELDS.with(|fields| {
+        fields
+            .borrow()
+            .iter()
+            .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+            .collect()
+    });
+    format!(
+        "{{\"tick\":{},\"level\":{},\"target\":{},\"message\":{},\"fields\":{{{}}}}}",
+        screeps::game::time(),
+        json_string(&record.level().to_string()),
+        json_string(module_of(record.target())),
+        json_string(text),
+        fields.join(",")
+    )
+}
+
+/// Formats a console line as `[tick] LEVEL module: message`, in a span colored by level unless
+/// plain lines are asked for.
+fn format_console(
+    out: fern::FormatCallback<'_>,
+    message: &std::fmt::Arguments<'_>,
+    record: &Record<'_>,
+) {
+    let mut text = message.to_string();
+    if let Some((end, _)) = text.char_indices().nth(MAX_LENGTH.with(Cell::get)) {
+        text.truncate(end);
+        text.push('…');
+    }
+    if JSON.with(Cell::get) {
+        out.finish(format_args!("{}", format_json(&text, record)));
+        return;
+    }
+    text.push_str(&fields_suffix());
+    let time = screeps::game::time();
+    let module = module_of(record.target());
+
+    if PLAIN.with(Cell::get) {
+        out.finish(format_args!(
+            "[{}] {} {}: {}",
+            time,
+            record.level(),
+            module,
+            text
+        ));
+        return;
+    }
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let style = match record.level() {
+        Level::Error => "color: #ff5555",
+        Level::Warn => "color: #ffcc44",
+        Level::Info => "",
+        Level::Debug | Level::Trace => "color: #888888",
+    };
+    out.finish(format_args!(
+        "<span style=\"{}\">[{}] {} {}: {}</span>",
+        style,
+        time,
+        record.level(),
+        module,
+        text
+    ));
+}
+
+/// A record's target without the crate name, like `movement::flee`.
+fn module_of(target: &str) -> &str {
+    let krate = module_path!().split("::").next().unwrap_or_default();
+    target
+        .strip_prefix(krate)
+        .map_or(target, |rest| rest.trim_start_matches("::"))
+}
+
+/// Whether a record passes the filter of its module, or the global level if there's none.
+fn allows(metadata: &Metadata<'_>) -> bool {
+    let module = module_of(metadata.target());
+    TARGETS.with(|targets| {
+        if !targets.borrow().contains(module) {
+            targets.borrow_mut().insert(module.to_string());
+        }
+    });
+
+    let level = FILTERS.with(|filters| {
+        filters
+            .borrow()
+            .iter()
+            .find(|(path, _)| {
+                module == path || module.starts_with(path) && module[path.len()..].starts_with("::")
+            })
+            .map(|&(_, level)| level)
+    });
+    metadata.level() <= level.unwrap_or_else(|| GLOBAL_LEVEL.with(Cell::get))
+}
+
+/// Describes the active filters and the modules which logged so far, for the console.
+pub fn describe_filters() -> String {
+    let global = GLOBAL_LEVEL.with(Cell::get);
+    let filters = FILTERS.with(|filters| {
+        filters
+            .borrow()
+            .iter()
+            .map(|(path, level)| format!("{}: {}", path, level))
+            .collect::<Vec<_>>()
+    });
+    let targets = TARGETS.with(|targets| targets.borrow().iter().cloned().collect::<Vec<_>>());
+    format!(
+        "global level {}, filters: {}; modules which logged: {}",
+        global,
+        if filters.is_empty() {
+            "none".to_string()
+        } else {
+            filters.join(", ")
+        },
+        targets.join(", ")
+    )
+}
+
+/// Applies the levels set in `Memory.config`, falling back to the default when the global one
+/// isn't set or can't be read.
+pub fn update_levels() {
+    let setting = screeps::memory::root()
+        .path_string(LEVEL_PATH)
+        .ok()
+        .flatten();
+    let default = DEFAULT_LEVEL.with(Cell::get);
+    let level = match setting {
+        Some(setting) => match LevelFilter::from_str(&setting) {
+            Ok(level) => {
+                INVALID_LEVEL.with(|i| i.borrow_mut().take());
+                level
+            }
+            Err(_) => {
+                let first = INVALID_LEVEL.with(|i| {
+                    let mut invalid = i.borrow_mut();
+                    let first = invalid.as_deref() != Some(setting.as_str());
+                    *invalid = Some(setting.clone());
+                    first
+                });
+                if first {
+                    warn!(
+                        "unknown log level {:?} in Memory.config.log_level, using {}",
+                        setting, default
+                    );
+                }
+                default
+            }
+        },
+        None => default,
+    };
+
+    let mut filters: Vec<(String, LevelFilter)> = Vec::new();
+    if let Ok(Some(config)) = screeps::memory::root().path_dict(FILTERS_PATH) {
+        for path in config.keys() {
+            let setting = config.string(&path).ok().flatten().unwrap_or_default();
+            match LevelFilter::from_str(&setting) {
+                Ok(level) => filters.push((path, level)),
+                Err(_) => warn!("unknown log level {:?} for module {}", setting, path),
+            }
+        }
+    }
+    filters.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    // the most verbose level anything is allowed at has to get past `log`'s own check
+    let max_level = filters
+        .iter()
+        .map(|&(_, level)| level)
+        .fold(level, std::cmp::max);
+    let notify_level = match screeps::memory::root()
+        .path_string(NOTIFY_LEVEL_PATH)
+        .ok()
+        .flatten()
+    {
+        Some(setting) => match LevelFilter::from_str(&setting) {
+            Ok(level) => level,
+            Err(_) => {
+                warn!("unknown notify level {:?}, sending only errors", setting);
+                LevelFilter::Error
+            }
+        },
+        None => LevelFilter::Error,
+    };
+    NOTIFY_LEVEL.with(|n| n.set(notify_level));
+
+    let repeat_limit = screeps::memory::root()
+        .path_i32(REPEAT_LIMIT_PATH)
+        .ok()
+        .flatten()
+        .map_or(REPEAT_LIMIT, |limit| limit.max(1) as u32);
+    let repeat_window = screeps::memory::root()
+        .path_i32(REPEAT_WINDOW_PATH)
+        .ok()
+        .flatten()
+        .map_or(REPEAT_WINDOW, |window| window.max(1) as u32);
+    REPEAT_LIMIT_SETTING.with(|l| l.set(repeat_limit));
+
+    let max_length = screeps::memory::root()
+        .path_i32(MAX_LENGTH_PATH)
+        .ok()
+        .flatten()
+        .map_or(MAX_LINE_LENGTH, |length| length.max(1) as usize);
+    PLAIN.with(|p| p.set(screeps::memory::root().path_bool(PLAIN_PATH)));
+    let format = screeps::memory::root()
+        .path_string(FORMAT_PATH)
+        .ok()
+        .flatten();
+    JSON.with(|j| j.set(format.as_deref() == Some("json")));
+    MAX_LENGTH.with(|m| m.set(max_length));
+    REPEAT_WINDOW_SETTING.with(|w| w.set(repeat_window));
+    update_sampling();
+
+    let changed = GLOBAL_LEVEL.with(|g| g.replace(level)) != level
+        || FILTERS.with(|f| *f.borrow() != filters);
+    FILTERS.with(|f| *f.borrow_mut() = filters);
+    log::set_max_level(max_level);
+    if changed {
+        info!("log level set to {}", level);
+    }
 }
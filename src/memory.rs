@@ -0,0 +1,43 @@
+//! Keeping `Memory` and the heap free of what dead creeps leave behind.
+//!
+//! Every subsystem saves and loads its own state, in a format of its own, so this only holds what
+//! runs across all of them. Every so often the memory of creeps which are gone is removed, burying
+//! the ones [`lifetimes`] lost track of across a reset, and the subsystems drop what they keep on
+//! the heap for creeps and trips which are over.
+
+use std::collections::HashSet;
+
+use log::*;
+
+use crate::{creep_costs, creeps, emergency, lifetimes, movement, panics};
+
+/// Removes the memory and heap state of creeps which are gone.
+pub fn cleanup() -> Result<(), Box<dyn std::error::Error>> {
+    let alive_creeps: HashSet<String> = screeps::game::creeps::keys().into_iter().collect();
+
+    creeps::forget_dead();
+    panics::forget_expired();
+    emergency::forget_reclaimed();
+    creep_costs::forget_dead(&alive_creeps);
+    movement::forget_dead(&alive_creeps);
+
+    let screeps_memory = match screeps::memory::root().dict("creeps")? {
+        Some(v) => v,
+        None => {
+            warn!("not cleaning game creep memory: no Memory.creeps dict");
+            return Ok(());
+        }
+    };
+
+    for mem_name in screeps_memory.keys() {
+        if !alive_creeps.contains(&mem_name) {
+            debug!("cleaning up creep memory of dead creep {}", mem_name);
+            if let Some(memory) = screeps_memory.dict(&mem_name)? {
+                lifetimes::bury_forgotten(&mem_name, &memory);
+            }
+            screeps_memory.del(&mem_name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Fixed commutes.
+//!
+//! Trips creeps make over and over, like carrying energy from a source to the same structure, get
+//! their path searched once and stored serialized in a cache keyed by where the trip starts and
+//! ends. Every creep making the same trip then follows the stored path with `moveByPath` instead
+//! of searching its own. Stored paths are dropped once the structures in the room change, since a
+//! finished road can make for a better path.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use screeps::{prelude::*, Creep, Position, ReturnCode};
+
+use super::{costs, intents};
+use crate::heap::CacheSize;
+
+/// How many paths are kept before the cache is cleared.
+const MAX_COMMUTES: usize = 500;
+
+struct Commute {
+    serialized: String,
+    steps: Vec<Position>,
+    generation: u32,
+}
+
+thread_local! {
+    static COMMUTES: RefCell<HashMap<(Position, Position), Commute>> = RefCell::new(HashMap::new());
+    /// Where the trip each creep is on started and ends.
+    static CURRENT: RefCell<HashMap<String, (Position, Position)>> = RefCell::new(HashMap::new());
+}
+
+/// Moves a creep along the stored path of its trip to `target`.
+///
+/// Returns `None` if there is no usable path, or the creep isn't on it, in which case the caller
+/// should move it normally.
+pub fn follow(creep: &Creep, target: Position, range: u32) -> Option<bool> {
+    let pos = creep.pos();
+    if target.room_name() != pos.room_name() {
+        return None;
+    }
+    let generation = costs::generation(pos.room_name())?;
+    let name = creep.name();
+
+    let key = CURRENT
+        .with(|current| current.borrow().get(&name).copied())
+        .filter(|&(_, to)| to == target)
+        .unwrap_or((pos, target));
+
+    let (serialized, next) = COMMUTES.with(|commutes| {
+        let mut commutes = commutes.borrow_mut();
+        let fresh = commutes
+            .get(&key)
+            .map_or(false, |c| c.generation == generation);
+        if !fresh {
+            let steps = super::search(
+                key.0,
+                target,
+                range,
+                super::terrain_costs(creep),
+                false,
+                None,
+            );
+            if steps.is_empty() {
+                return None;
+            }
+            if commutes.len() >= MAX_COMMUTES {
+                commutes.clear();
+            }
+            commutes.insert(
+                key,
+                Commute {
+                    serialized: serialize(key.0, &steps)?,
+                    steps,
+                    generation,
+                },
+            );
+        }
+
+        let commute = commutes.get(&key)?;
+        let next = if pos == key.0 {
+            commute.steps.first().copied()
+        } else {
+            let index = commute.steps.iter().position(|&p| p == pos)?;
+            commute.steps.get(index + 1).copied()
+        };
+        Some((commute.serialized.clone(), next?))
+    })?;
+
+    match creep.move_by_path_serialized(&serialized) {
+        ReturnCode::Ok => {
+            CURRENT.with(|current| current.borrow_mut().insert(name.clone(), key));
+            intents::register(name, pos, next);
+            Some(true)
+        }
+        ReturnCode::Tired => Some(true),
+        _ => {
+            CURRENT.with(|current| current.borrow_mut().remove(&name));
+            None
+        }
+    }
+}
+
+/// Drops the trips of creeps which are no longer alive.
+pub(super) fn forget_dead(alive_creeps: &HashSet<String>) {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+}
+
+pub(super) fn cache_sizes() -> Vec<CacheSize> {
+    vec![
+        COMMUTES.with(|c| {
+            CacheSize::of_map("movement.commutes", &c.borrow(), |_, commute| {
+                commute.serialized.capacity()
+                    + commute.steps.capacity() * std::mem::size_of::<Position>()
+            })
+        }),
+        CURRENT.with(|c| {
+            CacheSize::of_map("movement.current_commutes", &c.borrow(), |name, _| {
+                name.capacity()
+            })
+        }),
+    ]
+}
+
+pub(super) fn purge() {
+    COMMUTES.with(|c| std::mem::take(&mut *c.borrow_mut()));
+    CURRENT.with(|c| std::mem::take(&mut *c.borrow_mut()));
+}
+
+/// Serializes a path within one room the way `Room.serializePath` does: the first step's
+/// coordinates as two digits each, then the direction of every step.
+fn serialize(origin: Position, steps: &[Position]) -> Option<String> {
+    let first = steps.first()?;
+    let mut serialized = format!("{:02}{:02}", first.x(), first.y());
+    let mut from = origin;
+    for &step in steps {
+        serialized.push_str(&(from.get_direction_to(&step)? as u32).to_string());
+        from = step;
+    }
+    Some(serialized)
+}
@@ -0,0 +1,331 @@
+//! Cost matrices for pathing.
+//!
+//! Each visible room gets a matrix which knows about its structures: roads are cheap, containers
+//! and our own ramparts are walkable, and everything else built is an obstacle. Containers next
+//! to sources are also obstacles, as that's where miners stand. The tiles around keeper lairs
+//! are expensive, so paths through source keeper rooms keep clear of the keepers.
+//!
+//! Matrices are kept in heap memory along with a fingerprint of the room's structures, their
+//! types and positions, and are only rebuilt once it changes, which is checked at most once per
+//! tick. So a road finished or a wall destroyed is noticed even if something else was built or
+//! destroyed the same tick. How often the cache was used is logged with the movement report and
+//! written to `Memory.stats.cost_matrices`.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use log::*;
+use screeps::{
+    find, pathfinder::LocalCostMatrix, prelude::*, Position, Room, RoomName, Structure,
+    StructureType, Terrain,
+};
+
+use crate::heap::CacheSize;
+
+/// The bits of the raw terrain buffer.
+const TERRAIN_MASK_WALL: u8 = 1;
+const TERRAIN_MASK_SWAMP: u8 = 2;
+
+/// How far around keeper lairs tiles are expensive.
+const LAIR_RANGE: u32 = 4;
+const LAIR_COST: u8 = 40;
+
+const STATS_PATH: &str = "stats.cost_matrices";
+
+struct CachedMatrix {
+    matrix: LocalCostMatrix,
+    fingerprint: u64,
+    checked_at: u32,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct CacheStats {
+    hits: u32,
+    misses: u32,
+}
+
+thread_local! {
+    static MATRICES: RefCell<HashMap<RoomName, CachedMatrix>> = RefCell::new(HashMap::new());
+    static STATS: RefCell<CacheStats> = RefCell::new(CacheStats::default());
+    static GENERATION: Cell<u32> = Cell::new(0);
+}
+
+/// The cost matrix of a room, or `None` if the room isn't visible.
+pub fn cost_matrix(room_name: RoomName) -> Option<LocalCostMatrix> {
+    with_matrix(room_name, |cached| cached.matrix.clone())
+}
+
+/// A number which changes whenever the structures of a room do, or `None` if the room isn't
+/// visible.
+pub fn generation(room_name: RoomName) -> Option<u32> {
+    with_matrix(room_name, |cached| cached.generation)
+}
+
+/// Whether a creep can stand on a tile, ignoring other creeps.
+pub fn is_passable(pos: Position) -> bool {
+    let (x, y) = (pos.x(), pos.y());
+    screeps::game::map::get_room_terrain(pos.room_name()).get(x, y) != Terrain::Wall
+        && with_matrix(pos.room_name(), |cached| {
+            cached.matrix.get(x as u8, y as u8) < 255
+        })
+        .unwrap_or(true)
+}
+
+/// A matrix for a square of creeps `size` tiles across moving as one, by the tile of its top
+/// left creep. A tile costs what the most expensive tile under the square does, and is
+/// impassable if any of them is or the square would stick out of the room. Rooms which aren't
+/// visible only have their terrain.
+pub fn block_matrix(
+    room_name: RoomName,
+    size: u32,
+    (plain_cost, swamp_cost): (u8, u8),
+) -> LocalCostMatrix {
+    let terrain = screeps::game::map::get_room_terrain(room_name).get_raw_buffer();
+    let structures = with_matrix(room_name, |cached| cached.matrix.clone());
+    block_matrix_from(
+        &terrain,
+        structures.as_ref(),
+        size,
+        (plain_cost, swamp_cost),
+    )
+}
+
+/// The [`block_matrix`] of a room with the raw `terrain` buffer, and the matrix of its
+/// `structures` if it's visible.
+pub fn block_matrix_from(
+    terrain: &[u8],
+    structures: Option<&LocalCostMatrix>,
+    size: u32,
+    (plain_cost, swamp_cost): (u8, u8),
+) -> LocalCostMatrix {
+    let cost = |x: u32, y: u32| {
+        let tile = terrain[(y * 50 + x) as usize];
+        let built = structures.map_or(0, |m| m.get(x as u8, y as u8));
+        if tile & TERRAIN_MASK_WALL != 0 {
+            255
+        } else if built > 0 {
+            built
+        } else if tile & TERRAIN_MASK_SWAMP != 0 {
+            swamp_cost
+        } else {
+            plain_cost
+        }
+    };
+    let mut matrix = LocalCostMatrix::new();
+    for y in 0..50 {
+        for x in 0..50 {
+            let worst = if x + size > 50 || y + size > 50 {
+                255
+            } else {
+                (0..size)
+                    .flat_map(|dy| (0..size).map(move |dx| (x + dx, y + dy)))
+                    .map(|(x, y)| cost(x, y))
+                    .max()
+                    .unwrap_or(255)
+            };
+            matrix.set(x as u8, y as u8, worst);
+        }
+    }
+    matrix
+}
+
+fn with_matrix<R>(room_name: RoomName, f: impl FnOnce(&CachedMatrix) -> R) -> Option<R> {
+    let room = screeps::game::rooms::get(room_name)?;
+    let time = screeps::game::time();
+
+    MATRICES.with(|matrices| {
+        let mut matrices = matrices.borrow_mut();
+        if let Some(cached) = matrices.get(&room_name) {
+            if cached.checked_at == time {
+                STATS.with(|s| s.borrow_mut().hits += 1);
+                return Some(f(cached));
+            }
+        }
+
+        let structures = room.find(find::STRUCTURES);
+        let fingerprint = fingerprint(structures.iter().map(|structure| {
+            let pos = structure.pos();
+            let passable = match structure {
+                Structure::Rampart(rampart) => rampart.my() || rampart.is_public(),
+                _ => false,
+            };
+            (structure.structure_type(), pos.x(), pos.y(), passable)
+        }));
+        if let Some(cached) = matrices.get_mut(&room_name) {
+            if cached.fingerprint == fingerprint {
+                cached.checked_at = time;
+                STATS.with(|s| s.borrow_mut().hits += 1);
+                return Some(f(cached));
+            }
+        }
+
+        STATS.with(|s| s.borrow_mut().misses += 1);
+        let cached = CachedMatrix {
+            matrix: build(&room, &structures),
+            fingerprint,
+            checked_at: time,
+            generation: GENERATION.with(|g| {
+                g.set(g.get().wrapping_add(1));
+                g.get()
+            }),
+        };
+        let result = f(&cached);
+        matrices.insert(room_name, cached);
+        Some(result)
+    })
+}
+
+/// A hash of a room's structures, as their type, position and whether they can be walked on,
+/// which doesn't depend on the order they're listed in.
+fn fingerprint(structures: impl Iterator<Item = (StructureType, u32, u32, bool)>) -> u64 {
+    structures
+        .map(|structure| {
+            let mut hasher = DefaultHasher::new();
+            structure.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+/// Logs and stores how often the cache was used since the last report, and starts counting
+/// again.
+pub(super) fn report() {
+    let stats = STATS.with(|s| std::mem::take(&mut *s.borrow_mut()));
+    let total = stats.hits + stats.misses;
+    if total > 0 {
+        info!(
+            "cost matrix cache: {} hits, {} misses ({}% hit rate)",
+            stats.hits,
+            stats.misses,
+            stats.hits * 100 / total
+        );
+    }
+    let memory = screeps::memory::root();
+    memory.path_set(&format!("{}.hits", STATS_PATH), stats.hits);
+    memory.path_set(&format!("{}.misses", STATS_PATH), stats.misses);
+    memory.path_set(
+        &format!("{}.hit_rate", STATS_PATH),
+        if total > 0 {
+            stats.hits as f64 / total as f64
+        } else {
+            0.0
+        },
+    );
+}
+
+pub(super) fn cache_sizes() -> Vec<CacheSize> {
+    // every matrix keeps its tiles on the heap
+    vec![MATRICES.with(|m| CacheSize::of_map("movement.cost_matrices", &m.borrow(), |_, _| 2500))]
+}
+
+pub(super) fn purge() {
+    MATRICES.with(|m| std::mem::take(&mut *m.borrow_mut()));
+}
+
+fn build(room: &Room, structures: &[Structure]) -> LocalCostMatrix {
+    let mut matrix = LocalCostMatrix::new();
+    for structure in structures {
+        let pos = structure.pos();
+        let cost = match structure {
+            Structure::Road(_) => 1,
+            Structure::Container(_) => continue,
+            Structure::Rampart(rampart) if rampart.my() || rampart.is_public() => continue,
+            _ => 255,
+        };
+        raise(&mut matrix, pos.x(), pos.y(), cost);
+    }
+
+    for source in room.find(find::SOURCES) {
+        for structure in structures {
+            if let Structure::Container(container) = structure {
+                if container.pos().is_near_to(&source) {
+                    raise(&mut matrix, container.pos().x(), container.pos().y(), 255);
+                }
+            }
+        }
+    }
+
+    for structure in structures {
+        if let Structure::KeeperLair(lair) = structure {
+            let pos = lair.pos();
+            for x in pos.x().saturating_sub(LAIR_RANGE)..=(pos.x() + LAIR_RANGE).min(49) {
+                for y in pos.y().saturating_sub(LAIR_RANGE)..=(pos.y() + LAIR_RANGE).min(49) {
+                    raise(&mut matrix, x, y, LAIR_COST);
+                }
+            }
+        }
+    }
+
+    matrix
+}
+
+/// Sets the cost of a tile unless it's already higher.
+fn raise(matrix: &mut LocalCostMatrix, x: u32, y: u32, cost: u8) {
+    let (x, y) = (x as u8, y as u8);
+    if matrix.get(x, y) < cost {
+        matrix.set(x, y, cost);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROAD: (StructureType, u32, u32, bool) = (StructureType::Road, 10, 10, false);
+    const WALL: (StructureType, u32, u32, bool) = (StructureType::Wall, 11, 10, false);
+
+    #[test]
+    fn fingerprint_ignores_the_order() {
+        assert_eq!(
+            fingerprint(vec![ROAD, WALL].into_iter()),
+            fingerprint(vec![WALL, ROAD].into_iter())
+        );
+    }
+
+    #[test]
+    fn fingerprint_changes_with_the_structures() {
+        let before = fingerprint(vec![ROAD, WALL].into_iter());
+        // one structure replaced by another, keeping the count
+        let replaced = (StructureType::Road, 11, 10, false);
+        assert_ne!(before, fingerprint(vec![ROAD, replaced].into_iter()));
+        let moved = (StructureType::Wall, 12, 10, false);
+        assert_ne!(before, fingerprint(vec![ROAD, moved].into_iter()));
+        assert_ne!(before, fingerprint(vec![ROAD].into_iter()));
+
+        let closed = (StructureType::Rampart, 12, 12, false);
+        let opened = (StructureType::Rampart, 12, 12, true);
+        assert_ne!(
+            fingerprint(vec![closed].into_iter()),
+            fingerprint(vec![opened].into_iter())
+        );
+    }
+
+    #[test]
+    fn block_matrix_takes_the_worst_tile_under_the_square() {
+        let mut terrain = vec![0; 2500];
+        terrain[10 * 50 + 10] = TERRAIN_MASK_WALL;
+        terrain[20 * 50 + 20] = TERRAIN_MASK_SWAMP;
+        let mut structures = LocalCostMatrix::new();
+        structures.set(30, 30, 1);
+
+        let matrix = block_matrix_from(&terrain, Some(&structures), 2, (2, 10));
+        // every square with the wall under it
+        for &(x, y) in &[(9, 9), (10, 9), (9, 10), (10, 10)] {
+            assert_eq!(matrix.get(x, y), 255);
+        }
+        assert_eq!(matrix.get(11, 11), 2);
+        assert_eq!(matrix.get(19, 19), 10);
+        // a single creep can take the road, which a square also stands on plains next to
+        let single = block_matrix_from(&terrain, Some(&structures), 1, (2, 10));
+        assert_eq!(single.get(30, 30), 1);
+        assert_eq!(matrix.get(30, 30), 2);
+        // the square would stick out of the room
+        assert_eq!(matrix.get(49, 0), 255);
+        assert_eq!(matrix.get(0, 49), 255);
+        assert_eq!(matrix.get(48, 48), 2);
+    }
+}
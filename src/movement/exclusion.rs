@@ -0,0 +1,249 @@
+//! Keeping idle creeps out of the core of our rooms.
+//!
+//! The core zone of a room is every tile next to a planned spawn or storage, and the planned
+//! roads within [`LANE_RANGE`] of them, which haulers run along. Tiles listed in
+//! `Memory.rooms.<name>.core_tiles` as `x,y;x,y;...` are added to it. Creeps with nothing to do
+//! step out of the zone instead of idling in it, shoved creeps aren't pushed into it, and creeps
+//! standing in it which aren't working on something are pushed out even if nobody wants their
+//! tile. Each zone tile knows how many steps it is from the edge, so creeps deep inside head
+//! for the nearest way out.
+//!
+//! Zones are worked out from the plan every [`ZONE_TICKS`] ticks, and drawn in every room of
+//! ours while `Memory.debug_core_zone` is set.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+};
+
+use log::*;
+use screeps::{prelude::*, Position, RoomName, StructureType, Terrain};
+use stdweb::js;
+
+use crate::{heap::CacheSize, planner};
+
+const OVERRIDES_KEY: &str = "core_tiles";
+
+const DEBUG_KEY: &str = "debug_core_zone";
+
+/// How far from a spawn or storage the roads are part of the zone.
+pub const LANE_RANGE: u32 = 4;
+
+/// How long a zone is kept before it's worked out again.
+pub const ZONE_TICKS: u32 = 100;
+
+type Tile = (u8, u8);
+
+struct Zone {
+    built_at: u32,
+    /// How many steps each tile of the zone is from a tile outside it.
+    depths: HashMap<Tile, u8>,
+}
+
+thread_local! {
+    static ZONES: RefCell<HashMap<RoomName, Zone>> = RefCell::new(HashMap::new());
+    static STEPS: Cell<u32> = Cell::new(0);
+}
+
+/// The tiles added to a room's zone from its memory.
+fn overrides(room_name: RoomName) -> Vec<Tile> {
+    let encoded = match planner::room_memory(room_name)
+        .and_then(|memory| memory.string(OVERRIDES_KEY).ok().flatten())
+    {
+        Some(encoded) => encoded,
+        None => return Vec::new(),
+    };
+    encoded
+        .split(';')
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let mut fields = entry.split(',');
+            let tile = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+            Some(tile).filter(|&(x, y): &Tile| x < 50 && y < 50)
+        })
+        .collect()
+}
+
+fn neighbours((x, y): Tile) -> impl Iterator<Item = Tile> {
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x as i32 + dx, y as i32 + dy)))
+        .filter(move |&(nx, ny)| (nx, ny) != (x as i32, y as i32))
+        .filter(|&(nx, ny)| nx >= 0 && nx < 50 && ny >= 0 && ny < 50)
+        .map(|(nx, ny)| (nx as u8, ny as u8))
+}
+
+/// Works out a room's zone, and how deep into it each tile is.
+fn build(room_name: RoomName) -> HashMap<Tile, u8> {
+    let owned = screeps::game::rooms::get(room_name)
+        .and_then(|room| room.controller())
+        .map_or(false, |controller| controller.my());
+    if !owned {
+        return HashMap::new();
+    }
+
+    let mut tiles: Vec<Tile> = Vec::new();
+    if let Some(plan) = planner::load(room_name) {
+        let centers: Vec<Tile> = plan
+            .entries
+            .iter()
+            .filter(|e| {
+                e.structure == StructureType::Spawn || e.structure == StructureType::Storage
+            })
+            .map(|e| (e.x, e.y))
+            .collect();
+        for &center in &centers {
+            tiles.extend(neighbours(center));
+        }
+        let in_lane = |x: u8, y: u8| {
+            centers.iter().any(|&(cx, cy)| {
+                let range = (x as i32 - cx as i32)
+                    .abs()
+                    .max((y as i32 - cy as i32).abs());
+                range as u32 <= LANE_RANGE
+            })
+        };
+        tiles.extend(
+            plan.entries
+                .iter()
+                .filter(|e| e.structure == StructureType::Road && in_lane(e.x, e.y))
+                .map(|e| (e.x, e.y)),
+        );
+    }
+    tiles.extend(overrides(room_name));
+
+    let terrain = screeps::game::map::get_room_terrain(room_name);
+    let mut depths: HashMap<Tile, u8> = tiles.into_iter().map(|tile| (tile, u8::MAX)).collect();
+    // outward from the tiles around the zone a creep could stand on
+    let mut queue: VecDeque<Tile> = VecDeque::new();
+    let edge: Vec<Tile> = depths
+        .keys()
+        .copied()
+        .filter(|&tile| {
+            neighbours(tile).any(|(x, y)| {
+                !depths.contains_key(&(x, y)) && terrain.get(x as u32, y as u32) != Terrain::Wall
+            })
+        })
+        .collect();
+    for tile in edge {
+        depths.insert(tile, 1);
+        queue.push_back(tile);
+    }
+    while let Some(tile) = queue.pop_front() {
+        let depth = depths[&tile];
+        for neighbour in neighbours(tile) {
+            if let Some(d) = depths.get_mut(&neighbour) {
+                if *d == u8::MAX {
+                    *d = depth.saturating_add(1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    depths
+}
+
+fn with_zone<R>(room_name: RoomName, f: impl FnOnce(&HashMap<Tile, u8>) -> R) -> R {
+    let time = screeps::game::time();
+    ZONES.with(|zones| {
+        let mut zones = zones.borrow_mut();
+        let stale = zones
+            .get(&room_name)
+            .map_or(true, |zone| time >= zone.built_at + ZONE_TICKS);
+        if stale {
+            let depths = build(room_name);
+            zones.insert(
+                room_name,
+                Zone {
+                    built_at: time,
+                    depths,
+                },
+            );
+        }
+        f(&zones[&room_name].depths)
+    })
+}
+
+/// How many steps a tile is from the edge of its room's zone, or `None` if it's outside it.
+pub fn depth(pos: Position) -> Option<u8> {
+    with_zone(pos.room_name(), |depths| {
+        depths.get(&(pos.x() as u8, pos.y() as u8)).copied()
+    })
+}
+
+pub fn contains(pos: Position) -> bool {
+    depth(pos).is_some()
+}
+
+/// The neighbouring tile a creep in the zone should step onto to get out of it, of the ones
+/// `usable` allows, or `None` if it isn't in the zone or there's no way out.
+pub fn way_out(pos: Position, usable: impl Fn(Position) -> bool) -> Option<Position> {
+    let here = depth(pos)?;
+    let room_name = pos.room_name();
+    neighbours((pos.x() as u8, pos.y() as u8))
+        .map(|(x, y)| Position::new(x as u32, y as u32, room_name))
+        .map(|tile| (tile, depth(tile).unwrap_or(0)))
+        .filter(|&(tile, d)| d < here && usable(tile))
+        .min_by_key(|&(_, d)| d)
+        .map(|(tile, _)| tile)
+}
+
+/// Counts a creep stepping out of a zone, for the movement report.
+pub fn count_step() {
+    STEPS.with(|steps| steps.set(steps.get() + 1));
+}
+
+/// Logs how often creeps stepped out of zones since the last report.
+pub fn report() {
+    let steps = STEPS.with(|steps| steps.replace(0));
+    if steps > 0 {
+        info!("creeps stepped out of core zones {} times", steps);
+    }
+}
+
+/// Draws the zone of every room of ours, shaded deeper towards the middle, while the debug flag
+/// is set.
+pub fn draw() {
+    if !screeps::memory::root().bool(DEBUG_KEY) {
+        return;
+    }
+    for room in screeps::game::rooms::values() {
+        let (xs, ys, depths): (Vec<u32>, Vec<u32>, Vec<u32>) = with_zone(room.name(), |zone| {
+            let mut xs = Vec::with_capacity(zone.len());
+            let mut ys = Vec::with_capacity(zone.len());
+            let mut depths = Vec::with_capacity(zone.len());
+            for (&(x, y), &depth) in zone {
+                xs.push(x as u32);
+                ys.push(y as u32);
+                depths.push(depth.min(5) as u32);
+            }
+            (xs, ys, depths)
+        });
+        if xs.is_empty() {
+            continue;
+        }
+        js! {
+            var visual = new RoomVisual(@{room.name().to_string()});
+            var xs = @{xs};
+            var ys = @{ys};
+            var depths = @{depths};
+            for (var i = 0; i < xs.length; i++) {
+                visual.rect(xs[i] - 0.5, ys[i] - 0.5, 1, 1, {
+                    fill: "#ff4040", opacity: 0.08 * depths[i]
+                });
+            }
+        }
+    }
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![ZONES.with(|z| {
+        CacheSize::of_map("movement.core_zones", &z.borrow(), |_, zone| {
+            zone.depths.capacity() * std::mem::size_of::<(Tile, u8)>()
+        })
+    })]
+}
+
+pub fn purge() {
+    ZONES.with(|z| std::mem::take(&mut *z.borrow_mut()));
+}
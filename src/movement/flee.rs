@@ -0,0 +1,110 @@
+//! Fleeing from hostile creeps.
+//!
+//! Every hostile creep which can fight has a danger range, 3 tiles for melee attackers and 5 for
+//! ranged ones, and a threatened creep searches a path out of all of them at once with the
+//! pathfinder's flee mode. Tiles our towers cover are made cheaper, so creeps run towards their
+//! protection rather than away into the open. A creep which is boxed in with nowhere further to
+//! go hides on the nearest rampart instead.
+
+use screeps::{
+    find,
+    pathfinder::{self, MultiRoomCostResult, SearchOptions},
+    prelude::*,
+    Creep, Part, Position, ReturnCode, StructureType,
+};
+
+use super::{costs, intents};
+use crate::context::TickContext;
+
+const MELEE_DANGER_RANGE: u32 = 3;
+const RANGED_DANGER_RANGE: u32 = 5;
+
+/// How close to a tower tiles count as covered.
+const TOWER_COVER_RANGE: u32 = 10;
+
+/// Moves a creep away from nearby hostiles, returning whether it's in danger at all.
+pub fn flee(creep: &Creep, ctx: &TickContext) -> bool {
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return false,
+    };
+    let pos = creep.pos();
+
+    let goals: Vec<(Position, u32)> = ctx
+        .rooms
+        .snapshot(&room)
+        .hostiles()
+        .iter()
+        .filter_map(|hostile| {
+            let range = if hostile.get_active_bodyparts(Part::RangedAttack) > 0 {
+                RANGED_DANGER_RANGE
+            } else if hostile.get_active_bodyparts(Part::Attack) > 0 {
+                MELEE_DANGER_RANGE
+            } else {
+                return None;
+            };
+            Some((hostile.pos(), range))
+        })
+        .collect();
+    if !goals
+        .iter()
+        .any(|(hostile, range)| pos.in_range_to(hostile, *range))
+    {
+        return false;
+    }
+
+    let towers: Vec<Position> = room
+        .find(find::MY_STRUCTURES)
+        .iter()
+        .filter(|s| s.structure_type() == StructureType::Tower)
+        .map(|s| s.pos())
+        .collect();
+    let options = SearchOptions::new()
+        .flee(true)
+        .plain_cost(2)
+        .swamp_cost(10)
+        .max_rooms(1)
+        .room_callback(|room_name| {
+            let mut matrix = match costs::cost_matrix(room_name) {
+                Some(matrix) => matrix,
+                None => return MultiRoomCostResult::Default,
+            };
+            for tower in &towers {
+                for y in tower.y().saturating_sub(TOWER_COVER_RANGE)
+                    ..=(tower.y() + TOWER_COVER_RANGE).min(49)
+                {
+                    for x in tower.x().saturating_sub(TOWER_COVER_RANGE)
+                        ..=(tower.x() + TOWER_COVER_RANGE).min(49)
+                    {
+                        let (x, y) = (x as u8, y as u8);
+                        if matrix.get(x, y) == 0 {
+                            matrix.set(x, y, 1);
+                        }
+                    }
+                }
+            }
+            MultiRoomCostResult::CostMatrix(matrix.upload())
+        });
+    let path = pathfinder::search_many(&pos, goals, options).load_local_path();
+
+    match path.first() {
+        Some(&next) => {
+            if let Some(direction) = pos.get_direction_to(&next) {
+                if creep.move_direction(direction) == ReturnCode::Ok {
+                    intents::register(creep.name(), pos, next);
+                }
+            }
+        }
+        None => {
+            let rampart = room
+                .find(find::MY_STRUCTURES)
+                .into_iter()
+                .filter(|s| s.structure_type() == StructureType::Rampart)
+                .min_by_key(|s| pos.get_range_to(s));
+            if let Some(rampart) = rampart {
+                super::move_creep_to(creep, &rampart, 0);
+            }
+        }
+    }
+    true
+}
@@ -0,0 +1,170 @@
+//! Moving idle creeps out of the way.
+//!
+//! Every move made through [`super::move_creep_to`] is registered here with the tile the creep
+//! is stepping onto. After all creeps have run, any creep standing still on a tile someone else
+//! wants to step onto is shoved to a free neighbouring tile, or swapped with the creep pushing it
+//! if there's no room. Since creeps on the move are never shoved, this also clears chains where
+//! one moving creep waits on another which is itself blocked by an idle creep.
+//!
+//! Creeps working on something from a distance register that with [`hold`], and are only shoved
+//! to tiles from which they can keep working. That way creeps fan out around a shared target like
+//! the controller instead of pushing each other away from it.
+//!
+//! Shoved creeps aren't pushed into a room's core zone, unless swapping is all that's left, and
+//! creeps standing in a zone without a hold are pushed out of it too, whether or not anyone wants
+//! their tile. See [`super::exclusion`].
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use screeps::{prelude::*, Position, ReturnCode};
+
+use super::{costs, exclusion};
+use crate::{creep_debug, rng};
+
+struct Intent {
+    from: Position,
+    to: Position,
+}
+
+thread_local! {
+    static INTENTS: RefCell<HashMap<String, Intent>> = RefCell::new(HashMap::new());
+    static HOLDS: RefCell<HashMap<String, (Position, u32)>> = RefCell::new(HashMap::new());
+}
+
+/// Records that a creep is stepping from one tile onto another this tick.
+pub fn register(name: String, from: Position, to: Position) {
+    INTENTS.with(|intents| intents.borrow_mut().insert(name, Intent { from, to }));
+}
+
+/// Whether a creep has already moved this tick.
+pub fn is_moving(name: &str) -> bool {
+    INTENTS.with(|intents| intents.borrow().contains_key(name))
+}
+
+/// Records that a creep is working on something this tick and has to stay within `range` of it.
+pub fn hold(name: String, target: Position, range: u32) {
+    HOLDS.with(|holds| holds.borrow_mut().insert(name, (target, range)));
+}
+
+/// Shoves idle creeps off the tiles moving creeps want and out of core zones, then forgets this
+/// tick's moves.
+pub fn resolve() {
+    let intents = INTENTS.with(|intents| std::mem::take(&mut *intents.borrow_mut()));
+    let holds = HOLDS.with(|holds| std::mem::take(&mut *holds.borrow_mut()));
+
+    let creeps = screeps::game::creeps::values();
+    let mut occupied: HashSet<Position> = creeps.iter().map(|c| c.pos()).collect();
+    let mut destinations: HashSet<Position> = intents.values().map(|i| i.to).collect();
+    let standing: HashMap<Position, _> = creeps
+        .into_iter()
+        .filter(|c| !c.spawning() && !intents.contains_key(&c.name()))
+        .map(|c| (c.pos(), c))
+        .collect();
+
+    let mut shoved: HashSet<String> = HashSet::new();
+    for intent in intents.values() {
+        let blocker = match standing.get(&intent.to) {
+            Some(blocker) if !shoved.contains(&blocker.name()) => blocker,
+            _ => continue,
+        };
+
+        // any free tile nobody is heading for, swapping with the pushing creep otherwise. The
+        // tiles are tried in a random order, so shoved creeps spread out instead of all going
+        // the same way
+        let pos = blocker.pos();
+        let in_range = |tile: &Position| match holds.get(&blocker.name()) {
+            Some((target, range)) => tile.in_range_to(target, *range),
+            None => true,
+        };
+        let mut tiles = neighbours(pos);
+        rng::shuffle(&mut tiles);
+        let tile = tiles
+            .into_iter()
+            .find(|tile| {
+                !occupied.contains(tile)
+                    && !destinations.contains(tile)
+                    && in_range(tile)
+                    && !exclusion::contains(*tile)
+                    && costs::is_passable(*tile)
+            })
+            .or_else(|| Some(intent.from).filter(in_range));
+        let tile = match tile {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let direction = match pos.get_direction_to(&tile) {
+            Some(direction) => direction,
+            None => continue,
+        };
+
+        match blocker.move_direction(direction) {
+            ReturnCode::Ok => {
+                creep_debug!(
+                    blocker.name(),
+                    "shoved creep {} out of the way to {}",
+                    blocker.name(),
+                    tile
+                );
+                occupied.insert(tile);
+                destinations.insert(tile);
+                shoved.insert(blocker.name());
+            }
+            r => creep_debug!(
+                blocker.name(),
+                "couldn't shove creep {}: {:?}",
+                blocker.name(),
+                r
+            ),
+        }
+    }
+
+    // loitering in a core zone, which only creeps working on something may do
+    for (&pos, creep) in &standing {
+        if shoved.contains(&creep.name())
+            || holds.contains_key(&creep.name())
+            || creep.fatigue() > 0
+            || !exclusion::contains(pos)
+        {
+            continue;
+        }
+        let tile = exclusion::way_out(pos, |tile| {
+            !occupied.contains(&tile) && !destinations.contains(&tile) && costs::is_passable(tile)
+        });
+        let tile = match tile {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let direction = match pos.get_direction_to(&tile) {
+            Some(direction) => direction,
+            None => continue,
+        };
+        if creep.move_direction(direction) == ReturnCode::Ok {
+            creep_debug!(
+                creep.name(),
+                "pushed creep {} out of the core to {}",
+                creep.name(),
+                tile
+            );
+            occupied.insert(tile);
+            destinations.insert(tile);
+            exclusion::count_step();
+        }
+    }
+}
+
+fn neighbours(pos: Position) -> Vec<Position> {
+    let (x, y) = (pos.x() as i32, pos.y() as i32);
+    let mut tiles = Vec::with_capacity(8);
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (nx, ny) = (x + dx, y + dy);
+            if (dx, dy) != (0, 0) && nx >= 1 && nx <= 48 && ny >= 1 && ny <= 48 {
+                tiles.push(Position::new(nx as u32, ny as u32, pos.room_name()));
+            }
+        }
+    }
+    tiles
+}
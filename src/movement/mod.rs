@@ -0,0 +1,421 @@
+//! Creep movement.
+//!
+//! All movement goes through [`move_creep_to`], so there's one place to tune pathing. Paths are
+//! searched against the structure-aware matrices from [`costs`], kept in heap memory, and
+//! followed for a few ticks before they're searched again. Setting
+//! `Memory.config.debug_no_path_reuse = true` from the console turns the reuse off, which helps
+//! when checking whether a bug is caused by a stale path.
+//!
+//! Creeps which stop getting anywhere are detected by [`stuck`] and search a new path around
+//! other creeps. Creeps headed for another room only path through the rooms of their route.
+//!
+//! Trips creeps make over and over can use [`commute_to`] instead, which shares one stored path
+//! between every creep making the same trip.
+//!
+//! Portals count as obstacles like any other structure, so creeps never stumble into one; only
+//! [`move_through_portal`] steps onto them on purpose.
+//!
+//! Exit tiles are never somewhere to stop, as the game moves creeps on them to the next room. Paths
+//! within a room avoid them, and creeps which end up idle on one anyway step back inward. Nor is
+//! the core of a room, around its spawns and storage, which idle creeps leave as described in
+//! [`exclusion`].
+//!
+//! Terrain costs follow how fast a creep actually is: creeps with a Move part for every part
+//! weighing them down cross plains in one tick and may cut through swamps, while slower creeps
+//! stick to roads.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{
+    find,
+    pathfinder::{self, MultiRoomCostResult, SearchOptions},
+    prelude::*,
+    Creep, Part, Position, ReturnCode, RoomName,
+};
+
+use crate::{context::TickContext, creep_debug, heap::CacheSize, rng};
+
+mod commutes;
+pub mod costs;
+pub mod exclusion;
+mod flee;
+pub mod intents;
+mod routes;
+pub mod stuck;
+
+pub use flee::flee;
+
+/// How many ticks a cached path is followed before it's searched again, give or take
+/// [`REUSE_PATH_JITTER`] so creeps don't all search on the same tick.
+const REUSE_PATH_TICKS: u32 = 10;
+const REUSE_PATH_JITTER: u32 = 3;
+
+pub const NO_REUSE_PATH: &str = "config.debug_no_path_reuse";
+
+/// How close to a room's center [`move_to_room`] takes creeps.
+const ROOM_RANGE: u32 = 20;
+
+struct CachedPath {
+    target: Position,
+    range: u32,
+    path: Vec<Position>,
+    searched_at: u32,
+    /// How many ticks the path is followed for.
+    reuse_ticks: u32,
+}
+
+thread_local! {
+    static PATHS: RefCell<HashMap<String, CachedPath>> = RefCell::new(HashMap::new());
+    static EXIT_STEPS: Cell<u32> = Cell::new(0);
+    /// This tick's number, and whether paths are reused on it.
+    static TICK: Cell<(u32, bool)> = Cell::new((0, true));
+}
+
+/// Takes the tick and whether paths are reused from its context, so moving a creep doesn't ask
+/// the game for them again. Called once at the start of the loop.
+pub fn begin_tick(ctx: &TickContext) {
+    TICK.with(|tick| tick.set((ctx.time, !ctx.config.no_path_reuse)));
+}
+
+/// Moves a creep towards `target` until it's within `range` of it.
+///
+/// Returns `false` if the creep is stuck for good or can't get to the target's room, and should
+/// pick a different target.
+pub fn move_creep_to<T: HasPosition + ?Sized>(creep: &Creep, target: &T, range: u32) -> bool {
+    let target = target.pos();
+    let pos = creep.pos();
+    if pos.get_range_to(&target) <= range {
+        return true;
+    }
+    // a tired creep can't move anyway, and would only look stuck
+    if creep.fatigue() > 0 {
+        return true;
+    }
+    let (time, reuse) = TICK.with(Cell::get);
+
+    let progress = stuck::record(&creep.name(), pos, time);
+    if progress == stuck::Progress::GaveUp {
+        PATHS.with(|paths| paths.borrow_mut().remove(&creep.name()));
+        return false;
+    }
+    let avoid_creeps = progress == stuck::Progress::Stuck;
+
+    let route = if target.room_name() != pos.room_name() {
+        match routes::route(creep, target.room_name()) {
+            Some(route) => Some(route),
+            None => return false,
+        }
+    } else {
+        None
+    };
+    let new_route = route.as_ref().map_or(false, |(_, new)| *new);
+    let rooms = route.map(|(rooms, _)| rooms);
+
+    let next = PATHS.with(|paths| {
+        let mut paths = paths.borrow_mut();
+        let name = creep.name();
+
+        let cached = paths.get_mut(&name).filter(|cached| {
+            reuse
+                && !avoid_creeps
+                && !new_route
+                && cached.target == target
+                && cached.range == range
+                && time - cached.searched_at < cached.reuse_ticks
+        });
+        if let Some(next) = cached.and_then(|cached| next_step(&mut cached.path, pos)) {
+            return Some(next);
+        }
+
+        let mut path = search(
+            pos,
+            target,
+            range,
+            terrain_costs(creep),
+            avoid_creeps,
+            rooms.as_deref(),
+        );
+        let next = next_step(&mut path, pos);
+        paths.insert(
+            name,
+            CachedPath {
+                target,
+                range,
+                path,
+                searched_at: time,
+                reuse_ticks: rng::jitter(REUSE_PATH_TICKS, REUSE_PATH_JITTER),
+            },
+        );
+        next
+    });
+
+    let (next, direction) = match next.and_then(|next| Some((next, pos.get_direction_to(&next)?))) {
+        Some(step) => step,
+        None => {
+            creep_debug!(
+                creep.name(),
+                "no path for creep {} to {}",
+                creep.name(),
+                target
+            );
+            return true;
+        }
+    };
+    match creep.move_direction(direction) {
+        ReturnCode::Ok => intents::register(creep.name(), pos, next),
+        ReturnCode::Tired => {}
+        r => creep_debug!(
+            creep.name(),
+            "couldn't move creep {}: {:?}",
+            creep.name(),
+            r
+        ),
+    }
+    true
+}
+
+/// Moves a creep on a trip it makes regularly towards `target`, following a stored path shared
+/// with every creep making the same trip where possible.
+///
+/// Returns `false` under the same conditions as [`move_creep_to`].
+pub fn commute_to<T: HasPosition + ?Sized>(creep: &Creep, target: &T, range: u32) -> bool {
+    let target = target.pos();
+    if creep.pos().get_range_to(&target) <= range {
+        return true;
+    }
+    match commutes::follow(creep, target, range) {
+        Some(moving) => moving,
+        None => move_creep_to(creep, &target, range),
+    }
+}
+
+/// Moves a creep into a room, away from its exits, returning whether it's there.
+pub fn move_to_room(creep: &Creep, room_name: RoomName) -> bool {
+    let center = Position::new(25, 25, room_name);
+    if creep.pos().in_range_to(&center, ROOM_RANGE) {
+        return true;
+    }
+    move_creep_to(creep, &center, ROOM_RANGE);
+    false
+}
+
+/// Moves a creep onto a portal, which every other path treats as an obstacle.
+///
+/// Returns `false` once the creep steps in, as it comes out somewhere else entirely.
+pub fn move_through_portal(creep: &Creep, portal: Position) -> bool {
+    let pos = creep.pos();
+    if !pos.is_near_to(&portal) {
+        return move_creep_to(creep, &portal, 1);
+    }
+    let direction = match pos.get_direction_to(&portal) {
+        Some(direction) => direction,
+        None => return false,
+    };
+    match creep.move_direction(direction) {
+        ReturnCode::Ok => {
+            info!(
+                "creep {} is stepping through the portal at {}",
+                creep.name(),
+                portal
+            );
+            intents::register(creep.name(), pos, portal);
+            false
+        }
+        ReturnCode::Tired => true,
+        r => {
+            warn!(
+                "creep {} couldn't step into the portal at {}: {:?}",
+                creep.name(),
+                portal,
+                r
+            );
+            false
+        }
+    }
+}
+
+/// Steps a creep which is idling on an exit tile back into the room.
+pub fn step_off_exit(creep: &Creep) {
+    let pos = creep.pos();
+    let (x, y) = (pos.x() as i32, pos.y() as i32);
+    if !is_exit(pos) || creep.fatigue() > 0 || intents::is_moving(&creep.name()) {
+        return;
+    }
+    let inward = ((24 - x).signum(), (24 - y).signum());
+    let tiles = [
+        (x + inward.0, y + inward.1),
+        (x + inward.0, y),
+        (x, y + inward.1),
+    ];
+    let tile = tiles
+        .iter()
+        .filter(|&&(tx, ty)| (tx, ty) != (x, y))
+        .map(|&(tx, ty)| Position::new(tx as u32, ty as u32, pos.room_name()))
+        .find(|&tile| !is_exit(tile) && costs::is_passable(tile));
+    if let Some(direction) = tile.and_then(|tile| pos.get_direction_to(&tile)) {
+        if creep.move_direction(direction) == ReturnCode::Ok {
+            EXIT_STEPS.with(|steps| steps.set(steps.get() + 1));
+        }
+    }
+}
+
+/// Steps a creep with nothing to do out of its room's core zone.
+pub fn leave_core(creep: &Creep) {
+    let pos = creep.pos();
+    if creep.fatigue() > 0 || intents::is_moving(&creep.name()) {
+        return;
+    }
+    let tile = match exclusion::way_out(pos, costs::is_passable) {
+        Some(tile) => tile,
+        None => return,
+    };
+    if let Some(direction) = pos.get_direction_to(&tile) {
+        if creep.move_direction(direction) == ReturnCode::Ok {
+            creep_debug!(
+                creep.name(),
+                "creep {} left the core for {}",
+                creep.name(),
+                tile
+            );
+            intents::register(creep.name(), pos, tile);
+            exclusion::count_step();
+        }
+    }
+}
+
+/// Logs the movement stats gathered since the last report.
+pub fn report() {
+    costs::report();
+    exclusion::report();
+    stuck::report();
+    let exit_steps = EXIT_STEPS.with(|steps| steps.replace(0));
+    if exit_steps > 0 {
+        info!("creeps stepped off exit tiles {} times", exit_steps);
+    }
+}
+
+/// Keeps a creep which is working on `target` from being shoved out of `range` of it this tick.
+pub fn hold<T: HasPosition + ?Sized>(creep: &Creep, target: &T, range: u32) {
+    intents::hold(creep.name(), target.pos(), range);
+}
+
+/// Drops the cached paths and routes of creeps which are no longer alive.
+pub fn forget_dead(alive_creeps: &HashSet<String>) {
+    PATHS.with(|paths| {
+        paths
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+    commutes::forget_dead(alive_creeps);
+    routes::forget_dead(alive_creeps);
+    stuck::forget_dead(alive_creeps);
+}
+
+/// Sizes of the movement caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    let mut sizes = vec![PATHS.with(|p| {
+        CacheSize::of_map("movement.paths", &p.borrow(), |name, path| {
+            name.capacity() + path.path.capacity() * std::mem::size_of::<Position>()
+        })
+    })];
+    sizes.extend(commutes::cache_sizes());
+    sizes.extend(costs::cache_sizes());
+    sizes.extend(exclusion::cache_sizes());
+    sizes.extend(routes::cache_sizes());
+    sizes.extend(stuck::cache_sizes());
+    sizes
+}
+
+/// Clears every movement cache, so paths, routes and cost matrices are searched again.
+pub fn purge() {
+    PATHS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    commutes::purge();
+    costs::purge();
+    exclusion::purge();
+    routes::purge();
+    stuck::purge();
+}
+
+/// Searches a path, treating other creeps as obstacles if `avoid_creeps` is set, and staying in
+/// `rooms` if given. Without `rooms` the path stays in the room it starts in.
+fn search(
+    from: Position,
+    to: Position,
+    range: u32,
+    (plain_cost, swamp_cost): (u8, u8),
+    avoid_creeps: bool,
+    rooms: Option<&[RoomName]>,
+) -> Vec<Position> {
+    let options = SearchOptions::new()
+        .plain_cost(plain_cost)
+        .swamp_cost(swamp_cost)
+        .room_callback(|room_name| {
+            if rooms.map_or(false, |rooms| !rooms.contains(&room_name)) {
+                return MultiRoomCostResult::Impassable;
+            }
+            let mut matrix = match costs::cost_matrix(room_name) {
+                Some(matrix) => matrix,
+                None => return MultiRoomCostResult::Default,
+            };
+            if rooms.is_none() {
+                for i in 0..50 {
+                    for &(x, y) in &[(i, 0), (i, 49), (0, i), (49, i)] {
+                        matrix.set(x, y, 255);
+                    }
+                }
+            }
+            if avoid_creeps {
+                if let Some(room) = screeps::game::rooms::get(room_name) {
+                    for creep in room.find(find::CREEPS) {
+                        let pos = creep.pos();
+                        matrix.set(pos.x() as u8, pos.y() as u8, 255);
+                    }
+                }
+            }
+            MultiRoomCostResult::CostMatrix(matrix.upload())
+        });
+    pathfinder::search(&from, &to, range, options).load_local_path()
+}
+
+/// The plain and swamp costs to search a creep's path with.
+///
+/// Every part except Move and empty Carry parts adds fatigue, 2 per tile on plains and 10 on
+/// swamps, and every active Move part takes 2 off each tick. A creep which gets across plains in
+/// one tick pays swamps by how long they actually take it, any slower creep avoids them strongly.
+fn terrain_costs(creep: &Creep) -> (u8, u8) {
+    let body = creep.body();
+    let moves = body
+        .iter()
+        .filter(|p| p.part == Part::Move && p.hits > 0)
+        .count() as u32;
+    let carries = body.iter().filter(|p| p.part == Part::Carry).count() as u32;
+    let full_carries = ((creep.store_used_capacity(None) + 49) / 50).min(carries);
+    let weight = body
+        .iter()
+        .filter(|p| p.part != Part::Move && p.part != Part::Carry)
+        .count() as u32
+        + full_carries;
+
+    if moves == 0 || weight > moves {
+        return (2, 10);
+    }
+    let swamp_ticks = (5 * weight + moves - 1) / moves;
+    (1, swamp_ticks.max(1) as u8)
+}
+
+fn is_exit(pos: Position) -> bool {
+    pos.x() == 0 || pos.x() == 49 || pos.y() == 0 || pos.y() == 49
+}
+
+/// Advances a path to where the creep is standing, returning the tile it should move to next, or
+/// `None` if the creep has left the path.
+fn next_step(path: &mut Vec<Position>, pos: Position) -> Option<Position> {
+    if let Some(index) = path.iter().position(|&p| p == pos) {
+        path.drain(..=index);
+    }
+    path.first().copied().filter(|next| pos.is_near_to(next))
+}
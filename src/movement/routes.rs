@@ -0,0 +1,112 @@
+//! Routes between rooms.
+//!
+//! Before a creep heads for another room it gets a route: the sequence of rooms to cross, found
+//! with `Game.map.findRoute`. Highways are preferred, rooms marked hostile by [`intel`] are
+//! avoided where possible, and source keeper rooms are off limits for creeps which can't fight.
+//! Paths are then only searched through the rooms of the route.
+//!
+//! Routes are kept in heap memory until the creep heads somewhere else or a room on the way turns
+//! hostile.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{prelude::*, Creep, Part, RoomName};
+
+use crate::{heap::CacheSize, intel};
+
+struct Route {
+    destination: RoomName,
+    rooms: Vec<RoomName>,
+}
+
+thread_local! {
+    static ROUTES: RefCell<HashMap<String, Route>> = RefCell::new(HashMap::new());
+}
+
+/// The rooms a creep crosses to reach `destination`, starting with the room it's in, and whether
+/// the route is new this tick. `None` if there is no way there.
+pub fn route(creep: &Creep, destination: RoomName) -> Option<(Vec<RoomName>, bool)> {
+    let from = creep.pos().room_name();
+    ROUTES.with(|routes| {
+        let mut routes = routes.borrow_mut();
+        let name = creep.name();
+
+        if let Some(route) = routes.get(&name) {
+            let still_safe = route
+                .rooms
+                .iter()
+                .all(|&room| room == destination || !intel::is_hostile(room));
+            if route.destination == destination && route.rooms.contains(&from) && still_safe {
+                return Some((route.rooms.clone(), false));
+            }
+        }
+
+        let fighter = creep.get_active_bodyparts(Part::Attack)
+            + creep.get_active_bodyparts(Part::RangedAttack)
+            > 0;
+        let steps = screeps::game::map::find_route_with_callback(from, destination, |room, _| {
+            room_cost(room, destination, fighter)
+        });
+        let steps = match steps {
+            Ok(steps) => steps,
+            Err(r) => {
+                warn!(
+                    "no route for creep {} from {} to {}: {:?}",
+                    name, from, destination, r
+                );
+                routes.remove(&name);
+                return None;
+            }
+        };
+
+        let mut rooms = vec![from];
+        rooms.extend(steps.into_iter().map(|step| step.room));
+        routes.insert(
+            name,
+            Route {
+                destination,
+                rooms: rooms.clone(),
+            },
+        );
+        Some((rooms, true))
+    })
+}
+
+/// Drops the routes of creeps which are no longer alive.
+pub fn forget_dead(alive_creeps: &HashSet<String>) {
+    ROUTES.with(|routes| {
+        routes
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+}
+
+pub(super) fn cache_sizes() -> Vec<CacheSize> {
+    vec![ROUTES.with(|r| {
+        CacheSize::of_map("movement.routes", &r.borrow(), |name, route| {
+            name.capacity() + route.rooms.capacity() * std::mem::size_of::<RoomName>()
+        })
+    })]
+}
+
+pub(super) fn purge() {
+    ROUTES.with(|r| std::mem::take(&mut *r.borrow_mut()));
+}
+
+fn room_cost(room: RoomName, destination: RoomName, fighter: bool) -> f64 {
+    if room == destination {
+        1.0
+    } else if intel::is_source_keeper(room) && !fighter {
+        f64::INFINITY
+    } else if intel::is_hostile(room) {
+        10.0
+    } else if intel::is_highway(room) {
+        1.0
+    } else {
+        2.0
+    }
+}
@@ -0,0 +1,136 @@
+//! Stuck detection.
+//!
+//! The last few positions of every moving creep are kept in heap memory. A creep which keeps
+//! trying to move but stays on the same one or two tiles is stuck, either behind a structure its
+//! cached path doesn't know about or behind other creeps, and searches a new path which steers
+//! around creeps. If that keeps failing it gives up on where it was going.
+//!
+//! Every tile creeps get stuck on is counted, so chronic chokepoints show up in the logs.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+};
+
+use log::*;
+use screeps::Position;
+
+use crate::heap::CacheSize;
+
+/// How many ticks of trying to move without getting anywhere make a creep stuck.
+const STUCK_TICKS: usize = 5;
+
+/// How many times a stuck creep searches a new path before giving up.
+const MAX_REPATHS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    Moving,
+    /// The creep should search a new path around other creeps.
+    Stuck,
+    /// The creep should give up on its target.
+    GaveUp,
+}
+
+struct History {
+    positions: VecDeque<Position>,
+    last_tick: u32,
+    repaths: u32,
+}
+
+thread_local! {
+    static HISTORY: RefCell<HashMap<String, History>> = RefCell::new(HashMap::new());
+    static STUCK_TILES: RefCell<HashMap<Position, u32>> = RefCell::new(HashMap::new());
+}
+
+/// Records where a creep is as it tries to move, and whether it's getting anywhere.
+pub fn record(name: &str, pos: Position, time: u32) -> Progress {
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let entry = history.entry(name.to_owned()).or_insert_with(|| History {
+            positions: VecDeque::with_capacity(STUCK_TICKS),
+            last_tick: time,
+            repaths: 0,
+        });
+
+        // a creep which stopped to work isn't stuck, so only count consecutive ticks of moving
+        if entry.last_tick + 1 != time {
+            entry.positions.clear();
+        }
+        entry.last_tick = time;
+        if entry.positions.len() == STUCK_TICKS {
+            entry.positions.pop_front();
+        }
+        entry.positions.push_back(pos);
+        if entry.positions.len() < STUCK_TICKS {
+            return Progress::Moving;
+        }
+
+        let tiles: HashSet<&Position> = entry.positions.iter().collect();
+        if tiles.len() > 2 {
+            entry.repaths = 0;
+            return Progress::Moving;
+        }
+
+        entry.positions.clear();
+        entry.repaths += 1;
+        STUCK_TILES.with(|tiles| *tiles.borrow_mut().entry(pos).or_insert(0) += 1);
+        if entry.repaths > MAX_REPATHS {
+            entry.repaths = 0;
+            info!(
+                "creep {} got stuck at {}, giving up on its target",
+                name, pos
+            );
+            Progress::GaveUp
+        } else {
+            Progress::Stuck
+        }
+    })
+}
+
+/// Drops the history of creeps which are no longer alive.
+pub(super) fn forget_dead(alive_creeps: &HashSet<String>) {
+    HISTORY.with(|history| {
+        history
+            .borrow_mut()
+            .retain(|name, _| alive_creeps.contains(name))
+    });
+}
+
+pub(super) fn cache_sizes() -> Vec<CacheSize> {
+    vec![
+        HISTORY.with(|h| {
+            CacheSize::of_map("movement.stuck_history", &h.borrow(), |name, history| {
+                name.capacity() + history.positions.capacity() * std::mem::size_of::<Position>()
+            })
+        }),
+        STUCK_TILES.with(|t| CacheSize::of_map("movement.stuck_tiles", &t.borrow(), |_, _| 0)),
+    ]
+}
+
+pub(super) fn purge() {
+    HISTORY.with(|h| std::mem::take(&mut *h.borrow_mut()));
+    STUCK_TILES.with(|t| std::mem::take(&mut *t.borrow_mut()));
+}
+
+/// Logs the tiles creeps got stuck on the most, and halves the counts so old chokepoints fade.
+pub(super) fn report() {
+    STUCK_TILES.with(|tiles| {
+        let mut tiles = tiles.borrow_mut();
+        let mut worst: Vec<(Position, u32)> = tiles.iter().map(|(&p, &n)| (p, n)).collect();
+        if !worst.is_empty() {
+            worst.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+            let top: Vec<String> = worst
+                .iter()
+                .take(5)
+                .map(|(pos, n)| format!("{} ({})", pos, n))
+                .collect();
+            info!("creeps got stuck most at: {}", top.join(", "));
+        }
+
+        for count in tiles.values_mut() {
+            *count /= 2;
+        }
+        tiles.retain(|_, &mut n| n > 0);
+    });
+}
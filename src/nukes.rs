@@ -0,0 +1,504 @@
+//! Watching for nukes launched at our rooms.
+//!
+//! Every tick the rooms we own are searched for incoming nukes, and ones which weren't there
+//! before are warned about and notified right away, with the tick and tile they land on. The
+//! nukes of each room are kept in `Memory.rooms.<name>.nukes`, which marks every tile within
+//! [`BLAST_RANGE`] of them as the blast radius.
+//!
+//! A nuke does [`CENTER_DAMAGE`] to the structures on its own tile and [`BLAST_DAMAGE`] to those
+//! around it, and removes every creep in the room wherever it stands. So for the last
+//! [`EVACUATE_TICKS`] ticks before impact creeps leave the room for a neighbour and wait there,
+//! and no construction sites are placed in the blast radius. Afterwards the room's workers are
+//! sent back home.
+//!
+//! Until then workers reinforce the ramparts over the important structures in the blast radius,
+//! but only if a rampart at the room's level can take what lands on its tile and the storage has
+//! the energy to get all of them there. Otherwise the energy would be wasted.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{
+    find, prelude::*, Attackable, Creep, Position, ResourceType, ReturnCode, Room, RoomName,
+    Structure, StructureType,
+};
+
+use crate::{
+    cache::RoomSnapshot,
+    context::TickContext,
+    creeps::{self, CreepTarget},
+    events,
+    heap::CacheSize,
+    intel, movement, planner,
+    spawning::{self, Role},
+};
+
+const NUKES_KEY: &str = "nukes";
+
+/// The damage a nuke does to structures on the tile it lands on.
+pub const CENTER_DAMAGE: u32 = 10_000_000;
+
+/// The damage a nuke does to structures around the tile it lands on.
+pub const BLAST_DAMAGE: u32 = 5_000_000;
+
+/// How far from where a nuke lands structures are damaged.
+pub const BLAST_RANGE: u32 = 2;
+
+/// How long before impact creeps leave the room.
+pub const EVACUATE_TICKS: u32 = 100;
+
+/// Hits ramparts are reinforced to beyond what's needed to survive, for decay and rounding.
+const REINFORCE_MARGIN: u32 = 100_000;
+
+/// How many hits one energy repairs.
+const REPAIR_HITS_PER_ENERGY: u32 = 100;
+
+/// The structures worth keeping ramparts over in the blast radius.
+const CRITICAL_STRUCTURES: &[StructureType] = &[
+    StructureType::Spawn,
+    StructureType::Storage,
+    StructureType::Terminal,
+    StructureType::Tower,
+    StructureType::PowerSpawn,
+    StructureType::Nuker,
+    StructureType::Lab,
+    StructureType::Factory,
+];
+
+/// A nuke on its way.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Impact {
+    pub pos: Position,
+    pub lands_at: u32,
+    pub launched_from: RoomName,
+}
+
+/// The nukes on their way to a room, and the ramparts to reinforce against them.
+#[derive(Clone, Debug, Default)]
+struct Watch {
+    impacts: Vec<Impact>,
+    /// The tiles of the ramparts to reinforce, with the hits each needs.
+    reinforce: Vec<(Position, u32)>,
+}
+
+thread_local! {
+    /// What's on its way to each room, refreshed every tick.
+    static WATCHES: RefCell<HashMap<RoomName, Watch>> = RefCell::new(HashMap::new());
+}
+
+/// The damage a nuke does to a structure `range` tiles from where it lands.
+pub fn damage_at(range: u32) -> u32 {
+    match range {
+        0 => CENTER_DAMAGE,
+        r if r <= BLAST_RANGE => BLAST_DAMAGE,
+        _ => 0,
+    }
+}
+
+/// The damage all of the nukes do to a tile.
+pub fn total_damage(impacts: &[Impact], pos: Position) -> u32 {
+    impacts
+        .iter()
+        .map(|impact| damage_at(impact.pos.get_range_to(&pos)))
+        .sum()
+}
+
+/// The most hits a rampart can have at a controller level.
+fn rampart_hits_max(rcl: u32) -> u32 {
+    match rcl {
+        0 | 1 => 0,
+        2 => 300_000,
+        3 => 1_000_000,
+        4 => 3_000_000,
+        5 => 10_000_000,
+        6 => 30_000_000,
+        7 => 100_000_000,
+        _ => 300_000_000,
+    }
+}
+
+fn encode(impacts: &[Impact]) -> String {
+    impacts
+        .iter()
+        .map(|i| {
+            format!(
+                "{},{},{},{}",
+                i.pos.x(),
+                i.pos.y(),
+                i.lands_at,
+                i.launched_from
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn decode(room_name: RoomName, encoded: &str) -> Vec<Impact> {
+    encoded
+        .split(';')
+        .filter_map(|entry| {
+            let mut fields = entry.split(',');
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            Some(Impact {
+                pos: Position::new(x, y, room_name),
+                lands_at: fields.next()?.parse().ok()?,
+                launched_from: RoomName::new(fields.next()?).ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Looks for nukes on their way to our rooms, notifying about new ones and placing ramparts over
+/// what's worth saving from them.
+pub fn run(ctx: &TickContext) {
+    let time = ctx.time;
+    let mut found = HashMap::new();
+    for room in screeps::game::rooms::values() {
+        if !room.controller().map_or(false, |c| c.my()) {
+            continue;
+        }
+        let room_name = room.name();
+        let mut impacts: Vec<Impact> = room
+            .find(find::NUKES)
+            .iter()
+            .map(|nuke| Impact {
+                pos: nuke.pos(),
+                lands_at: time + nuke.time_to_land(),
+                launched_from: nuke.launch_room_name(),
+            })
+            .collect();
+        impacts.sort_by_key(|i| (i.lands_at, i.pos.x(), i.pos.y()));
+
+        let memory = match planner::room_memory(room_name) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        let known = memory
+            .string(NUKES_KEY)
+            .ok()
+            .flatten()
+            .map(|encoded| decode(room_name, &encoded))
+            .unwrap_or_default();
+        let landed = known.iter().any(|k| k.lands_at <= time);
+        for impact in &impacts {
+            if !known
+                .iter()
+                .any(|k| k.pos == impact.pos && k.lands_at == impact.lands_at)
+            {
+                report(impact);
+            }
+        }
+        if landed && impacts.iter().all(|i| i.lands_at > time + EVACUATE_TICKS) {
+            info!(
+                "nuke landed in room {}, bringing its creeps back",
+                room_name
+            );
+            bring_back(room_name);
+        }
+        if impacts.is_empty() {
+            memory.del(NUKES_KEY);
+            continue;
+        }
+        if impacts != known {
+            memory.set(NUKES_KEY, encode(&impacts));
+        }
+        let snapshot = ctx.rooms.snapshot(&room);
+        let reinforce = reinforcements(&room, &snapshot, &impacts);
+        if impacts.iter().any(|i| i.lands_at > time + EVACUATE_TICKS) {
+            place_ramparts(&room, &snapshot, &reinforce);
+        }
+        found.insert(room_name, Watch { impacts, reinforce });
+    }
+    WATCHES.with(|w| *w.borrow_mut() = found);
+}
+
+fn report(impact: &Impact) {
+    let message = format!(
+        "nuke from {} lands on {} at tick {}, in {} ticks",
+        impact.launched_from,
+        impact.pos,
+        impact.lands_at,
+        impact.lands_at.saturating_sub(screeps::game::time())
+    );
+    warn!("{}", message);
+    events::notify(&message);
+}
+
+fn with_watch<T>(room_name: RoomName, f: impl FnOnce(&Watch) -> T) -> Option<T> {
+    WATCHES.with(|w| w.borrow().get(&room_name).map(f))
+}
+
+/// Whether a nuke lands on a room within [`EVACUATE_TICKS`].
+pub fn is_imminent(room_name: RoomName) -> bool {
+    let time = screeps::game::time();
+    with_watch(room_name, |watch| {
+        watch
+            .impacts
+            .iter()
+            .any(|impact| impact.lands_at <= time + EVACUATE_TICKS)
+    })
+    .unwrap_or(false)
+}
+
+/// Whether a tile is in the blast radius of a nuke landing within [`EVACUATE_TICKS`].
+pub fn in_imminent_blast(pos: Position) -> bool {
+    let time = screeps::game::time();
+    with_watch(pos.room_name(), |watch| {
+        watch.impacts.iter().any(|impact| {
+            impact.lands_at <= time + EVACUATE_TICKS && impact.pos.get_range_to(&pos) <= BLAST_RANGE
+        })
+    })
+    .unwrap_or(false)
+}
+
+/// Takes a creep out of a room a nuke is about to land on, and keeps workers of that room waiting
+/// outside. Returns whether that's all the creep does this tick.
+pub fn evacuate(creep: &Creep) -> bool {
+    let current = creep.pos().room_name();
+    if is_imminent(current) {
+        match shelter(current) {
+            Some(shelter) => {
+                movement::move_to_room(creep, shelter);
+            }
+            None => warn!("creep {} has nowhere to flee the nuke to", creep.name()),
+        }
+        return true;
+    }
+    let home = spawning::home_room(creep);
+    if spawning::role_of(creep) == Role::Worker && home.map_or(false, is_imminent) {
+        movement::step_off_exit(creep);
+        return true;
+    }
+    false
+}
+
+/// The neighbour creeps wait out a nuke in, going by name so they all pick the same one.
+fn shelter(room_name: RoomName) -> Option<RoomName> {
+    let mut neighbours: Vec<RoomName> = screeps::game::map::describe_exits(room_name)
+        .into_iter()
+        .map(|(_, name)| name)
+        .filter(|&name| !intel::is_hostile(name))
+        .collect();
+    neighbours.sort_by_key(|name| name.to_string());
+    neighbours.into_iter().next()
+}
+
+/// Sends the workers of a room which waited out a nuke back home.
+fn bring_back(room_name: RoomName) {
+    for creep in screeps::game::creeps::values() {
+        if spawning::role_of(&creep) == Role::Worker
+            && spawning::home_room(&creep) == Some(room_name)
+            && creep.pos().room_name() != room_name
+        {
+            creeps::set_target(&creep.name(), CreepTarget::Rebase(room_name));
+        }
+    }
+}
+
+/// The critical structures in the blast radius, with the damage landing on each, if ramparts at
+/// the room's level can take all of it and the storage can pay for getting them there.
+fn reinforcements(
+    room: &Room,
+    snapshot: &RoomSnapshot,
+    impacts: &[Impact],
+) -> Vec<(Position, u32)> {
+    let rcl = room.controller().map_or(0, |c| c.level());
+    let hits_max = rampart_hits_max(rcl);
+    let mut tiles: Vec<(Position, u32)> = CRITICAL_STRUCTURES
+        .iter()
+        .flat_map(|&ty| snapshot.my_structures(ty))
+        .map(|s| (s.pos(), total_damage(impacts, s.pos())))
+        .filter(|&(_, damage)| damage > 0)
+        .collect();
+    tiles.sort_by_key(|&(pos, _)| (pos.x(), pos.y()));
+    tiles.dedup();
+    let ramparts: Vec<(u32, u32)> = tiles
+        .iter()
+        .map(|&(pos, damage)| (damage, rampart_at(snapshot, pos).map_or(0, |r| r.hits())))
+        .collect();
+    let stored = snapshot
+        .my_structures(StructureType::Storage)
+        .filter_map(|s| match s {
+            Structure::Storage(storage) => {
+                Some(storage.store_used_capacity(Some(ResourceType::Energy)))
+            }
+            _ => None,
+        })
+        .sum::<u32>();
+    if !can_reinforce(&ramparts, hits_max, stored) {
+        return Vec::new();
+    }
+    tiles
+        .into_iter()
+        .map(|(pos, damage)| (pos, damage + REINFORCE_MARGIN))
+        .collect()
+}
+
+/// Whether ramparts of at most `hits_max` hits can take the damage landing on each of them, given
+/// with the hits they have as `(damage, hits)`, and `stored` energy is enough to repair them all
+/// up to it.
+fn can_reinforce(ramparts: &[(u32, u32)], hits_max: u32, stored: u32) -> bool {
+    if ramparts
+        .iter()
+        .any(|&(damage, _)| damage + REINFORCE_MARGIN > hits_max)
+    {
+        return false;
+    }
+    let needed: u32 = ramparts
+        .iter()
+        .map(|&(damage, hits)| {
+            (damage + REINFORCE_MARGIN).saturating_sub(hits) / REPAIR_HITS_PER_ENERGY
+        })
+        .sum();
+    needed <= stored
+}
+
+fn rampart_at(snapshot: &RoomSnapshot, pos: Position) -> Option<&screeps::StructureRampart> {
+    snapshot
+        .my_structures(StructureType::Rampart)
+        .find_map(|s| match s {
+            Structure::Rampart(rampart) if rampart.pos() == pos => Some(rampart),
+            _ => None,
+        })
+}
+
+/// Places ramparts over the critical structures worth reinforcing which don't have one yet.
+fn place_ramparts(room: &Room, snapshot: &RoomSnapshot, reinforce: &[(Position, u32)]) {
+    let sites = snapshot.construction_sites();
+    for &(pos, _) in reinforce {
+        let has_site = sites
+            .iter()
+            .any(|s| s.pos() == pos && s.structure_type() == StructureType::Rampart);
+        if rampart_at(snapshot, pos).is_some() || has_site {
+            continue;
+        }
+        let r = room.create_construction_site(&pos, StructureType::Rampart);
+        if r != ReturnCode::Ok {
+            debug!(
+                "couldn't place a rampart against the nuke at {}: {:?}",
+                pos, r
+            );
+        }
+    }
+}
+
+/// The rampart with the furthest to go of those which are to survive a nuke, if any.
+pub fn rampart_to_reinforce(room_name: RoomName, snapshot: &RoomSnapshot) -> Option<&Structure> {
+    let reinforce = with_watch(room_name, |watch| watch.reinforce.clone())?;
+    reinforce
+        .into_iter()
+        .filter_map(|(pos, goal)| {
+            snapshot
+                .my_structures(StructureType::Rampart)
+                .find(|s| s.pos() == pos)
+                .and_then(|s| match s {
+                    Structure::Rampart(r) if r.hits() < goal => Some((goal - r.hits(), s)),
+                    _ => None,
+                })
+        })
+        .max_by_key(|&(missing, _)| missing)
+        .map(|(_, s)| s)
+}
+
+/// The hits a rampart needs to survive the nukes on their way, or 0 if it isn't being
+/// reinforced.
+pub fn rampart_goal(pos: Position) -> u32 {
+    with_watch(pos.room_name(), |watch| {
+        watch
+            .reinforce
+            .iter()
+            .find(|&&(p, _)| p == pos)
+            .map_or(0, |&(_, goal)| goal)
+    })
+    .unwrap_or(0)
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![WATCHES.with(|w| {
+        CacheSize::of_map("nukes.watches", &w.borrow(), |_, watch| {
+            watch.impacts.capacity() * std::mem::size_of::<Impact>()
+                + watch.reinforce.capacity() * std::mem::size_of::<(Position, u32)>()
+        })
+    })]
+}
+
+/// Forgets the nukes on their way, which are found again next tick.
+pub fn purge() {
+    WATCHES.with(|w| std::mem::take(&mut *w.borrow_mut()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> RoomName {
+        RoomName::new("W1N1").unwrap()
+    }
+
+    fn impact(x: u32, y: u32) -> Impact {
+        Impact {
+            pos: Position::new(x, y, room()),
+            lands_at: 50_000,
+            launched_from: RoomName::new("W9N9").unwrap(),
+        }
+    }
+
+    #[test]
+    fn damage_falls_off_with_range() {
+        assert_eq!(damage_at(0), 10_000_000);
+        assert_eq!(damage_at(1), 5_000_000);
+        assert_eq!(damage_at(2), 5_000_000);
+        assert_eq!(damage_at(3), 0);
+        assert_eq!(damage_at(40), 0);
+    }
+
+    #[test]
+    fn damage_of_several_nukes_adds_up() {
+        let impacts = [impact(20, 20), impact(22, 20)];
+        let at = |x, y| total_damage(&impacts, Position::new(x, y, room()));
+        assert_eq!(at(20, 20), CENTER_DAMAGE + BLAST_DAMAGE);
+        assert_eq!(at(21, 21), 2 * BLAST_DAMAGE);
+        assert_eq!(at(18, 18), BLAST_DAMAGE);
+        assert_eq!(at(25, 20), 0);
+        assert_eq!(total_damage(&[], Position::new(20, 20, room())), 0);
+    }
+
+    #[test]
+    fn rampart_hits_follow_the_controller_level() {
+        assert_eq!(rampart_hits_max(1), 0);
+        assert_eq!(rampart_hits_max(5), 10_000_000);
+        assert_eq!(rampart_hits_max(8), 300_000_000);
+        // a center hit is more than a level 5 rampart can take, but a level 6 one holds
+        assert!(!can_reinforce(
+            &[(CENTER_DAMAGE, 0)],
+            rampart_hits_max(5),
+            u32::MAX
+        ));
+        assert!(can_reinforce(
+            &[(CENTER_DAMAGE, 0)],
+            rampart_hits_max(6),
+            u32::MAX
+        ));
+    }
+
+    #[test]
+    fn reinforcing_needs_the_energy_for_every_rampart() {
+        let hits_max = rampart_hits_max(7);
+        let ramparts = [(BLAST_DAMAGE, 0), (BLAST_DAMAGE, 5_000_000)];
+        // the first needs all of its hits, the second only the margin
+        let needed = (BLAST_DAMAGE + 2 * REINFORCE_MARGIN) / REPAIR_HITS_PER_ENERGY;
+        assert!(can_reinforce(&ramparts, hits_max, needed));
+        assert!(!can_reinforce(&ramparts, hits_max, needed - 1));
+        assert!(can_reinforce(&[], hits_max, 0));
+    }
+
+    #[test]
+    fn impacts_survive_a_round_trip_through_memory() {
+        let impacts = vec![impact(1, 2), impact(48, 17)];
+        assert_eq!(decode(room(), &encode(&impacts)), impacts);
+        assert!(decode(room(), "").is_empty());
+        assert!(decode(room(), "1,2,three,W9N9").is_empty());
+    }
+}
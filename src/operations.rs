@@ -0,0 +1,434 @@
+//! The long-running jobs creeps are spawned for, like mining a remote or bootstrapping a room.
+//!
+//! Each operation is kept in `Memory.operations.<kind>:<room>`, with the room it's about, the
+//! home room whose spawns serve it, its phase, the creeps working on it and the creeps it wants.
+//! Once a tick the manager rebuilds every operation's creeps from the work rooms in creep memory,
+//! so they're right again straight after a reset. Creeps of a kind whose operation is gone are
+//! orphans, and are handed to another operation of the same kind and home room if there is one.
+//!
+//! Every [`CHECK_INTERVAL`] ticks each operation's kind checks on it, updating its phase and what
+//! it wants, and the manager queues whatever isn't alive or queued yet. Operations which are done
+//! or have failed are retired, dropping their queued requests.
+
+use std::collections::HashMap;
+
+use log::*;
+use screeps::{memory::MemoryReference, prelude::*, Creep, RoomName};
+
+use crate::{
+    bootstrap, cleanup,
+    context::TickContext,
+    remotes,
+    spawning::{self, Role, SpawnRequest},
+};
+
+const OPERATIONS_KEY: &str = "operations";
+
+const ROOM_KEY: &str = "room";
+const HOME_KEY: &str = "home";
+const PHASE_KEY: &str = "phase";
+const CREEPS_KEY: &str = "creeps";
+const WANTED_KEY: &str = "wanted";
+const STARTED_KEY: &str = "started";
+
+/// How often operations are checked on.
+pub const CHECK_INTERVAL: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Remote,
+    Bootstrap,
+    Cleanup,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::Remote, Kind::Bootstrap, Kind::Cleanup];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Remote => "remote",
+            Kind::Bootstrap => "bootstrap",
+            Kind::Cleanup => "cleanup",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kind> {
+        Kind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    /// The roles of the creeps operations of this kind spawn.
+    pub fn roles(self) -> &'static [Role] {
+        match self {
+            Kind::Remote => &[
+                Role::Reserver,
+                Role::RemoteMiner,
+                Role::Hauler,
+                Role::Defender,
+                Role::KeeperKiller,
+                Role::DuoAttacker,
+                Role::DuoHealer,
+            ],
+            Kind::Bootstrap => &[Role::Pioneer],
+            Kind::Cleanup => &[Role::Dismantler],
+        }
+    }
+
+    fn of_role(role: Role) -> Option<Kind> {
+        Kind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.roles().contains(&role))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Running,
+    /// Waiting for the room to be safe again, spawning only what makes it so.
+    Suspended,
+    /// Giving up on the room for a while, spawning nothing and recycling its creeps.
+    WindingDown,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Running => "running",
+            Phase::Suspended => "suspended",
+            Phase::WindingDown => "winding_down",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Phase> {
+        [Phase::Running, Phase::Suspended, Phase::WindingDown]
+            .iter()
+            .copied()
+            .find(|phase| phase.name() == name)
+    }
+}
+
+/// Some creeps an operation wants alive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wanted {
+    pub role: Role,
+    pub count: u32,
+    /// The size they're spawned at, see [`SpawnRequest::size`].
+    pub size: u32,
+    pub priority: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct Operation {
+    pub kind: Kind,
+    pub room: RoomName,
+    pub home: RoomName,
+    pub phase: Phase,
+    /// The names of the creeps working on it.
+    pub creeps: Vec<String>,
+    pub wanted: Vec<Wanted>,
+    /// The tick it was started.
+    pub started: u32,
+}
+
+impl Operation {
+    pub fn id(&self) -> String {
+        id(self.kind, self.room)
+    }
+
+    /// Its creeps which are still alive.
+    pub fn live_creeps(&self) -> Vec<Creep> {
+        self.creeps
+            .iter()
+            .filter_map(|name| screeps::game::creeps::get(name))
+            .collect()
+    }
+}
+
+/// What an operation's kind found when checking on it.
+pub enum Outcome {
+    Continue,
+    Done(String),
+    Failed(String),
+}
+
+fn id(kind: Kind, room: RoomName) -> String {
+    format!("{}:{}", kind.name(), room)
+}
+
+/// Starts an operation, unless there's one of the kind for the room already.
+pub fn start(kind: Kind, room: RoomName, home: RoomName) {
+    let operations = match screeps::memory::root().dict_or_create(OPERATIONS_KEY) {
+        Ok(operations) => operations,
+        Err(e) => {
+            warn!(
+                "couldn't start a {} operation in {}: {}",
+                kind.name(),
+                room,
+                e
+            );
+            return;
+        }
+    };
+    let key = id(kind, room);
+    if operations.dict(&key).ok().flatten().is_some() {
+        return;
+    }
+    info!("starting operation {} from {}", key, home);
+    save(&Operation {
+        kind,
+        room,
+        home,
+        phase: Phase::Running,
+        creeps: Vec::new(),
+        wanted: Vec::new(),
+        started: screeps::game::time(),
+    });
+}
+
+/// Every operation, ordered by id.
+pub fn all() -> Vec<Operation> {
+    let operations = match screeps::memory::root().dict(OPERATIONS_KEY) {
+        Ok(Some(operations)) => operations,
+        _ => return Vec::new(),
+    };
+    let mut keys = operations.keys();
+    keys.sort();
+    keys.into_iter()
+        .filter_map(|key| {
+            let kind = Kind::from_name(key.split(':').next()?)?;
+            load(kind, &operations.dict(&key).ok()??)
+        })
+        .collect()
+}
+
+/// The operation of a kind for a room, if there is one.
+pub fn get(kind: Kind, room: RoomName) -> Option<Operation> {
+    let operations = screeps::memory::root().dict(OPERATIONS_KEY).ok()??;
+    load(kind, &operations.dict(&id(kind, room)).ok()??)
+}
+
+fn load(kind: Kind, memory: &MemoryReference) -> Option<Operation> {
+    let string = |key| memory.string(key).ok().flatten().unwrap_or_default();
+    Some(Operation {
+        kind,
+        room: RoomName::new(&string(ROOM_KEY)).ok()?,
+        home: RoomName::new(&string(HOME_KEY)).ok()?,
+        phase: Phase::from_name(&string(PHASE_KEY)).unwrap_or(Phase::Running),
+        creeps: string(CREEPS_KEY)
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+        wanted: string(WANTED_KEY)
+            .split(';')
+            .filter_map(|entry| {
+                let mut fields = entry.split(',');
+                Some(Wanted {
+                    role: Role::from_name(fields.next()?)?,
+                    count: fields.next()?.parse().ok()?,
+                    size: fields.next()?.parse().ok()?,
+                    priority: fields.next()?.parse().ok()?,
+                })
+            })
+            .collect(),
+        started: memory.i32(STARTED_KEY).ok().flatten().unwrap_or(0) as u32,
+    })
+}
+
+/// Stores an operation, with what it wants as `role,count,size,priority;...`.
+fn save(operation: &Operation) {
+    let memory = screeps::memory::root()
+        .dict_or_create(OPERATIONS_KEY)
+        .and_then(|operations| operations.dict_or_create(&operation.id()));
+    let memory = match memory {
+        Ok(memory) => memory,
+        Err(e) => {
+            warn!("couldn't store operation {}: {}", operation.id(), e);
+            return;
+        }
+    };
+    let wanted: Vec<String> = operation
+        .wanted
+        .iter()
+        .map(|w| format!("{},{},{},{}", w.role.name(), w.count, w.size, w.priority))
+        .collect();
+    memory.set(ROOM_KEY, operation.room.to_string());
+    memory.set(HOME_KEY, operation.home.to_string());
+    memory.set(PHASE_KEY, operation.phase.name());
+    memory.set(CREEPS_KEY, operation.creeps.join(","));
+    memory.set(WANTED_KEY, wanted.join(";"));
+    memory.set(STARTED_KEY, operation.started);
+}
+
+fn retire(operation: &Operation) {
+    if let Ok(Some(operations)) = screeps::memory::root().dict(OPERATIONS_KEY) {
+        operations.del(&operation.id());
+    }
+    let roles = operation.kind.roles();
+    spawning::cancel(|r| r.work_room == Some(operation.room) && roles.contains(&r.role));
+}
+
+/// Keeps every operation's creeps up to date, and checks on the operations every
+/// [`CHECK_INTERVAL`] ticks.
+pub fn run(ctx: &TickContext) {
+    let checking = ctx.is_due(CHECK_INTERVAL, 5);
+    if checking {
+        bootstrap::migrate();
+        for (remote, home) in remotes::remotes() {
+            start(Kind::Remote, remote, home);
+        }
+        cleanup::start_flagged();
+    }
+
+    let operations: HashMap<String, Operation> = all()
+        .into_iter()
+        .map(|operation| (operation.id(), operation))
+        .collect();
+    let mut assigned: HashMap<String, Vec<String>> = HashMap::new();
+    let mut orphans = Vec::new();
+    for creep in screeps::game::creeps::values() {
+        let kind = match Kind::of_role(spawning::role_of(&creep)) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let key = match spawning::work_room(&creep) {
+            Some(room) => id(kind, room),
+            None => continue,
+        };
+        if operations.contains_key(&key) {
+            assigned.entry(key).or_default().push(creep.name());
+        } else {
+            orphans.push((kind, creep));
+        }
+    }
+    if checking {
+        for (kind, creep) in orphans {
+            adopt(kind, &creep, &operations, &mut assigned);
+        }
+    }
+
+    let mut operations: Vec<(String, Operation)> = operations.into_iter().collect();
+    operations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (key, mut operation) in operations {
+        let mut creeps = assigned.remove(&key).unwrap_or_default();
+        creeps.sort();
+        let changed = creeps != operation.creeps;
+        operation.creeps = creeps;
+        if !checking {
+            if changed {
+                save(&operation);
+            }
+            continue;
+        }
+
+        let outcome = match operation.kind {
+            Kind::Remote => remotes::check_operation(&mut operation, ctx),
+            Kind::Bootstrap => bootstrap::check_operation(&mut operation, ctx),
+            Kind::Cleanup => cleanup::check_operation(&mut operation, ctx),
+        };
+        match outcome {
+            Outcome::Continue => {
+                request_missing(&operation);
+                save(&operation);
+            }
+            Outcome::Done(reason) => {
+                info!("operation {} is done, {}", key, reason);
+                retire(&operation);
+            }
+            Outcome::Failed(reason) => {
+                warn!("operation {} failed, {}", key, reason);
+                retire(&operation);
+            }
+        }
+    }
+}
+
+/// Hands a creep whose operation is gone to another of its kind from the same home room.
+fn adopt(
+    kind: Kind,
+    creep: &Creep,
+    operations: &HashMap<String, Operation>,
+    assigned: &mut HashMap<String, Vec<String>>,
+) {
+    let home = spawning::home_room(creep);
+    let mut candidates: Vec<&Operation> = operations
+        .values()
+        .filter(|operation| operation.kind == kind && Some(operation.home) == home)
+        .collect();
+    candidates.sort_by_key(|operation| operation.id());
+    let operation = match candidates.first() {
+        Some(operation) => operation,
+        None => {
+            debug!(
+                "creep {} has no {} operation to work for",
+                creep.name(),
+                kind.name()
+            );
+            return;
+        }
+    };
+    info!(
+        "creep {} lost its operation, moving it to {}",
+        creep.name(),
+        operation.id()
+    );
+    spawning::set_work_room(creep, operation.room);
+    assigned
+        .entry(operation.id())
+        .or_default()
+        .push(creep.name());
+}
+
+/// Queues the creeps an operation wants which aren't alive or queued yet.
+fn request_missing(operation: &Operation) {
+    let alive = operation.live_creeps();
+    let queue = spawning::queue();
+    for wanted in &operation.wanted {
+        let have = alive
+            .iter()
+            .filter(|c| spawning::role_of(c) == wanted.role)
+            .count() as u32
+            + queue
+                .iter()
+                .filter(|r| r.role == wanted.role && r.work_room == Some(operation.room))
+                .count() as u32;
+        for _ in have..wanted.count {
+            debug!(
+                "requesting a {} for operation {}",
+                wanted.role.name(),
+                operation.id()
+            );
+            spawning::request(SpawnRequest {
+                room_name: operation.home,
+                role: wanted.role,
+                priority: wanted.priority,
+                work_room: Some(operation.room),
+                size: wanted.size,
+            });
+        }
+    }
+}
+
+/// One line per operation, for the console.
+pub fn describe() -> Vec<String> {
+    let time = screeps::game::time();
+    all()
+        .iter()
+        .map(|operation| {
+            let wanted: Vec<String> = operation
+                .wanted
+                .iter()
+                .map(|w| format!("{} {}", w.count, w.role.name()))
+                .collect();
+            format!(
+                "{} from {}, {} for {} ticks, creeps: [{}], wants: [{}]",
+                operation.id(),
+                operation.home,
+                operation.phase.name(),
+                time.saturating_sub(operation.started),
+                operation.creeps.join(", "),
+                wanted.join(", ")
+            )
+        })
+        .collect()
+}
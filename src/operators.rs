@@ -0,0 +1,343 @@
+//! Power creeps: keeping our operators spawned and using their powers.
+//!
+//! Each power creep is based in a room of ours with a power spawn, picked the first time and kept
+//! in its memory. It's spawned there whenever it isn't alive, and renewed there with fewer than
+//! [`RENEW_TICKS`] left to live. Once the room is enabled for powers, it uses one power a tick,
+//! as soon as it's off cooldown and has the ops for it:
+//!
+//! - operate spawn, on a spawn of a room with [`SPAWN_BACKLOG`] or more creeps queued,
+//! - operate extension, filling the extensions from storage while a room waiting to spawn has
+//!   less than half its energy, and
+//! - generate ops, whenever it has nothing better to do.
+//!
+//! Between powers it keeps [`KEEP_OPS`] ops in its store, topping up from storage, and takes what
+//! it has over [`MAX_OPS`] there. Accounts without power creeps don't run any of this.
+//!
+//! The API for powers isn't all there in the bindings, so using them and reading their cooldowns
+//! and effects goes through JavaScript.
+
+use log::*;
+use screeps::{
+    memory::MemoryReference, prelude::*, AccountPowerCreep, PowerCreep, ResourceType, ReturnCode,
+    Room, RoomName, Structure, StructurePowerSpawn, StructureStorage, StructureType,
+};
+use stdweb::{js, unstable::TryInto};
+
+use crate::{context::TickContext, failures, spawning};
+
+const HOME_KEY: &str = "home";
+
+/// Operators are renewed with fewer ticks than this left to live.
+pub const RENEW_TICKS: u32 = 500;
+
+/// How many ops an operator keeps for its powers.
+pub const KEEP_OPS: u32 = 200;
+
+/// How many ops an operator carries before taking some to storage.
+pub const MAX_OPS: u32 = 500;
+
+/// How many creeps have to be queued in a room before its spawns are operated.
+pub const SPAWN_BACKLOG: usize = 3;
+
+/// How much energy storage needs for the extensions to be filled from it.
+const MIN_STORAGE_ENERGY: u32 = 10_000;
+
+/// How far powers reach.
+const POWER_RANGE: u32 = 3;
+
+/// The powers operators use, with the game's numbers for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Power {
+    GenerateOps = 1,
+    OperateSpawn = 2,
+    OperateExtension = 6,
+}
+
+impl Power {
+    fn name(self) -> &'static str {
+        match self {
+            Power::GenerateOps => "generate_ops",
+            Power::OperateSpawn => "operate_spawn",
+            Power::OperateExtension => "operate_extension",
+        }
+    }
+
+    /// How many ops using the power takes.
+    fn ops(self) -> u32 {
+        match self {
+            Power::GenerateOps => 0,
+            Power::OperateSpawn => 100,
+            Power::OperateExtension => 2,
+        }
+    }
+}
+
+/// Runs every power creep we have.
+pub fn run(ctx: &TickContext) {
+    for name in screeps::game::power_creeps::keys() {
+        let account = match screeps::game::power_creeps::get(&name) {
+            Some(account) => account,
+            None => continue,
+        };
+        match account.get_power_creep() {
+            Some(power_creep) => run_operator(&power_creep, ctx),
+            None => spawn_operator(&name, &account, ctx),
+        }
+    }
+}
+
+fn spawn_operator(name: &str, account: &AccountPowerCreep, ctx: &TickContext) {
+    let room = match home(name, ctx) {
+        Some(room) => room,
+        None => return,
+    };
+    let power_spawn = match power_spawn(&room, ctx) {
+        Some(power_spawn) => power_spawn,
+        None => return,
+    };
+    match account.spawn(&power_spawn) {
+        ReturnCode::Ok => info!("spawning operator {} in room {}", name, room.name()),
+        // still on cooldown from its last death
+        ReturnCode::Tired => {}
+        r => failures::report(name, "spawn", r),
+    }
+}
+
+fn run_operator(power_creep: &PowerCreep, ctx: &TickContext) {
+    let name = power_creep.name();
+    let room = match home(&name, ctx) {
+        Some(room) => room,
+        None => return,
+    };
+    let power_spawn = match power_spawn(&room, ctx) {
+        Some(power_spawn) => power_spawn,
+        None => return,
+    };
+
+    let ticks_to_live = match power_creep.ticks_to_live() {
+        Ok(ticks) => ticks,
+        Err(_) => return,
+    };
+    if power_creep.pos().room_name() != room.name() {
+        power_creep.move_to(&power_spawn);
+        return;
+    }
+
+    if let Some(controller) = room.controller() {
+        if !is_power_enabled(room.name()) {
+            if power_creep.pos().is_near_to(&controller) {
+                let code = js_call(&name, "enableRoom", &controller.id().to_string());
+                if code != 0 {
+                    warn!(
+                        "operator {} couldn't enable room {}: error {}",
+                        name,
+                        room.name(),
+                        code
+                    );
+                }
+            } else {
+                power_creep.move_to(&controller);
+            }
+            return;
+        }
+    }
+
+    if ticks_to_live < RENEW_TICKS {
+        if power_creep.pos().is_near_to(&power_spawn) {
+            let r = power_creep.renew(&power_spawn);
+            if r != ReturnCode::Ok {
+                failures::report(&name, "renew", r);
+            }
+        } else {
+            power_creep.move_to(&power_spawn);
+        }
+        return;
+    }
+
+    let ops = power_creep.store_used_capacity(Some(ResourceType::Ops));
+    if let Some((power, target)) = choose_power(&name, &room, ops, ctx) {
+        match target {
+            Some(target) if !power_creep.pos().in_range_to(&target, POWER_RANGE) => {
+                power_creep.move_to(&target);
+                return;
+            }
+            _ => {
+                let id = target.map(|target| target.id().to_string());
+                let code = use_power(&name, power, id.as_deref());
+                if code != 0 {
+                    warn!(
+                        "operator {} couldn't {}: error {}",
+                        name,
+                        power.name(),
+                        code
+                    );
+                }
+            }
+        }
+    }
+    manage_ops(power_creep, &room, ops);
+}
+
+/// The memory of a power creep, which it keeps while it isn't spawned.
+fn memory(name: &str) -> Option<MemoryReference> {
+    screeps::memory::root()
+        .dict_or_create("powerCreeps")
+        .and_then(|power_creeps| power_creeps.dict_or_create(name))
+        .ok()
+}
+
+/// The room a power creep is based in, picking the first of ours with a power spawn if it has
+/// none or lost it.
+fn home(name: &str, ctx: &TickContext) -> Option<Room> {
+    let memory = memory(name)?;
+    let stored = memory
+        .string(HOME_KEY)
+        .ok()
+        .flatten()
+        .and_then(|name| RoomName::new(&name).ok())
+        .and_then(screeps::game::rooms::get)
+        .filter(|room| power_spawn(room, ctx).is_some());
+    if stored.is_some() {
+        return stored;
+    }
+    let mut rooms: Vec<Room> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .filter(|room| power_spawn(room, ctx).is_some())
+        .collect();
+    rooms.sort_by_key(|room| room.name().to_string());
+    let room = rooms.into_iter().next()?;
+    info!("basing operator {} in room {}", name, room.name());
+    memory.set(HOME_KEY, room.name().to_string());
+    Some(room)
+}
+
+fn power_spawn(room: &Room, ctx: &TickContext) -> Option<StructurePowerSpawn> {
+    ctx.rooms
+        .snapshot(room)
+        .my_structures(StructureType::PowerSpawn)
+        .find_map(|structure| match structure {
+            Structure::PowerSpawn(power_spawn) => Some(power_spawn.clone()),
+            _ => None,
+        })
+}
+
+/// The power most worth using this tick and what to use it on, if any is off cooldown and
+/// affordable.
+fn choose_power(
+    name: &str,
+    room: &Room,
+    ops: u32,
+    ctx: &TickContext,
+) -> Option<(Power, Option<Structure>)> {
+    let ready = |power: Power| cooldown(name, power) == Some(0) && ops >= power.ops();
+    let queued = spawning::queue()
+        .iter()
+        .filter(|request| request.room_name == room.name())
+        .count();
+    let snapshot = ctx.rooms.snapshot(room);
+
+    if queued >= SPAWN_BACKLOG && ready(Power::OperateSpawn) {
+        let spawn = snapshot
+            .my_structures(StructureType::Spawn)
+            .find(|spawn| !has_effect(&spawn.id().to_string(), Power::OperateSpawn));
+        if let Some(spawn) = spawn {
+            return Some((Power::OperateSpawn, Some(spawn.clone())));
+        }
+    }
+
+    let low = room.energy_available() < room.energy_capacity_available() / 2;
+    if queued > 0 && low && ready(Power::OperateExtension) {
+        let storage = room.storage().filter(|storage| {
+            storage.store_used_capacity(Some(ResourceType::Energy)) >= MIN_STORAGE_ENERGY
+        });
+        if let Some(storage) = storage {
+            return Some((Power::OperateExtension, Some(Structure::Storage(storage))));
+        }
+    }
+
+    if ready(Power::GenerateOps) {
+        return Some((Power::GenerateOps, None));
+    }
+    None
+}
+
+/// Tops up the ops in a power creep's store from storage, or takes the excess there.
+fn manage_ops(power_creep: &PowerCreep, room: &Room, ops: u32) {
+    let storage: StructureStorage = match room.storage() {
+        Some(storage) => storage,
+        None => return,
+    };
+    let stored = storage.store_used_capacity(Some(ResourceType::Ops));
+    let (withdraw, amount) = if ops > MAX_OPS {
+        (false, ops - KEEP_OPS)
+    } else if ops < KEEP_OPS / 2 && stored > 0 {
+        (true, (KEEP_OPS - ops).min(stored))
+    } else {
+        return;
+    };
+    if !power_creep.pos().is_near_to(&storage) {
+        power_creep.move_to(&storage);
+        return;
+    }
+    let r = if withdraw {
+        power_creep.withdraw_amount(&storage, ResourceType::Ops, amount)
+    } else {
+        power_creep.transfer_amount(&storage, ResourceType::Ops, amount)
+    };
+    if r != ReturnCode::Ok {
+        failures::report(
+            &power_creep.name(),
+            if withdraw { "withdraw" } else { "transfer" },
+            r,
+        );
+    }
+}
+
+/// How many ticks until a power creep can use a power again, or `None` if it doesn't have it.
+fn cooldown(name: &str, power: Power) -> Option<u32> {
+    let cooldown = js! {
+        var power = Game.powerCreeps[@{name}].powers[@{power as u32}];
+        return power ? power.cooldown || 0 : null
+    };
+    cooldown.try_into().ok()
+}
+
+/// Whether the object with `id` is already under a power's effect.
+fn has_effect(id: &str, power: Power) -> bool {
+    let found = js! {
+        var object = Game.getObjectById(@{id});
+        return !!object && (object.effects || []).some(function(effect) {
+            return effect.effect === @{power as u32};
+        })
+    };
+    found.try_into().unwrap_or(false)
+}
+
+fn is_power_enabled(room_name: RoomName) -> bool {
+    let enabled = js! {
+        var room = Game.rooms[@{room_name.to_string()}];
+        return !!room && !!room.controller && !!room.controller.isPowerEnabled
+    };
+    enabled.try_into().unwrap_or(false)
+}
+
+/// Has a power creep use a power, returning the game's return code.
+fn use_power(name: &str, power: Power, target: Option<&str>) -> i32 {
+    let code = js! {
+        var target = @{target};
+        return Game.powerCreeps[@{name}].usePower(
+            @{power as u32},
+            target ? Game.getObjectById(target) : undefined
+        )
+    };
+    code.try_into().unwrap_or(-1)
+}
+
+/// Calls a power creep's method on the object with `id`, returning the game's return code.
+fn js_call(name: &str, method: &str, id: &str) -> i32 {
+    let code = js! {
+        return Game.powerCreeps[@{name}][@{method}](Game.getObjectById(@{id}))
+    };
+    code.try_into().unwrap_or(-1)
+}
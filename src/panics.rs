@@ -0,0 +1,196 @@
+//! Reporting panics, and keeping the code which panicked from running again right away.
+//!
+//! A panic in the wasm module only reaches the console as an opaque "unreachable executed", so a
+//! hook logs the message and where it happened first. The last panic is also kept in
+//! `Memory.last_panic`, which outlives the VM reset that follows it.
+//!
+//! Panics abort in the wasm build, so one can't be caught and the rest of the tick is lost either
+//! way. What can be done is not losing the next ticks too: work run through [`guard`] is
+//! remembered while it runs, and a panic within it trips it for [`TRIP_TICKS`] ticks, listed in
+//! `Memory.tripped`. Tripped work is skipped while everything else runs as usual, so a single
+//! creep in a bad state only stops itself. Guarded work returning an `Err` trips the same way,
+//! which is how creeps report a state they can't be run in without panicking over it. How often
+//! each subsystem tripped is counted in `Memory.stats.trips`, with creeps counted together.
+//!
+//! After a panic outside any guarded work, only critical work runs for a few ticks.
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    panic::{self, PanicInfo},
+};
+
+use log::*;
+
+const MESSAGE_PATH: &str = "last_panic.message";
+const TICK_PATH: &str = "last_panic.tick";
+/// Which guarded work the last panic happened in, if any.
+const WORK_PATH: &str = "last_panic.work";
+
+const TRIPPED_KEY: &str = "tripped";
+const TRIPS_PATH: &str = "stats.trips";
+
+/// How long after a panic outside guarded work only critical work runs.
+const RECOVERY_TICKS: u32 = 10;
+
+/// How long tripped work is skipped.
+pub const TRIP_TICKS: u32 = 5;
+
+thread_local! {
+    /// The guarded work currently running, outermost first.
+    static RUNNING: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+pub fn install_hook() {
+    panic::set_hook(Box::new(report));
+}
+
+fn report(info: &PanicInfo<'_>) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    let message = match info.location() {
+        Some(location) => format!(
+            "panicked at {}:{}: {}",
+            location.file(),
+            location.line(),
+            message
+        ),
+        None => format!("panicked: {}", message),
+    };
+    // the innermost work is the one to blame
+    let work = RUNNING.with(|running| {
+        running
+            .try_borrow()
+            .ok()
+            .and_then(|running| running.last().cloned())
+    });
+
+    let memory = screeps::memory::root();
+    match &work {
+        Some(work) => {
+            trip(work, &message);
+            memory.path_set(WORK_PATH, work.as_str());
+        }
+        None => {
+            error!("{}", message);
+            memory.path_del(WORK_PATH);
+        }
+    }
+    memory.path_set(MESSAGE_PATH, message);
+    memory.path_set(TICK_PATH, screeps::game::time());
+}
+
+/// Whether the code panicked outside guarded work within the last few ticks.
+pub fn recently_panicked() -> bool {
+    let memory = screeps::memory::root();
+    if memory.path_string(WORK_PATH).ok().flatten().is_some() {
+        return false;
+    }
+    match memory.path_i32(TICK_PATH).ok().flatten() {
+        Some(tick) => screeps::game::time().saturating_sub(tick as u32) <= RECOVERY_TICKS,
+        None => false,
+    }
+}
+
+/// What guarded work returns, which trips it if it says the work failed.
+pub trait Outcome {
+    /// Why the work failed, if it did.
+    fn failure(&self) -> Option<String>;
+}
+
+impl Outcome for () {
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T, E: Display> Outcome for Result<T, E> {
+    fn failure(&self) -> Option<String> {
+        self.as_ref().err().map(|e| e.to_string())
+    }
+}
+
+/// Runs `f` unless the work named `work` is tripped, tripping it if `f` panics or returns an
+/// error. Work on a single creep is named `creep:<name>`.
+pub fn guard<R: Outcome>(work: &str, f: impl FnOnce() -> R) -> Option<R> {
+    if is_tripped(work) {
+        debug!("skipping tripped {}", work);
+        return None;
+    }
+    RUNNING.with(|running| running.borrow_mut().push(work.to_string()));
+    let result = f();
+    RUNNING.with(|running| running.borrow_mut().pop());
+    if let Some(reason) = result.failure() {
+        trip(work, &reason);
+    }
+    Some(result)
+}
+
+/// Skips the work named `work` for the next [`TRIP_TICKS`] ticks, logging why.
+pub fn trip(work: &str, reason: &str) {
+    error!(
+        "{} failed, skipping it for {} ticks: {}",
+        work, TRIP_TICKS, reason
+    );
+    let memory = screeps::memory::root();
+    if let Ok(tripped) = memory.dict_or_create(TRIPPED_KEY) {
+        tripped.set(work, screeps::game::time() + TRIP_TICKS);
+    }
+
+    // creeps come and go, so they're counted together
+    let key = work.split(':').next().unwrap_or(work);
+    let path = format!("{}.{}", TRIPS_PATH, key);
+    let trips = memory.path_i32(&path).ok().flatten().unwrap_or(0);
+    memory.path_set(&path, trips + 1);
+}
+
+/// Forgets trips which are over, like those of creeps which died since.
+pub fn forget_expired() {
+    let tripped = match screeps::memory::root().dict(TRIPPED_KEY) {
+        Ok(Some(tripped)) => tripped,
+        _ => return,
+    };
+    let time = screeps::game::time();
+    for work in tripped.keys() {
+        match tripped.i32(&work).ok().flatten() {
+            Some(until) if time < until as u32 => {}
+            _ => tripped.del(&work),
+        }
+    }
+}
+
+/// Whether the work named `work` is tripped, forgetting the trip once it's over.
+fn is_tripped(work: &str) -> bool {
+    let tripped = match screeps::memory::root().dict(TRIPPED_KEY) {
+        Ok(Some(tripped)) => tripped,
+        _ => return false,
+    };
+    match tripped.i32(work).ok().flatten() {
+        Some(until) if screeps::game::time() < until as u32 => true,
+        Some(_) => {
+            info!("trying {} again", work);
+            tripped.del(work);
+            false
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_errors_are_failures() {
+        assert_eq!(().failure(), None);
+        assert_eq!(Ok::<u32, String>(3).failure(), None);
+        assert_eq!(
+            Err::<(), _>("unknown role Miner".to_string()).failure(),
+            Some("unknown role Miner".to_string())
+        );
+    }
+}
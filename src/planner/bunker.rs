@@ -0,0 +1,285 @@
+//! Stamp-based base layout.
+//!
+//! The whole core of the base is a fixed 13x13 stamp: a diagonal road grid reaching every
+//! structure, a hub of storage, terminal, link and factory around the center tile, labs grouped
+//! in one corner so they're all in reaction range of the two center labs, and extensions filling
+//! the rest. The stamp is placed at the open spot closest to the sources and the controller.
+
+use screeps::StructureType;
+
+use super::{RoomGrid, RoomPlan};
+
+/// The stamp, centered on its middle tile, using the same structure codes as the stored plans.
+/// `.` is left free.
+const STAMP: [&str; 13] = [
+    "r...reeer...r",
+    ".rererererer.",
+    "..reeereeer..",
+    ".rererererer.",
+    "reeertstreeer",
+    "erertrSrtrere",
+    "eerstlrTteree",
+    "ererprfrnrere",
+    "reeerosereeer",
+    ".rererererLrL",
+    "..reeereeLrLL",
+    ".r.rerererLrL",
+    "r...reeerLLLr",
+];
+
+/// How far the stamp reaches from its center.
+pub const RADIUS: i32 = 6;
+
+/// Whether the stamp fits with its center on `anchor`.
+pub fn fits(distances: &[u8], anchor: (u8, u8)) -> bool {
+    let (x, y) = (anchor.0 as i32, anchor.1 as i32);
+    x - RADIUS >= 2
+        && x + RADIUS <= 47
+        && y - RADIUS >= 2
+        && y + RADIUS <= 47
+        && distances[y as usize * 50 + x as usize] as i32 > RADIUS
+}
+
+/// The range from every tile to the nearest wall, indexed by `y * 50 + x`.
+///
+/// Tiles outside the room count as walls, so the edge tiles have a range of 1.
+pub fn distance_transform(grid: &RoomGrid) -> Vec<u8> {
+    let mut distances = vec![0u8; 2500];
+    let get = |d: &[u8], x: i32, y: i32| {
+        if x < 0 || x > 49 || y < 0 || y > 49 {
+            0
+        } else {
+            d[y as usize * 50 + x as usize]
+        }
+    };
+
+    // forward pass from the top left, then backwards from the bottom right, each taking the
+    // minimum over the neighbours already visited
+    for y in 0..50 {
+        for x in 0..50 {
+            if grid.terrain(x as u8, y as u8) != screeps::Terrain::Wall {
+                let nearest = get(&distances, x - 1, y)
+                    .min(get(&distances, x - 1, y - 1))
+                    .min(get(&distances, x, y - 1))
+                    .min(get(&distances, x + 1, y - 1));
+                distances[y as usize * 50 + x as usize] = nearest + 1;
+            }
+        }
+    }
+    for y in (0..50).rev() {
+        for x in (0..50).rev() {
+            let index = y as usize * 50 + x as usize;
+            if distances[index] > 0 {
+                let nearest = get(&distances, x + 1, y)
+                    .min(get(&distances, x + 1, y + 1))
+                    .min(get(&distances, x, y + 1))
+                    .min(get(&distances, x - 1, y + 1));
+                distances[index] = distances[index].min(nearest + 1);
+            }
+        }
+    }
+
+    distances
+}
+
+/// Picks where to center the stamp.
+///
+/// Of all spots the stamp fits, the one with the smallest summed range to `goals` wins. If there
+/// is an existing spawn, spots which line one of the stamp's spawns up with it are preferred so
+/// the spawn doesn't have to be rebuilt.
+pub fn find_anchor(
+    distances: &[u8],
+    goals: &[(u8, u8)],
+    spawn: Option<(u8, u8)>,
+) -> Option<(u8, u8)> {
+    let score = |(x, y): (u8, u8)| -> (u32, u8, u8) {
+        let total = goals.iter().map(|&(gx, gy)| range((x, y), (gx, gy))).sum();
+        (total, y, x)
+    };
+
+    if let Some((sx, sy)) = spawn {
+        let aligned = stamp_tiles()
+            .filter(|&(_, _, structure)| structure == StructureType::Spawn)
+            .filter_map(|(dx, dy, _)| {
+                let (x, y) = (sx as i32 - dx, sy as i32 - dy);
+                if x < 0 || x > 49 || y < 0 || y > 49 {
+                    None
+                } else {
+                    Some((x as u8, y as u8))
+                }
+            })
+            .filter(|&anchor| fits(distances, anchor))
+            .min_by_key(|&anchor| score(anchor));
+        if aligned.is_some() {
+            return aligned;
+        }
+    }
+
+    let mut best = None;
+    for y in 0..50 {
+        for x in 0..50 {
+            if fits(distances, (x, y)) && best.map_or(true, |b| score((x, y)) < score(b)) {
+                best = Some((x, y));
+            }
+        }
+    }
+    best
+}
+
+/// Adds the stamp to a plan, centered on the plan's anchor.
+///
+/// If `spawn` is one of the stamp's spawn tiles it's planned as the first spawn, so the builder
+/// doesn't wait for a higher controller level before counting it.
+pub fn apply_stamp(plan: &mut RoomPlan, spawn: Option<(u8, u8)>) {
+    let (ax, ay) = (plan.anchor.0 as i32, plan.anchor.1 as i32);
+
+    let mut tiles: Vec<(i32, i32, StructureType)> = stamp_tiles().collect();
+    tiles.sort_by_key(|&(dx, dy, _)| (dx.abs() + dy.abs(), dx.abs().max(dy.abs()), dy, dx));
+    if let Some((sx, sy)) = spawn {
+        let existing = (sx as i32 - ax, sy as i32 - ay);
+        if let Some(index) = tiles.iter().position(|&(dx, dy, structure)| {
+            structure == StructureType::Spawn && (dx, dy) == existing
+        }) {
+            let tile = tiles.remove(index);
+            tiles.insert(0, tile);
+        }
+    }
+
+    let mut counts: Vec<(StructureType, usize)> = Vec::new();
+    for (dx, dy, structure) in tiles {
+        let index = match counts.iter_mut().find(|(ty, _)| *ty == structure) {
+            Some((_, count)) => {
+                *count += 1;
+                *count - 1
+            }
+            None => {
+                counts.push((structure, 1));
+                0
+            }
+        };
+        plan.add(
+            (ax + dx) as u8,
+            (ay + dy) as u8,
+            structure,
+            super::min_rcl(structure, index),
+        );
+    }
+}
+
+/// Every structure of the stamp as an offset from its center.
+fn stamp_tiles() -> impl Iterator<Item = (i32, i32, StructureType)> {
+    STAMP.iter().enumerate().flat_map(|(row, line)| {
+        line.chars().enumerate().filter_map(move |(column, code)| {
+            super::structure_from_code(code)
+                .map(|structure| (column as i32 - RADIUS, row as i32 - RADIUS, structure))
+        })
+    })
+}
+
+fn range(a: (u8, u8), b: (u8, u8)) -> u32 {
+    (a.0 as i32 - b.0 as i32)
+        .abs()
+        .max((a.1 as i32 - b.1 as i32).abs()) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Terrain;
+
+    use super::*;
+
+    fn at(distances: &[u8], x: usize, y: usize) -> u8 {
+        distances[y * 50 + x]
+    }
+
+    #[test]
+    fn open_room_distances_grow_from_the_edges() {
+        let distances = distance_transform(&RoomGrid::new(|_, _| Terrain::Plain));
+        assert_eq!(at(&distances, 0, 0), 1);
+        assert_eq!(at(&distances, 49, 20), 1);
+        assert_eq!(at(&distances, 3, 30), 4);
+        assert_eq!(at(&distances, 24, 25), 25);
+        for y in 0..50 {
+            for x in 0..50 {
+                let expected = (x + 1).min(y + 1).min(50 - x).min(50 - y) as u8;
+                assert_eq!(at(&distances, x, y), expected, "at {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn walls_have_no_distance_and_corridors_are_narrow() {
+        // a corridor eleven tiles wide, running across the room
+        let grid = RoomGrid::new(|_, y| {
+            if (20..=30).contains(&y) {
+                Terrain::Plain
+            } else {
+                Terrain::Wall
+            }
+        });
+        let distances = distance_transform(&grid);
+        assert_eq!(at(&distances, 25, 19), 0);
+        assert_eq!(at(&distances, 25, 20), 1);
+        assert_eq!(at(&distances, 25, 25), 6);
+        assert_eq!(at(&distances, 25, 30), 1);
+        assert_eq!(at(&distances, 25, 31), 0);
+
+        // the stamp needs a range of more than its radius, which the corridor doesn't have
+        assert!((0..50).all(|x| !fits(&distances, (x, 25))));
+        assert_eq!(find_anchor(&distances, &[(25, 25)], None), None);
+    }
+
+    #[test]
+    fn stamp_fits_a_wide_enough_corridor() {
+        let grid = RoomGrid::new(|_, y| {
+            if (18..=32).contains(&y) {
+                Terrain::Plain
+            } else {
+                Terrain::Wall
+            }
+        });
+        let distances = distance_transform(&grid);
+        assert_eq!(find_anchor(&distances, &[(25, 25)], None), Some((25, 25)));
+        // equally close spots go to the top one
+        assert_eq!(find_anchor(&distances, &[(5, 25)], None), Some((8, 24)));
+    }
+
+    #[test]
+    fn anchor_near_the_edge_keeps_the_stamp_inside_the_room() {
+        let distances = distance_transform(&RoomGrid::new(|_, _| Terrain::Plain));
+        assert!(!fits(&distances, (7, 20)));
+        assert!(fits(&distances, (8, 20)));
+        assert!(!fits(&distances, (20, 42)));
+        assert!(fits(&distances, (20, 41)));
+
+        // goals in the corner pull the anchor as close as the stamp allows
+        assert_eq!(
+            find_anchor(&distances, &[(1, 1), (3, 2)], None),
+            Some((8, 8))
+        );
+        assert_eq!(find_anchor(&distances, &[(48, 48)], None), Some((41, 41)));
+    }
+
+    #[test]
+    fn anchor_lines_up_with_an_existing_spawn() {
+        let distances = distance_transform(&RoomGrid::new(|_, _| Terrain::Plain));
+        let anchor = find_anchor(&distances, &[(10, 10)], Some((30, 30))).unwrap();
+        let lined_up = stamp_tiles().any(|(dx, dy, structure)| {
+            structure == StructureType::Spawn
+                && (anchor.0 as i32 + dx, anchor.1 as i32 + dy) == (30, 30)
+        });
+        assert!(lined_up, "{:?} doesn't put a spawn on 30,30", anchor);
+
+        let mut plan = RoomPlan::new(anchor);
+        apply_stamp(&mut plan, Some((30, 30)));
+        let first_spawn = plan
+            .entries
+            .iter()
+            .find(|e| e.structure == StructureType::Spawn)
+            .unwrap();
+        assert_eq!(
+            (first_spawn.x, first_spawn.y, first_spawn.min_rcl),
+            (30, 30, 1)
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! Exit barrier layout.
+//!
+//! Every exit is sealed off by a line of walls two tiles in from the room edge, so attackers have
+//! to break through before they reach anything, and can't do it from the neighbouring room. Each
+//! side keeps one rampart as a gate for our own creeps, and tiles where the barrier crosses
+//! something else of the plan, like a road, get a rampart instead of a wall too.
+
+use std::{cmp::Ordering, collections::HashSet};
+
+use log::*;
+use screeps::{StructureType, Terrain};
+
+use super::{RoomGrid, RoomPlan};
+
+/// Walls cost a lot of energy to get to useful hits, so they wait until the room has towers.
+const BARRIER_MIN_RCL: u32 = 3;
+
+/// How far from the room edge the barrier is.
+const BARRIER_DISTANCE: u8 = 2;
+
+/// Every tile is visited at most once, so a flood fill taking longer than this is a bug.
+const MAX_FLOOD_STEPS: usize = 2500;
+
+pub fn plan_exit_barrier(grid: &RoomGrid, plan: &mut RoomPlan) {
+    let tiles = barrier_tiles(grid);
+
+    for side in 0..4 {
+        let mut side_tiles: Vec<(u8, u8)> = tiles
+            .iter()
+            .copied()
+            .filter(|&(x, y)| edge_side(x, y) == Some(side))
+            .collect();
+        if side_tiles.is_empty() {
+            continue;
+        }
+        side_tiles.sort_by_key(|&(x, y)| (x as u32 + y as u32, y, x));
+
+        // a road crossing the barrier makes the best gate, the middle of the side otherwise
+        let gate = side_tiles
+            .iter()
+            .copied()
+            .find(|&(x, y)| is_planned_as(plan, x, y, StructureType::Road))
+            .unwrap_or(side_tiles[side_tiles.len() / 2]);
+
+        for (x, y) in side_tiles {
+            if is_planned_as(plan, x, y, StructureType::Rampart)
+                || is_planned_as(plan, x, y, StructureType::Wall)
+            {
+                continue;
+            }
+            let structure = if (x, y) == gate || plan.is_planned(x, y) {
+                StructureType::Rampart
+            } else {
+                StructureType::Wall
+            };
+            plan.add(x, y, structure, BARRIER_MIN_RCL);
+        }
+    }
+}
+
+/// The tiles which seal every exit of a room, sorted by `(y, x)`.
+///
+/// Creeps move at most one tile closer to the middle of the room per step, so every way in from
+/// an exit passes from a tile one away from the edge to one [`BARRIER_DISTANCE`] away. Blocking
+/// every walkable tile at that distance which touches the edge band reachable from the exits seals
+/// them all.
+pub fn barrier_tiles(grid: &RoomGrid) -> Vec<(u8, u8)> {
+    let walkable = |x: u8, y: u8| grid.terrain(x, y) != Terrain::Wall;
+
+    let mut reached: HashSet<(u8, u8)> = HashSet::new();
+    let mut stack: Vec<(u8, u8)> = Vec::new();
+    for i in 0..50 {
+        for &tile in &[(i, 0), (i, 49), (0, i), (49, i)] {
+            if walkable(tile.0, tile.1) && reached.insert(tile) {
+                stack.push(tile);
+            }
+        }
+    }
+
+    let mut barrier: HashSet<(u8, u8)> = HashSet::new();
+    let mut steps = 0;
+    while let Some((x, y)) = stack.pop() {
+        steps += 1;
+        if steps > MAX_FLOOD_STEPS {
+            error!(
+                "exit flood fill ran past {} steps, aborting",
+                MAX_FLOOD_STEPS
+            );
+            break;
+        }
+        for (nx, ny) in neighbours(x, y) {
+            if !walkable(nx, ny) {
+                continue;
+            }
+            match edge_distance(nx, ny).cmp(&BARRIER_DISTANCE) {
+                Ordering::Less => {
+                    if reached.insert((nx, ny)) {
+                        stack.push((nx, ny));
+                    }
+                }
+                Ordering::Equal => {
+                    barrier.insert((nx, ny));
+                }
+                Ordering::Greater => {}
+            }
+        }
+    }
+
+    let mut barrier: Vec<(u8, u8)> = barrier.into_iter().collect();
+    barrier.sort_by_key(|&(x, y)| (y, x));
+    barrier
+}
+
+fn edge_distance(x: u8, y: u8) -> u8 {
+    x.min(y).min(49 - x).min(49 - y)
+}
+
+/// Which side of the room a barrier tile guards: top, right, bottom or left. Corner tiles count
+/// towards the first of those they're on.
+fn edge_side(x: u8, y: u8) -> Option<usize> {
+    if y == BARRIER_DISTANCE {
+        Some(0)
+    } else if x == 49 - BARRIER_DISTANCE {
+        Some(1)
+    } else if y == 49 - BARRIER_DISTANCE {
+        Some(2)
+    } else if x == BARRIER_DISTANCE {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+fn is_planned_as(plan: &RoomPlan, x: u8, y: u8, structure: StructureType) -> bool {
+    plan.entries
+        .iter()
+        .any(|e| e.x == x && e.y == y && e.structure == structure)
+}
+
+fn neighbours(x: u8, y: u8) -> impl Iterator<Item = (u8, u8)> {
+    let (x, y) = (x as i32, y as i32);
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && nx <= 49 && ny >= 0 && ny <= 49)
+        .map(|(nx, ny)| (nx as u8, ny as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walls all around the room, but for the tiles `open` lets through.
+    fn walled_in(open: impl Fn(u8, u8) -> bool) -> RoomGrid {
+        RoomGrid::new(|x, y| {
+            if edge_distance(x, y) > BARRIER_DISTANCE || open(x, y) {
+                Terrain::Plain
+            } else {
+                Terrain::Wall
+            }
+        })
+    }
+
+    /// Whether a creep coming in from an exit can get past the barrier.
+    fn sealed(grid: &RoomGrid, barrier: &[(u8, u8)]) -> bool {
+        let blocked: HashSet<(u8, u8)> = barrier.iter().copied().collect();
+        let passable =
+            |x: u8, y: u8| grid.terrain(x, y) != Terrain::Wall && !blocked.contains(&(x, y));
+        let mut stack: Vec<(u8, u8)> = (0..50)
+            .flat_map(|i| vec![(i, 0), (i, 49), (0, i), (49, i)])
+            .filter(|&(x, y)| passable(x, y))
+            .collect();
+        let mut seen: HashSet<(u8, u8)> = stack.iter().copied().collect();
+        while let Some((x, y)) = stack.pop() {
+            if edge_distance(x, y) > BARRIER_DISTANCE {
+                return false;
+            }
+            for tile in neighbours(x, y) {
+                if passable(tile.0, tile.1) && seen.insert(tile) {
+                    stack.push(tile);
+                }
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn room_without_exits_needs_no_barrier() {
+        let grid = walled_in(|_, _| false);
+        assert!(barrier_tiles(&grid).is_empty());
+
+        let mut plan = RoomPlan::new((25, 25));
+        plan_exit_barrier(&grid, &mut plan);
+        assert!(plan.entries.is_empty());
+    }
+
+    #[test]
+    fn single_tile_choke_is_sealed_by_its_gate() {
+        let grid = walled_in(|x, y| x == 25 && y <= BARRIER_DISTANCE);
+        let barrier = barrier_tiles(&grid);
+        assert_eq!(barrier, vec![(25, 2)]);
+        assert!(sealed(&grid, &barrier));
+
+        let mut plan = RoomPlan::new((25, 25));
+        plan_exit_barrier(&grid, &mut plan);
+        assert_eq!(plan.entries.len(), 1);
+        let gate = &plan.entries[0];
+        assert_eq!((gate.x, gate.y), (25, 2));
+        assert_eq!(gate.structure, StructureType::Rampart);
+        assert_eq!(gate.min_rcl, BARRIER_MIN_RCL);
+    }
+
+    #[test]
+    fn wide_exit_gets_a_wall_with_one_gate() {
+        let grid = walled_in(|x, y| y <= BARRIER_DISTANCE && (10..=20).contains(&x));
+        let barrier = barrier_tiles(&grid);
+        assert!(sealed(&grid, &barrier));
+        assert!(!sealed(&grid, &barrier[1..]));
+        assert!(barrier
+            .iter()
+            .all(|&(x, y)| y == 2 && (9..=21).contains(&x)));
+
+        let mut plan = RoomPlan::new((25, 25));
+        plan_exit_barrier(&grid, &mut plan);
+        let ramparts = plan
+            .entries
+            .iter()
+            .filter(|e| e.structure == StructureType::Rampart)
+            .count();
+        assert_eq!(ramparts, 1);
+        assert_eq!(plan.entries.len(), barrier.len());
+    }
+
+    #[test]
+    fn roads_through_the_barrier_become_gates() {
+        let grid = walled_in(|x, y| y <= BARRIER_DISTANCE && (10..=20).contains(&x));
+        let mut plan = RoomPlan::new((25, 25));
+        plan.add(12, 2, StructureType::Road, 3);
+        plan_exit_barrier(&grid, &mut plan);
+
+        let at = |x: u8, y: u8| -> Vec<StructureType> {
+            plan.entries
+                .iter()
+                .filter(|e| (e.x, e.y) == (x, y))
+                .map(|e| e.structure)
+                .collect()
+        };
+        assert_eq!(at(12, 2), vec![StructureType::Road, StructureType::Rampart]);
+        assert_eq!(at(15, 2), vec![StructureType::Wall]);
+    }
+
+    #[test]
+    fn open_room_is_sealed_on_every_side() {
+        let grid = RoomGrid::new(|_, _| Terrain::Plain);
+        let barrier = barrier_tiles(&grid);
+        assert!(sealed(&grid, &barrier));
+        assert!(barrier
+            .iter()
+            .all(|&(x, y)| edge_distance(x, y) == BARRIER_DISTANCE));
+
+        let mut plan = RoomPlan::new((25, 25));
+        plan_exit_barrier(&grid, &mut plan);
+        let ramparts = plan
+            .entries
+            .iter()
+            .filter(|e| e.structure == StructureType::Rampart)
+            .count();
+        assert_eq!(ramparts, 4);
+    }
+}
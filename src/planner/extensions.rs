@@ -0,0 +1,165 @@
+//! Extension layout.
+//!
+//! Extensions are laid out on a checkerboard around the anchor, so every extension touches
+//! walkable tiles on all four sides, and the row and column through the anchor are kept clear as
+//! a plus-shaped pair of lanes leading out of the base.
+
+use screeps::StructureType;
+
+use super::{RoomGrid, RoomPlan};
+
+/// How far from the anchor the layout may spread.
+const MAX_RADIUS: i32 = 15;
+
+pub fn plan_extensions(grid: &RoomGrid, plan: &mut RoomPlan) {
+    let count = super::controller_structures(StructureType::Extension)[8];
+    let positions = extension_positions(grid, plan, count);
+    for (index, (x, y)) in positions.into_iter().enumerate() {
+        plan.add(
+            x,
+            y,
+            StructureType::Extension,
+            super::min_rcl(StructureType::Extension, index),
+        );
+    }
+}
+
+/// Picks up to `count` extension tiles, nearest to the anchor first.
+///
+/// The result only depends on the grid and the plan, so replanning the same room gives the
+/// same positions.
+fn extension_positions(grid: &RoomGrid, plan: &RoomPlan, count: usize) -> Vec<(u8, u8)> {
+    let (ax, ay) = (plan.anchor.0 as i32, plan.anchor.1 as i32);
+    let mut positions = Vec::with_capacity(count);
+
+    for radius in 2..=MAX_RADIUS {
+        let mut ring = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx.abs().max(dy.abs()) == radius {
+                    ring.push((dx, dy));
+                }
+            }
+        }
+        ring.sort_by_key(|&(dx, dy)| (dx.abs() + dy.abs(), ay + dy, ax + dx));
+
+        for (dx, dy) in ring {
+            if positions.len() == count {
+                return positions;
+            }
+            let (x, y) = (ax + dx, ay + dy);
+            if super::on_checkerboard(plan, x, y) && super::is_buildable(grid, plan, x, y) {
+                let tile = (x as u8, y as u8);
+                if !positions.contains(&tile) {
+                    positions.push(tile);
+                }
+            }
+        }
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Terrain;
+
+    use super::*;
+
+    fn open_grid() -> RoomGrid {
+        RoomGrid::new(|x, y| {
+            if x == 0 || x == 49 || y == 0 || y == 49 {
+                Terrain::Wall
+            } else {
+                Terrain::Plain
+            }
+        })
+    }
+
+    fn extensions(plan: &RoomPlan) -> Vec<&crate::planner::PlanEntry> {
+        plan.entries
+            .iter()
+            .filter(|e| e.structure == StructureType::Extension)
+            .collect()
+    }
+
+    #[test]
+    fn open_room_gets_all_extensions_on_the_checkerboard() {
+        let grid = open_grid();
+        let mut plan = RoomPlan::new((25, 25));
+        plan_extensions(&grid, &mut plan);
+
+        let planned = extensions(&plan);
+        assert_eq!(planned.len(), 60);
+        for e in planned {
+            let (dx, dy) = (e.x as i32 - 25, e.y as i32 - 25);
+            assert!(dx != 0 && dy != 0, "{:?} is on a lane", e);
+            assert_eq!((dx + dy) % 2, 0, "{:?} is off the checkerboard", e);
+        }
+    }
+
+    #[test]
+    fn extensions_unlock_with_the_controller_level() {
+        let grid = open_grid();
+        let mut plan = RoomPlan::new((25, 25));
+        plan_extensions(&grid, &mut plan);
+
+        let planned = extensions(&plan);
+        for (rcl, count) in &[(1, 0), (2, 5), (3, 10), (4, 20), (7, 50), (8, 60)] {
+            let buildable = planned.iter().filter(|e| e.min_rcl <= *rcl).count();
+            assert_eq!(buildable, *count, "at rcl {}", rcl);
+        }
+    }
+
+    #[test]
+    fn same_room_gets_the_same_layout() {
+        let grid = open_grid();
+        let mut first = RoomPlan::new((20, 30));
+        let mut second = RoomPlan::new((20, 30));
+        plan_extensions(&grid, &mut first);
+        plan_extensions(&grid, &mut second);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn walls_and_existing_structures_are_avoided() {
+        // a wall across the room right next to the anchor, and a blocked tile below it
+        let mut grid = RoomGrid::new(|x, y| {
+            if x == 0 || x == 49 || y == 0 || y == 49 || y == 22 {
+                Terrain::Wall
+            } else {
+                Terrain::Plain
+            }
+        });
+        grid.block(26, 26);
+        let mut plan = RoomPlan::new((25, 25));
+        plan_extensions(&grid, &mut plan);
+
+        let planned = extensions(&plan);
+        assert_eq!(planned.len(), 60);
+        assert!(planned.iter().all(|e| e.y != 22));
+        assert!(planned.iter().all(|e| (e.x, e.y) != (26, 26)));
+    }
+
+    #[test]
+    fn cramped_room_gets_what_fits() {
+        // a 9 by 9 pocket of plains, walled in
+        let grid = RoomGrid::new(|x, y| {
+            if (21..=29).contains(&x) && (21..=29).contains(&y) {
+                Terrain::Plain
+            } else {
+                Terrain::Wall
+            }
+        });
+        let mut plan = RoomPlan::new((25, 25));
+        plan_extensions(&grid, &mut plan);
+
+        let planned = extensions(&plan);
+        assert!(!planned.is_empty() && planned.len() < 60);
+        for e in &planned {
+            assert!((21..=29).contains(&e.x) && (21..=29).contains(&e.y));
+        }
+        // the lowest levels still get the nearest tiles first
+        assert!(planned.iter().take(5).all(|e| e.min_rcl == 2));
+    }
+}
@@ -0,0 +1,62 @@
+//! Storage and hub link layout for rooms planned around the spawn.
+//!
+//! The storage goes on one of the diagonals next to the anchor, where it touches both lanes out
+//! of the base, and the hub link goes next to the storage so one hauler can serve both.
+
+use screeps::StructureType;
+
+use super::{RoomGrid, RoomPlan};
+
+pub fn plan_hub(grid: &RoomGrid, plan: &mut RoomPlan) {
+    let (ax, ay) = (plan.anchor.0 as i32, plan.anchor.1 as i32);
+
+    // the diagonal with the most open tiles around it, so haulers can reach it from every side
+    let storage = [(1, 1), (-1, 1), (1, -1), (-1, -1)]
+        .iter()
+        .map(|&(dx, dy)| (ax + dx, ay + dy))
+        .filter(|&(x, y)| super::is_buildable(grid, plan, x, y))
+        .max_by_key(|&(x, y)| (open_neighbours(grid, plan, x, y), -y, -x));
+    let (sx, sy) = match storage {
+        Some(tile) => tile,
+        None => return,
+    };
+    plan.add(
+        sx as u8,
+        sy as u8,
+        StructureType::Storage,
+        super::min_rcl(StructureType::Storage, 0),
+    );
+
+    let link = neighbours(sx, sy)
+        .filter(|&(x, y)| {
+            super::on_checkerboard(plan, x, y) && super::is_buildable(grid, plan, x, y)
+        })
+        .min_by_key(|&(x, y)| ((x - ax).abs().max((y - ay).abs()), y, x));
+    if let Some((x, y)) = link {
+        plan.add(
+            x as u8,
+            y as u8,
+            StructureType::Link,
+            super::min_rcl(StructureType::Link, 0),
+        );
+    }
+}
+
+fn neighbours(x: i32, y: i32) -> impl Iterator<Item = (i32, i32)> {
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&tile| tile != (x, y))
+}
+
+fn open_neighbours(grid: &RoomGrid, plan: &RoomPlan, x: i32, y: i32) -> usize {
+    neighbours(x, y)
+        .filter(|&(nx, ny)| {
+            nx >= 0
+                && nx <= 49
+                && ny >= 0
+                && ny <= 49
+                && grid.is_free(nx as u8, ny as u8)
+                && !plan.is_planned(nx as u8, ny as u8)
+        })
+        .count()
+}
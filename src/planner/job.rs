@@ -0,0 +1,234 @@
+//! Planning a room over several ticks.
+//!
+//! A plan is built by a series of layout passes, several of which search paths or scan the whole
+//! room. Together they make for a heavy tick, so [`PlanJob`] runs them as a task, one pass per
+//! step, and saves the plan once the last one is done.
+
+use log::*;
+use screeps::{find, prelude::*, Position, Room, RoomName, StructureType};
+
+use super::{bunker, exits, extensions, hub, ramparts, roads, towers, RoomGrid, RoomPlan};
+use crate::tasks::Task;
+
+/// There are only a handful of layout passes, so running more than this is a bug.
+const MAX_PASSES: usize = 16;
+
+/// What the layout passes work from, read from the room when planning starts.
+pub struct Layout {
+    room_name: RoomName,
+    grid: RoomGrid,
+    road_goals: Vec<(Position, u32)>,
+    /// The first spawn, if the room has one.
+    spawn: Option<(u8, u8)>,
+}
+
+impl Layout {
+    pub fn survey(room: &Room, grid: RoomGrid) -> Self {
+        let spawn = room
+            .find(find::MY_SPAWNS)
+            .into_iter()
+            .min_by_key(|s| s.name())
+            .map(|s| (s.pos().x() as u8, s.pos().y() as u8));
+        Layout {
+            room_name: room.name(),
+            grid,
+            road_goals: super::road_goals(room),
+            spawn,
+        }
+    }
+
+    /// Runs the layout pass `index` of the bunker plan, returning false once there are none left.
+    fn bunker_pass(&self, plan: &mut RoomPlan, index: usize) -> bool {
+        match index {
+            0 => bunker::apply_stamp(plan, self.spawn),
+            1 => roads::plan_roads(&self.grid, plan, self.room_name, &self.road_goals),
+            2 => ramparts::plan_critical_ramparts(plan),
+            3 => ramparts::plan_perimeter(&self.grid, plan, bunker::RADIUS + 1),
+            4 => exits::plan_exit_barrier(&self.grid, plan),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs the layout pass `index` of the plan for rooms too cramped for the bunker, returning
+    /// false once there are none left.
+    fn fallback_pass(&self, plan: &mut RoomPlan, index: usize) -> bool {
+        match index {
+            0 => plan.add(plan.anchor.0, plan.anchor.1, StructureType::Spawn, 1),
+            1 => hub::plan_hub(&self.grid, plan),
+            2 => towers::plan_towers(&self.grid, plan),
+            3 => extensions::plan_extensions(&self.grid, plan),
+            4 => roads::plan_roads(&self.grid, plan, self.room_name, &self.road_goals),
+            5 => ramparts::plan_critical_ramparts(plan),
+            6 => exits::plan_exit_barrier(&self.grid, plan),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Plans the bunker centered on `anchor` in one go.
+    pub fn bunker_plan(&self, anchor: (u8, u8)) -> RoomPlan {
+        let mut plan = RoomPlan::new(anchor);
+        let mut index = 0;
+        while self.bunker_pass(&mut plan, index) {
+            index += 1;
+            if index == MAX_PASSES {
+                error!("bunker plan ran past {} passes, aborting", MAX_PASSES);
+                break;
+            }
+        }
+        plan
+    }
+}
+
+enum Stage {
+    /// Reading the room's terrain and structures.
+    Survey,
+    /// Looking for a spot the bunker fits.
+    Anchor(Layout),
+    /// Running the layout passes, `next` being the next one to run.
+    Layout {
+        layout: Layout,
+        plan: RoomPlan,
+        bunker: bool,
+        next: usize,
+    },
+}
+
+/// What's done with a finished plan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Saved unless the room got a plan in the meantime.
+    Missing,
+    /// Saved over the room's plan, if it has one.
+    Replace,
+    /// Only kept on the heap to be drawn, until it's accepted or expires.
+    Preview,
+}
+
+/// Plans an owned room.
+///
+/// Rooms with enough open space get the bunker stamp, which is previewed until it's accepted.
+/// Cramped rooms fall back to laying structures out around the spawn.
+pub struct PlanJob {
+    room_name: RoomName,
+    mode: Mode,
+    stage: Option<Stage>,
+}
+
+impl PlanJob {
+    pub fn new(room_name: RoomName, mode: Mode) -> Self {
+        PlanJob {
+            room_name,
+            mode,
+            stage: Some(Stage::Survey),
+        }
+    }
+}
+
+impl Task for PlanJob {
+    fn step(&mut self) -> bool {
+        let room = match screeps::game::rooms::get(self.room_name) {
+            Some(room) => room,
+            None => {
+                warn!("lost sight of room {}, not planning it", self.room_name);
+                return false;
+            }
+        };
+
+        self.stage = match self.stage.take() {
+            Some(Stage::Survey) => {
+                let layout = Layout::survey(&room, super::room_grid(&room));
+                if layout.spawn.is_none() {
+                    return false;
+                }
+                Some(Stage::Anchor(layout))
+            }
+            Some(Stage::Anchor(layout)) => {
+                let distances = bunker::distance_transform(&layout.grid);
+                let goals: Vec<(u8, u8)> = layout
+                    .road_goals
+                    .iter()
+                    .map(|(pos, _)| (pos.x() as u8, pos.y() as u8))
+                    .collect();
+                let spawn = layout.spawn.unwrap_or_default();
+                let (plan, bunker) = match bunker::find_anchor(&distances, &goals, layout.spawn) {
+                    Some(anchor) => (RoomPlan::new(anchor), true),
+                    None => (RoomPlan::new(spawn), false),
+                };
+                Some(Stage::Layout {
+                    layout,
+                    plan,
+                    bunker,
+                    next: 0,
+                })
+            }
+            Some(Stage::Layout {
+                layout,
+                mut plan,
+                bunker,
+                next,
+            }) => {
+                let ran = if bunker {
+                    layout.bunker_pass(&mut plan, next)
+                } else {
+                    layout.fallback_pass(&mut plan, next)
+                };
+                if !ran {
+                    finish(self.room_name, plan, bunker, self.mode);
+                    return false;
+                }
+                if next + 1 == MAX_PASSES {
+                    error!(
+                        "plan for room {} ran past {} passes, aborting",
+                        self.room_name, MAX_PASSES
+                    );
+                    return false;
+                }
+                Some(Stage::Layout {
+                    layout,
+                    plan,
+                    bunker,
+                    next: next + 1,
+                })
+            }
+            None => return false,
+        };
+        true
+    }
+}
+
+/// Saves a finished plan, leaving bunker plans in preview.
+fn finish(room_name: RoomName, plan: RoomPlan, bunker: bool, mode: Mode) {
+    match mode {
+        // the plan may have been replaced from the console while this one was in progress
+        Mode::Missing if super::load(room_name).is_some() => return,
+        Mode::Missing | Mode::Replace => {}
+        Mode::Preview => {
+            info!(
+                "planned room {} with {} structures, previewing it for {} ticks until \
+                 accept_plan(\"{}\")",
+                room_name,
+                plan.entries.len(),
+                super::PREVIEW_TICKS,
+                room_name
+            );
+            super::set_preview(room_name, plan);
+            return;
+        }
+    }
+    super::save(room_name, &plan);
+    if bunker {
+        super::set_pending(room_name, true);
+        info!(
+            "planned a bunker at {},{} in room {}, previewing it until accept_plan(\"{}\")",
+            plan.anchor.0, plan.anchor.1, room_name, room_name
+        );
+    } else {
+        info!(
+            "planned room {} with {} structures",
+            room_name,
+            plan.entries.len()
+        );
+    }
+}
@@ -0,0 +1,489 @@
+//! Room layout planning.
+//!
+//! A [`RoomPlan`] lists where each structure in a room should go, along with the controller level
+//! at which it becomes buildable. Plans are generated once per owned room, spread over several
+//! ticks as a background task, and stored in `Memory.rooms.<name>.plan`, so they stay stable
+//! across global resets; the construction module then places sites from the plan as the room
+//! levels up.
+//!
+//! Bunker plans start out as previews, drawn in the room but not built until they're approved
+//! with `accept_plan(room)` or moved with `reanchor_plan(room, x, y)` from the console.
+//!
+//! `plan_room(room, preview_only)` plans a room again on demand. With `preview_only` the new
+//! plan is only kept on the heap and drawn for [`PREVIEW_TICKS`] ticks, replacing the room's plan
+//! only if it's accepted in that time.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{
+    find, memory::MemoryReference, prelude::*, Position, Room, RoomName, StructureType, Terrain,
+};
+
+use crate::{emergency, heap::CacheSize, journal, tasks};
+
+mod bunker;
+mod exits;
+mod extensions;
+mod hub;
+mod job;
+mod ramparts;
+mod roads;
+mod towers;
+
+const PLAN_KEY: &str = "plan";
+const PENDING_KEY: &str = "plan_pending";
+
+/// Planning isn't urgent, but goes ahead of other background work since nothing is built until
+/// it's done.
+const PLAN_PRIORITY: u8 = 10;
+
+/// Roads are cheap to place but cost upkeep, so they wait until the room has some income.
+const ROAD_MIN_RCL: u32 = 3;
+
+/// How far a blocked structure may be moved from its planned tile.
+const MAX_RELOCATE_RANGE: i32 = 5;
+
+/// How long a plan made with `preview_only` is drawn, waiting to be accepted.
+pub const PREVIEW_TICKS: u32 = 100;
+
+thread_local! {
+    /// Plans only made to be looked at, with the tick they expire at.
+    static PREVIEWS: RefCell<HashMap<RoomName, (RoomPlan, u32)>> = RefCell::new(HashMap::new());
+}
+
+/// One planned structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub x: u8,
+    pub y: u8,
+    pub structure: StructureType,
+    pub min_rcl: u32,
+}
+
+/// The full structure layout of one room.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomPlan {
+    /// The tile everything else is laid out around, usually the first spawn.
+    pub anchor: (u8, u8),
+    pub entries: Vec<PlanEntry>,
+}
+
+impl RoomPlan {
+    pub fn new(anchor: (u8, u8)) -> Self {
+        RoomPlan {
+            anchor,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, x: u8, y: u8, structure: StructureType, min_rcl: u32) {
+        self.entries.push(PlanEntry {
+            x,
+            y,
+            structure,
+            min_rcl,
+        });
+    }
+
+    pub fn is_planned(&self, x: u8, y: u8) -> bool {
+        self.entries.iter().any(|e| e.x == x && e.y == y)
+    }
+
+    /// Entries which are buildable at the given controller level.
+    pub fn entries_at(&self, rcl: u32) -> impl Iterator<Item = &PlanEntry> {
+        self.entries.iter().filter(move |e| e.min_rcl <= rcl)
+    }
+
+    /// Serializes the plan as `ax,ay|x,y,code,rcl;x,y,code,rcl;...`.
+    fn encode(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "{},{},{},{}",
+                    e.x,
+                    e.y,
+                    structure_code(e.structure),
+                    e.min_rcl
+                )
+            })
+            .collect();
+        format!("{},{}|{}", self.anchor.0, self.anchor.1, entries.join(";"))
+    }
+
+    fn decode(s: &str) -> Option<RoomPlan> {
+        let mut halves = s.splitn(2, '|');
+        let mut anchor = halves.next()?.split(',');
+        let mut plan = RoomPlan::new((anchor.next()?.parse().ok()?, anchor.next()?.parse().ok()?));
+        for entry in halves.next()?.split(';').filter(|e| !e.is_empty()) {
+            let mut fields = entry.split(',');
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            let structure = structure_from_code(fields.next()?.chars().next()?)?;
+            let min_rcl = fields.next()?.parse().ok()?;
+            plan.add(x, y, structure, min_rcl);
+        }
+        Some(plan)
+    }
+}
+
+const STRUCTURE_CODES: &[(StructureType, char)] = &[
+    (StructureType::Spawn, 's'),
+    (StructureType::Extension, 'e'),
+    (StructureType::Road, 'r'),
+    (StructureType::Wall, 'w'),
+    (StructureType::Rampart, 'R'),
+    (StructureType::Link, 'l'),
+    (StructureType::Storage, 'S'),
+    (StructureType::Tower, 't'),
+    (StructureType::Observer, 'o'),
+    (StructureType::PowerSpawn, 'p'),
+    (StructureType::Extractor, 'x'),
+    (StructureType::Lab, 'L'),
+    (StructureType::Terminal, 'T'),
+    (StructureType::Container, 'c'),
+    (StructureType::Nuker, 'n'),
+    (StructureType::Factory, 'f'),
+];
+
+pub fn structure_code(structure: StructureType) -> char {
+    STRUCTURE_CODES
+        .iter()
+        .find(|(ty, _)| *ty == structure)
+        .map(|(_, code)| *code)
+        .expect("expected only buildable structure types to be planned")
+}
+
+fn structure_from_code(code: char) -> Option<StructureType> {
+    STRUCTURE_CODES
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(ty, _)| *ty)
+}
+
+/// How many of a structure the controller allows, indexed by controller level.
+fn controller_structures(structure: StructureType) -> [usize; 9] {
+    match structure {
+        StructureType::Spawn => [0, 1, 1, 1, 1, 1, 1, 2, 3],
+        StructureType::Extension => [0, 0, 5, 10, 20, 30, 40, 50, 60],
+        StructureType::Tower => [0, 0, 0, 1, 1, 2, 2, 3, 6],
+        StructureType::Storage => [0, 0, 0, 0, 1, 1, 1, 1, 1],
+        StructureType::Link => [0, 0, 0, 0, 0, 2, 3, 4, 6],
+        StructureType::Extractor | StructureType::Terminal => [0, 0, 0, 0, 0, 0, 1, 1, 1],
+        StructureType::Lab => [0, 0, 0, 0, 0, 0, 3, 6, 10],
+        StructureType::Factory => [0, 0, 0, 0, 0, 0, 0, 1, 1],
+        StructureType::Observer | StructureType::PowerSpawn | StructureType::Nuker => {
+            [0, 0, 0, 0, 0, 0, 0, 0, 1]
+        }
+        StructureType::Container => [5; 9],
+        StructureType::Wall | StructureType::Rampart => {
+            [0, 0, 2500, 2500, 2500, 2500, 2500, 2500, 2500]
+        }
+        _ => [2500; 9],
+    }
+}
+
+/// The lowest controller level at which the `index`th structure of a type can be built.
+fn min_rcl(structure: StructureType, index: usize) -> u32 {
+    if structure == StructureType::Road {
+        return ROAD_MIN_RCL;
+    }
+    controller_structures(structure)
+        .iter()
+        .position(|&count| count > index)
+        .unwrap_or(8) as u32
+}
+
+/// Terrain and obstructions of one room, used as the input to the layout generators.
+pub struct RoomGrid {
+    terrain: Vec<Terrain>,
+    blocked: Vec<bool>,
+}
+
+impl RoomGrid {
+    pub fn new(terrain: impl Fn(u8, u8) -> Terrain) -> Self {
+        let mut tiles = Vec::with_capacity(2500);
+        for y in 0..50 {
+            for x in 0..50 {
+                tiles.push(terrain(x, y));
+            }
+        }
+        RoomGrid {
+            terrain: tiles,
+            blocked: vec![false; 2500],
+        }
+    }
+
+    pub fn terrain(&self, x: u8, y: u8) -> Terrain {
+        self.terrain[y as usize * 50 + x as usize]
+    }
+
+    /// Marks a tile as unusable, e.g. because a structure already stands on it.
+    pub fn block(&mut self, x: u8, y: u8) {
+        self.blocked[y as usize * 50 + x as usize] = true;
+    }
+
+    /// Whether a structure can be placed on the tile.
+    pub fn is_free(&self, x: u8, y: u8) -> bool {
+        self.terrain(x, y) != Terrain::Wall && !self.blocked[y as usize * 50 + x as usize]
+    }
+}
+
+/// Whether a tile is part of the checkerboard around the anchor which structures are laid out on.
+///
+/// The row and column through the anchor are kept clear as lanes out of the base, and every other
+/// tile is left free so creeps can walk between the structures.
+fn on_checkerboard(plan: &RoomPlan, x: i32, y: i32) -> bool {
+    let (dx, dy) = (x - plan.anchor.0 as i32, y - plan.anchor.1 as i32);
+    dx != 0 && dy != 0 && (dx + dy) % 2 == 0
+}
+
+/// Whether a structure can be planned on a tile.
+///
+/// The tile has to be free, away from the exits, and next to at least one tile creeps can still
+/// walk over to reach it.
+fn is_buildable(grid: &RoomGrid, plan: &RoomPlan, x: i32, y: i32) -> bool {
+    if x < 2 || x > 47 || y < 2 || y > 47 {
+        return false;
+    }
+    let (x, y) = (x as u8, y as u8);
+    grid.is_free(x, y)
+        && !plan.is_planned(x, y)
+        && [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+            .iter()
+            .any(|&(nx, ny)| grid.is_free(nx, ny) && !plan.is_planned(nx, ny))
+}
+
+pub fn room_memory(room_name: RoomName) -> Option<MemoryReference> {
+    screeps::memory::root()
+        .dict_or_create("rooms")
+        .ok()?
+        .dict_or_create(&room_name.to_string())
+        .ok()
+}
+
+pub fn load(room_name: RoomName) -> Option<RoomPlan> {
+    let encoded = room_memory(room_name)?.string(PLAN_KEY).ok()??;
+    let plan = RoomPlan::decode(&encoded);
+    if plan.is_none() {
+        warn!("ignoring unreadable plan for room {}", room_name);
+    }
+    plan
+}
+
+pub fn save(room_name: RoomName, plan: &RoomPlan) {
+    let encoded = plan.encode();
+    journal::record("planner", &encoded, || {
+        format!("saved a plan for room {}", room_name)
+    });
+    match room_memory(room_name) {
+        Some(memory) => memory.set(PLAN_KEY, encoded),
+        None => warn!(
+            "couldn't save plan for room {}: bad Memory.rooms",
+            room_name
+        ),
+    }
+}
+
+/// Queues planning jobs for owned rooms which don't have a plan yet.
+pub fn run() {
+    for room in screeps::game::rooms::values() {
+        match room.controller() {
+            Some(controller) if controller.my() => {}
+            _ => continue,
+        }
+        if load(room.name()).is_some() || emergency::is_abandoned(room.name()) {
+            continue;
+        }
+        let name = format!("plan {}", room.name());
+        if !tasks::is_queued(&name) {
+            tasks::spawn(
+                name,
+                PLAN_PRIORITY,
+                job::PlanJob::new(room.name(), job::Mode::Missing),
+            );
+        }
+    }
+}
+
+/// Queues planning an owned room again, only previewing the new plan if `preview_only` is set.
+pub fn plan_room(room: &Room, preview_only: bool) -> Result<(), String> {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return Err(format!("room {} isn't ours", room.name())),
+    }
+    let name = format!("plan {}", room.name());
+    if tasks::is_queued(&name) {
+        return Err(format!("room {} is already being planned", room.name()));
+    }
+    let mode = if preview_only {
+        job::Mode::Preview
+    } else {
+        job::Mode::Replace
+    };
+    tasks::spawn(name, PLAN_PRIORITY, job::PlanJob::new(room.name(), mode));
+    Ok(())
+}
+
+fn set_preview(room_name: RoomName, plan: RoomPlan) {
+    let until = screeps::game::time() + PREVIEW_TICKS;
+    PREVIEWS.with(|previews| previews.borrow_mut().insert(room_name, (plan, until)));
+}
+
+/// Whether a room's plan is only being previewed, and shouldn't be built yet.
+pub fn is_pending(room_name: RoomName) -> bool {
+    room_memory(room_name)
+        .map(|memory| memory.bool(PENDING_KEY))
+        .unwrap_or(false)
+}
+
+fn set_pending(room_name: RoomName, pending: bool) {
+    if let Some(memory) = room_memory(room_name) {
+        if pending {
+            memory.set(PENDING_KEY, true);
+        } else {
+            memory.del(PENDING_KEY);
+        }
+    }
+}
+
+/// Approves a previewed plan so the construction module starts building it.
+pub fn accept(room_name: RoomName) -> Result<(), String> {
+    let preview = PREVIEWS.with(|previews| previews.borrow_mut().remove(&room_name));
+    if let Some((plan, _)) = preview {
+        save(room_name, &plan);
+        set_pending(room_name, false);
+        return Ok(());
+    }
+    if load(room_name).is_none() {
+        return Err(format!("room {} has no plan", room_name));
+    }
+    if !is_pending(room_name) {
+        return Err(format!(
+            "the plan for room {} is already accepted",
+            room_name
+        ));
+    }
+    journal::record("planner", room_name.to_string(), || {
+        format!("accepted the plan for room {}", room_name)
+    });
+    set_pending(room_name, false);
+    Ok(())
+}
+
+/// Replans a room with the bunker centered on `anchor`, leaving the new plan in preview.
+pub fn reanchor(room: &Room, anchor: (u8, u8)) -> Result<(), String> {
+    let grid = room_grid(room);
+    let distances = bunker::distance_transform(&grid);
+    if !bunker::fits(&distances, anchor) {
+        return Err(format!(
+            "the bunker doesn't fit at {},{} in room {}",
+            anchor.0,
+            anchor.1,
+            room.name()
+        ));
+    }
+    let plan = job::Layout::survey(room, grid).bunker_plan(anchor);
+    save(room.name(), &plan);
+    set_pending(room.name(), true);
+    Ok(())
+}
+
+/// Draws every plan which is waiting to be accepted.
+pub fn draw_previews() {
+    let time = screeps::game::time();
+    PREVIEWS.with(|previews| {
+        previews.borrow_mut().retain(|room_name, (plan, until)| {
+            if *until <= time {
+                info!("dropped the unaccepted preview of room {}", room_name);
+                return false;
+            }
+            crate::visuals::draw_plan(*room_name, plan);
+            true
+        })
+    });
+    for room in screeps::game::rooms::values() {
+        if is_pending(room.name()) {
+            if let Some(plan) = load(room.name()) {
+                crate::visuals::draw_plan(room.name(), &plan);
+            }
+        }
+    }
+}
+
+/// Moves a planned structure whose tile turned out to be blocked to the nearest tile it can still
+/// be built on, saving the updated plan.
+pub fn relocate(room: &Room, entry: &PlanEntry) -> Option<(u8, u8)> {
+    let mut plan = load(room.name())?;
+    let index = plan.entries.iter().position(|e| e == entry)?;
+    plan.entries.remove(index);
+
+    let grid = room_grid(room);
+    let (ex, ey) = (entry.x as i32, entry.y as i32);
+    let tile = (1..=MAX_RELOCATE_RANGE).find_map(|radius| {
+        let mut ring = Vec::new();
+        for y in ey - radius..=ey + radius {
+            for x in ex - radius..=ex + radius {
+                if (x - ex).abs().max((y - ey).abs()) == radius {
+                    ring.push((x, y));
+                }
+            }
+        }
+        ring.into_iter()
+            .find(|&(x, y)| on_checkerboard(&plan, x, y) && is_buildable(&grid, &plan, x, y))
+            .map(|(x, y)| (x as u8, y as u8))
+    })?;
+
+    plan.add(tile.0, tile.1, entry.structure, entry.min_rcl);
+    save(room.name(), &plan);
+    Some(tile)
+}
+
+/// Where roads lead from the base: every source, then the controller.
+fn road_goals(room: &Room) -> Vec<(Position, u32)> {
+    let mut sources: Vec<Position> = room.find(find::SOURCES).iter().map(|s| s.pos()).collect();
+    sources.sort_by_key(|pos| (pos.y(), pos.x()));
+    let mut goals: Vec<(Position, u32)> = sources.into_iter().map(|pos| (pos, 1)).collect();
+    if let Some(controller) = room.controller() {
+        goals.push((controller.pos(), 2));
+    }
+    goals
+}
+
+fn room_grid(room: &Room) -> RoomGrid {
+    let terrain = room.get_terrain();
+    let mut grid = RoomGrid::new(|x, y| terrain.get(x as u32, y as u32));
+
+    // Extensions and ramparts may stay where the plan puts them, anything else is in the way.
+    let keep = |ty: StructureType| ty == StructureType::Extension || ty == StructureType::Rampart;
+    for structure in room.find(find::STRUCTURES) {
+        if !keep(structure.structure_type()) {
+            let pos = structure.pos();
+            grid.block(pos.x() as u8, pos.y() as u8);
+        }
+    }
+    for site in room.find(find::CONSTRUCTION_SITES) {
+        if !keep(site.structure_type()) {
+            let pos = site.pos();
+            grid.block(pos.x() as u8, pos.y() as u8);
+        }
+    }
+
+    grid
+}
+
+/// Sizes of the previewed plans, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![PREVIEWS.with(|p| {
+        CacheSize::of_map("planner.previews", &p.borrow(), |_, (plan, _)| {
+            plan.entries.capacity() * std::mem::size_of::<PlanEntry>()
+        })
+    })]
+}
+
+/// Drops the previewed plans, which `plan_room()` makes again.
+pub fn purge() {
+    PREVIEWS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+}
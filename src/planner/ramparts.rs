@@ -0,0 +1,60 @@
+//! Rampart layout.
+//!
+//! The structures a room can't afford to lose get a rampart on their own tile, so they survive a
+//! raid even before the base has a full perimeter. Bunker plans are also ringed by a perimeter of
+//! ramparts once the room can keep it repaired.
+
+use screeps::{StructureType, Terrain};
+
+use super::{RoomGrid, RoomPlan};
+
+/// Structures which are covered by a rampart on their own tile.
+const CRITICAL_STRUCTURES: &[StructureType] = &[
+    StructureType::Spawn,
+    StructureType::Storage,
+    StructureType::Tower,
+    StructureType::Terminal,
+];
+
+/// Ramparts cost upkeep from the moment they're built, so they wait until the room has towers.
+const CRITICAL_MIN_RCL: u32 = 3;
+
+/// The perimeter is a lot of hits to keep up, so it waits until the room has storage.
+const PERIMETER_MIN_RCL: u32 = 4;
+
+/// Plans a rampart on top of every critical structure of the plan.
+pub fn plan_critical_ramparts(plan: &mut RoomPlan) {
+    let covered: Vec<(u8, u8, u32)> = plan
+        .entries
+        .iter()
+        .filter(|e| CRITICAL_STRUCTURES.contains(&e.structure))
+        .map(|e| (e.x, e.y, e.min_rcl.max(CRITICAL_MIN_RCL)))
+        .collect();
+    for (x, y, min_rcl) in covered {
+        if !has_rampart(plan, x, y) {
+            plan.add(x, y, StructureType::Rampart, min_rcl);
+        }
+    }
+}
+
+/// Plans a ring of ramparts `radius` tiles out from the anchor, skipping walls.
+pub fn plan_perimeter(grid: &RoomGrid, plan: &mut RoomPlan, radius: i32) {
+    let (ax, ay) = (plan.anchor.0 as i32, plan.anchor.1 as i32);
+    for y in ay - radius..=ay + radius {
+        for x in ax - radius..=ax + radius {
+            if (x - ax).abs().max((y - ay).abs()) != radius || x < 1 || x > 48 || y < 1 || y > 48 {
+                continue;
+            }
+            let (x, y) = (x as u8, y as u8);
+            if grid.terrain(x, y) != Terrain::Wall && !has_rampart(plan, x, y) {
+                plan.add(x, y, StructureType::Rampart, PERIMETER_MIN_RCL);
+            }
+        }
+    }
+}
+
+fn has_rampart(plan: &RoomPlan, x: u8, y: u8) -> bool {
+    plan.entries
+        .iter()
+        .any(|e| e.x == x && e.y == y && e.structure == StructureType::Rampart)
+}
@@ -0,0 +1,93 @@
+//! Road layout.
+//!
+//! Roads run from the anchor to a container next to each source and to a container near the
+//! controller. Swamps and plains cost the same while planning, since a road makes them equally
+//! fast, and tiles already holding a planned road are cheaper so routes share them.
+
+use screeps::{
+    pathfinder::{self, LocalCostMatrix, MultiRoomCostResult, SearchOptions},
+    Position, RoomName, StructureType,
+};
+
+use super::{RoomGrid, RoomPlan};
+
+const CONTAINER_MIN_RCL: u32 = 2;
+
+/// Plans a road and container for each `(goal, range)` pair, in order.
+pub fn plan_roads(
+    grid: &RoomGrid,
+    plan: &mut RoomPlan,
+    room_name: RoomName,
+    goals: &[(Position, u32)],
+) {
+    let anchor = Position::new(plan.anchor.0 as u32, plan.anchor.1 as u32, room_name);
+    let mut roads: Vec<(u8, u8)> = Vec::new();
+
+    for &(goal, range) in goals {
+        let costs = path_costs(grid, plan, &roads);
+        let options = SearchOptions::new()
+            .plain_cost(2)
+            .swamp_cost(2)
+            .max_rooms(1)
+            .room_callback(move |name| {
+                if name != room_name {
+                    return MultiRoomCostResult::Impassable;
+                }
+                let mut matrix = LocalCostMatrix::new();
+                for &(x, y, cost) in &costs {
+                    matrix.set(x, y, cost);
+                }
+                MultiRoomCostResult::CostMatrix(matrix.upload())
+            });
+        let result = pathfinder::search(&anchor, &goal, range, options);
+        if result.incomplete {
+            continue;
+        }
+
+        let mut path = result.load_local_path();
+        let container = match path.pop() {
+            Some(pos) => (pos.x() as u8, pos.y() as u8),
+            None => continue,
+        };
+        if !plan.is_planned(container.0, container.1) {
+            plan.add(
+                container.0,
+                container.1,
+                StructureType::Container,
+                CONTAINER_MIN_RCL,
+            );
+        }
+        for pos in path {
+            let tile = (pos.x() as u8, pos.y() as u8);
+            if !roads.contains(&tile) {
+                roads.push(tile);
+            }
+        }
+    }
+
+    for (x, y) in roads {
+        if !plan.is_planned(x, y) {
+            plan.add(x, y, StructureType::Road, super::ROAD_MIN_RCL);
+        }
+    }
+}
+
+fn path_costs(grid: &RoomGrid, plan: &RoomPlan, roads: &[(u8, u8)]) -> Vec<(u8, u8, u8)> {
+    let mut costs = Vec::new();
+    for y in 0..50 {
+        for x in 0..50 {
+            if !grid.is_free(x, y) {
+                costs.push((x, y, 255));
+            }
+        }
+    }
+    for entry in &plan.entries {
+        if entry.structure != StructureType::Road && entry.structure != StructureType::Rampart {
+            costs.push((entry.x, entry.y, 255));
+        }
+    }
+    for &(x, y) in roads {
+        costs.push((x, y, 1));
+    }
+    costs
+}
@@ -0,0 +1,100 @@
+//! Tower layout.
+//!
+//! Towers go close to the core of the base, each as far from the others as the area allows, so a
+//! single breach or nuke doesn't take out all of them at once. They use the same checkerboard
+//! tiles as extensions, so they never block a lane.
+
+use screeps::StructureType;
+
+use super::{RoomGrid, RoomPlan};
+
+/// How far from the core towers may be placed.
+const MAX_CORE_RANGE: i32 = 6;
+
+pub fn plan_towers(grid: &RoomGrid, plan: &mut RoomPlan) {
+    let (cx, cy) = core(plan);
+    let (min, max) = bounds(plan);
+
+    let mut candidates = Vec::new();
+    for y in (cy - MAX_CORE_RANGE).max(min.1)..=(cy + MAX_CORE_RANGE).min(max.1) {
+        for x in (cx - MAX_CORE_RANGE).max(min.0)..=(cx + MAX_CORE_RANGE).min(max.0) {
+            let range = (x - cx).abs().max((y - cy).abs());
+            if range >= 2
+                && super::on_checkerboard(plan, x, y)
+                && super::is_buildable(grid, plan, x, y)
+            {
+                candidates.push((x as u8, y as u8));
+            }
+        }
+    }
+
+    let mut towers: Vec<(u8, u8)> = Vec::new();
+    for index in 0..super::controller_structures(StructureType::Tower)[8] {
+        // the candidate furthest from the towers picked so far, nearest to the core on ties
+        let best = candidates
+            .iter()
+            .filter(|tile| !towers.contains(tile))
+            .max_by_key(|&&(x, y)| {
+                let spread = towers
+                    .iter()
+                    .map(|&(tx, ty)| range((x, y), (tx, ty)))
+                    .min()
+                    .unwrap_or(0);
+                (
+                    spread.min(MAX_CORE_RANGE),
+                    -range((x, y), (cx as u8, cy as u8)),
+                    -(y as i32),
+                    -(x as i32),
+                )
+            })
+            .copied();
+        let (x, y) = match best {
+            Some(tile) => tile,
+            None => break,
+        };
+        towers.push((x, y));
+        plan.add(
+            x,
+            y,
+            StructureType::Tower,
+            super::min_rcl(StructureType::Tower, index),
+        );
+    }
+}
+
+/// The center of the base: the storage if one is planned, the anchor otherwise.
+fn core(plan: &RoomPlan) -> (i32, i32) {
+    plan.entries
+        .iter()
+        .find(|e| e.structure == StructureType::Storage)
+        .map(|e| (e.x as i32, e.y as i32))
+        .unwrap_or((plan.anchor.0 as i32, plan.anchor.1 as i32))
+}
+
+/// The area towers may go in: inside the planned rampart perimeter if there is one, anywhere away
+/// from the exits otherwise.
+fn bounds(plan: &RoomPlan) -> ((i32, i32), (i32, i32)) {
+    let ramparts = plan
+        .entries
+        .iter()
+        .filter(|e| e.structure == StructureType::Rampart);
+    let mut min = (47, 47);
+    let mut max = (2, 2);
+    let mut any = false;
+    for e in ramparts {
+        any = true;
+        min = (min.0.min(e.x as i32 + 1), min.1.min(e.y as i32 + 1));
+        max = (max.0.max(e.x as i32 - 1), max.1.max(e.y as i32 - 1));
+    }
+    if any {
+        (min, max)
+    } else {
+        ((2, 2), (47, 47))
+    }
+}
+
+fn range(a: (u8, u8), b: (u8, u8)) -> i32 {
+    (a.0 as i32 - b.0 as i32)
+        .abs()
+        .max((a.1 as i32 - b.1 as i32).abs())
+}
@@ -0,0 +1,430 @@
+//! Harvesting the power banks which show up on the highways.
+//!
+//! With `Memory.config.power_harvesting` set, every power bank intel knows of near one of our
+//! level 8 rooms with a storage is considered. Before anything is spawned, the ticks the bank
+//! has left are checked against how long attackers take to get there and break it, picking the
+//! fewest attacker and healer pairs, up to [`MAX_PAIRS`], which make it in time. Banks which
+//! can't be broken in time are left alone.
+//!
+//! Operations are kept in `Memory.power.<room>`. Haulers are spawned once the bank is about to
+//! break in the time they need to get there, enough to carry all of its power home to storage.
+//! An operation is aborted if the math stops working out or if another player's creeps are at
+//! the bank, and ends once the bank and its power are gone. Operations which are running when
+//! the flag is turned off are finished.
+
+use log::*;
+use screeps::{
+    find, memory::MemoryReference, prelude::*, Attackable, Creep, Position, ResourceType,
+    ReturnCode, RoomName, Structure,
+};
+
+use crate::{
+    failures, intel, movement,
+    spawning::{self, Role, SpawnRequest},
+};
+
+const POWER_HARVESTING_PATH: &str = "config.power_harvesting";
+const OPERATIONS_KEY: &str = "power";
+
+const HOME_KEY: &str = "home";
+const PAIRS_KEY: &str = "pairs";
+const HAULERS_KEY: &str = "haulers_requested";
+
+const MIN_RCL: u32 = 8;
+
+/// The most attacker and healer pairs sent to a bank.
+pub const MAX_PAIRS: u32 = 3;
+
+/// How far from the home room banks are harvested.
+const MAX_DISTANCE: u32 = 5;
+
+/// The sizes attackers, healers and haulers are spawned at.
+const ATTACKER_SIZE: u32 = 20;
+const HEALER_SIZE: u32 = 25;
+const HAULER_MAX_SIZE: u32 = 25;
+
+/// The damage an attack part does per tick.
+const ATTACK_POWER: u32 = 30;
+const CARRY_CAPACITY: u32 = 50;
+
+/// Roughly how long a creep takes to spawn and cross a room.
+const SPAWN_TICKS: u32 = 150;
+const TICKS_PER_ROOM: u32 = 50;
+
+/// How many ticks to spare when working out whether a bank can be broken in time.
+const MARGIN_TICKS: u32 = 200;
+
+/// Attackers stop hitting the bank below this share of their hits, until they're healed.
+const ATTACKER_MIN_HITS: f64 = 0.5;
+
+/// How close to a bank which is still standing haulers wait.
+const HAULER_WAIT_RANGE: u32 = 4;
+
+/// How many ticks the pairs take to break a bank with the hits given.
+pub fn ticks_to_break(hits: u32, pairs: u32) -> u32 {
+    let per_tick = pairs * ATTACKER_SIZE * ATTACK_POWER;
+    (hits + per_tick - 1) / per_tick
+}
+
+/// The fewest pairs which break a bank in time, or `None` if it'd decay first even with
+/// [`MAX_PAIRS`].
+pub fn pairs_needed(hits: u32, ticks_left: u32, arrival: u32) -> Option<u32> {
+    (1..=MAX_PAIRS)
+        .find(|&pairs| arrival + ticks_to_break(hits, pairs) + MARGIN_TICKS <= ticks_left)
+}
+
+/// How long creeps from the home room take to get to a room, spawning included.
+fn arrival_ticks(home: RoomName, room_name: RoomName) -> u32 {
+    SPAWN_TICKS
+        + TICKS_PER_ROOM * screeps::game::map::get_room_linear_distance(home, room_name, false)
+}
+
+/// Starts operations on new banks and keeps the running ones going.
+pub fn run() {
+    let enabled = screeps::memory::root().path_bool(POWER_HARVESTING_PATH);
+    let operations = match screeps::memory::root().dict_or_create(OPERATIONS_KEY) {
+        Ok(operations) => operations,
+        Err(e) => {
+            warn!("couldn't load power operations: {}", e);
+            return;
+        }
+    };
+    let time = screeps::game::time();
+
+    for key in operations.keys() {
+        let room_name = match RoomName::new(&key) {
+            Ok(room_name) => room_name,
+            Err(_) => {
+                operations.del(&key);
+                continue;
+            }
+        };
+        if let Err(reason) = run_operation(room_name) {
+            info!("power operation in {} ended: {}", room_name, reason);
+            operations.del(&key);
+            spawning::cancel(|r| {
+                r.work_room == Some(room_name)
+                    && matches!(
+                        r.role,
+                        Role::PowerAttacker | Role::PowerHealer | Role::PowerHauler
+                    )
+            });
+        }
+    }
+
+    if !enabled {
+        return;
+    }
+    let homes: Vec<RoomName> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.storage().is_some())
+        .filter(|room| {
+            room.controller()
+                .map_or(false, |c| c.my() && c.level() >= MIN_RCL)
+        })
+        .map(|room| room.name())
+        .collect();
+    for (room_name, room) in intel::known_rooms() {
+        let bank = match room.power_bank {
+            Some(bank) => bank,
+            None => continue,
+        };
+        if operations
+            .dict(&room_name.to_string())
+            .ok()
+            .flatten()
+            .is_some()
+        {
+            continue;
+        }
+        let home = homes
+            .iter()
+            .copied()
+            .map(|home| {
+                (
+                    screeps::game::map::get_room_linear_distance(home, room_name, false),
+                    home,
+                )
+            })
+            .filter(|&(distance, _)| distance <= MAX_DISTANCE)
+            .min();
+        let home = match home {
+            Some((_, home)) => home,
+            None => continue,
+        };
+        let ticks_left = bank.decays_at.saturating_sub(time);
+        let pairs = match pairs_needed(bank.hits, ticks_left, arrival_ticks(home, room_name)) {
+            Some(pairs) => pairs,
+            None => continue,
+        };
+        let operation = match operations.dict_or_create(&room_name.to_string()) {
+            Ok(operation) => operation,
+            Err(_) => continue,
+        };
+        info!(
+            "harvesting the power bank in {} from {} with {} pairs, {} power with {} ticks left",
+            room_name, home, pairs, bank.power, ticks_left
+        );
+        operation.set(HOME_KEY, home.to_string());
+        operation.set(PAIRS_KEY, pairs);
+    }
+}
+
+/// Runs one operation, returning why it ended if it did.
+fn run_operation(room_name: RoomName) -> Result<(), String> {
+    let operation = screeps::memory::root()
+        .path_dict(&format!("{}.{}", OPERATIONS_KEY, room_name))
+        .ok()
+        .flatten()
+        .ok_or("its record is gone")?;
+    let home = operation
+        .string(HOME_KEY)
+        .ok()
+        .flatten()
+        .and_then(|home| RoomName::new(&home).ok())
+        .ok_or("it has no home room")?;
+    let pairs = operation.i32(PAIRS_KEY).ok().flatten().unwrap_or(1) as u32;
+    let time = screeps::game::time();
+
+    let room = screeps::game::rooms::get(room_name);
+    let bank = intel::get(room_name).and_then(|intel| intel.power_bank);
+    let dropped_power = room.as_ref().map_or(0, |room| {
+        room.find(find::DROPPED_RESOURCES)
+            .iter()
+            .filter(|r| r.resource_type() == ResourceType::Power)
+            .map(|r| r.amount())
+            .sum()
+    });
+
+    let bank = match bank {
+        Some(bank) if bank.decays_at > time => bank,
+        // a broken bank leaves its power on the ground until the haulers have it
+        _ if dropped_power > 0 => {
+            if !operation.bool(HAULERS_KEY) {
+                request_haulers(&operation, home, room_name, dropped_power);
+            }
+            return Ok(());
+        }
+        _ => return Err("the bank and its power are gone".to_string()),
+    };
+    if let Some(room) = &room {
+        let bank_pos = Position::new(bank.pos.0 as u32, bank.pos.1 as u32, room_name);
+        let contested = room
+            .find(find::HOSTILE_CREEPS)
+            .iter()
+            .any(|c| c.pos().is_near_to(&bank_pos));
+        if contested {
+            return Err("someone else is already at the bank".to_string());
+        }
+    }
+    let ticks_left = bank.decays_at - time;
+    let attackers_there = count(room_name, Role::PowerAttacker, false) > 0;
+    let arrival = if attackers_there {
+        0
+    } else {
+        arrival_ticks(home, room_name)
+    };
+    if arrival + ticks_to_break(bank.hits, pairs) > ticks_left {
+        return Err(format!(
+            "the bank would decay before {} pairs break it",
+            pairs
+        ));
+    }
+
+    for role in &[Role::PowerAttacker, Role::PowerHealer] {
+        let size = if *role == Role::PowerAttacker {
+            ATTACKER_SIZE
+        } else {
+            HEALER_SIZE
+        };
+        for _ in count(room_name, *role, true)..pairs {
+            request(home, room_name, *role, size);
+        }
+    }
+
+    let haulers_requested = operation.bool(HAULERS_KEY);
+    if !haulers_requested && ticks_to_break(bank.hits, pairs) <= arrival_ticks(home, room_name) {
+        request_haulers(&operation, home, room_name, bank.power);
+    }
+    Ok(())
+}
+
+/// Sends haulers enough to carry the power home.
+fn request_haulers(operation: &MemoryReference, home: RoomName, room_name: RoomName, power: u32) {
+    let parts = ((power + CARRY_CAPACITY - 1) / CARRY_CAPACITY).max(1);
+    let haulers = (parts + HAULER_MAX_SIZE - 1) / HAULER_MAX_SIZE;
+    let size = (parts + haulers - 1) / haulers;
+    info!(
+        "sending {} haulers for the {} power in {}",
+        haulers, power, room_name
+    );
+    for _ in 0..haulers {
+        request(home, room_name, Role::PowerHauler, size);
+    }
+    operation.set(HAULERS_KEY, true);
+}
+
+/// How many creeps of a role work on the bank in a room, counting queued ones if asked to.
+fn count(room_name: RoomName, role: Role, with_queued: bool) -> u32 {
+    let alive = screeps::game::creeps::values()
+        .iter()
+        .filter(|c| spawning::role_of(c) == role && spawning::work_room(c) == Some(room_name))
+        .count();
+    let queued = if with_queued {
+        spawning::queue()
+            .iter()
+            .filter(|r| r.role == role && r.work_room == Some(room_name))
+            .count()
+    } else {
+        0
+    };
+    (alive + queued) as u32
+}
+
+fn request(home: RoomName, room_name: RoomName, role: Role, size: u32) {
+    debug!(
+        "requesting a {} for the power bank in {}",
+        role.name(),
+        room_name
+    );
+    spawning::request(SpawnRequest {
+        room_name: home,
+        role,
+        priority: spawning::DEFAULT_REQUEST_PRIORITY,
+        work_room: Some(room_name),
+        size,
+    });
+}
+
+/// Whether a room has a power operation running.
+fn is_running(room_name: RoomName) -> bool {
+    screeps::memory::root()
+        .path_dict(&format!("{}.{}", OPERATIONS_KEY, room_name))
+        .ok()
+        .flatten()
+        .is_some()
+}
+
+/// Runs a creep of a power operation. Creeps whose operation ended go home, bringing whatever
+/// power they carry.
+pub fn run_creep(creep: &Creep, role: Role) {
+    let (home, room_name) = match (spawning::home_room(creep), spawning::work_room(creep)) {
+        (Some(home), Some(room_name)) => (home, room_name),
+        _ => return,
+    };
+    let running = is_running(room_name);
+    let carried = creep.store_used_capacity(Some(ResourceType::Power));
+    let full = creep.store_free_capacity(Some(ResourceType::Power)) <= 0;
+    if role == Role::PowerHauler && carried > 0 && (full || !running) {
+        deliver(creep, home);
+        return;
+    }
+    if !running {
+        movement::move_to_room(creep, home);
+        return;
+    }
+    if creep.pos().room_name() != room_name {
+        movement::move_to_room(creep, room_name);
+        return;
+    }
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return,
+    };
+    let bank = room
+        .find(find::STRUCTURES)
+        .into_iter()
+        .find_map(|s| match s {
+            Structure::PowerBank(bank) => Some(bank),
+            _ => None,
+        });
+
+    match role {
+        Role::PowerAttacker => {
+            let bank = match bank {
+                Some(bank) => bank,
+                None => return,
+            };
+            if !creep.pos().is_near_to(&bank) {
+                movement::move_creep_to(creep, &bank, 1);
+                return;
+            }
+            // the bank hits back, so attackers wait for their healer when they're low
+            let hits = Attackable::hits(creep) as f64;
+            let hits_max = Attackable::hits_max(creep) as f64;
+            if hits >= hits_max * ATTACKER_MIN_HITS {
+                let r = creep.attack(&bank);
+                if r != ReturnCode::Ok {
+                    failures::report(&creep.name(), "attack", r);
+                }
+            }
+            movement::hold(creep, &bank, 1);
+        }
+        Role::PowerHealer => {
+            let patient = room
+                .find(find::MY_CREEPS)
+                .into_iter()
+                .filter(|c| spawning::role_of(c) == Role::PowerAttacker)
+                .min_by_key(|c| Attackable::hits(c));
+            let patient = match patient {
+                Some(patient) => patient,
+                None => return,
+            };
+            if creep.pos().is_near_to(&patient) {
+                creep.heal(&patient);
+                movement::hold(creep, &patient, 1);
+            } else {
+                creep.ranged_heal(&patient);
+                movement::move_creep_to(creep, &patient, 1);
+            }
+        }
+        Role::PowerHauler => collect(creep, &room, bank.map(|bank| bank.pos()), home),
+        _ => {}
+    }
+}
+
+/// Picks up the power of a broken bank, waiting near it while it's still standing.
+fn collect(creep: &Creep, room: &screeps::Room, bank: Option<Position>, home: RoomName) {
+    if let Some(bank) = bank {
+        movement::move_creep_to(creep, &bank, HAULER_WAIT_RANGE);
+        return;
+    }
+    let pos = creep.pos();
+    let power = room
+        .find(find::DROPPED_RESOURCES)
+        .into_iter()
+        .filter(|r| r.resource_type() == ResourceType::Power)
+        .min_by_key(|r| pos.get_range_to(r));
+    match power {
+        Some(power) if pos.is_near_to(&power) => {
+            let r = creep.pickup(&power);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "pickup", r);
+            }
+        }
+        Some(power) => {
+            movement::move_creep_to(creep, &power, 1);
+        }
+        // the rest was picked up by the others
+        None if creep.store_used_capacity(Some(ResourceType::Power)) > 0 => deliver(creep, home),
+        None => {}
+    }
+}
+
+fn deliver(creep: &Creep, home: RoomName) {
+    let storage = match screeps::game::rooms::get(home).and_then(|room| room.storage()) {
+        Some(storage) => storage,
+        None => {
+            movement::move_to_room(creep, home);
+            return;
+        }
+    };
+    if !creep.pos().is_near_to(&storage) {
+        movement::move_creep_to(creep, &storage, 1);
+        return;
+    }
+    let r = creep.transfer_all(&storage, ResourceType::Power);
+    if r != ReturnCode::Ok {
+        failures::report(&creep.name(), "transfer", r);
+    }
+}
@@ -0,0 +1,162 @@
+//! Per-subsystem CPU profiling.
+//!
+//! Work wrapped in [`Stats::time_section`] has its CPU use recorded under the section's name. Sections
+//! nest, and a nested one is named by the path of the sections around it, like
+//! `creeps.movement`. Every [`REPORT_INTERVAL`] ticks the average use per tick and the most one
+//! run took are logged and written to `Memory.stats.cpu`. Other per-tick numbers recorded with
+//! [`Stats::count`] are reported the same way, in `Memory.stats.counts`.
+//!
+//! Profiling is off unless `Memory.config.debug_profile` is set, which `set_profiling(on)` does
+//! from the console. The flag is read once per tick, so a section costs a single check while it's
+//! off.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use log::*;
+
+use crate::context::TickContext;
+
+/// How many ticks of measurements go into each report.
+pub const REPORT_INTERVAL: u32 = 100;
+
+pub const ENABLED_PATH: &str = "config.debug_profile";
+
+#[derive(Default)]
+struct Section {
+    total: f64,
+    max: f64,
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = Cell::new(false);
+    /// The names of the sections currently running, outermost first.
+    static STACK: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+    /// Measurements since the last report, by section path.
+    static SECTIONS: RefCell<HashMap<String, Section>> = RefCell::new(HashMap::new());
+    /// Counts since the last report, by name.
+    static COUNTS: RefCell<HashMap<&'static str, Section>> = RefCell::new(HashMap::new());
+    /// How many profiled ticks the measurements cover.
+    static TICKS: Cell<u32> = Cell::new(0);
+}
+
+/// Takes whether profiling is enabled for this tick from its config. Called once at the start
+/// of the loop.
+pub fn begin_tick(ctx: &TickContext) {
+    let enabled = ctx.config.profile;
+    ENABLED.with(|e| e.set(enabled));
+    if enabled {
+        TICKS.with(|t| t.set(t.get() + 1));
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Turns profiling on or off, starting with the rest of this tick.
+pub fn set_enabled(enabled: bool) {
+    screeps::memory::root().path_set(ENABLED_PATH, enabled);
+    ENABLED.with(|e| e.set(enabled));
+}
+
+/// A handle on where the numbers of a tick go, which the loop hands out in its [`TickContext`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    _private: (),
+}
+
+impl Stats {
+    /// Runs `f`, recording the CPU it used under `name` if profiling is enabled.
+    pub fn time_section<R>(&self, name: &'static str, f: impl FnOnce() -> R) -> R {
+        if !ENABLED.with(Cell::get) {
+            return f();
+        }
+
+        let path = STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            stack.push(name);
+            stack.join(".")
+        });
+        let start = screeps::game::cpu::get_used();
+        let result = f();
+        let used = screeps::game::cpu::get_used() - start;
+        STACK.with(|stack| stack.borrow_mut().pop());
+
+        SECTIONS.with(|sections| {
+            let mut sections = sections.borrow_mut();
+            let section = sections.entry(path).or_default();
+            section.total += used;
+            section.max = section.max.max(used);
+        });
+        result
+    }
+
+    /// Records a number for this tick, like the length of a queue, if profiling is enabled.
+    pub fn count(&self, name: &'static str, value: f64) {
+        if !ENABLED.with(Cell::get) {
+            return;
+        }
+        COUNTS.with(|counts| {
+            let mut counts = counts.borrow_mut();
+            let count = counts.entry(name).or_default();
+            count.total += value;
+            count.max = count.max.max(value);
+        });
+    }
+}
+
+/// Logs the measurements since the last report, stores them in `Memory.stats` and starts a
+/// new window.
+pub fn report() {
+    let ticks = TICKS.with(|t| t.replace(0));
+    let sections = SECTIONS.with(|s| s.replace(HashMap::new()));
+    let counts = COUNTS.with(|c| c.replace(HashMap::new()));
+    if ticks == 0 {
+        return;
+    }
+
+    if !sections.is_empty() {
+        info!("cpu use over the last {} ticks:", ticks);
+        report_window("cpu", sections.into_iter().collect(), ticks);
+    }
+    if !counts.is_empty() {
+        info!("counts over the last {} ticks:", ticks);
+        let counts = counts
+            .into_iter()
+            .map(|(name, count)| (name.to_string(), count))
+            .collect();
+        report_window("counts", counts, ticks);
+    }
+}
+
+/// Logs one kind of measurement and replaces `Memory.stats.<key>` with it.
+fn report_window(key: &str, mut sections: Vec<(String, Section)>, ticks: u32) {
+    sections.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let stats = screeps::memory::root()
+        .dict_or_create("stats")
+        .and_then(|stats| {
+            stats.del(key);
+            stats.dict_or_create(key)
+        });
+    if let Err(e) = &stats {
+        warn!("couldn't store {} stats: {}", key, e);
+    }
+
+    for (path, section) in sections {
+        let average = section.total / ticks as f64;
+        info!(
+            "  {}: {:.2} per tick, {:.2} max",
+            path, average, section.max
+        );
+        if let Ok(stats) = &stats {
+            if let Ok(entry) = stats.dict_or_create(&path) {
+                entry.set("avg", average);
+                entry.set("max", section.max);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! How quickly GCL, GPL and each room's controller level up.
+//!
+//! Every [`SAMPLE_INTERVAL`] ticks the progress of each is sampled, along with the time of day,
+//! and the last [`MAX_SAMPLES`] samples are kept in `Memory.progress`. The progress per tick over
+//! the samples comes down to how many ticks and days are left until the next level, which is
+//! logged and written to `Memory.stats.progress`. Progress starts over at each level, so a new
+//! level starts a new window of samples.
+
+use log::*;
+use screeps::prelude::*;
+use stdweb::{js, unstable::TryInto};
+
+const PROGRESS_KEY: &str = "progress";
+
+const STATS_PATH: &str = "stats.progress";
+
+/// How often the progress is sampled.
+pub const SAMPLE_INTERVAL: u32 = 100;
+
+/// How many samples are kept of each.
+pub const MAX_SAMPLES: usize = 50;
+
+const MS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    time: u32,
+    progress: f64,
+    /// When the sample was taken, in milliseconds since the epoch.
+    timestamp: f64,
+}
+
+/// The samples since the last level up.
+struct Window {
+    level: u32,
+    samples: Vec<Sample>,
+}
+
+impl Window {
+    /// Stores a window as `level;time,progress,timestamp;...`.
+    fn encode(&self) -> String {
+        let mut encoded = self.level.to_string();
+        for sample in &self.samples {
+            encoded.push_str(&format!(
+                ";{},{},{}",
+                sample.time, sample.progress, sample.timestamp
+            ));
+        }
+        encoded
+    }
+
+    fn decode(encoded: &str) -> Option<Window> {
+        let mut parts = encoded.split(';');
+        let level = parts.next()?.parse().ok()?;
+        let samples = parts
+            .filter_map(|sample| {
+                let mut fields = sample.split(',');
+                Some(Sample {
+                    time: fields.next()?.parse().ok()?,
+                    progress: fields.next()?.parse().ok()?,
+                    timestamp: fields.next()?.parse().ok()?,
+                })
+            })
+            .collect();
+        Some(Window { level, samples })
+    }
+
+    /// The progress per tick and the milliseconds per tick over the window, if it covers any
+    /// ticks.
+    fn rates(&self) -> Option<(f64, f64)> {
+        let first = self.samples.first()?;
+        let last = self.samples.last()?;
+        let ticks = last
+            .time
+            .checked_sub(first.time)
+            .filter(|&ticks| ticks > 0)? as f64;
+        Some((
+            (last.progress - first.progress) / ticks,
+            (last.timestamp - first.timestamp) / ticks,
+        ))
+    }
+}
+
+fn now() -> f64 {
+    let timestamp = js! { return Date.now() };
+    timestamp.try_into().unwrap_or(0.0)
+}
+
+/// Adds a sample to the window at `path`, starting a new window if the level changed, and
+/// returns a summary of how long the next level is going to take.
+fn sample(name: &str, path: &str, level: u32, progress: f64, total: f64) -> String {
+    let memory = screeps::memory::root();
+    let stored = memory.path_string(path).ok().flatten();
+    let mut window = stored
+        .as_deref()
+        .and_then(Window::decode)
+        .filter(|window| window.level == level)
+        .unwrap_or(Window {
+            level,
+            samples: Vec::new(),
+        });
+    // progress only drops with a level, in case that's missed
+    if window
+        .samples
+        .last()
+        .map_or(false, |last| last.progress > progress)
+    {
+        window.samples.clear();
+    }
+    window.samples.push(Sample {
+        time: screeps::game::time(),
+        progress,
+        timestamp: now(),
+    });
+    let excess = window.samples.len().saturating_sub(MAX_SAMPLES);
+    window.samples.drain(..excess);
+    memory.path_set(path, window.encode());
+
+    let stats = format!("{}.{}", STATS_PATH, name);
+    memory.path_set(&format!("{}.level", stats), level);
+    memory.path_set(&format!("{}.progress", stats), progress / total.max(1.0));
+    let percent = progress / total.max(1.0) * 100.0;
+    let (rate, ms_per_tick) = match window.rates() {
+        Some((rate, ms_per_tick)) if rate > 0.0 => (rate, ms_per_tick),
+        _ => {
+            for key in &["rate", "eta_ticks", "eta_days"] {
+                memory.path_del(&format!("{}.{}", stats, key));
+            }
+            return format!("{} {} at {:.1}%, no progress yet", name, level, percent);
+        }
+    };
+    let ticks = (total - progress).max(0.0) / rate;
+    let days = ticks * ms_per_tick / MS_PER_DAY;
+    memory.path_set(&format!("{}.rate", stats), rate);
+    memory.path_set(&format!("{}.eta_ticks", stats), ticks.round());
+    memory.path_set(&format!("{}.eta_days", stats), days);
+    format!(
+        "{} {} at {:.1}%, {:.1}/tick, next in {:.0} ticks (~{:.1} days)",
+        name, level, percent, rate, ticks, days
+    )
+}
+
+/// Samples GCL, GPL and the controllers of our rooms, and logs when each levels up next.
+pub fn run() {
+    let mut lines = vec![sample(
+        "gcl",
+        &format!("{}.gcl", PROGRESS_KEY),
+        screeps::game::gcl::level(),
+        screeps::game::gcl::progress(),
+        screeps::game::gcl::progress_total(),
+    )];
+    // accounts which never processed power have nothing to track
+    if screeps::game::gpl::level() > 0 || screeps::game::gpl::progress() > 0.0 {
+        lines.push(sample(
+            "gpl",
+            &format!("{}.gpl", PROGRESS_KEY),
+            screeps::game::gpl::level(),
+            screeps::game::gpl::progress(),
+            screeps::game::gpl::progress_total(),
+        ));
+    }
+
+    let mut owned = Vec::new();
+    for room in screeps::game::rooms::values() {
+        let controller = match room.controller() {
+            Some(controller) if controller.my() => controller,
+            _ => continue,
+        };
+        let name = room.name().to_string();
+        // rooms at the last level have no progress to make
+        if let (Some(progress), Some(total)) = (controller.progress(), controller.progress_total())
+        {
+            lines.push(sample(
+                &name,
+                &format!("{}.rooms.{}", PROGRESS_KEY, name),
+                controller.level(),
+                progress as f64,
+                total as f64,
+            ));
+            owned.push(name);
+        }
+    }
+
+    // forget rooms which were lost or maxed out
+    let memory = screeps::memory::root();
+    if let Ok(Some(rooms)) = memory.path_dict(&format!("{}.rooms", PROGRESS_KEY)) {
+        for name in rooms.keys() {
+            if !owned.contains(&name) {
+                rooms.del(&name);
+                memory.path_del(&format!("{}.{}", STATS_PATH, name));
+            }
+        }
+    }
+
+    info!("progress: {}", lines.join("; "));
+}
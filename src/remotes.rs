@@ -0,0 +1,995 @@
+//! Remote mining: harvesting the sources of rooms next to ours and hauling the energy home.
+//!
+//! Remotes are listed in `Memory.config.remotes`, mapping each remote to the owned room whose
+//! spawns serve it, like `{"W2N1": "W1N1"}`, and each runs as an [`operations`] operation this
+//! module checks on. Each one gets a reserver keeping its controller reserved, a miner per source
+//! harvesting onto the ground, and haulers with enough carry parts between them to bring all of it
+//! home. How many that takes follows from each source's round trip to the home room, loaded haulers
+//! being slower off roads. The trips are searched once and kept in
+//! `Memory.rooms.<remote>.remote_trips` until the number of roads in the remote changes. The parts
+//! are spread over as few haulers as the home room can afford.
+//!
+//! A remote whose [`threat`] assessment has armed hostiles in it, or did when it was last seen, is
+//! suspended: its creeps leave for home as soon as they're seen, and nothing but defenders is
+//! spawned for it. One defender is sent per [`PARTS_PER_DEFENDER`] fighting parts the hostiles had
+//! when last seen, and the remote resumes on its own once it's seen clear again. Boosted hostiles,
+//! or a medium threat, get attacker and healer [`duo`]s instead, one per [`PARTS_PER_DUO`] fighting
+//! parts. A remote someone else owns is suspended too, but isn't fought over.
+//!
+//! Source keeper rooms can be remotes too once `Memory.config.source_keeper_mining` is set, for
+//! home rooms at [`SOURCE_KEEPER_MIN_RCL`] or above, as they aren't worth it before. They get a
+//! keeper killer instead of a reserver, which waits at the lair spawning next and kills its
+//! keeper. Miners and haulers keep away from sources and energy a keeper is near, and pathing
+//! avoids the tiles around the lairs. Their sources hold 4000 energy rather than 3000, so their
+//! miners are twice the size and their haulers get a third more carry parts. The keepers don't
+//! suspend a remote, but an invader core intel saw in any remote does, until it's gone.
+//!
+//! The energy hauled home from each remote and spent on spawning for it is added up in
+//! `Memory.stats.remotes.<remote>`. Every [`REPORT_INTERVAL`] ticks the window's net income is
+//! logged and kept as `net`, to show whether a remote pays for itself. What its sources would
+//! have given while it was suspended is counted as `lost`, and how often that happened as
+//! `interruptions`, to show which remotes invaders keep coming back to. Remotes next to a player
+//! who keeps attacking us, as [`attackers`] tracks, spawn behind the other remotes.
+//!
+//! A remote which hauls home less than [`MIN_INCOME_RATIO`] of what its creeps cost for
+//! [`POOR_WINDOWS`] windows in a row is wound down: nothing more is spawned for it, its creeps
+//! are recycled at home, and intel marks it not to be used as a remote for
+//! [`WIND_DOWN_COOLDOWN`] ticks, with a notification giving the numbers. `enable_remote(room)`
+//! from the console, or a flag named `enable_remote` or `enable_remote:<anything>` in the room,
+//! mines it again before that.
+
+use log::*;
+use screeps::{
+    find, look,
+    pathfinder::{self, SearchOptions},
+    prelude::*,
+    Attackable, Creep, Part, Position, ResourceType, ReturnCode, Room, RoomName, Structure,
+    StructureType,
+};
+
+use crate::{
+    actions::{self, Action, ActionOutcome},
+    attackers,
+    context::TickContext,
+    creeps, duo, energy, events, failures, intel, lifetimes, movement,
+    operations::{self, Operation, Outcome, Phase, Wanted},
+    planner, retreat,
+    spawning::{self, Role, SpawnRequest},
+    threat::{self, Level},
+};
+
+/// How many ticks of income go into each report, a creep's lifetime so each creep's cost is
+/// counted in about one window.
+pub const REPORT_INTERVAL: u32 = 1500;
+
+const REMOTES_PATH: &str = "config.remotes";
+const SOURCE_KEEPER_MINING_PATH: &str = "config.source_keeper_mining";
+
+/// The controller level home rooms need to mine source keeper rooms.
+pub const SOURCE_KEEPER_MIN_RCL: u32 = 7;
+
+/// How close to a keeper miners and haulers don't go.
+const KEEPER_RANGE: u32 = 5;
+const TRIPS_KEY: &str = "remote_trips";
+/// How many roads the remote had when its trips were searched.
+const ROADS_KEY: &str = "remote_roads";
+/// Where the average trip was kept before each source had its own.
+const LEGACY_TRIP_KEY: &str = "remote_trip";
+const STATS_PATH: &str = "stats.remotes";
+
+/// Remotes hauling home less than this share of what's spent on them don't pay for themselves.
+pub const MIN_INCOME_RATIO: f64 = 1.0;
+
+/// How many windows in a row a remote may not pay for itself before it's wound down.
+pub const POOR_WINDOWS: i32 = 3;
+
+/// How long a wound down remote isn't mined for.
+pub const WIND_DOWN_COOLDOWN: u32 = 50_000;
+
+const ENABLE_FLAG: &str = "enable_remote";
+const ENABLE_FLAG_PREFIX: &str = "enable_remote:";
+
+/// The source a miner was given, as `x,y`, in its memory.
+const SOURCE_KEY: &str = "source";
+/// Set in a hauler's memory while it's bringing energy home.
+const DELIVERING_KEY: &str = "delivering";
+
+/// The priority of spawn requests for remotes, below the console's default.
+const REMOTE_PRIORITY: u8 = 100;
+
+/// The priority of spawn requests for remotes next to a player who keeps attacking us, so the
+/// other remotes are served first.
+const HARASSED_PRIORITY: u8 = 95;
+
+/// The priority of defender spawn requests, above everything else for the remotes.
+const DEFENDER_PRIORITY: u8 = 180;
+
+/// How many of the hostiles' attack, ranged attack and heal parts one defender takes on.
+pub const PARTS_PER_DEFENDER: u32 = 4;
+
+/// The most defenders sent to one remote at a time.
+const MAX_DEFENDERS: u32 = 3;
+
+/// How many of the hostiles' fighting parts one attacker and healer duo takes on.
+pub const PARTS_PER_DUO: u32 = 12;
+
+/// The most duos sent to one remote at a time.
+const MAX_DUOS: u32 = 2;
+
+/// What a source holds when it regenerates, every [`SOURCE_REGEN_TICKS`].
+const SOURCE_ENERGY: u32 = 3000;
+
+/// What a source in a source keeper room holds, which is more than elsewhere.
+const KEEPER_SOURCE_ENERGY: u32 = 4000;
+
+const SOURCE_REGEN_TICKS: u32 = 300;
+
+/// How many times over a miner's body is repeated in source keeper rooms. Ten work parts drain
+/// their bigger sources before they regenerate, where five don't.
+const KEEPER_MINER_SIZE: u32 = 2;
+
+const CARRY_CAPACITY: u32 = 50;
+
+/// Reservers aren't spawned while more than this much of the reservation is left.
+const RESERVATION_TARGET: u32 = 2000;
+
+/// How much the path search for a remote's round trip may cost.
+const TRIP_SEARCH_OPS: u32 = 20_000;
+
+/// How close to a source haulers wait for energy to be mined.
+const HAULER_WAIT_RANGE: u32 = 3;
+
+/// Every remote along with its home room.
+pub fn remotes() -> Vec<(RoomName, RoomName)> {
+    let config = match screeps::memory::root().path_dict(REMOTES_PATH) {
+        Ok(Some(config)) => config,
+        _ => return Vec::new(),
+    };
+    config
+        .keys()
+        .into_iter()
+        .filter_map(|remote| {
+            let home = config.string(&remote).ok()??;
+            Some((RoomName::new(&remote).ok()?, RoomName::new(&home).ok()?))
+        })
+        .collect()
+}
+
+/// Whether a remote is too dangerous to work in right now.
+pub fn is_suspended(remote: RoomName) -> bool {
+    is_threatened(remote)
+        || intel::is_hostile(remote)
+        || intel::get(remote).map_or(false, |intel| intel.invader_core)
+}
+
+/// Whether there are armed hostiles in a remote, going by its last threat assessment while it
+/// isn't visible, or by intel if it never had one.
+fn is_threatened(remote: RoomName) -> bool {
+    match threat::get(remote) {
+        Some(assessment) => assessment.fighting_parts() > 0,
+        None => intel::is_threatened(remote),
+    }
+}
+
+/// The defenders it takes to clear a remote of what was last seen in it: duos against boosted
+/// hostiles or a medium threat, which would outlast lone defenders, and defenders otherwise.
+fn wanted_defense(remote: RoomName) -> Vec<(Role, u32)> {
+    let assessment = threat::get(remote);
+    let parts = assessment
+        .as_ref()
+        .map_or(1, |assessment| assessment.fighting_parts().max(1));
+    let strong = assessment.map_or(false, |assessment| {
+        assessment.boosted || assessment.level >= Level::Medium
+    });
+    if strong {
+        let duos = ((parts + PARTS_PER_DUO - 1) / PARTS_PER_DUO).min(MAX_DUOS);
+        vec![(Role::DuoAttacker, duos), (Role::DuoHealer, duos)]
+    } else {
+        let defenders = ((parts + PARTS_PER_DEFENDER - 1) / PARTS_PER_DEFENDER).min(MAX_DEFENDERS);
+        vec![(Role::Defender, defenders)]
+    }
+}
+
+/// Checks on a remote's operation, suspending or resuming it and working out what it wants.
+pub fn check_operation(operation: &mut Operation, ctx: &TickContext) -> Outcome {
+    let remote = operation.room;
+    match remotes().into_iter().find(|&(r, _)| r == remote) {
+        Some((_, home)) => operation.home = home,
+        None => return Outcome::Done("it isn't a remote any more".to_string()),
+    }
+    let home = operation.home;
+
+    if take_enable_flag(remote) && intel::clear_do_not_remote(remote) {
+        info!("remote {} was enabled again with a flag", remote);
+    }
+    if let Some(until) = intel::do_not_remote_until(remote) {
+        if operation.phase != Phase::WindingDown {
+            info!("winding remote {} down until tick {}", remote, until);
+            operation.phase = Phase::WindingDown;
+        }
+        operation.wanted.clear();
+        return Outcome::Continue;
+    }
+    if operation.phase == Phase::WindingDown {
+        info!("remote {} may be mined again", remote);
+        operation.phase = Phase::Running;
+    }
+
+    let suspended = is_suspended(remote);
+    let was_suspended = operation.phase == Phase::Suspended;
+    if suspended && !was_suspended {
+        warn!("suspending remote {}, it's hostile", remote);
+        add_stat(remote, "interruptions", 1);
+        operation.phase = Phase::Suspended;
+    } else if !suspended && was_suspended {
+        info!("remote {} is clear, resuming it", remote);
+        operation.phase = Phase::Running;
+    }
+    if suspended {
+        let sources = intel::get(remote).map_or(0, |intel| intel.sources.len() as u32);
+        let lost = source_energy(remote) * operations::CHECK_INTERVAL / SOURCE_REGEN_TICKS;
+        add_stat(remote, "lost", sources * lost);
+    }
+    operation.wanted.clear();
+    let home_rcl = match screeps::game::rooms::get(home).and_then(|room| room.controller()) {
+        Some(controller) if controller.my() => controller.level(),
+        _ => {
+            debug!(
+                "not serving remote {}, home room {} isn't ours",
+                remote, home
+            );
+            return Outcome::Continue;
+        }
+    };
+    if intel::is_source_keeper(remote) && !source_keeper_mining(home_rcl) {
+        debug!(
+            "not serving source keeper remote {}, it's turned off or {} is too low",
+            remote, home
+        );
+        return Outcome::Continue;
+    }
+
+    let wanted = if !suspended {
+        wanted_creeps(remote)
+    } else if intel::is_hostile(remote) {
+        // rooms someone else owns aren't worth a fight
+        Vec::new()
+    } else {
+        wanted_defense(remote)
+    };
+    let remote_priority = if attackers::is_harassed(remote) {
+        debug!(
+            "remote {} is next to a player who keeps attacking us",
+            remote
+        );
+        HARASSED_PRIORITY
+    } else {
+        REMOTE_PRIORITY
+    };
+    for (role, count) in wanted {
+        let size = match role {
+            Role::KeeperKiller | Role::DuoAttacker | Role::DuoHealer => {
+                affordable_size(home, role, ctx)
+            }
+            Role::RemoteMiner if intel::is_source_keeper(remote) => KEEPER_MINER_SIZE,
+            _ => 1,
+        };
+        let priority = match role {
+            Role::Defender | Role::DuoAttacker | Role::DuoHealer => DEFENDER_PRIORITY,
+            _ => remote_priority,
+        };
+        operation.wanted.push(Wanted {
+            role,
+            count,
+            size,
+            priority,
+        });
+    }
+    if !suspended {
+        operation
+            .wanted
+            .extend(wanted_haulers(operation, remote_priority, ctx));
+    }
+    Outcome::Continue
+}
+
+/// Removes the enable flags in a remote, returning whether there were any.
+fn take_enable_flag(remote: RoomName) -> bool {
+    let mut found = false;
+    for flag in screeps::game::flags::values() {
+        let name = flag.name();
+        if flag.pos().room_name() == remote
+            && (name == ENABLE_FLAG || name.starts_with(ENABLE_FLAG_PREFIX))
+        {
+            flag.remove();
+            found = true;
+        }
+    }
+    found
+}
+
+/// Whether source keeper rooms are mined for a home room at the given level.
+fn source_keeper_mining(home_rcl: u32) -> bool {
+    home_rcl >= SOURCE_KEEPER_MIN_RCL
+        && screeps::memory::root().path_bool(SOURCE_KEEPER_MINING_PATH)
+}
+
+/// How many reservers or keeper killers and miners a remote needs.
+fn wanted_creeps(remote: RoomName) -> Vec<(Role, u32)> {
+    let miners = intel::sources(remote).map_or(0, |sources| sources.len() as u32);
+    if intel::is_source_keeper(remote) {
+        return vec![(Role::KeeperKiller, 1), (Role::RemoteMiner, miners)];
+    }
+
+    let reserved = screeps::game::rooms::get(remote)
+        .and_then(|room| room.controller())
+        .and_then(|controller| controller.reservation())
+        .map_or(0, |reservation| reservation.ticks_to_end);
+    let reservers = if reserved > RESERVATION_TARGET { 0 } else { 1 };
+
+    // until the room has been seen, the reserver scouts it
+    vec![(Role::Reserver, reservers), (Role::RemoteMiner, miners)]
+}
+
+/// What each source of a remote holds when it regenerates.
+fn source_energy(remote: RoomName) -> u32 {
+    if intel::is_source_keeper(remote) {
+        KEEPER_SOURCE_ENERGY
+    } else {
+        SOURCE_ENERGY
+    }
+}
+
+/// The carry parts which keep up with a source holding `energy` whose round trip takes `trip`
+/// ticks.
+fn carry_parts_for(energy: u32, trip: u32) -> u32 {
+    let per_trip = energy * trip;
+    let per_part = SOURCE_REGEN_TICKS * CARRY_CAPACITY;
+    (per_trip + per_part - 1) / per_part
+}
+
+/// Haulers for the carry parts a remote is missing, as few and as big as the home room can
+/// afford, on top of the ones it has, spawned at `priority`.
+fn wanted_haulers(operation: &Operation, priority: u8, ctx: &TickContext) -> Option<Wanted> {
+    let (remote, home) = (operation.room, operation.home);
+    let sources = intel::sources(remote).filter(|sources| !sources.is_empty())?;
+    let trips = source_trips(remote, home, &sources, ctx)?;
+    let energy = source_energy(remote);
+    let required: u32 = trips
+        .iter()
+        .map(|&trip| carry_parts_for(energy, trip))
+        .sum();
+
+    let per_size = carry_parts_per_size();
+    let alive: Vec<Creep> = operation
+        .live_creeps()
+        .into_iter()
+        .filter(|c| spawning::role_of(c) == Role::Hauler)
+        .collect();
+    let queued: Vec<SpawnRequest> = spawning::queue()
+        .into_iter()
+        .filter(|r| r.role == Role::Hauler && r.work_room == Some(remote))
+        .collect();
+    let have = alive
+        .iter()
+        .map(|c| c.get_active_bodyparts(Part::Carry))
+        .sum::<u32>()
+        + queued.iter().map(|r| r.size * per_size).sum::<u32>();
+    let missing = required.saturating_sub(have);
+    let existing = (alive.len() + queued.len()) as u32;
+    if missing == 0 {
+        return Some(Wanted {
+            role: Role::Hauler,
+            count: existing,
+            size: 1,
+            priority,
+        });
+    }
+
+    let (haulers, size) =
+        split_haulers(missing, per_size, affordable_size(home, Role::Hauler, ctx));
+    debug!(
+        "remote {} is missing {} carry parts, wanting {} more haulers",
+        remote, missing, haulers
+    );
+    Some(Wanted {
+        role: Role::Hauler,
+        count: existing + haulers,
+        size,
+        priority,
+    })
+}
+
+/// How few haulers of which size make up `missing` carry parts, with `per_size` of them in each
+/// size of the body and at most `max_size` sizes to a hauler.
+fn split_haulers(missing: u32, per_size: u32, max_size: u32) -> (u32, u32) {
+    let sizes_missing = (missing + per_size - 1) / per_size;
+    let haulers = (sizes_missing + max_size - 1) / max_size;
+    let size = (sizes_missing + haulers - 1) / haulers;
+    (haulers, size)
+}
+
+/// The biggest size of a role's body the home room can afford.
+fn affordable_size(home: RoomName, role: Role, ctx: &TickContext) -> u32 {
+    screeps::game::rooms::get(home).map_or(1, |room| {
+        spawning::affordable_size(role, &*ctx.rooms.snapshot(&room))
+    })
+}
+
+fn carry_parts_per_size() -> u32 {
+    Role::Hauler
+        .body()
+        .iter()
+        .filter(|&&part| part == Part::Carry)
+        .count() as u32
+}
+
+/// The ticks a hauler's round trip from the home room to each of the remote's sources takes.
+fn source_trips(
+    remote: RoomName,
+    home: RoomName,
+    sources: &[Position],
+    ctx: &TickContext,
+) -> Option<Vec<u32>> {
+    let memory = planner::room_memory(remote)?;
+    let roads = screeps::game::rooms::get(remote).map(|room| {
+        ctx.rooms
+            .snapshot(&room)
+            .structures(StructureType::Road)
+            .len() as i32
+    });
+    let roads_changed = roads.is_some() && roads != memory.i32(ROADS_KEY).ok().flatten();
+    if !roads_changed {
+        let trips: Vec<u32> = memory
+            .string(TRIPS_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|trip| trip.parse().ok())
+            .collect();
+        if trips.len() == sources.len() {
+            return Some(trips);
+        }
+    }
+
+    let from = drop_off_anchor(home)?;
+    let trips: Vec<u32> = sources
+        .iter()
+        .map(|source| measure_trip(&from, source, home, remote, ctx))
+        .collect();
+    debug!(
+        "the trips to the sources of remote {} take {:?} ticks",
+        remote, trips
+    );
+    let encoded: Vec<String> = trips.iter().map(|trip| trip.to_string()).collect();
+    memory.set(TRIPS_KEY, encoded.join(","));
+    if let Some(roads) = roads {
+        memory.set(ROADS_KEY, roads);
+    }
+    memory.del(LEGACY_TRIP_KEY);
+    Some(trips)
+}
+
+/// A hauler's round trip to a source: out empty at a tile a tick, and back loaded at a tile a
+/// tick on roads and half that off them.
+fn measure_trip(
+    from: &Position,
+    source: &Position,
+    home: RoomName,
+    remote: RoomName,
+    ctx: &TickContext,
+) -> u32 {
+    let options = SearchOptions::new().max_ops(TRIP_SEARCH_OPS);
+    let result = pathfinder::search(from, source, 1, options);
+    // a search which didn't get there is guessed from how many rooms away the remote is, so it
+    // isn't searched again and again
+    if result.incomplete {
+        warn!(
+            "couldn't find a path from {} to remote {}, guessing the trip",
+            home, remote
+        );
+        return 6 * 25 * screeps::game::map::get_room_linear_distance(home, remote, false).max(1);
+    }
+    let path = result.load_local_path();
+    let on_roads = path.iter().filter(|pos| has_road(pos, ctx)).count() as u32;
+    let length = path.len() as u32;
+    length + on_roads + 2 * (length - on_roads)
+}
+
+fn has_road(pos: &Position, ctx: &TickContext) -> bool {
+    screeps::game::rooms::get(pos.room_name()).map_or(false, |room| {
+        ctx.rooms
+            .snapshot(&room)
+            .structures(StructureType::Road)
+            .iter()
+            .any(|road| road.pos() == *pos)
+    })
+}
+
+/// Where haulers bring energy in a home room, roughly.
+fn drop_off_anchor(home: RoomName) -> Option<Position> {
+    let room = screeps::game::rooms::get(home)?;
+    if let Some(storage) = room.storage() {
+        return Some(storage.pos());
+    }
+    room.find(find::MY_SPAWNS).first().map(|spawn| spawn.pos())
+}
+
+/// Runs a creep working in a remote.
+pub fn run_creep(creep: &Creep, role: Role, ctx: &TickContext) {
+    let (home, remote) = match (spawning::home_room(creep), spawning::work_room(creep)) {
+        (Some(home), Some(remote)) => (home, remote),
+        _ => return,
+    };
+    if intel::do_not_remote_until(remote).is_some() {
+        recycle(creep, home);
+        return;
+    }
+    let suspended = is_suspended(remote);
+    match role {
+        Role::Hauler => run_hauler(creep, home, remote, suspended, ctx),
+        Role::Defender => run_defender(creep, remote, ctx),
+        Role::KeeperKiller => run_keeper_killer(creep, remote, ctx),
+        Role::DuoAttacker | Role::DuoHealer => duo::run_creep(creep, role, home, remote, ctx),
+        // creeps of suspended remotes wait at home
+        _ if suspended => {
+            movement::move_to_room(creep, home);
+        }
+        Role::Reserver => run_reserver(creep, remote),
+        Role::RemoteMiner => run_miner(creep, remote, ctx),
+        Role::Worker
+        | Role::Claimer
+        | Role::Pioneer
+        | Role::PowerAttacker
+        | Role::PowerHealer
+        | Role::PowerHauler
+        | Role::RampartDefender
+        | Role::Dismantler
+        | Role::BoostedDefender => {}
+    }
+}
+
+/// Takes a creep home to be recycled at a spawn there.
+pub fn recycle(creep: &Creep, home: RoomName) {
+    if creep.pos().room_name() != home {
+        movement::move_to_room(creep, home);
+        return;
+    }
+    let pos = creep.pos();
+    let spawn = creep.room().and_then(|room| {
+        room.find(find::MY_SPAWNS)
+            .into_iter()
+            .min_by_key(|spawn| pos.get_range_to(spawn))
+    });
+    match spawn {
+        Some(spawn) if pos.is_near_to(&spawn) => {
+            let r = spawn.recycle_creep(creep);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "recycle", r);
+            }
+        }
+        Some(spawn) => {
+            movement::move_creep_to(creep, &spawn, 1);
+        }
+        None => {
+            creep.suicide();
+        }
+    }
+}
+
+/// Hunts down the hostiles in a remote, healing itself on the way, and falls back to be healed
+/// when it's badly hurt.
+pub fn run_defender(creep: &Creep, remote: RoomName, ctx: &TickContext) {
+    if Attackable::hits(creep) < Attackable::hits_max(creep) {
+        creep.heal(creep);
+    }
+    if retreat::update(creep) {
+        if let Some(home) = spawning::home_room(creep) {
+            retreat::run(creep, home, ctx);
+            return;
+        }
+    }
+    if creep.pos().room_name() != remote {
+        movement::move_to_room(creep, remote);
+        return;
+    }
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return,
+    };
+    let snapshot = ctx.rooms.snapshot(&room);
+    let pos = creep.pos();
+    let hostile = snapshot
+        .hostiles()
+        .iter()
+        .min_by_key(|hostile| pos.get_range_to(*hostile));
+    match hostile {
+        Some(hostile) if pos.is_near_to(hostile) => {
+            let r = creep.attack(hostile);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "attack", r);
+            }
+        }
+        Some(hostile) => {
+            movement::move_creep_to(creep, hostile, 1);
+        }
+        // stays around for the next wave
+        None => {
+            movement::move_to_room(creep, remote);
+        }
+    }
+}
+
+/// Kills the keepers of a source keeper room, waiting for each next to its lair.
+fn run_keeper_killer(creep: &Creep, remote: RoomName, ctx: &TickContext) {
+    if Attackable::hits(creep) < Attackable::hits_max(creep) {
+        creep.heal(creep);
+    }
+    if creep.pos().room_name() != remote {
+        movement::move_to_room(creep, remote);
+        return;
+    }
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return,
+    };
+    let snapshot = ctx.rooms.snapshot(&room);
+    let pos = creep.pos();
+    let keeper = snapshot
+        .hostiles()
+        .iter()
+        .filter(|hostile| hostile.owner_name() == intel::SOURCE_KEEPER_OWNER)
+        .min_by_key(|hostile| pos.get_range_to(*hostile));
+    if let Some(keeper) = keeper {
+        if pos.is_near_to(keeper) {
+            let r = creep.attack(keeper);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "attack", r);
+            }
+        } else {
+            movement::move_creep_to(creep, keeper, 1);
+        }
+        return;
+    }
+
+    // with every keeper dead, the next one is waited for where it'll spawn
+    let next_lair = snapshot
+        .structures(StructureType::KeeperLair)
+        .iter()
+        .filter_map(|s| match s {
+            Structure::KeeperLair(lair) => Some((lair.ticks_to_spawn(), s)),
+            _ => None,
+        })
+        .min_by_key(|(ticks, _)| *ticks);
+    if let Some((_, lair)) = next_lair {
+        movement::move_creep_to(creep, lair, 1);
+    }
+}
+
+/// Whether a keeper is close to a position, in a room which is visible.
+fn keeper_near(pos: &Position, ctx: &TickContext) -> bool {
+    let room = match screeps::game::rooms::get(pos.room_name()) {
+        Some(room) => room,
+        None => return false,
+    };
+    ctx.rooms.snapshot(&room).hostiles().iter().any(|hostile| {
+        hostile.owner_name() == intel::SOURCE_KEEPER_OWNER
+            && hostile.pos().in_range_to(pos, KEEPER_RANGE)
+    })
+}
+
+fn run_reserver(creep: &Creep, remote: RoomName) {
+    if creep.pos().room_name() != remote {
+        movement::move_to_room(creep, remote);
+        return;
+    }
+    let controller = match creep.room().and_then(|room| room.controller()) {
+        Some(controller) => controller,
+        None => return,
+    };
+    if !creep.pos().is_near_to(&controller) {
+        movement::move_creep_to(creep, &controller, 1);
+        return;
+    }
+    let r = creep.reserve_controller(&controller);
+    if r != ReturnCode::Ok {
+        failures::report(&creep.name(), "reserve", r);
+    }
+    movement::hold(creep, &controller, 1);
+}
+
+fn run_miner(creep: &Creep, remote: RoomName, ctx: &TickContext) {
+    let source_pos = match assigned_source(creep, remote) {
+        Some(pos) => pos,
+        None => {
+            movement::move_to_room(creep, remote);
+            return;
+        }
+    };
+    // the keeper killer deals with the source's keeper first
+    if keeper_near(&source_pos, ctx) {
+        return;
+    }
+    if !creep.pos().is_near_to(&source_pos) {
+        movement::move_creep_to(creep, &source_pos, 1);
+        return;
+    }
+    let source = creep.room().and_then(|room| {
+        room.look_for_at(look::SOURCES, &source_pos)
+            .into_iter()
+            .next()
+    });
+    if let Some(source) = source {
+        // miners stay put while the source regenerates, as there's nowhere better for them
+        if creeps::at_source(&source, 0, true) == creeps::AtSource::Harvest {
+            // without carry parts, what's harvested drops for the haulers
+            let r = creep.harvest(&source);
+            if r == ReturnCode::Ok {
+                let harvested = energy::record_harvest(creep, &source);
+                lifetimes::record(&creep.name(), Action::Harvest(&source), harvested);
+            } else {
+                failures::report(&creep.name(), "harvest", r);
+            }
+        }
+        movement::hold(creep, &source, 1);
+    }
+}
+
+/// The source a miner works, picking the one with the fewest miners the first time.
+fn assigned_source(creep: &Creep, remote: RoomName) -> Option<Position> {
+    let memory = creep.memory();
+    if let Some(encoded) = memory.string(SOURCE_KEY).ok().flatten() {
+        return decode_tile(&encoded, remote);
+    }
+
+    let taken: Vec<String> = screeps::game::creeps::values()
+        .iter()
+        .filter(|c| c.name() != creep.name() && spawning::work_room(c) == Some(remote))
+        .filter(|c| spawning::role_of(c) == Role::RemoteMiner)
+        .filter_map(|c| c.memory().string(SOURCE_KEY).ok().flatten())
+        .collect();
+    let source = intel::sources(remote)?
+        .into_iter()
+        .map(|pos| format!("{},{}", pos.x(), pos.y()))
+        .min_by_key(|encoded| taken.iter().filter(|t| *t == encoded).count())?;
+    memory.set(SOURCE_KEY, source.as_str());
+    decode_tile(&source, remote)
+}
+
+fn decode_tile(encoded: &str, room_name: RoomName) -> Option<Position> {
+    let mut fields = encoded.split(',');
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    Some(Position::new(x, y, room_name))
+}
+
+fn run_hauler(creep: &Creep, home: RoomName, remote: RoomName, suspended: bool, ctx: &TickContext) {
+    let memory = creep.memory();
+    let carried = creep.store_used_capacity(Some(ResourceType::Energy));
+    let mut delivering = memory.bool(DELIVERING_KEY);
+    if delivering && carried == 0 {
+        delivering = false;
+        memory.del(DELIVERING_KEY);
+    } else if !delivering
+        && (creep.store_free_capacity(Some(ResourceType::Energy)) <= 0
+            || (suspended && carried > 0))
+    {
+        delivering = true;
+        memory.set(DELIVERING_KEY, true);
+    }
+
+    if delivering {
+        deliver(creep, home, remote, carried, ctx);
+    } else if suspended {
+        movement::move_to_room(creep, home);
+    } else {
+        collect(creep, remote, ctx);
+    }
+}
+
+/// Picks up energy the miners dropped or tops off from a container, or waits by a source for
+/// some.
+fn collect(creep: &Creep, remote: RoomName, ctx: &TickContext) {
+    if creep.pos().room_name() != remote {
+        movement::move_to_room(creep, remote);
+        return;
+    }
+    let room = match creep.room() {
+        Some(room) => room,
+        None => return,
+    };
+    let pos = creep.pos();
+    let dropped = room
+        .find(find::DROPPED_RESOURCES)
+        .into_iter()
+        .filter(|r| r.resource_type() == ResourceType::Energy && !keeper_near(&r.pos(), ctx))
+        .min_by_key(|r| pos.get_range_to(r));
+    match dropped {
+        Some(resource) if pos.is_near_to(&resource) => {
+            let r = creep.pickup(&resource);
+            if r != ReturnCode::Ok {
+                failures::report(&creep.name(), "pickup", r);
+            }
+        }
+        Some(resource) => {
+            movement::move_creep_to(creep, &resource, 1);
+        }
+        None => {
+            let container = ctx
+                .rooms
+                .snapshot(&room)
+                .structures(StructureType::Container)
+                .iter()
+                .filter(|s| creeps::holds_energy(*s) && !keeper_near(&s.pos(), ctx))
+                .min_by_key(|s| pos.get_range_to(*s))
+                .cloned();
+            if let Some(container) = container {
+                actions::perform(creep, Action::Withdraw(&container, ResourceType::Energy));
+                return;
+            }
+            let source = intel::sources(remote)
+                .and_then(|sources| sources.into_iter().find(|source| !keeper_near(source, ctx)));
+            if let Some(source) = source {
+                movement::move_creep_to(creep, &source, HAULER_WAIT_RANGE);
+            }
+        }
+    }
+}
+
+/// Brings energy to the home room's storage, or its spawns and extensions without one.
+fn deliver(creep: &Creep, home: RoomName, remote: RoomName, carried: u32, ctx: &TickContext) {
+    let target = screeps::game::rooms::get(home).and_then(|room| drop_off(&room, creep, ctx));
+    let target = match target {
+        Some(target) => target,
+        None => {
+            movement::move_to_room(creep, home);
+            return;
+        }
+    };
+    let free = energy::free_capacity(&target);
+    let action = Action::Transfer(&target, ResourceType::Energy);
+    if actions::perform(creep, action) == ActionOutcome::Done {
+        add_stat(remote, "hauled", carried.min(free));
+    }
+}
+
+fn drop_off(room: &Room, creep: &Creep, ctx: &TickContext) -> Option<Structure> {
+    if let Some(storage) = room.storage() {
+        if storage.store_free_capacity(Some(ResourceType::Energy)) > 0 {
+            return Some(Structure::Storage(storage));
+        }
+    }
+    let snapshot = ctx.rooms.snapshot(room);
+    let pos = creep.pos();
+    [StructureType::Spawn, StructureType::Extension]
+        .iter()
+        .flat_map(|&ty| snapshot.my_structures(ty))
+        .filter(|s| creeps::accepts_energy(*s))
+        .min_by_key(|s| pos.get_range_to(*s))
+        .cloned()
+}
+
+/// Counts the energy spent spawning a creep for a remote, if the room is one.
+pub fn record_spawn(remote: RoomName, cost: u32) {
+    if remotes().iter().any(|&(r, _)| r == remote) {
+        add_stat(remote, "spawned", cost);
+    }
+}
+
+fn add_stat(remote: RoomName, key: &str, amount: u32) {
+    let path = format!("{}.{}.{}", STATS_PATH, remote, key);
+    let memory = screeps::memory::root();
+    let total = memory.path_i32(&path).ok().flatten().unwrap_or(0);
+    memory.path_set(&path, total + amount as i32);
+}
+
+/// Logs what each remote hauled and cost over the window, and starts a new one.
+pub fn report() {
+    let memory = screeps::memory::root();
+    for (remote, _) in remotes() {
+        let path = format!("{}.{}", STATS_PATH, remote);
+        let stats = match memory.path_dict(&path) {
+            Ok(Some(stats)) => stats,
+            _ => continue,
+        };
+        let hauled = stats.i32("hauled").ok().flatten().unwrap_or(0);
+        let spawned = stats.i32("spawned").ok().flatten().unwrap_or(0);
+        let lost = stats.i32("lost").ok().flatten().unwrap_or(0);
+        let interruptions = stats.i32("interruptions").ok().flatten().unwrap_or(0);
+        info!(
+            "remote {} hauled {} energy for {} spent on creeps over the last {} ticks, {} net",
+            remote,
+            hauled,
+            spawned,
+            REPORT_INTERVAL,
+            hauled - spawned
+        );
+        if interruptions > 0 || lost > 0 {
+            warn!(
+                "remote {} was suspended {} times, losing about {} energy",
+                remote, interruptions, lost
+            );
+        }
+        stats.set("net", hauled - spawned);
+
+        let paying = spawned == 0 || hauled as f64 >= spawned as f64 * MIN_INCOME_RATIO;
+        let poor_windows = if paying {
+            stats.del("poor_windows");
+            stats.del("poor_hauled");
+            stats.del("poor_spawned");
+            0
+        } else {
+            let add = |key: &str, amount: i32| {
+                let total = stats.i32(key).ok().flatten().unwrap_or(0) + amount;
+                stats.set(key, total);
+                total
+            };
+            add("poor_hauled", hauled);
+            add("poor_spawned", spawned);
+            add("poor_windows", 1)
+        };
+        if poor_windows >= POOR_WINDOWS {
+            let poor_hauled = stats.i32("poor_hauled").ok().flatten().unwrap_or(0);
+            let poor_spawned = stats.i32("poor_spawned").ok().flatten().unwrap_or(0);
+            wind_down(remote, poor_hauled, poor_spawned);
+            stats.del("poor_windows");
+            stats.del("poor_hauled");
+            stats.del("poor_spawned");
+        }
+        stats.set("hauled", 0);
+        stats.set("spawned", 0);
+        stats.set("lost", 0);
+        stats.set("interruptions", 0);
+    }
+}
+
+/// Stops mining a remote which doesn't pay for itself, until the cooldown is over.
+fn wind_down(remote: RoomName, hauled: i32, spawned: i32) {
+    let until = screeps::game::time() + WIND_DOWN_COOLDOWN;
+    intel::mark_do_not_remote(remote, until);
+    spawning::cancel(|r| r.work_room == Some(remote));
+    let message = format!(
+        "winding remote {} down until tick {}: it hauled {} energy for {} spent on creeps over \
+         the last {} ticks, under {:.0}% of the cost",
+        remote,
+        until,
+        hauled,
+        spawned,
+        POOR_WINDOWS as u32 * REPORT_INTERVAL,
+        MIN_INCOME_RATIO * 100.0
+    );
+    warn!("{}", message);
+    events::notify(&message);
+}
+
+/// Lets a wound down remote be mined again, returning whether it was wound down.
+pub fn enable(remote: RoomName) -> bool {
+    intel::clear_do_not_remote(remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_carry_parts_go_to_as_few_haulers_as_fit() {
+        // two carry parts to each size of a hauler
+        assert_eq!(split_haulers(1, 2, 16), (1, 1));
+        assert_eq!(split_haulers(10, 2, 16), (1, 5));
+        assert_eq!(split_haulers(32, 2, 16), (1, 16));
+        // split evenly over the haulers rather than a full one and a small one
+        assert_eq!(split_haulers(34, 2, 16), (2, 9));
+        assert_eq!(split_haulers(10, 2, 2), (3, 2));
+    }
+
+    #[test]
+    fn keeper_sources_need_more_carry_parts() {
+        // 10 energy a tick over a 100 tick trip is 1000, in 20 carry parts
+        assert_eq!(carry_parts_for(SOURCE_ENERGY, 100), 20);
+        // a third more, rounded up
+        assert_eq!(carry_parts_for(KEEPER_SOURCE_ENERGY, 100), 27);
+        assert_eq!(carry_parts_for(SOURCE_ENERGY, 1), 1);
+        assert_eq!(carry_parts_for(SOURCE_ENERGY, 0), 0);
+    }
+}
@@ -0,0 +1,142 @@
+//! Pulling damaged defenders back to be healed.
+//!
+//! A defender which drops below [`RETREAT_SHARE`] of its hits stops fighting and falls back to a
+//! tile our towers heal at full power, in the room it's in if that's ours and has towers, or
+//! otherwise in its home room. Ramparts come first, and tiles next to a spawn are never used, so
+//! retreating creeps don't block freshly spawned ones in. It waits there, healing itself if it
+//! can while the towers heal it ahead of anyone else, and goes back in once it's healed up to
+//! [`RECOVER_SHARE`]. The gap between the two keeps a creep from flapping between fighting and
+//! retreating on the same few hits.
+//!
+//! Whether a creep is retreating is kept in its memory as `retreating`, so it survives a reset.
+
+use log::*;
+use screeps::{find, prelude::*, Attackable, Creep, Part, Position, Room, RoomName, StructureType};
+
+use crate::{context::TickContext, movement, structures::towers};
+
+const RETREATING_KEY: &str = "retreating";
+
+/// The share of its hits a creep retreats at.
+pub const RETREAT_SHARE: f64 = 0.4;
+
+/// The share of its hits a retreating creep goes back in at.
+pub const RECOVER_SHARE: f64 = 0.9;
+
+fn hits_share(creep: &Creep) -> f64 {
+    Attackable::hits(creep) as f64 / Attackable::hits_max(creep).max(1) as f64
+}
+
+/// Updates whether a creep is retreating from its hits, returning whether it is.
+pub fn update(creep: &Creep) -> bool {
+    let memory = creep.memory();
+    let share = hits_share(creep);
+    let retreating = memory.bool(RETREATING_KEY);
+    if !retreating && share < RETREAT_SHARE {
+        info!(
+            "{} is retreating at {:.0}% of its hits",
+            creep.name(),
+            share * 100.0
+        );
+        memory.set(RETREATING_KEY, true);
+        true
+    } else if retreating && share >= RECOVER_SHARE {
+        info!("{} is healed up and going back in", creep.name());
+        memory.del(RETREATING_KEY);
+        false
+    } else {
+        retreating
+    }
+}
+
+/// Whether a creep is falling back to be healed.
+pub fn is_retreating(creep: &Creep) -> bool {
+    creep.memory().bool(RETREATING_KEY)
+}
+
+fn has_towers(room: &Room, ctx: &TickContext) -> bool {
+    ctx.rooms
+        .snapshot(room)
+        .my_structures(StructureType::Tower)
+        .next()
+        .is_some()
+}
+
+/// Takes a retreating creep to a tile the towers of its room, or of `home`, heal it on.
+pub fn run(creep: &Creep, home: RoomName, ctx: &TickContext) {
+    if creep.get_active_bodyparts(Part::Heal) > 0 {
+        creep.heal(creep);
+    }
+    let room = creep
+        .room()
+        .filter(|room| room.controller().map_or(false, |c| c.my()) && has_towers(room, ctx));
+    let room = match room.or_else(|| screeps::game::rooms::get(home)) {
+        Some(room) => room,
+        None => {
+            movement::move_to_room(creep, home);
+            return;
+        }
+    };
+    match retreat_position(creep, &room, ctx) {
+        Some(pos) => {
+            movement::move_creep_to(creep, &pos, 0);
+        }
+        None => {
+            movement::move_to_room(creep, room.name());
+        }
+    }
+}
+
+/// The closest free tile in full tower range which isn't next to a spawn, on a rampart if any
+/// is.
+fn retreat_position(creep: &Creep, room: &Room, ctx: &TickContext) -> Option<Position> {
+    let snapshot = ctx.rooms.snapshot(room);
+    let towers: Vec<Position> = snapshot
+        .my_structures(StructureType::Tower)
+        .map(|s| s.pos())
+        .collect();
+    let spawns: Vec<Position> = snapshot
+        .my_structures(StructureType::Spawn)
+        .map(|s| s.pos())
+        .collect();
+    let taken: Vec<Position> = room
+        .find(find::CREEPS)
+        .iter()
+        .filter(|c| c.name() != creep.name())
+        .map(|c| c.pos())
+        .collect();
+    let usable = |pos: &Position| {
+        (1..49).contains(&pos.x())
+            && (1..49).contains(&pos.y())
+            && towers
+                .iter()
+                .any(|t| t.get_range_to(pos) <= towers::TOWER_OPTIMAL_RANGE)
+            && !spawns.iter().any(|s| s.is_near_to(pos))
+            && !taken.contains(pos)
+            && movement::costs::is_passable(*pos)
+    };
+    let here = creep.pos();
+    let closest = |tiles: Vec<Position>| {
+        tiles
+            .into_iter()
+            .filter(|pos| usable(pos))
+            .min_by_key(|pos| (here.get_range_to(pos), pos.x(), pos.y()))
+    };
+
+    let ramparts: Vec<Position> = snapshot
+        .my_structures(StructureType::Rampart)
+        .map(|s| s.pos())
+        .collect();
+    closest(ramparts).or_else(|| {
+        let range = towers::TOWER_OPTIMAL_RANGE;
+        let mut tiles = Vec::new();
+        for tower in &towers {
+            for y in tower.y().saturating_sub(range)..=(tower.y() + range).min(49) {
+                for x in tower.x().saturating_sub(range)..=(tower.x() + range).min(49) {
+                    tiles.push(Position::new(x, y, room.name()));
+                }
+            }
+        }
+        closest(tiles)
+    })
+}
@@ -0,0 +1,262 @@
+//! Random numbers which can be reproduced.
+//!
+//! The generator is seeded from `Memory.rng_seed` and the tick of the reset, both of which are
+//! logged, so a run can be replayed. The seed is made up on the very first run and can be
+//! replaced with `reseed(seed)` from the console. Nothing here needs to be seeded by callers.
+//!
+//! The functions here all draw from one shared [`Rng`]. The generator itself knows nothing of
+//! memory, so one can also be made from a fixed seed, as the tests do.
+
+use std::cell::Cell;
+
+use log::*;
+#[cfg(target_arch = "wasm32")]
+use stdweb::{js, unstable::TryInto};
+
+const SEED_KEY: &str = "rng_seed";
+
+/// A splitmix64 generator, apart from where its seed comes from.
+#[derive(Clone, Copy, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+thread_local! {
+    /// The generator, once it's been seeded.
+    static RNG: Cell<Option<Rng>> = Cell::new(None);
+}
+
+/// Mixes the bits of `x`, the output function of splitmix64.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// The next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// A random number below `n`, or 0 if `n` is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    /// A random number from 0 up to but not including 1.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// See [`jitter`].
+    pub fn jitter(&mut self, base: u32, spread: u32) -> u32 {
+        let offset = self.below(2 * spread as u64 + 1) as u32;
+        (base + offset).saturating_sub(spread)
+    }
+
+    /// See [`choose_weighted`].
+    pub fn choose_weighted<'a, T>(&mut self, choices: &'a [(T, f64)]) -> Option<&'a T> {
+        let usable = |weight: f64| weight.is_finite() && weight > 0.0;
+        let total: f64 = choices.iter().map(|&(_, w)| w).filter(|&w| usable(w)).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut pick = self.unit() * total;
+        let mut last = None;
+        for (choice, weight) in choices {
+            if !usable(*weight) {
+                continue;
+            }
+            if pick < *weight {
+                return Some(choice);
+            }
+            pick -= weight;
+            last = Some(choice);
+        }
+        // rounding may leave a sliver past the last weight
+        last
+    }
+
+    /// See [`shuffle`].
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u64 + 1) as usize);
+        }
+    }
+}
+
+/// The stored seed, made up and stored if there isn't one yet.
+#[cfg(target_arch = "wasm32")]
+fn base_seed() -> u64 {
+    let stored = screeps::memory::root().string(SEED_KEY).ok().flatten();
+    if let Some(seed) = stored.and_then(|s| s.parse().ok()) {
+        return seed;
+    }
+    let random = js! { return Math.random() };
+    let random: f64 = random.try_into().unwrap_or_default();
+    let seed = mix((random * (1u64 << 53) as f64) as u64);
+    // stored as a string, as memory only holds numbers as doubles
+    screeps::memory::root().set(SEED_KEY, seed.to_string());
+    seed
+}
+
+/// Seeds the generator from `seed` and the current tick.
+fn seed_rng(seed: u64) -> Rng {
+    let time = screeps::game::time();
+    info!("seeding the rng from seed {} at tick {}", seed, time);
+    let rng = Rng::new(mix(seed ^ mix(time as u64)));
+    RNG.with(|cell| cell.set(Some(rng)));
+    rng
+}
+
+/// Replaces the stored seed and seeds the generator from it, to reproduce a run.
+pub fn reseed(seed: u64) {
+    screeps::memory::root().set(SEED_KEY, seed.to_string());
+    seed_rng(seed);
+}
+
+/// The generator for the first use since the reset.
+#[cfg(target_arch = "wasm32")]
+fn first_rng() -> Rng {
+    seed_rng(base_seed())
+}
+
+/// Off the game, in the tests and benchmarks, there's no memory or tick to seed from.
+#[cfg(not(target_arch = "wasm32"))]
+fn first_rng() -> Rng {
+    Rng::new(0x5eed)
+}
+
+/// Runs `f` with the generator, seeding it first if this is the first use since the reset.
+fn with_rng<R>(f: impl FnOnce(&mut Rng) -> R) -> R {
+    let mut rng = RNG.with(Cell::get).unwrap_or_else(first_rng);
+    let result = f(&mut rng);
+    RNG.with(|cell| cell.set(Some(rng)));
+    result
+}
+
+/// A random number below `n`, or 0 if `n` is 0.
+pub fn below(n: u64) -> u64 {
+    with_rng(|rng| rng.below(n))
+}
+
+/// A random number within `spread` of `base`, so periodic jobs don't all land on the same tick.
+pub fn jitter(base: u32, spread: u32) -> u32 {
+    with_rng(|rng| rng.jitter(base, spread))
+}
+
+/// Picks one of `choices` with a chance in proportion to its weight. Weights which aren't
+/// positive are never picked, and `None` is returned if none are.
+pub fn choose_weighted<T>(choices: &[(T, f64)]) -> Option<&T> {
+    with_rng(|rng| rng.choose_weighted(choices))
+}
+
+/// Puts `items` in a random order.
+pub fn shuffle<T>(items: &mut [T]) {
+    with_rng(|rng| rng.shuffle(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 0x5eed;
+
+    #[test]
+    fn same_seed_gives_the_same_numbers() {
+        let mut a = Rng::new(SEED);
+        let mut b = Rng::new(SEED);
+        let mut c = Rng::new(SEED + 1);
+        let first: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..10).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..10).map(|_| c.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn below_stays_below() {
+        let mut rng = Rng::new(SEED);
+        assert!((0..1000).all(|_| rng.below(7) < 7));
+        assert!((0..100).all(|_| rng.below(0) == 0));
+    }
+
+    #[test]
+    fn jitter_stays_within_the_spread() {
+        let mut rng = Rng::new(SEED);
+        let mut seen = [0u32; 11];
+        for _ in 0..10_000 {
+            let value = rng.jitter(100, 5);
+            assert!((95..=105).contains(&value), "{}", value);
+            seen[(value - 95) as usize] += 1;
+        }
+        // every offset turns up about as often
+        assert!(seen.iter().all(|&n| n > 700 && n < 1100), "{:?}", seen);
+
+        assert_eq!(rng.jitter(100, 0), 100);
+        // jitter never goes below zero
+        assert!((0..100).all(|_| rng.jitter(2, 5) <= 7));
+    }
+
+    #[test]
+    fn weighted_choice_follows_the_weights() {
+        let mut rng = Rng::new(SEED);
+        let choices = [
+            ("rare", 1.0),
+            ("never", 0.0),
+            ("common", 3.0),
+            ("bad", f64::NAN),
+        ];
+        let mut rare = 0;
+        for _ in 0..10_000 {
+            match rng.choose_weighted(&choices) {
+                Some(&"rare") => rare += 1,
+                Some(&"common") => {}
+                other => panic!("picked {:?}", other),
+            }
+        }
+        assert!(rare > 2200 && rare < 2800, "{}", rare);
+    }
+
+    #[test]
+    fn weighted_choice_of_nothing_is_none() {
+        let mut rng = Rng::new(SEED);
+        let empty: [(u32, f64); 0] = [];
+        assert_eq!(rng.choose_weighted(&empty), None);
+        assert_eq!(rng.choose_weighted(&[(1, 0.0), (2, 0.0)]), None);
+        assert_eq!(rng.choose_weighted(&[(1, -1.0), (2, f64::INFINITY)]), None);
+        assert_eq!(rng.choose_weighted(&[(1, 0.0), (2, 0.5)]), Some(&2));
+    }
+
+    #[test]
+    fn shuffle_keeps_every_item() {
+        let mut rng = Rng::new(SEED);
+        let mut items: Vec<u32> = (0..20).collect();
+        rng.shuffle(&mut items);
+        assert_ne!(items, (0..20).collect::<Vec<_>>());
+        items.sort_unstable();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+
+        let mut empty: Vec<u32> = Vec::new();
+        rng.shuffle(&mut empty);
+        let mut one = vec![1];
+        rng.shuffle(&mut one);
+        assert_eq!(one, vec![1]);
+    }
+
+    #[test]
+    fn shuffle_puts_items_anywhere() {
+        let mut rng = Rng::new(SEED);
+        let mut firsts = [0u32; 4];
+        for _ in 0..4000 {
+            let mut items = [0, 1, 2, 3];
+            rng.shuffle(&mut items);
+            firsts[items[0]] += 1;
+        }
+        assert!(firsts.iter().all(|&n| n > 850 && n < 1150), "{:?}", firsts);
+    }
+}
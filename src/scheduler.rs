@@ -0,0 +1,261 @@
+//! Sheds non-critical work while the CPU bucket is low.
+//!
+//! Every job in the main loop is run through [`run`] with a [`Tier`] saying how much it matters.
+//! Critical work always runs; the other tiers are skipped while the bucket is below their
+//! threshold, so a drained bucket is refilled instead of ending in script timeouts. Work which
+//! panicked is skipped for a few ticks, see [`panics`], and right after a panic outside of any
+//! job only critical work runs, whatever the bucket.
+//!
+//! A watchdog also stops starting new work once the tick's CPU use nears the limit, since a tick
+//! which runs out of CPU loses all of its intents. Expensive work stops first and normal work
+//! next, while critical work always runs; the creep loop checks it between creeps. The limit is
+//! `Memory.config.cpu_watchdog` as a share of the CPU limit, 0.9 by default.
+//!
+//! `emergency_halt()` from the console stops everything but towers, spawning and memory cleanup
+//! until `emergency_resume()`, as a safe mode while debugging a runaway loop. `pause()` goes
+//! further and stops every intent, leaving only memory cleanup and stats, while structures are
+//! rearranged by hand. It lasts until `resume()`, or for a number of ticks with `pause_for()`.
+//! Creeps keep their targets while paused.
+//!
+//! A bucket which is full with nothing shed is turned into pixels, if
+//! `Memory.config.generate_pixels` is set.
+
+use std::cell::{Cell, RefCell};
+
+use log::*;
+use stdweb::{js, unstable::TryInto};
+
+use crate::{
+    context::TickContext,
+    features::{self, Feature},
+    panics,
+};
+
+/// How important a job is, which decides how much bucket it needs to run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    /// Creep actions, towers and spawning.
+    Critical,
+    /// Site placement, repair scans and stats.
+    Normal,
+    /// Room planning and anything else heavy which can wait.
+    Expensive,
+}
+
+impl Tier {
+    /// The bucket needed for work of this tier to run.
+    fn min_bucket(self) -> u32 {
+        match self {
+            Tier::Critical => 0,
+            Tier::Normal => 3000,
+            Tier::Expensive => 8000,
+        }
+    }
+
+    /// The highest tier allowed to run with `bucket` CPU left.
+    fn allowed(bucket: u32) -> Tier {
+        [Tier::Expensive, Tier::Normal]
+            .iter()
+            .copied()
+            .find(|tier| bucket > tier.min_bucket())
+            .unwrap_or(Tier::Critical)
+    }
+
+    /// The share of the watchdog limit after which work of this tier isn't started any more.
+    fn watchdog_share(self) -> f64 {
+        match self {
+            Tier::Critical => 1.0,
+            Tier::Normal => 0.9,
+            Tier::Expensive => 0.8,
+        }
+    }
+}
+
+/// The most CPU the bucket holds.
+const BUCKET_MAX: u32 = 10_000;
+
+/// Where the last finished tick is kept, to notice ticks which didn't finish.
+const LAST_TICK_PATH: &str = "stats.last_tick";
+
+const PIXELS_PATH: &str = "stats.pixels";
+
+pub const GENERATE_PIXELS_PATH: &str = "config.generate_pixels";
+
+pub const WATCHDOG_PATH: &str = "config.cpu_watchdog";
+
+const HALT_KEY: &str = "emergency_halt";
+const PAUSE_KEY: &str = "paused";
+const PAUSE_UNTIL_KEY: &str = "paused_until";
+
+/// The share of the CPU limit the watchdog allows by default.
+const DEFAULT_WATCHDOG_SHARE: f64 = 0.9;
+
+thread_local! {
+    static ALLOWED: Cell<Tier> = Cell::new(Tier::Expensive);
+    /// Whether any work was skipped this tick.
+    static SHED: Cell<bool> = Cell::new(false);
+    /// How much CPU may be used this tick before the watchdog stops new work.
+    static WATCHDOG_LIMIT: Cell<f64> = Cell::new(f64::INFINITY);
+    /// What the watchdog skipped this tick.
+    static WATCHDOG_SKIPPED: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    /// Whether the tick before this one didn't finish, most likely for running out of CPU.
+    static MISSED_TICK: Cell<bool> = Cell::new(false);
+}
+
+/// Checks the bucket and decides which tiers run this tick. Called once at the start of the loop.
+pub fn begin_tick(ctx: &TickContext) {
+    let bucket = ctx.bucket;
+    let recovering = panics::recently_panicked();
+    let allowed = if recovering {
+        Tier::Critical
+    } else {
+        Tier::allowed(bucket)
+    };
+    let previous = ALLOWED.with(|a| a.replace(allowed));
+    SHED.with(|s| s.set(false));
+
+    let share = ctx.config.watchdog_share.unwrap_or(DEFAULT_WATCHDOG_SHARE);
+    WATCHDOG_LIMIT.with(|l| l.set(ctx.cpu_limit as f64 * share));
+
+    let time = ctx.time;
+    let last_tick = screeps::memory::root()
+        .path_i32(LAST_TICK_PATH)
+        .ok()
+        .flatten();
+    let missed = last_tick.map_or(false, |last| last as u32 + 1 != time);
+    MISSED_TICK.with(|m| m.set(missed));
+    if missed {
+        warn!("ticks after {} didn't finish", last_tick.unwrap_or(0));
+    }
+
+    if allowed != previous {
+        match allowed {
+            _ if recovering => warn!("recovering from a panic, running only critical work"),
+            Tier::Expensive => info!("bucket at {}, running all work again", bucket),
+            Tier::Normal => info!("bucket at {}, shedding expensive work", bucket),
+            Tier::Critical => warn!("bucket at {}, shedding all but critical work", bucket),
+        }
+    }
+}
+
+/// Whether the bot was halted from the console, and should only run its bare essentials.
+pub fn is_halted() -> bool {
+    screeps::memory::root().bool(HALT_KEY)
+}
+
+/// Halts or resumes the bot.
+pub fn set_halted(halted: bool) {
+    if halted {
+        screeps::memory::root().set(HALT_KEY, true);
+    } else {
+        screeps::memory::root().del(HALT_KEY);
+    }
+}
+
+/// Whether the bot is paused, resuming it if a `pause_for()` ran out.
+pub fn is_paused() -> bool {
+    let root = screeps::memory::root();
+    if !root.bool(PAUSE_KEY) {
+        return false;
+    }
+    match root.f64(PAUSE_UNTIL_KEY).ok().flatten() {
+        Some(until) if screeps::game::time() as f64 >= until => {
+            info!("pause is over, resuming");
+            unpause();
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Pauses the bot, until the tick `until` if given.
+pub fn pause(until: Option<u32>) {
+    let root = screeps::memory::root();
+    root.set(PAUSE_KEY, true);
+    match until {
+        Some(until) => root.set(PAUSE_UNTIL_KEY, until),
+        None => root.del(PAUSE_UNTIL_KEY),
+    }
+}
+
+pub fn unpause() {
+    let root = screeps::memory::root();
+    root.del(PAUSE_KEY);
+    root.del(PAUSE_UNTIL_KEY);
+}
+
+/// Runs `f` as a profiled section if the bucket and the watchdog allow work of `tier`, and it
+/// isn't tripped from panicking or failing recently.
+pub fn run<R: panics::Outcome>(
+    ctx: &TickContext,
+    tier: Tier,
+    name: &'static str,
+    f: impl FnOnce() -> R,
+) -> Option<R> {
+    if tier > ALLOWED.with(Cell::get) {
+        debug!("skipping {} this tick", name);
+        SHED.with(|s| s.set(true));
+        return None;
+    }
+    if tier != Tier::Critical && near_limit(tier) {
+        skip(name.to_string());
+        return None;
+    }
+    panics::guard(name, || ctx.stats.time_section(name, f))
+}
+
+/// Whether the tick's CPU use is close enough to the limit that work of `tier` shouldn't be
+/// started.
+pub fn near_limit(tier: Tier) -> bool {
+    let limit = WATCHDOG_LIMIT.with(Cell::get) * tier.watchdog_share();
+    screeps::game::cpu::get_used() > limit
+}
+
+/// Records that the watchdog stopped `work` from running this tick.
+pub fn skip(work: String) {
+    SHED.with(|s| s.set(true));
+    WATCHDOG_SKIPPED.with(|s| s.borrow_mut().push(work));
+}
+
+/// Reports what the watchdog skipped and records that the tick finished. Called once at the end
+/// of the loop.
+pub fn end_tick(ctx: &TickContext) {
+    let skipped = WATCHDOG_SKIPPED.with(|s| std::mem::take(&mut *s.borrow_mut()));
+    if !skipped.is_empty() {
+        warn!(
+            "cpu at {:.1} of {}, skipped {}",
+            screeps::game::cpu::get_used(),
+            ctx.cpu_limit,
+            skipped.join(", ")
+        );
+    }
+    screeps::memory::root().path_set(LAST_TICK_PATH, ctx.time);
+}
+
+/// Spends a full bucket on a pixel, unless this tick had to shed work or followed one which
+/// didn't finish.
+pub fn generate_pixel(ctx: &TickContext) {
+    if ctx.bucket < BUCKET_MAX
+        || SHED.with(Cell::get)
+        || MISSED_TICK.with(Cell::get)
+        || !ctx.config.generate_pixels
+        || !features::has(Feature::Pixels)
+    {
+        return;
+    }
+
+    let result = js! {
+        return Game.cpu.generatePixel()
+    };
+    let result: Option<i32> = result.try_into().ok();
+    match result {
+        Some(0) => {
+            let memory = screeps::memory::root();
+            let pixels = memory.path_i32(PIXELS_PATH).ok().flatten().unwrap_or(0);
+            memory.path_set(PIXELS_PATH, pixels + 1);
+            info!("generated a pixel");
+        }
+        Some(code) => warn!("couldn't generate a pixel: error {}", code),
+        None => warn!("couldn't generate a pixel, it returned nothing"),
+    }
+}
@@ -0,0 +1,42 @@
+//! Memory segments, for data too big to keep in `Memory`, which is parsed again every tick.
+//!
+//! A segment can only be read the tick after it's been asked for, so the segments in
+//! [`ACTIVE`] are asked for on every tick. Right after a reset they can't be read yet, which
+//! [`load`] returns as `None`, unlike an empty segment. Whoever keeps data in one has to wait
+//! for it to load before writing, or it'd overwrite what's there.
+
+use screeps::raw_memory;
+
+/// The segment room intel is kept in.
+pub const INTEL: u32 = 0;
+
+/// The segment the decision journal is kept in.
+pub const JOURNAL: u32 = 1;
+
+const ACTIVE: &[u32] = &[INTEL, JOURNAL];
+
+/// How much a segment holds.
+pub const MAX_LENGTH: usize = 100 * 1024;
+
+/// A segment's contents, or `None` if it can't be read this tick.
+pub fn load(id: u32) -> Option<String> {
+    raw_memory::get_segment(id)
+}
+
+/// Replaces a segment's contents, which is saved at the end of the tick.
+pub fn save(id: u32, data: &str) -> Result<(), String> {
+    if data.len() > MAX_LENGTH {
+        return Err(format!(
+            "{} characters don't fit in segment {}",
+            data.len(),
+            id
+        ));
+    }
+    raw_memory::set_segment(id, data);
+    Ok(())
+}
+
+/// Asks for the segments to be readable on the next tick. Called once at the end of the loop.
+pub fn end_tick() {
+    raw_memory::set_active_segments(ACTIVE);
+}
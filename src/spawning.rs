@@ -0,0 +1,528 @@
+//! Spawning creeps.
+//!
+//! Every idle spawn with enough energy spawns a creep. Requests queued with `request_spawn()`
+//! from the console, by the remotes or for claiming and bootstrapping new rooms go first,
+//! highest priority first, and are kept in `Memory.spawn_queue` until they've been spawned so a
+//! reset doesn't lose them. Without requests, spawns keep making workers.
+//!
+//! Creeps are spawned with their role and the room they were spawned in in their memory, and the
+//! room they work in if that's another one.
+
+use std::collections::HashMap;
+
+use log::*;
+use screeps::{
+    memory::MemoryReference, prelude::*, Creep, Part, ReturnCode, RoomName, SpawnOptions,
+};
+
+use crate::{
+    context::TickContext, emergency, energy, failures, id, journal, remotes, state::RoomState,
+};
+
+const QUEUE_KEY: &str = "spawn_queue";
+
+const ROLE_KEY: &str = "role";
+const HOME_ROOM_KEY: &str = "home_room";
+const WORK_ROOM_KEY: &str = "work_room";
+
+/// The most parts a creep can have.
+const MAX_PARTS: usize = 50;
+
+/// The priority of console requests which don't give one.
+pub const DEFAULT_REQUEST_PRIORITY: u8 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Harvests, fills, builds, repairs and upgrades, whatever's needed most.
+    Worker,
+    /// Keeps the controller of a remote reserved.
+    Reserver,
+    /// Sits at a source in a remote, harvesting onto the ground.
+    RemoteMiner,
+    /// Carries what the remote miners harvested home.
+    Hauler,
+    /// Clears remotes of invaders.
+    Defender,
+    /// Kills the keepers of a source keeper remote as they spawn.
+    KeeperKiller,
+    /// Claims the controller of the room we're expanding into.
+    Claimer,
+    /// Works in a newly claimed room until it has a spawn of its own.
+    Pioneer,
+    /// Breaks power banks.
+    PowerAttacker,
+    /// Heals the power attackers, which power banks hit back.
+    PowerHealer,
+    /// Carries the power of a broken bank home.
+    PowerHauler,
+    /// Fights boosted hostiles with a healer at its side.
+    DuoAttacker,
+    /// Follows a duo attacker, keeping it alive.
+    DuoHealer,
+    /// Holds a rampart of its own room against melee attackers.
+    RampartDefender,
+    /// Tears down the structures in a room marked with an attack flag.
+    Dismantler,
+    /// Holds a rampart of its own room against a serious attack, boosted from the room's labs.
+    BoostedDefender,
+}
+
+impl Role {
+    pub const ALL: &'static [Role] = &[
+        Role::Worker,
+        Role::Reserver,
+        Role::RemoteMiner,
+        Role::Hauler,
+        Role::Defender,
+        Role::KeeperKiller,
+        Role::Claimer,
+        Role::Pioneer,
+        Role::PowerAttacker,
+        Role::PowerHealer,
+        Role::PowerHauler,
+        Role::DuoAttacker,
+        Role::DuoHealer,
+        Role::RampartDefender,
+        Role::Dismantler,
+        Role::BoostedDefender,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Worker => "worker",
+            Role::Reserver => "reserver",
+            Role::RemoteMiner => "remote_miner",
+            Role::Hauler => "hauler",
+            Role::Defender => "defender",
+            Role::KeeperKiller => "keeper_killer",
+            Role::Claimer => "claimer",
+            Role::Pioneer => "pioneer",
+            Role::PowerAttacker => "power_attacker",
+            Role::PowerHealer => "power_healer",
+            Role::PowerHauler => "power_hauler",
+            Role::DuoAttacker => "duo_attacker",
+            Role::DuoHealer => "duo_healer",
+            Role::RampartDefender => "rampart_defender",
+            Role::Dismantler => "dismantler",
+            Role::BoostedDefender => "boosted_defender",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.iter().copied().find(|role| role.name() == name)
+    }
+
+    /// What the names of creeps of this role start with, so the role can be told from the name.
+    pub fn short_code(self) -> &'static str {
+        match self {
+            Role::Worker => "W",
+            Role::Reserver => "R",
+            Role::RemoteMiner => "M",
+            Role::Hauler => "H",
+            Role::Defender => "D",
+            Role::KeeperKiller => "K",
+            Role::Claimer => "C",
+            Role::Pioneer => "P",
+            Role::PowerAttacker => "PA",
+            Role::PowerHealer => "PH",
+            Role::PowerHauler => "PC",
+            Role::DuoAttacker => "DA",
+            Role::DuoHealer => "DH",
+            Role::RampartDefender => "RD",
+            Role::Dismantler => "X",
+            Role::BoostedDefender => "BD",
+        }
+    }
+
+    /// The role a creep name starts with the code of, if it does.
+    pub fn from_creep_name(creep_name: &str) -> Option<Role> {
+        let code = id::role_code(creep_name)?;
+        Role::ALL
+            .iter()
+            .copied()
+            .find(|role| role.short_code() == code)
+    }
+
+    /// Whether creeps of this role need a room to work in other than their own.
+    pub fn is_remote(self) -> bool {
+        self != Role::Worker
+    }
+
+    pub fn body(self) -> &'static [Part] {
+        match self {
+            Role::Worker => &[Part::Move, Part::Move, Part::Carry, Part::Work],
+            Role::Reserver | Role::Claimer => &[Part::Claim, Part::Move],
+            // five work parts drain a source just as it regenerates
+            Role::RemoteMiner => &[
+                Part::Work,
+                Part::Work,
+                Part::Work,
+                Part::Work,
+                Part::Work,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+            ],
+            // tough parts go first, so they're what gets hit
+            Role::Defender => &[
+                Part::Tough,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Attack,
+                Part::Attack,
+                Part::Attack,
+                Part::Heal,
+            ],
+            // sized to what the home room affords, a keeper has 5000 hits and hits back hard
+            Role::KeeperKiller => &[
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Attack,
+                Part::Attack,
+                Part::Attack,
+                Part::Heal,
+            ],
+            Role::Pioneer => &[
+                Part::Work,
+                Part::Work,
+                Part::Carry,
+                Part::Carry,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+                Part::Move,
+            ],
+            // these are sized by whoever requests them, see `SpawnRequest::size`
+            Role::PowerAttacker | Role::DuoAttacker => &[Part::Move, Part::Attack],
+            Role::PowerHealer | Role::DuoHealer => &[Part::Move, Part::Heal],
+            Role::PowerHauler => &[Part::Carry, Part::Move],
+            // sized to the remote
+            Role::Hauler => &[Part::Carry, Part::Carry, Part::Move],
+            // sized to the room, it hardly moves once it's on its rampart
+            Role::RampartDefender => &[Part::Attack, Part::Attack, Part::Move],
+            // sized to the home room
+            Role::Dismantler => &[Part::Work, Part::Move],
+            // sized to the room, with a part for each compound it can be boosted with
+            Role::BoostedDefender => &[
+                Part::Tough,
+                Part::Attack,
+                Part::Attack,
+                Part::Attack,
+                Part::Move,
+                Part::Move,
+                Part::Heal,
+            ],
+        }
+    }
+
+    pub fn cost(self) -> u32 {
+        self.body().iter().map(|p| p.cost()).sum()
+    }
+
+    /// The body repeated `size` times, as far as it fits in a creep.
+    pub fn sized_body(self, size: u32) -> Vec<Part> {
+        let mut body = Vec::new();
+        for _ in 0..size.min(self.max_size()).max(1) {
+            body.extend_from_slice(self.body());
+        }
+        body
+    }
+
+    /// The biggest size of the body a creep can have.
+    pub fn max_size(self) -> u32 {
+        (MAX_PARTS / self.body().len()) as u32
+    }
+}
+
+/// The biggest size of a role's body a room can afford, and at least one.
+pub fn affordable_size(role: Role, room: &impl RoomState) -> u32 {
+    (room.energy_capacity() / role.cost())
+        .min(role.max_size())
+        .max(1)
+}
+
+/// A creep's role, read from its memory. Creeps from before roles were kept are workers.
+pub fn role_of(creep: &Creep) -> Role {
+    checked_role_of(creep).unwrap_or(Role::Worker)
+}
+
+/// A creep's role, or an error if its memory has one which isn't known. Creeps without any go by
+/// the role code their name starts with, and are workers if it has none.
+pub fn checked_role_of(creep: &Creep) -> Result<Role, String> {
+    match creep.memory().string(ROLE_KEY) {
+        Ok(Some(name)) => Role::from_name(&name).ok_or_else(|| format!("unknown role {}", name)),
+        Ok(None) => Ok(Role::from_creep_name(&creep.name()).unwrap_or(Role::Worker)),
+        Err(e) => Err(format!("unreadable role: {}", e)),
+    }
+}
+
+/// The room a creep was spawned in, read from its memory.
+pub fn home_room(creep: &Creep) -> Option<RoomName> {
+    let name = creep.memory().string(HOME_ROOM_KEY).ok()??;
+    RoomName::new(&name).ok()
+}
+
+/// The room a creep works in, read from its memory, if it isn't its home room.
+pub fn work_room(creep: &Creep) -> Option<RoomName> {
+    let name = creep.memory().string(WORK_ROOM_KEY).ok()??;
+    RoomName::new(&name).ok()
+}
+
+/// Sends a creep to work in another room, keeping its role.
+pub fn set_work_room(creep: &Creep, room_name: RoomName) {
+    creep.memory().set(WORK_ROOM_KEY, room_name.to_string());
+}
+
+/// Turns a creep into a worker of `home`, whatever it was doing before.
+pub fn make_worker(creep: &Creep, home: RoomName) {
+    let memory = creep.memory();
+    memory.set(ROLE_KEY, Role::Worker.name());
+    memory.set(HOME_ROOM_KEY, home.to_string());
+    memory.del(WORK_ROOM_KEY);
+}
+
+/// A creep queued to be spawned in a room.
+#[derive(Clone, Debug)]
+pub struct SpawnRequest {
+    pub room_name: RoomName,
+    pub role: Role,
+    pub priority: u8,
+    /// The room the creep is to work in, if it isn't the one it's spawned in.
+    pub work_room: Option<RoomName>,
+    /// How many times the role's body is repeated, for bigger creeps.
+    pub size: u32,
+}
+
+/// The queued requests, highest priority first.
+fn load_queue() -> Vec<SpawnRequest> {
+    let encoded = screeps::memory::root()
+        .string(QUEUE_KEY)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    encoded
+        .split(';')
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let mut fields = entry.split(',');
+            let request = SpawnRequest {
+                room_name: RoomName::new(fields.next()?).ok()?,
+                role: Role::from_name(fields.next()?)?,
+                priority: fields.next()?.parse().ok()?,
+                work_room: fields.next().and_then(|name| RoomName::new(name).ok()),
+                size: fields
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .unwrap_or(1),
+            };
+            Some(request)
+        })
+        .collect()
+}
+
+/// Stores the requests as `room,role,priority[,work_room[,size]];...`, with the work room
+/// left empty for creeps working where they're spawned.
+fn save_queue(queue: &[SpawnRequest]) {
+    if queue.is_empty() {
+        screeps::memory::root().del(QUEUE_KEY);
+        return;
+    }
+    let entries: Vec<String> = queue
+        .iter()
+        .map(|r| {
+            let mut entry = format!("{},{},{}", r.room_name, r.role.name(), r.priority);
+            if r.work_room.is_some() || r.size > 1 {
+                let work_room = r.work_room.map(|name| name.to_string());
+                entry = format!("{},{}", entry, work_room.unwrap_or_default());
+            }
+            if r.size > 1 {
+                entry = format!("{},{}", entry, r.size);
+            }
+            entry
+        })
+        .collect();
+    screeps::memory::root().set(QUEUE_KEY, entries.join(";"));
+}
+
+/// Queues a creep, behind the requests of the same or a higher priority.
+pub fn request(request: SpawnRequest) {
+    let mut queue = load_queue();
+    let index = queue
+        .iter()
+        .position(|r| r.priority < request.priority)
+        .unwrap_or_else(|| queue.len());
+    let inputs = (
+        request.room_name.to_string(),
+        request.role.name(),
+        request.priority,
+        request.size,
+        queue.len(),
+    );
+    journal::record("spawning", inputs, || {
+        format!(
+            "queued a {} for room {} at {} of {}",
+            request.role.name(),
+            request.room_name,
+            index + 1,
+            queue.len() + 1
+        )
+    });
+    queue.insert(index, request);
+    save_queue(&queue);
+}
+
+pub fn queue() -> Vec<SpawnRequest> {
+    load_queue()
+}
+
+/// Drops the queued requests matching `f`.
+pub fn cancel(f: impl Fn(&SpawnRequest) -> bool) {
+    let mut queue = load_queue();
+    let before = queue.len();
+    queue.retain(|r| !f(r));
+    if queue.len() != before {
+        save_queue(&queue);
+    }
+}
+
+pub fn run(ctx: &TickContext) {
+    let mut queue = load_queue();
+    let mut changed = false;
+    // the room's energy only goes down at the end of the tick, so what's spent is counted here
+    let mut spent: HashMap<RoomName, u32> = HashMap::new();
+
+    for spawn in screeps::game::spawns::values() {
+        debug!("running spawn {}", spawn.name());
+        // busy spawns are skipped, so they don't use up names
+        if spawn.spawning().is_some() {
+            continue;
+        }
+        let room_name = spawn.pos().room_name();
+        if emergency::is_abandoned(room_name) {
+            continue;
+        }
+        let room = match spawn.room() {
+            Some(room) => room,
+            None => continue,
+        };
+        let requested = queue.iter().position(|r| r.room_name == room_name);
+        let (role, work_room, size) = requested.map_or((Role::Worker, None, 1), |index| {
+            let request = &queue[index];
+            (request.role, request.work_room, request.size)
+        });
+        let body = role.sized_body(size);
+        let cost: u32 = body.iter().map(|p| p.cost()).sum();
+        // a request the room can never afford would hold up the room forever
+        if cost > ctx.rooms.snapshot(&room).energy_capacity() {
+            if let Some(index) = requested {
+                warn!(
+                    "dropping the request for a {} in room {}, which can't afford its {} energy",
+                    role.name(),
+                    room_name,
+                    cost
+                );
+                queue.remove(index);
+                changed = true;
+            }
+            continue;
+        }
+        let room_spent = spent.entry(room_name).or_default();
+        if room.energy_available() < *room_spent + cost {
+            continue;
+        }
+
+        let memory = MemoryReference::new();
+        memory.set(ROLE_KEY, role.name());
+        memory.set(HOME_ROOM_KEY, room_name.to_string());
+        if let Some(work_room) = work_room {
+            memory.set(WORK_ROOM_KEY, work_room.to_string());
+        }
+        let options = SpawnOptions::new().memory(memory);
+
+        // a name can still be taken by a creep spawned earlier this tick, which isn't in
+        // Game.creeps yet, so another kind of name is tried then
+        let code = role.short_code();
+        let mut res = spawn.spawn_creep_with_options(&body, &id::creep_name(code), &options);
+        if res == ReturnCode::NameExists {
+            if let Some(name) = id::random_creep_name(code) {
+                res = spawn.spawn_creep_with_options(&body, &name, &options);
+            }
+        }
+
+        if res != ReturnCode::Ok {
+            failures::report(&spawn.name(), "spawn", res);
+            continue;
+        }
+        *room_spent += cost;
+        energy::record_spawn(room_name, cost);
+        if let Some(work_room) = work_room {
+            remotes::record_spawn(work_room, cost);
+        }
+        if let Some(index) = requested {
+            info!("spawning requested {} in room {}", role.name(), room_name);
+            queue.remove(index);
+            changed = true;
+        }
+    }
+
+    if changed {
+        save_queue(&queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fake::FakeRoom;
+
+    #[test]
+    fn bodies_repeat_up_to_the_part_limit() {
+        assert_eq!(Role::Worker.sized_body(0), Role::Worker.body());
+        assert_eq!(Role::Worker.sized_body(3).len(), 12);
+        assert_eq!(&Role::Worker.sized_body(3)[8..], Role::Worker.body());
+        assert_eq!(Role::Hauler.max_size(), 16);
+        assert_eq!(Role::Hauler.sized_body(40).len(), 48);
+        for &role in Role::ALL {
+            assert!(role.sized_body(u32::MAX).len() <= MAX_PARTS);
+        }
+    }
+
+    #[test]
+    fn sizes_are_what_the_room_can_afford() {
+        let room = |energy_capacity| FakeRoom {
+            energy_capacity,
+            ..FakeRoom::default()
+        };
+        // a hauler's carry, carry, move costs 150
+        assert_eq!(affordable_size(Role::Hauler, &room(0)), 1);
+        assert_eq!(affordable_size(Role::Hauler, &room(300)), 2);
+        assert_eq!(affordable_size(Role::Hauler, &room(449)), 2);
+        assert_eq!(affordable_size(Role::Hauler, &room(12_900)), 16);
+    }
+
+    #[test]
+    fn roles_survive_a_round_trip_through_their_names() {
+        for &role in Role::ALL {
+            assert_eq!(Role::from_name(role.name()), Some(role));
+        }
+        assert_eq!(Role::from_name("builder"), None);
+    }
+
+    #[test]
+    fn roles_are_told_from_creep_names() {
+        for &role in Role::ALL {
+            let code = role.short_code();
+            assert!(!code.is_empty() && code.len() <= id::MAX_ROLE_CODE_LENGTH);
+            let name = format!("{}-BraveOtter42", code);
+            assert_eq!(Role::from_creep_name(&name), Some(role));
+        }
+        assert_eq!(Role::from_creep_name("BraveOtter42"), None);
+        assert_eq!(Role::from_creep_name("c-1Z"), None);
+        assert_eq!(Role::from_creep_name("Q-BraveOtter42"), None);
+    }
+}
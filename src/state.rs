@@ -0,0 +1,482 @@
+//! What the decisions read of the game, behind traits.
+//!
+//! Picking targets, sizing creeps and aiming towers only need a few numbers of the game objects
+//! involved. They read them through the traits here, which the game's own types implement, so
+//! the same decisions also run on the plain structs of [`fake`] in the tests and benchmarks,
+//! without a game to call into. The method names stay clear of those of the game's traits, so
+//! both can be in scope at once.
+
+use screeps::{
+    prelude::*, Attackable, ConstructionSite, Creep, ObjectId, Part, ResourceType, Source,
+    Structure, StructureController, StructureType,
+};
+
+use crate::cache::RoomSnapshot;
+
+/// A creep of ours, as far as picking its target goes.
+pub trait CreepState: HasPosition {
+    /// How much energy it carries.
+    fn store_used(&self) -> u32;
+    /// How much more energy it can carry.
+    fn store_free(&self) -> u32;
+
+    fn pos_range_to(&self, target: &impl HasPosition) -> u32 {
+        self.pos().get_range_to(target)
+    }
+}
+
+pub trait StructureState: HasPosition {
+    fn structure_id(&self) -> ObjectId<Structure>;
+    fn structure_kind(&self) -> StructureType;
+    /// Whether it's owned, and by us.
+    fn is_mine(&self) -> bool;
+    /// Its hits and the most it can have, or none at all if it can't be attacked.
+    fn health(&self) -> (u32, u32);
+    /// How much energy it holds.
+    fn stored_energy(&self) -> u32;
+    /// How much more energy it has room for.
+    fn energy_room(&self) -> u32;
+}
+
+pub trait SourceState: HasPosition {
+    fn source_id(&self) -> ObjectId<Source>;
+    fn energy_left(&self) -> u32;
+    fn energy_max(&self) -> u32;
+    /// How many ticks until it's full again.
+    fn regenerates_in(&self) -> u32;
+}
+
+pub trait SiteState: HasPosition {
+    fn site_id(&self) -> ObjectId<ConstructionSite>;
+}
+
+/// Another player's creep, as far as aiming at it goes.
+pub trait HostileState: HasPosition {
+    fn hits_left(&self) -> u32;
+    /// The parts it has which aren't broken, with their boosts.
+    fn active_parts(&self) -> Vec<(Part, Option<ResourceType>)>;
+}
+
+/// What's in a room this tick.
+pub trait RoomState {
+    type Structure: StructureState;
+    type Source: SourceState;
+    type Site: SiteState;
+    type Hostile: HostileState;
+
+    /// The structures of one type, whoever owns them.
+    fn structures(&self, structure_type: StructureType) -> &[Self::Structure];
+    /// Every structure in the room, whoever owns them.
+    fn every_structure(&self) -> Box<dyn Iterator<Item = &Self::Structure> + '_>;
+    fn sources(&self) -> &[Self::Source];
+    /// Our construction sites.
+    fn construction_sites(&self) -> &[Self::Site];
+    /// Other players' creeps, except for our allies'.
+    fn hostiles(&self) -> &[Self::Hostile];
+    /// The room's controller, if it's ours.
+    fn my_controller(&self) -> Option<ObjectId<StructureController>>;
+    /// The most energy the room's spawns and extensions hold.
+    fn energy_capacity(&self) -> u32;
+
+    /// The structures of one type which are ours.
+    fn my_structures(&self, structure_type: StructureType) -> Vec<&Self::Structure> {
+        self.structures(structure_type)
+            .iter()
+            .filter(|s| s.is_mine())
+            .collect()
+    }
+}
+
+impl CreepState for Creep {
+    fn store_used(&self) -> u32 {
+        self.store_used_capacity(Some(ResourceType::Energy))
+    }
+
+    fn store_free(&self) -> u32 {
+        self.store_free_capacity(Some(ResourceType::Energy)).max(0) as u32
+    }
+}
+
+impl StructureState for Structure {
+    fn structure_id(&self) -> ObjectId<Structure> {
+        self.id()
+    }
+
+    fn structure_kind(&self) -> StructureType {
+        self.structure_type()
+    }
+
+    fn is_mine(&self) -> bool {
+        self.as_owned().map_or(false, |o| o.my())
+    }
+
+    fn health(&self) -> (u32, u32) {
+        self.as_attackable()
+            .map_or((0, 0), |a| (a.hits(), a.hits_max()))
+    }
+
+    fn stored_energy(&self) -> u32 {
+        let energy = Some(ResourceType::Energy);
+        match self {
+            Structure::Container(s) => s.store_used_capacity(energy),
+            Structure::Extension(s) => s.store_used_capacity(energy),
+            Structure::Link(s) => s.store_used_capacity(energy),
+            Structure::Spawn(s) => s.store_used_capacity(energy),
+            Structure::Storage(s) => s.store_used_capacity(energy),
+            Structure::Terminal(s) => s.store_used_capacity(energy),
+            Structure::Tower(s) => s.store_used_capacity(energy),
+            _ => 0,
+        }
+    }
+
+    fn energy_room(&self) -> u32 {
+        let energy = Some(ResourceType::Energy);
+        let free = match self {
+            Structure::Container(s) => s.store_free_capacity(energy),
+            Structure::Extension(s) => s.store_free_capacity(energy),
+            Structure::Link(s) => s.store_free_capacity(energy),
+            Structure::Spawn(s) => s.store_free_capacity(energy),
+            Structure::Storage(s) => s.store_free_capacity(energy),
+            Structure::Terminal(s) => s.store_free_capacity(energy),
+            Structure::Tower(s) => s.store_free_capacity(energy),
+            _ => 0,
+        };
+        free.max(0) as u32
+    }
+}
+
+impl SourceState for Source {
+    fn source_id(&self) -> ObjectId<Source> {
+        self.id()
+    }
+
+    fn energy_left(&self) -> u32 {
+        self.energy()
+    }
+
+    fn energy_max(&self) -> u32 {
+        self.energy_capacity()
+    }
+
+    fn regenerates_in(&self) -> u32 {
+        self.ticks_to_regeneration()
+    }
+}
+
+impl SiteState for ConstructionSite {
+    fn site_id(&self) -> ObjectId<ConstructionSite> {
+        self.id()
+    }
+}
+
+impl HostileState for Creep {
+    fn hits_left(&self) -> u32 {
+        Attackable::hits(self)
+    }
+
+    fn active_parts(&self) -> Vec<(Part, Option<ResourceType>)> {
+        self.body()
+            .into_iter()
+            .filter(|part| part.hits > 0)
+            .map(|part| (part.part, part.boost))
+            .collect()
+    }
+}
+
+impl RoomState for RoomSnapshot {
+    type Structure = Structure;
+    type Source = Source;
+    type Site = ConstructionSite;
+    type Hostile = Creep;
+
+    fn structures(&self, structure_type: StructureType) -> &[Structure] {
+        RoomSnapshot::structures(self, structure_type)
+    }
+
+    fn every_structure(&self) -> Box<dyn Iterator<Item = &Structure> + '_> {
+        Box::new(self.all_structures())
+    }
+
+    fn sources(&self) -> &[Source] {
+        RoomSnapshot::sources(self)
+    }
+
+    fn construction_sites(&self) -> &[ConstructionSite] {
+        RoomSnapshot::construction_sites(self)
+    }
+
+    fn hostiles(&self) -> &[Creep] {
+        RoomSnapshot::hostiles(self)
+    }
+
+    fn my_controller(&self) -> Option<ObjectId<StructureController>> {
+        RoomSnapshot::my_controller(self)
+    }
+
+    fn energy_capacity(&self) -> u32 {
+        RoomSnapshot::energy_capacity(self)
+    }
+}
+
+/// Plain structs standing in for the game's objects, for the tests and, with the `fakes`
+/// feature, the benchmarks.
+#[cfg(any(test, feature = "fakes"))]
+pub mod fake {
+    use std::collections::HashMap;
+
+    use screeps::{Position, RawObjectId, RoomName};
+
+    use super::*;
+
+    /// An id made up from a number, which is all the fakes need to tell objects apart.
+    pub fn id<T>(n: u32) -> ObjectId<T> {
+        RawObjectId::from_packed([0, 0, n]).into()
+    }
+
+    /// A tile of the room the fakes are in.
+    pub fn at(x: u32, y: u32) -> Position {
+        Position::new(x, y, RoomName::new("W1N1").unwrap())
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FakeCreep {
+        pub pos: Position,
+        pub energy: u32,
+        pub capacity: u32,
+    }
+
+    impl FakeCreep {
+        pub fn empty(x: u32, y: u32) -> FakeCreep {
+            FakeCreep {
+                pos: at(x, y),
+                energy: 0,
+                capacity: 50,
+            }
+        }
+
+        pub fn full(x: u32, y: u32) -> FakeCreep {
+            FakeCreep {
+                energy: 50,
+                ..FakeCreep::empty(x, y)
+            }
+        }
+    }
+
+    impl HasPosition for FakeCreep {
+        fn pos(&self) -> Position {
+            self.pos
+        }
+    }
+
+    impl CreepState for FakeCreep {
+        fn store_used(&self) -> u32 {
+            self.energy
+        }
+
+        fn store_free(&self) -> u32 {
+            self.capacity - self.energy
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FakeStructure {
+        pub id: u32,
+        pub kind: StructureType,
+        pub pos: Position,
+        pub mine: bool,
+        pub hits: u32,
+        pub hits_max: u32,
+        pub energy: u32,
+        pub energy_room: u32,
+    }
+
+    impl FakeStructure {
+        /// A structure of ours at full hits, without any energy.
+        pub fn new(id: u32, kind: StructureType, x: u32, y: u32) -> FakeStructure {
+            FakeStructure {
+                id,
+                kind,
+                pos: at(x, y),
+                mine: true,
+                hits: 1000,
+                hits_max: 1000,
+                energy: 0,
+                energy_room: 0,
+            }
+        }
+    }
+
+    impl HasPosition for FakeStructure {
+        fn pos(&self) -> Position {
+            self.pos
+        }
+    }
+
+    impl StructureState for FakeStructure {
+        fn structure_id(&self) -> ObjectId<Structure> {
+            id(self.id)
+        }
+
+        fn structure_kind(&self) -> StructureType {
+            self.kind
+        }
+
+        fn is_mine(&self) -> bool {
+            self.mine
+        }
+
+        fn health(&self) -> (u32, u32) {
+            (self.hits, self.hits_max)
+        }
+
+        fn stored_energy(&self) -> u32 {
+            self.energy
+        }
+
+        fn energy_room(&self) -> u32 {
+            self.energy_room
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FakeSource {
+        pub id: u32,
+        pub pos: Position,
+        pub energy: u32,
+        pub regenerates_in: u32,
+    }
+
+    impl FakeSource {
+        pub fn full(id: u32, x: u32, y: u32) -> FakeSource {
+            FakeSource {
+                id,
+                pos: at(x, y),
+                energy: 3000,
+                regenerates_in: 300,
+            }
+        }
+    }
+
+    impl HasPosition for FakeSource {
+        fn pos(&self) -> Position {
+            self.pos
+        }
+    }
+
+    impl SourceState for FakeSource {
+        fn source_id(&self) -> ObjectId<Source> {
+            id(self.id)
+        }
+
+        fn energy_left(&self) -> u32 {
+            self.energy
+        }
+
+        fn energy_max(&self) -> u32 {
+            3000
+        }
+
+        fn regenerates_in(&self) -> u32 {
+            self.regenerates_in
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FakeSite {
+        pub id: u32,
+        pub pos: Position,
+    }
+
+    impl HasPosition for FakeSite {
+        fn pos(&self) -> Position {
+            self.pos
+        }
+    }
+
+    impl SiteState for FakeSite {
+        fn site_id(&self) -> ObjectId<ConstructionSite> {
+            id(self.id)
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FakeHostile {
+        pub pos: Position,
+        pub hits: u32,
+        pub parts: Vec<(Part, Option<ResourceType>)>,
+    }
+
+    impl HasPosition for FakeHostile {
+        fn pos(&self) -> Position {
+            self.pos
+        }
+    }
+
+    impl HostileState for FakeHostile {
+        fn hits_left(&self) -> u32 {
+            self.hits
+        }
+
+        fn active_parts(&self) -> Vec<(Part, Option<ResourceType>)> {
+            self.parts.clone()
+        }
+    }
+
+    /// A room made up of whatever a test puts in it.
+    #[derive(Debug, Default)]
+    pub struct FakeRoom {
+        pub structures: HashMap<StructureType, Vec<FakeStructure>>,
+        pub sources: Vec<FakeSource>,
+        pub sites: Vec<FakeSite>,
+        pub hostiles: Vec<FakeHostile>,
+        pub controller: Option<u32>,
+        pub energy_capacity: u32,
+    }
+
+    impl FakeRoom {
+        pub fn with(mut self, structure: FakeStructure) -> FakeRoom {
+            self.structures
+                .entry(structure.kind)
+                .or_insert_with(Vec::new)
+                .push(structure);
+            self
+        }
+    }
+
+    impl RoomState for FakeRoom {
+        type Structure = FakeStructure;
+        type Source = FakeSource;
+        type Site = FakeSite;
+        type Hostile = FakeHostile;
+
+        fn structures(&self, structure_type: StructureType) -> &[FakeStructure] {
+            self.structures
+                .get(&structure_type)
+                .map_or(&[], |structures| structures.as_slice())
+        }
+
+        fn every_structure(&self) -> Box<dyn Iterator<Item = &FakeStructure> + '_> {
+            Box::new(self.structures.values().flatten())
+        }
+
+        fn sources(&self) -> &[FakeSource] {
+            &self.sources
+        }
+
+        fn construction_sites(&self) -> &[FakeSite] {
+            &self.sites
+        }
+
+        fn hostiles(&self) -> &[FakeHostile] {
+            &self.hostiles
+        }
+
+        fn my_controller(&self) -> Option<ObjectId<StructureController>> {
+            self.controller.map(id)
+        }
+
+        fn energy_capacity(&self) -> u32 {
+            self.energy_capacity
+        }
+    }
+}
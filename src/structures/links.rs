@@ -0,0 +1,134 @@
+//! Link behaviour.
+//!
+//! The hub link next to the storage is where the energy of a room's other links goes, like ones
+//! built at sources or at the room's edge for remote haulers. A link sends once it's at least
+//! half full and off cooldown, so the energy lost to each transfer is paid on big loads. Creeps
+//! empty the hub link on their way to fill the spawns, before they take from the storage.
+
+use screeps::{prelude::*, ObjectId, ReturnCode, Structure, StructureLink, StructureType};
+
+use crate::{
+    context::TickContext,
+    failures,
+    state::{RoomState, StructureState},
+};
+
+/// How far from the storage the hub link is.
+const HUB_RANGE: u32 = 2;
+
+/// How much energy a link has to hold before it sends it, half of what it holds at most.
+const SEND_THRESHOLD: u32 = 400;
+
+/// The link next to the room's storage, if it has both.
+pub fn hub<R: RoomState>(room: &R) -> Option<&R::Structure> {
+    let storage = room
+        .my_structures(StructureType::Storage)
+        .into_iter()
+        .next()?;
+    room.my_structures(StructureType::Link)
+        .into_iter()
+        .filter(|link| link.pos().get_range_to(storage) <= HUB_RANGE)
+        .min_by_key(|link| link.pos().get_range_to(storage))
+}
+
+/// The links which send their energy to the hub link this tick, if they aren't on cooldown,
+/// with the hub link. They're sent as long as the hub link has room for any of it.
+pub fn transfers<R: RoomState>(
+    room: &R,
+) -> Option<(ObjectId<Structure>, Vec<ObjectId<Structure>>)> {
+    let hub = hub(room)?;
+    let mut room_left = hub.energy_room();
+    let mut senders = Vec::new();
+    for link in room.my_structures(StructureType::Link) {
+        if room_left == 0 {
+            break;
+        }
+        if link.structure_id() == hub.structure_id() || link.stored_energy() < SEND_THRESHOLD {
+            continue;
+        }
+        room_left = room_left.saturating_sub(link.stored_energy());
+        senders.push(link.structure_id());
+    }
+    Some((hub.structure_id(), senders))
+}
+
+pub fn run(ctx: &TickContext) {
+    for room in screeps::game::rooms::values() {
+        let snapshot = ctx.rooms.snapshot(&room);
+        let (hub, senders) = match transfers(&*snapshot) {
+            Some((hub, senders)) if !senders.is_empty() => (hub, senders),
+            _ => continue,
+        };
+        let hub = match link(hub) {
+            Some(hub) => hub,
+            None => continue,
+        };
+        for sender in senders.into_iter().filter_map(link) {
+            if sender.cooldown() > 0 {
+                continue;
+            }
+            let r = sender.transfer_energy(&hub, None);
+            if r != ReturnCode::Ok {
+                failures::report(&sender.id().to_string(), "transfer", r);
+            }
+        }
+    }
+}
+
+fn link(id: ObjectId<Structure>) -> Option<StructureLink> {
+    match id.resolve()? {
+        Structure::Link(link) => Some(link),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fake::{id, FakeRoom, FakeStructure};
+
+    fn link(n: u32, x: u32, y: u32, energy: u32) -> FakeStructure {
+        let mut link = FakeStructure::new(n, StructureType::Link, x, y);
+        link.energy = energy;
+        link.energy_room = 800 - energy;
+        link
+    }
+
+    fn room() -> FakeRoom {
+        FakeRoom::default().with(FakeStructure::new(1, StructureType::Storage, 25, 25))
+    }
+
+    #[test]
+    fn the_hub_is_the_link_next_to_the_storage() {
+        let room = room().with(link(2, 10, 10, 0)).with(link(3, 26, 25, 0));
+        assert_eq!(hub(&room).map(|l| l.id), Some(3));
+        // without a storage there's no hub
+        let room = FakeRoom::default().with(link(3, 26, 25, 0));
+        assert!(hub(&room).is_none());
+        assert_eq!(transfers(&room), None);
+    }
+
+    #[test]
+    fn links_send_to_the_hub_once_half_full() {
+        let room = room()
+            .with(link(2, 26, 26, 0))
+            .with(link(3, 5, 5, 500))
+            .with(link(4, 45, 45, 399))
+            .with(link(5, 5, 45, 400));
+        assert_eq!(transfers(&room), Some((id(2), vec![id(3), id(5)])));
+    }
+
+    #[test]
+    fn links_only_send_while_the_hub_has_room() {
+        let room = room()
+            .with(link(2, 26, 26, 700))
+            .with(link(3, 5, 5, 800))
+            .with(link(4, 45, 45, 800));
+        assert_eq!(transfers(&room), Some((id(2), vec![id(3)])));
+
+        let room = self::room()
+            .with(link(2, 26, 26, 800))
+            .with(link(3, 5, 5, 800));
+        assert_eq!(transfers(&room), Some((id(2), Vec::new())));
+    }
+}
@@ -0,0 +1,4 @@
+//! Structures which act on their own each tick, without a creep working them.
+
+pub mod links;
+pub mod towers;
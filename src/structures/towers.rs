@@ -0,0 +1,368 @@
+//! Tower behaviour.
+//!
+//! Towers only fire in rooms the threat assessment found hostiles in, and all the towers of a
+//! room fire at the same target. What each hostile would take from all of them at their ranges
+//! is weighed against what its own and its friends' heal parts would undo, boosts included, and
+//! the target is the one losing the most hits per tick. If no hostile loses any, the healers
+//! would only undo the damage, so the towers hold fire to save energy unless a hostile comes
+//! within [`POINT_BLANK_RANGE`] of one of them.
+//!
+//! Towers heal our creeps too. Creeps retreating to be healed come first, each healed by the
+//! closest tower, though while there are hostiles one tower is always left to fire at them. The
+//! towers which have nothing worth firing at heal whoever is most hurt, retreating creeps first.
+
+use screeps::{
+    find, prelude::*, Attackable, Creep, Part, ResourceType, ReturnCode, Structure, StructureTower,
+    StructureType,
+};
+
+use crate::{
+    context::TickContext,
+    failures, retreat,
+    state::{HostileState, RoomState},
+    threat::{self, Level},
+};
+
+/// Towers do full damage, and heal at full power, up to this range,
+pub const TOWER_OPTIMAL_RANGE: u32 = 5;
+/// and falloff damage from this range on.
+const TOWER_FALLOFF_RANGE: u32 = 20;
+const TOWER_MAX_DAMAGE: u32 = 600;
+const TOWER_MIN_DAMAGE: u32 = 150;
+
+/// The energy a tower needs for one shot.
+const TOWER_ENERGY_COST: u32 = 10;
+
+const HEAL_POWER: u32 = 12;
+const RANGED_HEAL_POWER: u32 = 4;
+const RANGED_HEAL_RANGE: u32 = 3;
+
+/// Towers always fire at hostiles this close to one of them.
+pub const POINT_BLANK_RANGE: u32 = 3;
+
+/// A hostile as far as targeting goes.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub x: u32,
+    pub y: u32,
+    pub hits: u32,
+    /// What its heal parts heal per tick at close range, with boosts.
+    pub heal_power: u32,
+}
+
+impl Target {
+    pub fn of(hostile: &impl HostileState) -> Target {
+        let pos = hostile.pos();
+        Target {
+            x: pos.x(),
+            y: pos.y(),
+            hits: hostile.hits_left(),
+            heal_power: hostile
+                .active_parts()
+                .into_iter()
+                .filter(|&(part, _)| part == Part::Heal)
+                .map(|(_, boost)| HEAL_POWER * heal_multiplier(boost))
+                .sum(),
+        }
+    }
+}
+
+fn range((ax, ay): (u32, u32), (bx, by): (u32, u32)) -> u32 {
+    let dx = (ax as i32 - bx as i32).abs();
+    let dy = (ay as i32 - by as i32).abs();
+    dx.max(dy) as u32
+}
+
+/// The damage a tower does at a range.
+pub fn tower_damage(range: u32) -> u32 {
+    if range <= TOWER_OPTIMAL_RANGE {
+        TOWER_MAX_DAMAGE
+    } else if range >= TOWER_FALLOFF_RANGE {
+        TOWER_MIN_DAMAGE
+    } else {
+        TOWER_MAX_DAMAGE
+            - (TOWER_MAX_DAMAGE - TOWER_MIN_DAMAGE) * (range - TOWER_OPTIMAL_RANGE)
+                / (TOWER_FALLOFF_RANGE - TOWER_OPTIMAL_RANGE)
+    }
+}
+
+/// What the hostiles could heal one of them per tick.
+fn incoming_heal(targets: &[Target], target: &Target) -> u32 {
+    targets
+        .iter()
+        .map(
+            |healer| match range((healer.x, healer.y), (target.x, target.y)) {
+                r if r <= 1 => healer.heal_power,
+                r if r <= RANGED_HEAL_RANGE => healer.heal_power * RANGED_HEAL_POWER / HEAL_POWER,
+                _ => 0,
+            },
+        )
+        .sum()
+}
+
+/// Picks the target all of the towers fire at, by its index in `targets`, or `None` to hold
+/// fire.
+pub fn choose_target(towers: &[(u32, u32)], targets: &[Target]) -> Option<usize> {
+    if towers.is_empty() {
+        return None;
+    }
+    let best = targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| {
+            let damage: u32 = towers
+                .iter()
+                .map(|&tower| tower_damage(range(tower, (target.x, target.y))))
+                .sum();
+            let net = damage as i64 - incoming_heal(targets, target) as i64;
+            (i, net, target.hits)
+        })
+        .max_by_key(|&(i, net, hits)| (net, std::cmp::Reverse(hits), std::cmp::Reverse(i)));
+    match best {
+        Some((i, net, _)) if net > 0 => Some(i),
+        _ => targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| {
+                let closest = towers
+                    .iter()
+                    .map(|&tower| range(tower, (target.x, target.y)))
+                    .min()
+                    .unwrap_or(u32::MAX);
+                (closest, i)
+            })
+            .filter(|&(closest, _)| closest <= POINT_BLANK_RANGE)
+            .min()
+            .map(|(_, i)| i),
+    }
+}
+
+/// Picks the hostile of a room the towers on `towers` fire at, by its index in the room's
+/// hostiles, or `None` to hold fire.
+pub fn aim(room: &impl RoomState, towers: &[(u32, u32)]) -> Option<usize> {
+    let targets: Vec<Target> = room.hostiles().iter().map(Target::of).collect();
+    choose_target(towers, &targets)
+}
+
+/// How much a heal part heals with a boost.
+fn heal_multiplier(boost: Option<ResourceType>) -> u32 {
+    match boost {
+        Some(ResourceType::LemergiumOxide) => 2,
+        Some(ResourceType::LemergiumAlkalide) => 3,
+        Some(ResourceType::CatalyzedLemergiumAlkalide) => 4,
+        _ => 1,
+    }
+}
+
+/// A creep's hits in percent of its maximum.
+fn hits_percent(creep: &Creep) -> u32 {
+    Attackable::hits(creep) * 100 / Attackable::hits_max(creep).max(1)
+}
+
+pub fn run(ctx: &TickContext) {
+    for room in screeps::game::rooms::values() {
+        let level = threat::level(room.name());
+        let snapshot = ctx.rooms.snapshot(&room);
+        let mut towers: Vec<StructureTower> = snapshot
+            .my_structures(StructureType::Tower)
+            .filter_map(|s| match s {
+                Structure::Tower(tower)
+                    if tower.store_used_capacity(Some(ResourceType::Energy))
+                        >= TOWER_ENERGY_COST =>
+                {
+                    Some(tower.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        if towers.is_empty() {
+            continue;
+        }
+        let mut wounded: Vec<Creep> = room
+            .find(find::MY_CREEPS)
+            .into_iter()
+            .filter(|c| Attackable::hits(c) < Attackable::hits_max(c))
+            .collect();
+        wounded.sort_by_key(|c| (!retreat::is_retreating(c), hits_percent(c)));
+        if level == Level::None && wounded.is_empty() {
+            continue;
+        }
+
+        // retreating creeps are healed first, by the closest tower each, though one is always
+        // kept for the hostiles
+        let keep = if level == Level::None { 0 } else { 1 };
+        for creep in wounded.iter().filter(|c| retreat::is_retreating(c)) {
+            if towers.len() <= keep {
+                break;
+            }
+            let closest = towers
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, tower)| tower.pos().get_range_to(creep))
+                .map(|(i, _)| i);
+            if let Some(i) = closest {
+                heal(&towers.remove(i), creep);
+            }
+        }
+
+        let tower_tiles: Vec<(u32, u32)> = towers
+            .iter()
+            .map(|tower| (tower.pos().x(), tower.pos().y()))
+            .collect();
+        match aim(&*snapshot, &tower_tiles) {
+            Some(i) => {
+                for tower in towers {
+                    let r = tower.attack(&snapshot.hostiles()[i]);
+                    if r != ReturnCode::Ok {
+                        failures::report(&tower.id().to_string(), "attack", r);
+                    }
+                }
+            }
+            // towers with nothing worth shooting heal instead
+            None => {
+                if let Some(creep) = wounded.first() {
+                    for tower in towers {
+                        heal(&tower, creep);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn heal(tower: &StructureTower, creep: &Creep) {
+    let r = tower.heal(creep);
+    if r != ReturnCode::Ok {
+        failures::report(&tower.id().to_string(), "heal", r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fake::{at, FakeHostile, FakeRoom};
+
+    fn hostile(x: u32, y: u32, heal_parts: u32) -> Target {
+        Target {
+            x,
+            y,
+            hits: 2000,
+            heal_power: heal_parts * HEAL_POWER,
+        }
+    }
+
+    #[test]
+    fn damage_falls_off_between_the_ranges() {
+        assert_eq!(tower_damage(0), 600);
+        assert_eq!(tower_damage(5), 600);
+        assert_eq!(tower_damage(10), 450);
+        assert_eq!(tower_damage(20), 150);
+        assert_eq!(tower_damage(45), 150);
+    }
+
+    #[test]
+    fn no_towers_or_no_hostiles_holds_fire() {
+        assert_eq!(choose_target(&[], &[hostile(25, 25, 0)]), None);
+        assert_eq!(choose_target(&[(25, 25)], &[]), None);
+    }
+
+    #[test]
+    fn lone_attacker_is_shot() {
+        assert_eq!(choose_target(&[(25, 25)], &[hostile(40, 40, 0)]), Some(0));
+    }
+
+    #[test]
+    fn healer_out_of_reach_of_its_friend_is_shot_first() {
+        // an attacker far from a healer which can only heal itself
+        let targets = [hostile(10, 10, 0), hostile(40, 40, 10)];
+        assert_eq!(choose_target(&[(12, 12)], &targets), Some(0));
+    }
+
+    #[test]
+    fn healer_pair_which_outheals_the_towers_is_left_alone() {
+        // two healers next to each other, healing themselves and each other, which one tower
+        // across the room can't keep up with
+        let targets = [hostile(2, 2, 20), hostile(3, 2, 20)];
+        assert_eq!(choose_target(&[(30, 30)], &targets), None);
+    }
+
+    #[test]
+    fn healer_pair_at_point_blank_is_shot_anyway() {
+        let targets = [hostile(2, 2, 50), hostile(5, 2, 50)];
+        assert_eq!(choose_target(&[(7, 4)], &targets), Some(1));
+    }
+
+    #[test]
+    fn more_towers_break_through_the_healing() {
+        let targets = [hostile(10, 10, 40), hostile(11, 10, 40)];
+        // all of them within full damage range, but out of point blank range
+        let towers = [(15, 15), (15, 5), (5, 15), (5, 5), (6, 14)];
+        assert_eq!(choose_target(&towers[..1], &targets), None);
+        assert!(choose_target(&towers, &targets).is_some());
+    }
+
+    #[test]
+    fn attacker_ahead_of_its_healers_is_focused() {
+        // two healers healing each other, and an attacker three tiles ahead which only one of
+        // them reaches, from range
+        let targets = [hostile(20, 20, 10), hostile(21, 20, 10), hostile(24, 20, 0)];
+        assert_eq!(choose_target(&[(30, 30)], &targets), Some(2));
+    }
+
+    #[test]
+    fn ties_go_to_the_weakest() {
+        let mut weak = hostile(30, 30, 0);
+        weak.hits = 100;
+        let targets = [hostile(30, 30, 0), weak];
+        assert_eq!(choose_target(&[(25, 25)], &targets), Some(1));
+    }
+
+    #[test]
+    fn boosts_multiply_heal_parts() {
+        assert_eq!(heal_multiplier(None), 1);
+        assert_eq!(heal_multiplier(Some(ResourceType::LemergiumOxide)), 2);
+        assert_eq!(
+            heal_multiplier(Some(ResourceType::CatalyzedLemergiumAlkalide)),
+            4
+        );
+        assert_eq!(heal_multiplier(Some(ResourceType::UtriumHydride)), 1);
+    }
+
+    #[test]
+    fn targets_count_only_unbroken_heal_parts_with_their_boosts() {
+        let hostile = FakeHostile {
+            pos: at(12, 30),
+            hits: 1500,
+            parts: vec![
+                (Part::Heal, None),
+                (Part::Heal, Some(ResourceType::LemergiumOxide)),
+                (Part::Attack, Some(ResourceType::UtriumHydride)),
+                (Part::Move, None),
+            ],
+        };
+        let target = Target::of(&hostile);
+        assert_eq!((target.x, target.y, target.hits), (12, 30, 1500));
+        assert_eq!(target.heal_power, HEAL_POWER * 3);
+    }
+
+    #[test]
+    fn towers_aim_at_the_hostiles_of_a_room() {
+        let healer = |x| FakeHostile {
+            pos: at(x, 10),
+            hits: 2000,
+            parts: vec![(Part::Heal, None); 20],
+        };
+        let attacker = FakeHostile {
+            pos: at(30, 30),
+            hits: 2000,
+            parts: vec![(Part::Attack, None); 10],
+        };
+        let mut room = FakeRoom::default();
+        assert_eq!(aim(&room, &[(25, 25)]), None);
+        room.hostiles = vec![healer(2), healer(3), attacker];
+        assert_eq!(aim(&room, &[(25, 25)]), Some(2));
+        // with the attacker gone, the healers out-heal the one tower across the room
+        room.hostiles.pop();
+        assert_eq!(aim(&room, &[(40, 40)]), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Cooperative scheduling of long-running jobs.
+//!
+//! Jobs which are too heavy for a single tick, like planning a room, are written as a [`Task`]
+//! doing a bit of the work per step. Queued tasks are stepped in priority order until this
+//! tick's budget is spent, and pick up where they left off on the next tick.
+//!
+//! Game objects don't survive past the tick they were fetched in, so tasks keep names and
+//! positions around and look objects up again in every step.
+
+use std::cell::RefCell;
+
+use log::*;
+
+use crate::{context::TickContext, heap::CacheSize};
+
+/// The share of the CPU limit tasks may use in a tick.
+const BUDGET_SHARE: f64 = 0.2;
+
+/// How much bucket one CPU of task budget needs, so a draining bucket slows tasks down.
+const BUCKET_PER_CPU: f64 = 500.0;
+
+/// The most steps run in one tick, whatever the budget, in case the CPU counter doesn't move.
+const MAX_STEPS_PER_TICK: u32 = 1000;
+
+/// The most steps one task may take. Tasks which need more are assumed to be stuck in a loop and
+/// dropped.
+const MAX_TASK_STEPS: u32 = 10_000;
+
+/// A job split into steps.
+pub trait Task {
+    /// Does the next bit of work, returning whether there's more to do.
+    fn step(&mut self) -> bool;
+}
+
+struct QueuedTask {
+    name: String,
+    priority: u8,
+    task: Box<dyn Task>,
+    steps: u32,
+}
+
+thread_local! {
+    /// Queued tasks, highest priority first.
+    static TASKS: RefCell<Vec<QueuedTask>> = RefCell::new(Vec::new());
+}
+
+/// Queues a task, unless one with the same name is already queued. Tasks with a higher
+/// `priority` run first, and ties run in the order they were queued.
+pub fn spawn(name: String, priority: u8, task: impl Task + 'static) {
+    TASKS.with(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        if tasks.iter().any(|t| t.name == name) {
+            return;
+        }
+        debug!("queued task {}", name);
+        let index = tasks
+            .iter()
+            .position(|t| t.priority < priority)
+            .unwrap_or_else(|| tasks.len());
+        tasks.insert(
+            index,
+            QueuedTask {
+                name,
+                priority,
+                task: Box::new(task),
+                steps: 0,
+            },
+        );
+    });
+}
+
+pub fn is_queued(name: &str) -> bool {
+    TASKS.with(|tasks| tasks.borrow().iter().any(|t| t.name == name))
+}
+
+/// Steps queued tasks until this tick's budget is spent.
+pub fn run(ctx: &TickContext) {
+    let limit = ctx.cpu_limit as f64 * BUDGET_SHARE;
+    let allowance = ctx.bucket as f64 / BUCKET_PER_CPU;
+    let deadline = screeps::game::cpu::get_used() + limit.min(allowance);
+
+    let mut steps = 0;
+    while screeps::game::cpu::get_used() < deadline {
+        if steps == MAX_STEPS_PER_TICK {
+            error!("ran {} task steps this tick, stopping", MAX_STEPS_PER_TICK);
+            break;
+        }
+        // the task is taken out of the queue while it runs, so it may queue others
+        let next = TASKS.with(|tasks| {
+            let mut tasks = tasks.borrow_mut();
+            if tasks.is_empty() {
+                None
+            } else {
+                Some(tasks.remove(0))
+            }
+        });
+        let mut current = match next {
+            Some(current) => current,
+            None => break,
+        };
+
+        steps += 1;
+        current.steps += 1;
+        if !current.task.step() {
+            debug!("finished task {}", current.name);
+            continue;
+        }
+        if current.steps == MAX_TASK_STEPS {
+            error!(
+                "task {} ran for {} steps without finishing, dropping it",
+                current.name, MAX_TASK_STEPS
+            );
+            continue;
+        }
+        // an unfinished task goes back ahead of the others of its priority, so it's finished
+        // before the next one starts
+        TASKS.with(|tasks| {
+            let mut tasks = tasks.borrow_mut();
+            let index = tasks
+                .iter()
+                .position(|t| t.priority <= current.priority)
+                .unwrap_or_else(|| tasks.len());
+            tasks.insert(index, current);
+        });
+    }
+
+    let queued = TASKS.with(|tasks| tasks.borrow().len());
+    ctx.stats.count("tasks.queued", queued as f64);
+    ctx.stats.count("tasks.steps", steps as f64);
+}
+
+/// Sizes of the task queue, for the heap report. What each task holds isn't counted.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    let tasks = TASKS.with(|tasks| tasks.borrow().len());
+    vec![CacheSize {
+        name: "tasks",
+        entries: tasks,
+        bytes: tasks * std::mem::size_of::<QueuedTask>(),
+    }]
+}
+
+/// Drops every queued task. Whoever queued them queues them again when they're still needed.
+pub fn purge() {
+    TASKS.with(|tasks| std::mem::take(&mut *tasks.borrow_mut()));
+}
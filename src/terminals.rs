@@ -0,0 +1,162 @@
+//! Consolidating minerals and other resources in one hub room, so labs have them in one place.
+//!
+//! With `Memory.config.mineral_hub` set to one of our rooms with a terminal, every
+//! [`CONSOLIDATE_INTERVAL`] ticks the terminal of each other room of ours sends the hub the one
+//! resource it has the most of above [`LOCAL_RESERVE`], energy aside. Terminals which are cooling
+//! down are skipped until the next run, and sends are cut down to what the terminal's energy
+//! covers the fee of. The hub's free capacity is checked before every send and counted down as
+//! they're made, keeping [`HUB_MARGIN`] free, so nothing is sent to a hub which is nearly full.
+//!
+//! Each run logs a single report of what was sent and what was skipped.
+
+use log::*;
+use screeps::{prelude::*, ResourceType, ReturnCode, Room, RoomName, StructureTerminal};
+
+use crate::failures;
+
+const HUB_PATH: &str = "config.mineral_hub";
+
+/// How often resources are sent to the hub.
+pub const CONSOLIDATE_INTERVAL: u32 = 200;
+
+/// How much of each resource a room keeps for itself.
+pub const LOCAL_RESERVE: u32 = 1000;
+
+/// How much room the hub's terminal keeps free.
+pub const HUB_MARGIN: u32 = 20_000;
+
+/// Sends smaller than this aren't worth the cooldown.
+const MIN_SEND: u32 = 100;
+
+/// Distance over which the energy fee of a send grows, as the game works it out.
+const FEE_DISTANCE: f64 = 30.0;
+
+/// The room resources are consolidated in, if one is configured.
+pub fn hub() -> Option<RoomName> {
+    let name = screeps::memory::root().path_string(HUB_PATH).ok()??;
+    match RoomName::new(&name) {
+        Ok(room_name) => Some(room_name),
+        Err(_) => {
+            warn!("{} isn't a room name: {}", HUB_PATH, name);
+            None
+        }
+    }
+}
+
+/// The energy a terminal pays to send `amount` of a resource `distance` rooms.
+fn fee(amount: u32, distance: u32) -> u32 {
+    (amount as f64 * (1.0 - (-(distance as f64) / FEE_DISTANCE).exp())).ceil() as u32
+}
+
+/// The most that can be sent `distance` rooms with `energy` to pay for it.
+fn affordable(energy: u32, distance: u32) -> u32 {
+    let share = 1.0 - (-(distance as f64) / FEE_DISTANCE).exp();
+    if share <= 0.0 {
+        return u32::MAX;
+    }
+    let mut amount = (energy as f64 / share) as u32;
+    // rounding the fee up can take it over
+    while amount > 0 && fee(amount, distance) > energy {
+        amount -= 1;
+    }
+    amount
+}
+
+fn owned_terminal(room: &Room) -> Option<StructureTerminal> {
+    room.controller().filter(|c| c.my())?;
+    room.terminal().filter(|terminal| terminal.my())
+}
+
+/// The resource a terminal has the most of over the reserve, and how much over it is.
+fn largest_excess(terminal: &StructureTerminal) -> Option<(ResourceType, u32)> {
+    terminal
+        .store_types()
+        .into_iter()
+        .filter(|&resource| resource != ResourceType::Energy)
+        .map(|resource| {
+            let excess = terminal.store_of(resource).saturating_sub(LOCAL_RESERVE);
+            (resource, excess)
+        })
+        .filter(|&(_, excess)| excess >= MIN_SEND)
+        .max_by_key(|&(_, excess)| excess)
+}
+
+/// Sends each room's largest excess to the hub, and logs what went.
+pub fn consolidate() {
+    let hub = match hub() {
+        Some(hub) => hub,
+        None => return,
+    };
+    let hub_terminal = match screeps::game::rooms::get(hub).and_then(|room| owned_terminal(&room)) {
+        Some(terminal) => terminal,
+        None => {
+            warn!("mineral hub {} isn't a room of ours with a terminal", hub);
+            return;
+        }
+    };
+    let mut free =
+        (hub_terminal.store_free_capacity(None).max(0) as u32).saturating_sub(HUB_MARGIN);
+
+    let mut rooms: Vec<Room> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.name() != hub)
+        .collect();
+    rooms.sort_by_key(|room| room.name().to_string());
+
+    let mut sent = Vec::new();
+    let mut skipped = Vec::new();
+    for room in rooms {
+        let terminal = match owned_terminal(&room) {
+            Some(terminal) => terminal,
+            None => continue,
+        };
+        let (resource, excess) = match largest_excess(&terminal) {
+            Some(excess) => excess,
+            None => continue,
+        };
+        if terminal.cooldown() > 0 {
+            skipped.push(format!("{} (cooling down)", room.name()));
+            continue;
+        }
+        if free < MIN_SEND {
+            skipped.push(format!("{} (hub full)", room.name()));
+            continue;
+        }
+        let distance = screeps::game::map::get_room_linear_distance(room.name(), hub, true);
+        let energy = terminal.store_of(ResourceType::Energy);
+        let amount = excess.min(free).min(affordable(energy, distance));
+        if amount < MIN_SEND {
+            skipped.push(format!("{} (no energy for the fee)", room.name()));
+            continue;
+        }
+        let r = terminal.send(resource, amount, hub, Some("consolidation"));
+        if r != ReturnCode::Ok {
+            failures::report(&room.name().to_string(), "send", r);
+            continue;
+        }
+        free -= amount;
+        sent.push(format!(
+            "{} sent {} {:?} ({} energy fee)",
+            room.name(),
+            amount,
+            resource,
+            fee(amount, distance)
+        ));
+    }
+
+    if sent.is_empty() && skipped.is_empty() {
+        return;
+    }
+    info!(
+        "consolidating in {}: {}{}{}, {} free",
+        hub,
+        if sent.is_empty() {
+            "nothing sent".to_string()
+        } else {
+            sent.join(", ")
+        },
+        if skipped.is_empty() { "" } else { "; skipped " },
+        skipped.join(", "),
+        free
+    );
+}
@@ -0,0 +1,331 @@
+//! How dangerous the hostiles in our rooms and remotes are.
+//!
+//! Every tick each visible room we own or mine is assessed from the hostile creeps in it: their
+//! attack, ranged attack, heal and work parts, whether any of them are boosted, whether they
+//! belong to a player or to the game, and how close the armed ones are to the structures a room
+//! can't do without. That comes down to a [`Level`], which the towers, the remote defenders and
+//! automatic safe mode all go by. Source keepers, which are always there, aren't counted.
+//!
+//! The last assessment of each room is kept in `Memory.rooms.<name>.threat`, so rooms which
+//! aren't visible right now are judged by it, along with the tick the current attack started.
+//! An attack starting and ending is logged, and other players' creeps are recorded by
+//! [`attackers`].
+
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{prelude::*, Part, Room, RoomName, StructureType};
+
+use crate::{
+    attackers, context::TickContext, emergency, events, heap::CacheSize, intel, journal, planner,
+    remotes,
+};
+
+const THREAT_KEY: &str = "threat";
+
+/// Owners of the creeps the game itself spawns.
+pub const NPC_OWNERS: &[&str] = &["Invader", "Source Keeper", "Screeps"];
+
+/// NPC hostiles with more fighting parts than this are more than a nuisance.
+const LOW_MAX_PARTS: u32 = 10;
+
+/// Armed player creeps this close to a critical structure make for a critical threat.
+const CRITICAL_RANGE: u32 = 5;
+
+/// The structures a room can't do without.
+const CRITICAL_STRUCTURES: &[StructureType] = &[
+    StructureType::Spawn,
+    StructureType::Tower,
+    StructureType::Storage,
+    StructureType::Terminal,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// No hostiles.
+    None,
+    /// Unarmed creeps or a few weak invaders, which the towers or a defender deal with.
+    Low,
+    /// Armed players, or invaders in numbers.
+    Medium,
+    /// Boosted players, or armed ones near the structures we can't lose.
+    Critical,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::None => "none",
+            Level::Low => "low",
+            Level::Medium => "medium",
+            Level::Critical => "critical",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Level> {
+        [Level::None, Level::Low, Level::Medium, Level::Critical]
+            .iter()
+            .copied()
+            .find(|level| level.name() == name)
+    }
+}
+
+/// What the hostiles in a room amount to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assessment {
+    pub level: Level,
+    pub attack: u32,
+    pub ranged: u32,
+    pub heal: u32,
+    /// Work parts, which dismantle structures.
+    pub dismantle: u32,
+    pub boosted: bool,
+    /// Some of the hostiles belong to a player rather than the game.
+    pub player: bool,
+    /// Armed hostiles were within [`CRITICAL_RANGE`] of a critical structure.
+    pub near_critical: bool,
+    /// The tick the level rose above [`Level::None`], if it's above it.
+    pub since: Option<u32>,
+    /// The tick the room was assessed.
+    pub time: u32,
+}
+
+impl Assessment {
+    /// The attack, ranged attack and heal parts, which defenders have to match.
+    pub fn fighting_parts(&self) -> u32 {
+        self.attack + self.ranged + self.heal
+    }
+
+    /// Stores an assessment as `level,attack,ranged,heal,dismantle,boosted,player,near,since,time`.
+    fn encode(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.level.name(),
+            self.attack,
+            self.ranged,
+            self.heal,
+            self.dismantle,
+            self.boosted as u8,
+            self.player as u8,
+            self.near_critical as u8,
+            self.since.map(|t| t.to_string()).unwrap_or_default(),
+            self.time
+        )
+    }
+
+    fn decode(encoded: &str) -> Option<Assessment> {
+        let mut fields = encoded.split(',');
+        Some(Assessment {
+            level: Level::from_name(fields.next()?)?,
+            attack: fields.next()?.parse().ok()?,
+            ranged: fields.next()?.parse().ok()?,
+            heal: fields.next()?.parse().ok()?,
+            dismantle: fields.next()?.parse().ok()?,
+            boosted: fields.next()? == "1",
+            player: fields.next()? == "1",
+            near_critical: fields.next()? == "1",
+            since: fields.next()?.parse().ok(),
+            time: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+thread_local! {
+    /// This tick's assessments.
+    static ASSESSMENTS: RefCell<HashMap<RoomName, Assessment>> = RefCell::new(HashMap::new());
+}
+
+/// Assesses a room from the hostiles in it, carrying over when the attack started from `last`.
+fn assess(room: &Room, last: Option<&Assessment>, ctx: &TickContext) -> Assessment {
+    let time = ctx.time;
+    let snapshot = ctx.rooms.snapshot(room);
+    let critical: Vec<_> = CRITICAL_STRUCTURES
+        .iter()
+        .flat_map(|&ty| snapshot.my_structures(ty))
+        .map(|s| s.pos())
+        .collect();
+
+    let mut assessment = Assessment {
+        level: Level::None,
+        attack: 0,
+        ranged: 0,
+        heal: 0,
+        dismantle: 0,
+        boosted: false,
+        player: false,
+        near_critical: false,
+        since: None,
+        time,
+    };
+    let mut any = false;
+    for hostile in snapshot.hostiles() {
+        let owner = hostile.owner_name();
+        if owner == intel::SOURCE_KEEPER_OWNER {
+            continue;
+        }
+        any = true;
+        let attack = hostile.get_active_bodyparts(Part::Attack);
+        let ranged = hostile.get_active_bodyparts(Part::RangedAttack);
+        let dismantle = hostile.get_active_bodyparts(Part::Work);
+        assessment.attack += attack;
+        assessment.ranged += ranged;
+        assessment.heal += hostile.get_active_bodyparts(Part::Heal);
+        assessment.dismantle += dismantle;
+        assessment.boosted |= hostile.body().iter().any(|part| part.boost.is_some());
+        assessment.player |= !NPC_OWNERS.contains(&owner.as_str());
+        let armed = attack + ranged + dismantle > 0;
+        let pos = hostile.pos();
+        assessment.near_critical |= armed
+            && critical
+                .iter()
+                .any(|c| pos.get_range_to(c) <= CRITICAL_RANGE);
+    }
+
+    let armed_player = assessment.player && assessment.fighting_parts() + assessment.dismantle > 0;
+    assessment.level = if !any {
+        Level::None
+    } else if assessment.player && (assessment.boosted || assessment.near_critical) {
+        Level::Critical
+    } else if armed_player || assessment.fighting_parts() > LOW_MAX_PARTS {
+        Level::Medium
+    } else {
+        Level::Low
+    };
+    if assessment.level > Level::None {
+        assessment.since = last.and_then(|last| last.since).or(Some(time));
+    }
+    assessment
+}
+
+/// Assesses every visible room we own or mine, keeping the assessments, and notifying about and
+/// triggering safe mode in owned rooms which turn critical.
+pub fn run(ctx: &TickContext) {
+    let mut rooms: Vec<Room> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .collect();
+    for (remote, _) in remotes::remotes() {
+        if let Some(room) = screeps::game::rooms::get(remote) {
+            rooms.push(room);
+        }
+    }
+
+    let mut assessments = HashMap::new();
+    for room in rooms {
+        let room_name = room.name();
+        let memory = match planner::room_memory(room_name) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        let stored = memory.string(THREAT_KEY).ok().flatten();
+        let last = stored.as_deref().and_then(Assessment::decode);
+        let assessment = assess(&room, last.as_ref(), ctx);
+        attackers::record(&room, ctx);
+        let last_level = last.as_ref().map_or(Level::None, |last| last.level);
+        log_change(&room, last.as_ref(), &assessment);
+        let owned = room.controller().map_or(false, |c| c.my());
+        if owned && assessment.level == Level::Critical && last_level < Level::Critical {
+            events::notify(&format!(
+                "critical threat in room {}: {} attack, {} ranged, {} heal and {} work parts{}",
+                room_name,
+                assessment.attack,
+                assessment.ranged,
+                assessment.heal,
+                assessment.dismantle,
+                if assessment.boosted { ", boosted" } else { "" }
+            ));
+            let safe_mode = emergency::activate_safe_mode(&room, "against a critical threat");
+            journal::record("threat", assessment.encode(), || match &safe_mode {
+                Ok(()) => format!("safe mode in room {}", room_name),
+                Err(e) => format!("no safe mode in room {}: {}", room_name, e),
+            });
+            if let Err(e) = safe_mode {
+                warn!("couldn't go into safe mode against the attack: {}", e);
+            }
+        }
+
+        // the tick changes every time, so it's only stored along with something else
+        let changed = last.as_ref().map_or(true, |last| {
+            let mut last = last.clone();
+            last.time = assessment.time;
+            last != assessment
+        });
+        if assessment.level == Level::None && last.is_none() {
+            // nothing to remember
+        } else if assessment.level == Level::None {
+            memory.del(THREAT_KEY);
+        } else if changed {
+            memory.set(THREAT_KEY, assessment.encode());
+        }
+        assessments.insert(room_name, assessment);
+    }
+    ASSESSMENTS.with(|a| *a.borrow_mut() = assessments);
+}
+
+fn log_change(room: &Room, last: Option<&Assessment>, assessment: &Assessment) {
+    let last_level = last.map_or(Level::None, |last| last.level);
+    if assessment.level == last_level {
+        return;
+    }
+    journal::record("threat", assessment.encode(), || {
+        format!(
+            "room {} went from {} to {}",
+            room.name(),
+            last_level.name(),
+            assessment.level.name()
+        )
+    });
+    if last_level == Level::None {
+        warn!(
+            "attack started in room {} at tick {}: {} threat, {} attack, {} ranged, {} heal, {} \
+             work parts{}",
+            room.name(),
+            assessment.time,
+            assessment.level.name(),
+            assessment.attack,
+            assessment.ranged,
+            assessment.heal,
+            assessment.dismantle,
+            if assessment.boosted { ", boosted" } else { "" }
+        );
+    } else if assessment.level == Level::None {
+        let started = last.and_then(|last| last.since).unwrap_or(assessment.time);
+        info!(
+            "attack in room {} ended after {} ticks",
+            room.name(),
+            assessment.time.saturating_sub(started)
+        );
+    } else {
+        info!(
+            "threat in room {} went from {} to {}",
+            room.name(),
+            last_level.name(),
+            assessment.level.name()
+        );
+    }
+}
+
+/// The latest assessment of a room: this tick's if it was visible, otherwise the last one kept.
+/// Rooms with nothing kept have never had hostiles while we were looking.
+pub fn get(room_name: RoomName) -> Option<Assessment> {
+    if let Some(assessment) = ASSESSMENTS.with(|a| a.borrow().get(&room_name).cloned()) {
+        return Some(assessment);
+    }
+    let encoded = planner::room_memory(room_name)?.string(THREAT_KEY).ok()??;
+    Assessment::decode(&encoded)
+}
+
+/// The level of the latest assessment of a room.
+pub fn level(room_name: RoomName) -> Level {
+    get(room_name).map_or(Level::None, |assessment| assessment.level)
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![ASSESSMENTS.with(|a| CacheSize::of_map("threat.assessments", &a.borrow(), |_, _| 0))]
+}
+
+/// Forgets this tick's assessments, which leaves the ones kept in memory.
+pub fn purge() {
+    ASSESSMENTS.with(|a| std::mem::take(&mut *a.borrow_mut()));
+}
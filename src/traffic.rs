@@ -0,0 +1,268 @@
+//! Road usage tracking.
+//!
+//! The tile under every creep is counted each tick in heap memory, and the counts are regularly
+//! folded into `Memory.rooms.<name>.traffic` for the built road tiles of each room. Stored counts
+//! decay on every flush and are capped, so they follow recent use and stay bounded.
+//!
+//! Creeps only keep up roads which see enough traffic. Roads nobody walks on are left to decay,
+//! and once they're gone they're dropped from the plan so they aren't rebuilt.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use log::*;
+use screeps::{find, prelude::*, Creep, RoomName, StructureType};
+
+use crate::{heap::CacheSize, planner};
+
+const TRAFFIC_KEY: &str = "traffic";
+
+/// The most a single tile's count can reach.
+const MAX_TRAFFIC: u32 = 10_000;
+
+/// Counts are multiplied by this many tenths on every flush.
+const DECAY_TENTHS: u32 = 9;
+
+/// Roads with less traffic than this aren't repaired.
+pub const MIN_REPAIR_TRAFFIC: u32 = 5;
+
+/// Roads which decayed away with less traffic than this are removed from the plan.
+const ABANDON_TRAFFIC: u32 = 2;
+
+/// Per-tile counts of one room.
+pub type TileCounts = HashMap<(u8, u8), u32>;
+
+thread_local! {
+    static TICK_COUNTS: RefCell<HashMap<RoomName, TileCounts>> = RefCell::new(HashMap::new());
+}
+
+/// Counts the tile a creep is standing on.
+pub fn record(creep: &Creep) {
+    if creep.spawning() {
+        return;
+    }
+    let pos = creep.pos();
+    TICK_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let count = counts
+            .entry(pos.room_name())
+            .or_insert_with(HashMap::new)
+            .entry((pos.x() as u8, pos.y() as u8))
+            .or_insert(0);
+        *count = (*count + 1).min(MAX_TRAFFIC);
+    });
+}
+
+/// The stored traffic of every tracked road tile in a room.
+pub fn road_traffic(room_name: RoomName) -> TileCounts {
+    planner::room_memory(room_name)
+        .and_then(|memory| memory.string(TRAFFIC_KEY).ok()?)
+        .map(|encoded| decode(&encoded))
+        .unwrap_or_default()
+}
+
+/// Folds the heap counts into memory and drops abandoned roads from the plans.
+pub fn flush() {
+    let tick_counts =
+        TICK_COUNTS.with(|counts| std::mem::replace(&mut *counts.borrow_mut(), HashMap::new()));
+
+    for room in screeps::game::rooms::values() {
+        match room.controller() {
+            Some(controller) if controller.my() => {}
+            _ => continue,
+        }
+        let mut plan = match planner::load(room.name()) {
+            Some(plan) => plan,
+            None => continue,
+        };
+        let roads: HashSet<(u8, u8)> = plan
+            .entries
+            .iter()
+            .filter(|e| e.structure == StructureType::Road)
+            .map(|e| (e.x, e.y))
+            .collect();
+        let built: HashSet<(u8, u8)> = room
+            .find(find::STRUCTURES)
+            .into_iter()
+            .filter(|s| s.structure_type() == StructureType::Road)
+            .map(|s| (s.pos().x() as u8, s.pos().y() as u8))
+            .collect();
+
+        let mut traffic = road_traffic(room.name());
+        let abandoned = fold(&mut traffic, tick_counts.get(&room.name()), &roads, &built);
+        if !abandoned.is_empty() {
+            plan.entries
+                .retain(|e| e.structure != StructureType::Road || !abandoned.contains(&(e.x, e.y)));
+            planner::save(room.name(), &plan);
+            info!(
+                "dropped {} unused roads from the plan of room {}",
+                abandoned.len(),
+                room.name()
+            );
+        }
+
+        if log_enabled!(Level::Debug) {
+            let mut busiest: Vec<(&(u8, u8), &u32)> = traffic.iter().collect();
+            busiest.sort_by_key(|&(&(x, y), &count)| (std::cmp::Reverse(count), y, x));
+            let top: Vec<String> = busiest
+                .iter()
+                .take(10)
+                .map(|((x, y), count)| format!("{},{}: {}", x, y, count))
+                .collect();
+            debug!("busiest roads in room {}: {}", room.name(), top.join(", "));
+        }
+
+        if let Some(memory) = planner::room_memory(room.name()) {
+            memory.set(TRAFFIC_KEY, encode(&traffic));
+        }
+    }
+}
+
+/// Decays a room's stored counts and adds the tick counts of its built roads to them, returning
+/// the roads to drop from the plan.
+///
+/// A road is only tracked from the first flush it's standing at, so a count means it was built
+/// at some point. Planned roads which were never built aren't counted and can't be abandoned;
+/// tracked ones which decayed away with less than [`ABANDON_TRAFFIC`] left are.
+fn fold(
+    traffic: &mut TileCounts,
+    tick_counts: Option<&TileCounts>,
+    roads: &HashSet<(u8, u8)>,
+    built: &HashSet<(u8, u8)>,
+) -> HashSet<(u8, u8)> {
+    for count in traffic.values_mut() {
+        *count = *count * DECAY_TENTHS / 10;
+    }
+    for tile in roads.intersection(built) {
+        traffic.entry(*tile).or_insert(0);
+    }
+    // roads which decayed away keep being counted, so busy ones are rebuilt
+    for (tile, n) in tick_counts.into_iter().flatten() {
+        if let Some(count) = traffic.get_mut(tile) {
+            *count = (*count + n).min(MAX_TRAFFIC);
+        }
+    }
+
+    let abandoned: HashSet<(u8, u8)> = traffic
+        .iter()
+        .filter(|(tile, &count)| !built.contains(tile) && count < ABANDON_TRAFFIC)
+        .map(|(tile, _)| *tile)
+        .collect();
+    traffic.retain(|tile, _| roads.contains(tile) && !abandoned.contains(tile));
+    abandoned
+}
+
+/// Sizes of this module's caches, for the heap report.
+pub fn cache_sizes() -> Vec<CacheSize> {
+    vec![TICK_COUNTS.with(|c| {
+        CacheSize::of_map("traffic.tick_counts", &c.borrow(), |_, counts| {
+            counts.capacity() * std::mem::size_of::<((u8, u8), u32)>()
+        })
+    })]
+}
+
+/// Drops the counts since the last flush.
+pub fn purge() {
+    TICK_COUNTS.with(|c| std::mem::take(&mut *c.borrow_mut()));
+}
+
+/// Serializes counts as `x,y,count;x,y,count;...`.
+fn encode(traffic: &TileCounts) -> String {
+    let entries: Vec<String> = traffic
+        .iter()
+        .map(|((x, y), count)| format!("{},{},{}", x, y, count))
+        .collect();
+    entries.join(";")
+}
+
+fn decode(s: &str) -> TileCounts {
+    s.split(';')
+        .filter_map(|entry| {
+            let mut fields = entry.split(',');
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            Some(((x, y), count))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(tiles: &[(u8, u8)]) -> HashSet<(u8, u8)> {
+        tiles.iter().copied().collect()
+    }
+
+    #[test]
+    fn unbuilt_roads_are_neither_counted_nor_abandoned() {
+        let roads = tiles(&[(10, 10), (11, 10)]);
+        let built = tiles(&[(10, 10)]);
+        let walked: TileCounts = vec![((10, 10), 3), ((11, 10), 7)].into_iter().collect();
+        let mut traffic = TileCounts::new();
+
+        let abandoned = fold(&mut traffic, Some(&walked), &roads, &built);
+        assert!(abandoned.is_empty());
+        assert_eq!(traffic.get(&(10, 10)), Some(&3));
+        assert_eq!(traffic.get(&(11, 10)), None);
+
+        // nobody walks past a planned road which was never built
+        for _ in 0..20 {
+            assert!(fold(&mut traffic, None, &roads, &built).is_empty());
+        }
+        assert_eq!(traffic.get(&(11, 10)), None);
+    }
+
+    #[test]
+    fn built_roads_are_abandoned_once_unused_and_gone() {
+        let roads = tiles(&[(10, 10)]);
+        let mut traffic = TileCounts::new();
+        let walked: TileCounts = vec![((10, 10), 4)].into_iter().collect();
+        fold(&mut traffic, Some(&walked), &roads, &roads);
+
+        // unused while standing, it's kept however low its count gets
+        for _ in 0..20 {
+            assert!(fold(&mut traffic, None, &roads, &roads).is_empty());
+        }
+        assert_eq!(traffic.get(&(10, 10)), Some(&0));
+
+        // once it decayed away it's dropped
+        let abandoned = fold(&mut traffic, None, &roads, &HashSet::new());
+        assert_eq!(abandoned, roads);
+        assert!(traffic.is_empty());
+    }
+
+    #[test]
+    fn busy_roads_which_decayed_away_are_kept() {
+        let roads = tiles(&[(10, 10)]);
+        let mut traffic = TileCounts::new();
+        fold(&mut traffic, None, &roads, &roads);
+
+        let walked: TileCounts = vec![((10, 10), 50)].into_iter().collect();
+        let abandoned = fold(&mut traffic, Some(&walked), &roads, &HashSet::new());
+        assert!(abandoned.is_empty());
+        assert_eq!(traffic.get(&(10, 10)), Some(&50));
+    }
+
+    #[test]
+    fn counts_decay_and_are_capped() {
+        let roads = tiles(&[(10, 10)]);
+        let mut traffic: TileCounts = vec![((10, 10), 100)].into_iter().collect();
+        fold(&mut traffic, None, &roads, &roads);
+        assert_eq!(traffic[&(10, 10)], 90);
+
+        let walked: TileCounts = vec![((10, 10), MAX_TRAFFIC)].into_iter().collect();
+        fold(&mut traffic, Some(&walked), &roads, &roads);
+        assert_eq!(traffic[&(10, 10)], MAX_TRAFFIC);
+    }
+
+    #[test]
+    fn counts_survive_a_round_trip_through_memory() {
+        let traffic: TileCounts = vec![((1, 2), 3), ((49, 0), 10_000)].into_iter().collect();
+        assert_eq!(decode(&encode(&traffic)), traffic);
+        assert!(decode("").is_empty());
+    }
+}
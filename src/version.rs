@@ -0,0 +1,47 @@
+//! Which build is running.
+//!
+//! The crate version, build time and a hash of the sources are logged on the first tick after
+//! every global reset, and `version()` from the console reports them along with the tick of the
+//! last reset, to tell whether a fresh deploy is live.
+
+use std::cell::Cell;
+
+use log::*;
+use stdweb::{js, unstable::TryInto};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// When the build was made, in seconds since the Unix epoch.
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// A hash of the sources the build was made from.
+pub const BUILD_HASH: &str = env!("BUILD_HASH");
+
+thread_local! {
+    /// The first tick this instance ran.
+    static RESET_TICK: Cell<Option<u32>> = Cell::new(None);
+}
+
+/// Notes the tick of the reset and logs the version, on the first tick after a reset.
+pub fn begin_tick() {
+    if RESET_TICK.with(Cell::get).is_some() {
+        return;
+    }
+    let time = screeps::game::time();
+    RESET_TICK.with(|r| r.set(Some(time)));
+    info!("global reset, running {}", describe());
+}
+
+/// The version and build, and the tick of the last reset.
+pub fn describe() -> String {
+    let built = BUILD_TIMESTAMP.parse::<f64>().unwrap_or_default() * 1000.0;
+    let built = js! { return new Date(@{built}).toISOString() };
+    let built: String = built.try_into().unwrap_or_default();
+    let reset = RESET_TICK
+        .with(Cell::get)
+        .map_or("not yet".to_string(), |tick| format!("at tick {}", tick));
+    format!(
+        "version {} built {} ({}), last reset {}",
+        VERSION, built, BUILD_HASH, reset
+    )
+}
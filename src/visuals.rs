@@ -0,0 +1,292 @@
+//! Room visuals.
+//!
+//! Besides plan previews, every owned room can get a status panel while `Memory.config.dashboard`
+//! is set, toggled with `dashboard(true)` from the console. It shows the room's energy, storage,
+//! controller and towers, how many creeps work on each kind of target and what the spawns are
+//! doing, and marks each creep with its target.
+//!
+//! With `Memory.config.map_visuals` set, the world map shows our rooms in green, the rooms
+//! intel marked hostile in red, the best expansion candidates in yellow and the rooms intel
+//! hasn't seen for [`STALE_TICKS`] ticks in grey. Only up to [`MAX_MAP_ROOMS`] rooms are drawn,
+//! as map visuals have a size limit.
+//!
+//! `set_visuals(on)` from the console turns both of them on or off at once.
+
+use std::collections::BTreeMap;
+
+use screeps::{prelude::*, ResourceType, Room, RoomName, Structure, StructureType};
+use stdweb::js;
+
+use crate::{
+    context::TickContext,
+    creeps, expansion, intel,
+    planner::{self, PlanEntry, RoomPlan},
+};
+
+const DASHBOARD_PATH: &str = "config.dashboard";
+const MAP_VISUALS_PATH: &str = "config.map_visuals";
+
+/// How long a room can go unseen before the map shows it as stale.
+const STALE_TICKS: u32 = 5000;
+
+/// The most rooms shaded on the world map.
+pub const MAX_MAP_ROOMS: usize = 100;
+
+fn dashboard_enabled() -> bool {
+    screeps::memory::root().path_bool(DASHBOARD_PATH)
+}
+
+pub fn set_dashboard(enabled: bool) {
+    if let Ok(config) = screeps::memory::root().dict_or_create("config") {
+        config.set("dashboard", enabled);
+    }
+}
+
+/// Turns the dashboards and the map visuals on or off.
+pub fn set_visuals(enabled: bool) {
+    set_dashboard(enabled);
+    screeps::memory::root().path_set(MAP_VISUALS_PATH, enabled);
+}
+
+/// Describes which visuals are drawn.
+pub fn describe() -> String {
+    let on_off = |on| if on { "on" } else { "off" };
+    format!(
+        "dashboard {}, map {}",
+        on_off(dashboard_enabled()),
+        on_off(screeps::memory::root().path_bool(MAP_VISUALS_PATH))
+    )
+}
+
+/// Draws every planned structure as its plan code, with ramparts outlined underneath and the
+/// anchor circled.
+// js! turns its snippets into functions taking each value passed in
+#[allow(clippy::too_many_arguments)]
+pub fn draw_plan(room_name: RoomName, plan: &RoomPlan) {
+    let (ramparts, others): (Vec<&PlanEntry>, Vec<&PlanEntry>) = plan
+        .entries
+        .iter()
+        .partition(|e| e.structure == StructureType::Rampart);
+    let xs: Vec<u32> = others.iter().map(|e| e.x as u32).collect();
+    let ys: Vec<u32> = others.iter().map(|e| e.y as u32).collect();
+    let codes: Vec<String> = others
+        .iter()
+        .map(|e| planner::structure_code(e.structure).to_string())
+        .collect();
+    let rampart_xs: Vec<u32> = ramparts.iter().map(|e| e.x as u32).collect();
+    let rampart_ys: Vec<u32> = ramparts.iter().map(|e| e.y as u32).collect();
+    let (anchor_x, anchor_y) = (plan.anchor.0 as u32, plan.anchor.1 as u32);
+
+    js! {
+        var visual = new RoomVisual(@{room_name.to_string()});
+        var xs = @{xs};
+        var ys = @{ys};
+        var codes = @{codes};
+        var rampartXs = @{rampart_xs};
+        var rampartYs = @{rampart_ys};
+        for (var i = 0; i < rampartXs.length; i++) {
+            visual.rect(rampartXs[i] - 0.45, rampartYs[i] - 0.45, 0.9, 0.9, {
+                fill: "transparent", stroke: "#00ff00", opacity: 0.5
+            });
+        }
+        for (var i = 0; i < codes.length; i++) {
+            visual.text(codes[i], xs[i], ys[i] + 0.2, { font: 0.5, opacity: 0.8 });
+        }
+        visual.circle(@{anchor_x}, @{anchor_y}, {
+            radius: 0.6, fill: "transparent", stroke: "#ffff00", opacity: 0.8
+        });
+    }
+}
+
+/// Draws the status panel in every owned room, if the dashboard is enabled.
+pub fn draw_dashboards(ctx: &TickContext) {
+    if !dashboard_enabled() {
+        return;
+    }
+    let mut creeps_by_room: BTreeMap<RoomName, Vec<(u32, u32, &'static str)>> = BTreeMap::new();
+    for creep in screeps::game::creeps::values() {
+        let kind = if creep.spawning() {
+            "spawning"
+        } else {
+            creeps::current_target(&creep.name()).map_or("idle", |t| t.kind())
+        };
+        let pos = creep.pos();
+        creeps_by_room
+            .entry(pos.room_name())
+            .or_default()
+            .push((pos.x(), pos.y(), kind));
+    }
+
+    for room in screeps::game::rooms::values() {
+        let controller = match room.controller() {
+            Some(controller) if controller.my() => controller,
+            _ => continue,
+        };
+        let creeps = creeps_by_room.remove(&room.name()).unwrap_or_default();
+        // each line, with how full its bar is, if it has one
+        let mut lines: Vec<(String, Option<f64>)> = Vec::new();
+
+        let (energy, capacity) = (room.energy_available(), room.energy_capacity_available());
+        lines.push((
+            format!("energy {}/{}", energy, capacity),
+            Some(fraction(energy, capacity)),
+        ));
+        if let Some(storage) = room.storage() {
+            let used = storage.store_used_capacity(None);
+            let capacity = storage.store_capacity(None);
+            lines.push((
+                format!("storage {}k/{}k", used / 1000, capacity / 1000),
+                Some(fraction(used, capacity)),
+            ));
+        }
+        match (controller.progress(), controller.progress_total()) {
+            (Some(progress), Some(total)) => lines.push((
+                format!("rcl {} {}/{}", controller.level(), progress, total),
+                Some(fraction(progress, total)),
+            )),
+            _ => lines.push((format!("rcl {}", controller.level()), None)),
+        }
+        lines.push((
+            format!("downgrade in {}", controller.ticks_to_downgrade()),
+            None,
+        ));
+        lines.extend(spawn_lines(&room));
+        lines.push((tower_line(&room, ctx), None));
+
+        let mut kinds: BTreeMap<&'static str, u32> = BTreeMap::new();
+        for &(_, _, kind) in &creeps {
+            *kinds.entry(kind).or_default() += 1;
+        }
+        let kinds: Vec<String> = kinds
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        lines.push((format!("creeps: {}", kinds.join(", ")), None));
+
+        draw_panel(room.name(), &lines, &creeps);
+    }
+}
+
+fn fraction(value: u32, total: u32) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        value as f64 / total as f64
+    }
+}
+
+/// What each of the room's spawns is spawning.
+fn spawn_lines(room: &Room) -> Vec<(String, Option<f64>)> {
+    let mut spawns = room.find(screeps::find::MY_SPAWNS);
+    spawns.sort_by_key(|s| s.name());
+    spawns
+        .iter()
+        .map(|spawn| match spawn.spawning() {
+            Some(spawning) => {
+                let done = spawning.need_time() - spawning.remaining_time();
+                (
+                    format!("{}: {}", spawn.name(), spawning.name()),
+                    Some(fraction(done, spawning.need_time())),
+                )
+            }
+            None => (format!("{}: idle", spawn.name()), None),
+        })
+        .collect()
+}
+
+/// The energy in each of the room's towers.
+fn tower_line(room: &Room, ctx: &TickContext) -> String {
+    let snapshot = ctx.rooms.snapshot(room);
+    let energy: Vec<String> = snapshot
+        .my_structures(StructureType::Tower)
+        .filter_map(|s| match s {
+            Structure::Tower(tower) => Some(tower.store_of(ResourceType::Energy).to_string()),
+            _ => None,
+        })
+        .collect();
+    if energy.is_empty() {
+        "towers: none".to_string()
+    } else {
+        format!("towers: {}", energy.join(", "))
+    }
+}
+
+/// Draws the panel's lines in the room's top left corner, and each creep's target kind above it.
+fn draw_panel(room_name: RoomName, lines: &[(String, Option<f64>)], creeps: &[(u32, u32, &str)]) {
+    let texts: Vec<String> = lines.iter().map(|(text, _)| text.clone()).collect();
+    // lines without a bar are -1
+    let fills: Vec<f64> = lines.iter().map(|(_, fill)| fill.unwrap_or(-1.0)).collect();
+    let xs: Vec<u32> = creeps.iter().map(|&(x, _, _)| x).collect();
+    let ys: Vec<u32> = creeps.iter().map(|&(_, y, _)| y).collect();
+    let kinds: Vec<String> = creeps
+        .iter()
+        .map(|&(_, _, kind)| kind.to_string())
+        .collect();
+
+    js! {
+        var visual = new RoomVisual(@{room_name.to_string()});
+        var texts = @{texts};
+        var fills = @{fills};
+        visual.rect(0.5, 0.5, 9, texts.length + 0.5, { fill: "#000000", opacity: 0.5 });
+        for (var i = 0; i < texts.length; i++) {
+            var y = 1.2 + i;
+            if (fills[i] >= 0) {
+                visual.rect(5.5, y - 0.5, 3.5, 0.6, { fill: "#333333", opacity: 0.8 });
+                visual.rect(5.5, y - 0.5, 3.5 * Math.min(fills[i], 1), 0.6, {
+                    fill: "#44aa44", opacity: 0.8
+                });
+            }
+            visual.text(texts[i], 0.8, y, { align: "left", font: 0.5 });
+        }
+        var xs = @{xs};
+        var ys = @{ys};
+        var kinds = @{kinds};
+        for (var i = 0; i < kinds.length; i++) {
+            visual.text(kinds[i], xs[i], ys[i] - 0.6, { font: 0.35, opacity: 0.7 });
+        }
+    }
+}
+
+/// Shades our rooms and the hostile ones on the world map, if map visuals are enabled.
+pub fn draw_map() {
+    if !screeps::memory::root().path_bool(MAP_VISUALS_PATH) {
+        return;
+    }
+    let mut rooms: Vec<(String, &str)> = screeps::game::rooms::values()
+        .into_iter()
+        .filter(|room| room.controller().map_or(false, |c| c.my()))
+        .map(|room| (room.name().to_string(), "#00ff00"))
+        .collect();
+    rooms.extend(
+        intel::hostile_rooms()
+            .into_iter()
+            .map(|name| (name.to_string(), "#ff0000")),
+    );
+    rooms.extend(
+        expansion::candidates()
+            .into_iter()
+            .map(|(name, _)| (name.to_string(), "#ffff00")),
+    );
+    rooms.extend(
+        intel::rooms_needing_refresh(STALE_TICKS)
+            .into_iter()
+            .map(|name| (name.to_string(), "#808080")),
+    );
+    rooms.truncate(MAX_MAP_ROOMS);
+
+    let names: Vec<String> = rooms.iter().map(|(name, _)| name.clone()).collect();
+    let colors: Vec<String> = rooms.iter().map(|(_, color)| color.to_string()).collect();
+    js! {
+        // private servers may not have map visuals
+        if (typeof Game.map.visual === "undefined") {
+            return;
+        }
+        var names = @{names};
+        var colors = @{colors};
+        for (var i = 0; i < names.length; i++) {
+            Game.map.visual.rect(new RoomPosition(0, 0, names[i]), 50, 50, {
+                fill: colors[i], opacity: 0.25
+            });
+        }
+    }
+}